@@ -324,6 +324,7 @@ impl<'o> WalkProc<'o> {
             Statement::Break(_) => {},
             Statement::Goto(_) => {},
             Statement::Crash(_) => {},
+            Statement::Error { .. } => {},
             Statement::Label { name: _, block } => self.visit_block(block),
             Statement::Del(expr) => { self.visit_expression(location, expr, None); },
         }
@@ -395,6 +396,7 @@ impl<'o> WalkProc<'o> {
                 self.visit_expression(location, else_, type_hint);
                 ty
             }
+            Expression::Error { .. } => StaticType::None,
         }
     }
 
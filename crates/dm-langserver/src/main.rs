@@ -96,6 +96,10 @@ fn main() {
 
 const VERSION: Option<jsonrpc::Version> = Some(jsonrpc::Version::V2);
 
+/// How long to spend annotating a single file before giving up, so that a
+/// pathological file can't hang the server.
+const ANNOTATION_PARSE_BUDGET: std::time::Duration = std::time::Duration::from_secs(5);
+
 #[derive(PartialEq)]
 enum InitStatus {
     Starting,
@@ -589,7 +593,8 @@ impl Engine {
                     let mut annotations = AnnotationTree::default();
                     {
                         let indent = dm::indents::IndentProcessor::new(&self.context, &mut preprocessor);
-                        let parser = dm::parser::Parser::new(&self.context, indent);
+                        let mut parser = dm::parser::Parser::new(&self.context, indent);
+                        parser.set_deadline(std::time::Instant::now() + ANNOTATION_PARSE_BUDGET);
                         parser.parse_annotations_only(&mut annotations);
                     }
                     annotations.merge(preprocessor.take_annotations().unwrap());
@@ -604,6 +609,7 @@ impl Engine {
                     let file_id = self.context.get_file(filename.as_ref()).expect("file didn't exist?");
                     // Clear old errors for this file. Hacky, but it will work for now.
                     self.context.errors_mut().retain(|error| error.location().file != file_id);
+                    self.context.clear_lint_controls(file_id);
 
                     pp.enable_annotations();
                     let mut annotations = AnnotationTree::default();
@@ -902,7 +908,7 @@ impl Engine {
                     UnscopedVar::None => {}
                 }
             },
-            Annotation::ScopedCall(priors, proc_name) => {
+            Annotation::ScopedCall(priors, proc_name) | Annotation::LooseScopedCall(priors, proc_name) => {
                 let mut next = self.find_scoped_type(&iter, priors);
                 while let Some(ty) = next {
                     if let Some(proc) = ty.procs.get(proc_name) {
@@ -914,7 +920,7 @@ impl Engine {
                     next = ty.parent_type_without_root();
                 }
             },
-            Annotation::ScopedVar(priors, var_name) => {
+            Annotation::ScopedVar(priors, var_name) | Annotation::LooseScopedVar(priors, var_name) => {
                 let mut next = self.find_scoped_type(&iter, priors);
                 while let Some(ty) = next {
                     if let Some(var) = ty.vars.get(var_name) {
@@ -1396,16 +1402,18 @@ handle_method_call! {
                     let next = ty.or_else(|| Some(self.objtree.root()));
                     results.append(&mut self.construct_proc_hover(proc_name, next, false)?);
                 }
-                Annotation::ScopedCall(priors, proc_name) if symbol_id.is_some() => {
+                Annotation::ScopedCall(priors, proc_name) | Annotation::LooseScopedCall(priors, proc_name) if symbol_id.is_some() => {
                     let next = self.find_scoped_type(&iter, priors);
                     results.append(&mut self.construct_proc_hover(proc_name, next, true)?);
                 }
-                Annotation::ScopedVar(priors, var_name) if symbol_id.is_some() => {
+                Annotation::ScopedVar(priors, var_name) | Annotation::LooseScopedVar(priors, var_name) if symbol_id.is_some() => {
                     let next = self.find_scoped_type(&iter, priors);
                     results.append(&mut self.construct_var_hover(var_name, next, true)?);
                 }
-                Annotation::MacroUse { docs: Some(dc), .. } if !dc.is_empty() => {
-                    results.push(dc.text());
+                Annotation::MacroUse { docs: Some(id), .. } => {
+                    if let Some(dc) = annotations.macro_docs(*id) {
+                        results.push(dc.text());
+                    }
                 }
                 _ => {}
             }
@@ -1494,7 +1502,7 @@ handle_method_call! {
                     UnscopedVar::None => {}
                 }
             },
-            Annotation::ScopedCall(priors, proc_name) => {
+            Annotation::ScopedCall(priors, proc_name) | Annotation::LooseScopedCall(priors, proc_name) => {
                 let mut next = self.find_scoped_type(&iter, priors);
                 while let Some(ty) = next {
                     if let Some(proc) = ty.procs.get(proc_name) {
@@ -1504,7 +1512,7 @@ handle_method_call! {
                     next = ty.parent_type_without_root();
                 }
             },
-            Annotation::ScopedVar(priors, var_name) => {
+            Annotation::ScopedVar(priors, var_name) | Annotation::LooseScopedVar(priors, var_name) => {
                 let mut next = self.find_scoped_type(&iter, priors);
                 while let Some(ty) = next {
                     if let Some(var) = ty.vars.get(var_name) {
@@ -1514,7 +1522,24 @@ handle_method_call! {
                     next = ty.parent_type_without_root();
                 }
             },
-            Annotation::ParentCall => {
+            Annotation::GlobalVar(var_name) => {
+                if let Some(var) = self.objtree.resolve_global_var(var_name) {
+                    // `global.foo` is also annotated as a scoped var
+                    let location = self.convert_location(var.value.location, &var.value.docs, &["/var/", var_name])?;
+                    if !results.contains(&location) {
+                        results.push(location);
+                    }
+                }
+            },
+            Annotation::GlobalCall(proc_name) => {
+                if let Some(proc) = self.objtree.resolve_global_proc(proc_name) {
+                    let location = self.convert_location(proc.location, &proc.docs, &["/proc/", proc_name])?;
+                    if !results.contains(&location) {
+                        results.push(location);
+                    }
+                }
+            },
+            Annotation::ParentCall { .. } => {
                 if let (Some(ty), Some((proc_name, idx))) = self.find_type_context(&iter) {
                     // TODO: idx is always 0 unless there are multiple overrides in
                     // the same .dm file, due to annotations operating against a
@@ -1581,7 +1606,7 @@ handle_method_call! {
                     UnscopedVar::None => {}
                 }
             },
-            Annotation::ScopedVar(priors, var_name) => {
+            Annotation::ScopedVar(priors, var_name) | Annotation::LooseScopedVar(priors, var_name) => {
                 let mut next = self.find_scoped_type(&iter, priors);
                 while let Some(ty) = next {
                     if let Some(var) = ty.get().vars.get(var_name) {
@@ -1680,7 +1705,9 @@ handle_method_call! {
                 any_annotation = true;
             },
             Annotation::ScopedCall(priors, query) |
-            Annotation::ScopedVar(priors, query) => {
+            Annotation::ScopedVar(priors, query) |
+            Annotation::LooseScopedCall(priors, query) |
+            Annotation::LooseScopedVar(priors, query) => {
                 self.scoped_completions(&mut results, &iter, priors, query);
                 any_annotation = true;
             },
@@ -1987,7 +2014,7 @@ handle_method_call! {
                     continue;
                 }
                 match annotation {
-                    Annotation::Include(path) |
+                    Annotation::Include { path, .. } |
                     Annotation::Resource(path) => {
                         let pathbuf = if path.is_relative() {
                             std::env::current_dir().map_err(invalid_request)?.join(path)
@@ -1,5 +1,7 @@
 //! Query parser and evaluator for workspace symbol search.
 
+use dm::objtree::CompletionRank;
+
 /// A parsed symbol query.
 #[derive(Debug)]
 pub enum Query {
@@ -75,27 +77,13 @@ impl Query {
     }
 }
 
-fn simplify(s: &str) -> impl Iterator<Item=char> + Clone + '_ {
-    s.chars().flat_map(|c| c.to_lowercase()).filter(|c| c.is_alphanumeric())
-}
-
 // ignore case and underscores
 pub fn starts_with<'a>(fulltext: &'a str, query: &'a str) -> bool {
-    let mut query_chars = simplify(query);
-    simplify(fulltext).zip(&mut query_chars).all(|(a, b)| a == b) && query_chars.next().is_none()
+    CompletionRank::of(fulltext, query) == Some(CompletionRank::Prefix)
 }
 
 pub fn contains<'a>(fulltext: &'a str, query: &'a str) -> bool {
-    let (mut fulltext, query) = (simplify(fulltext), simplify(query));
-    loop {
-        let mut clone = query.clone();
-        if fulltext.clone().zip(&mut clone).all(|(a, b)| a == b) && clone.next().is_none() {
-            return true;
-        }
-        if fulltext.next().is_none() {
-            return false;
-        }
-    }
+    matches!(CompletionRank::of(fulltext, query), Some(CompletionRank::Prefix | CompletionRank::Substring))
 }
 
 fn any_alphanumeric(text: &str) -> bool {
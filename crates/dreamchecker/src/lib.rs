@@ -4,7 +4,7 @@
 
 use dm::ast::*;
 use dm::constants::{ConstFn, Constant};
-use dm::objtree::{ObjectTree, ProcRef, TypeRef};
+use dm::objtree::{NavigatePathResult, ObjectTree, ProcRef, TypeRef};
 use dm::{Context, DMError, Location, Severity};
 use dm::annotation::{Annotation, AnnotationTree};
 
@@ -23,6 +23,8 @@ mod type_expr;
 use type_expr::TypeExpr;
 mod switch_rand_range;
 use switch_rand_range::check_switch_rand_range;
+mod unused_vars;
+use unused_vars::check_unused_vars;
 
 #[doc(hidden)]  // Intended for the tests only.
 pub mod test_helpers;
@@ -583,6 +585,11 @@ pub struct AnalyzeObjectTree<'o> {
 
     call_tree: HashMap<ProcRef<'o>, Vec<(ProcRef<'o>, Location, bool)>>,
 
+    // proc -> positional arguments it takes, None for any number
+    positional_arguments: HashMap<ProcRef<'o>, Option<usize>>,
+    // proc -> the most positional arguments it or any override takes
+    max_positional_arguments: HashMap<ProcRef<'o>, Option<usize>>,
+
     sleeping_procs: ViolatingProcs<'o>,
     impure_procs: ViolatingProcs<'o>,
     /// Procs with waitfor=0 or waitfor=FALSE
@@ -622,6 +629,8 @@ impl<'o> AnalyzeObjectTree<'o> {
             can_be_redefined: ProcDirective::new("SpacemanDMM_can_be_redefined", false, false, false),
             used_kwargs: Default::default(),
             call_tree: Default::default(),
+            positional_arguments: Default::default(),
+            max_positional_arguments: Default::default(),
             sleeping_procs: Default::default(),
             impure_procs: Default::default(),
             waitfor_procs: Default::default(),
@@ -635,7 +644,26 @@ impl<'o> AnalyzeObjectTree<'o> {
         self.must_not_sleep.try_copy_from_parent(proc);
         self.must_be_pure.try_copy_from_parent(proc);
 
-        AnalyzeProc::new(self, self.context, self.objtree, proc).run(code, annotate_to)
+        AnalyzeProc::new(self, self.context, self.objtree, proc).run(code, annotate_to);
+        check_unused_vars(code, self.context);
+    }
+
+    /// The most positional arguments a proc or any override of it takes, or
+    /// `None` if one of them takes any number, through `...` or by reading
+    /// `args`.
+    fn max_positional_arguments(&mut self, proc: ProcRef<'o>) -> Option<usize> {
+        if let Some(&most) = self.max_positional_arguments.get(&proc) {
+            return most;
+        }
+        let cache = &mut self.positional_arguments;
+        let mut most = positional_arguments(cache, proc);
+        proc.recurse_children(&mut |each| {
+            if let Some(so_far) = most {
+                most = positional_arguments(cache, each).map(|count| count.max(so_far));
+            }
+        });
+        self.max_positional_arguments.insert(proc, most);
+        most
     }
 
     #[inline]
@@ -826,7 +854,7 @@ impl<'o> AnalyzeObjectTree<'o> {
             }
         }
 
-        for statement in code.iter() {
+        for statement in leading_settings(code) {
             if let Statement::Setting { ref name, ref value, .. } = statement.elem {
                 if name == "SpacemanDMM_return_type" {
                     if let Some(Term::Prefab(fab)) = value.as_term() {
@@ -847,48 +875,7 @@ impl<'o> AnalyzeObjectTree<'o> {
                     error(statement.location, format!("unknown setting {name:?}"))
                         .set_severity(Severity::Warning)
                         .register(self.context);
-                } else {
-                    match name.as_str() {
-                        "background" | "waitfor" | "hidden" | "instant" | "popup_menu" => {
-                            if directive_value_to_truthy(value, statement.location).is_err() {
-                                error(statement.location, format!("set {} must be 0/1/TRUE/FALSE", name.as_str()))
-                                    .set_severity(Severity::Warning)
-                                    .with_errortype("invalid_set_value")
-                                    .register(self.context);
-                            }
-                        },
-                        "name" | "category" | "desc" => {
-                            if let Some(term) = value.as_term() {
-                                match term {
-                                    // TODO: detect procs-as-verbs here
-                                    Term::String(_) | Term::InterpString(_, _) => {},
-                                    // category can be set null to hide it
-                                    Term::Null if name.as_str() == "category" => {},
-                                    other => {
-                                        error(statement.location, format!("set {} must have a string value", name.as_str()))
-                                            .set_severity(Severity::Warning)
-                                            .with_errortype("invalid_set_value")
-                                            .register(self.context);
-                                    },
-                                }
-                            }
-                        },
-                        "invisibility" => {
-                            if let Some(Term::Int(i)) = value.as_term() {
-                                if *i >= 0 && *i <= 100 {
-                                    continue;
-                                }
-                            }
-                            error(statement.location, "set invisibility must be 0-100")
-                                .set_severity(Severity::Warning)
-                                .with_errortype("invalid_set_value")
-                                .register(self.context);
-                        },
-                        _ => {},
-                    }
                 }
-            } else {
-                break;
             }
         }
     }
@@ -1161,6 +1148,56 @@ impl ControlFlow {
     }
 }
 
+/// Whether control never continues past this statement to the next one in
+/// its block, regardless of what any conditions evaluate to.
+///
+/// Unlike `ControlFlow`, which is computed while visiting, this is purely
+/// structural: `break`, `continue` and `goto` jump away, and an `if` leaves
+/// when it has an `else` and every arm leaves.
+fn always_leaves(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) |
+        Statement::Crash(_) |
+        Statement::Break(_) |
+        Statement::Continue(_) |
+        Statement::Goto(_) => true,
+        Statement::If { arms, else_arm: Some(else_arm) } => {
+            arms.iter().all(|(_, block)| block_always_leaves(block)) && block_always_leaves(else_arm)
+        }
+        _ => false,
+    }
+}
+
+fn block_always_leaves(block: &[Spanned<Statement>]) -> bool {
+    // A label can be jumped to, so only what follows the last one counts.
+    let start = block.iter().rposition(|stmt| matches!(stmt.elem, Statement::Label { .. })).map_or(0, |i| i + 1);
+    block[start..].iter().any(|stmt| always_leaves(&stmt.elem))
+}
+
+/// The positional arguments a proc takes, or `None` if it takes any number.
+fn positional_arguments<'o>(cache: &mut HashMap<ProcRef<'o>, Option<usize>>, proc: ProcRef<'o>) -> Option<usize> {
+    *cache.entry(proc).or_insert_with(|| {
+        let variadic = proc.parameters.iter().any(|param| param.name == "...")
+            || proc.code.as_ref().is_some_and(|code| reads_args(&proc.parameters, code));
+        (!variadic).then_some(proc.parameters.len())
+    })
+}
+
+fn reads_args(parameters: &[Parameter], code: &[Spanned<Statement>]) -> bool {
+    struct ReadsArgs(bool);
+    impl<'ast> Visitor<'ast> for ReadsArgs {
+        fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+            if matches!(&term.elem, Term::Ident(name) if name == "args") {
+                self.0 = true;
+            }
+            walk_term(self, term);
+        }
+    }
+    let mut visitor = ReadsArgs(false);
+    visitor.visit_proc(parameters, code);
+    visitor.0
+}
+
 #[derive(Debug, Clone)]
 struct LocalVar<'o> {
     location: Location,
@@ -1268,8 +1305,14 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
 
     fn visit_block(&mut self, block: &'o [Spanned<Statement>], local_vars: &mut HashMap<String, LocalVar<'o>>, mut setting_allowed : bool, annotate_to: Option<&RefCell<AnnotationTree>>) -> ControlFlow {
         let mut term = ControlFlow::allfalse();
+        let mut left = false;
         for stmt in block.iter().peekable() {
-            if term.terminates() {
+            if let Statement::Label { .. } = stmt.elem {
+                // reachable by `goto` even when falling through isn't
+                term = ControlFlow::allfalse();
+                left = false;
+            }
+            if left || term.terminates() {
                 error(stmt.location,"possible unreachable code here")
                     .with_errortype("unreachable_code")
                     .register(self.context);
@@ -1291,6 +1334,7 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
             }
             let state = self.visit_statement(stmt.location, &stmt.elem, local_vars, annotate_to);
             term.merge(state);
+            left |= always_leaves(&stmt.elem);
         }
         term
     }
@@ -1616,6 +1660,7 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
             Statement::Continue(_) => { return ControlFlow { returns: false, continues: true, breaks: false, fuzzy: true } },
             Statement::Break(_) => { return ControlFlow { returns: false, continues: false, breaks: true, fuzzy: true } },
             Statement::Goto(_) => {},
+            Statement::Error { .. } => {},
             Statement::Label { name: _, block } => { self.visit_block(block, &mut local_vars.clone(), false, annotate_to); },
             Statement::Del(expr) => { self.visit_expression(location, expr, None, local_vars); },
         }
@@ -1708,6 +1753,7 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
                             .with_note(location, "add parentheses to disambiguate: `(a ? b : c) in d`")
                             .register(self.context);
                     },
+                    Expression::Error { .. } => {},
                 };
                 let lty = self.visit_expression(location, lhs, None, local_vars);
                 let rty = self.visit_expression(location, rhs, None, local_vars);
@@ -1746,6 +1792,7 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
                 self.visit_expression(location, else_, type_hint, local_vars);
                 ty
             }
+            Expression::Error { .. } => Analysis::empty(),
         }
     }
 
@@ -1974,9 +2021,26 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
                 Analysis::empty()
             },
             Term::DynamicCall(lhs_args, rhs_args) => {
-                self.visit_arguments(location, lhs_args, local_vars);
-                self.visit_arguments(location, rhs_args, local_vars);
-                Analysis::empty()  // TODO
+                let targets: Vec<_> = lhs_args.iter()
+                    .map(|arg| self.visit_expression(location, arg, None, local_vars))
+                    .collect();
+                // check the call against the proc it calls, where that's known
+                let resolved = match DynamicCallTarget::from_call_args(lhs_args) {
+                    DynamicCallTarget::Named(_, name) => targets[0].static_ty.basic_type()
+                        .and_then(|ty| Some((ty, ty.get_proc(name)?))),
+                    DynamicCallTarget::Path(prefab) => match self.ty.navigate_path(&prefab.path) {
+                        Some(NavigatePathResult::ProcPath(proc, _)) => Some((proc.ty(), proc)),
+                        _ => None,
+                    },
+                    DynamicCallTarget::Unknown => None,
+                };
+                match resolved {
+                    Some((src, proc)) => self.visit_call(location, src, proc, rhs_args, false, local_vars),
+                    None => {
+                        self.visit_arguments(location, rhs_args, local_vars);
+                        Analysis::empty()
+                    }
+                }
             },
             Term::ExternalCall { library, function, args } => {
                 if let Some(library) = library {
@@ -2467,6 +2531,19 @@ impl<'o, 's> AnalyzeProc<'o, 's> {
             }
         }
 
+        // many builtins take more than their declared parameters, and
+        // arglist() can't be counted
+        if !arglist_used && !proc.is_builtin()
+            && let Some(most) = self.env.max_positional_arguments(proc)
+            && param_idx > most
+        {
+            error(location, format!("too many arguments to {proc}: given {param_idx}, takes at most {most}"))
+                .set_severity(Severity::Warning)
+                .with_errortype("too_many_arguments")
+                .with_note(proc.location, "declared here")
+                .register(self.context);
+        }
+
         if proc.ty().is_root() && proc.name() == "astype" {
             if let Some(type_val) = param_idx_map.get(&1) {
                 if let Some(Constant::Prefab(path)) = type_val.clone().value {
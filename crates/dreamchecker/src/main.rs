@@ -58,17 +58,39 @@ fn main() {
 
     println!("============================================================");
     println!("Parsing {}...\n", dme.display());
-    let pp = dm::preprocessor::Preprocessor::new(&context, dme)
+    let check_includes = context.config().code_standards.check_includes;
+    let root = dme.parent().map(ToOwned::to_owned).unwrap_or_default();
+    let mut pp = dm::preprocessor::Preprocessor::new(&context, dme)
         .expect("i/o error opening .dme");
-    let indents = dm::indents::IndentProcessor::new(&context, pp);
+    // unused includes are found from what the annotations reference
+    let mut annotations = dm::annotation::AnnotationTree::default();
+    if check_includes {
+        pp.enable_annotations();
+    }
+    let indents = dm::indents::IndentProcessor::new(&context, &mut pp);
     let mut parser = dm::parser::Parser::new(&context, indents);
     parser.enable_procs();
+    if check_includes {
+        parser.annotate_to(&mut annotations);
+    }
     let (fatal_errored, tree) = parser.parse_object_tree_2();
 
     if !parse_only && !fatal_errored {
         dreamchecker::run_cli(&context, &tree, None);
     }
 
+    if check_includes {
+        println!("============================================================");
+        println!("Checking includes...\n");
+        if let Some(preprocessed) = pp.take_annotations() {
+            annotations.merge(preprocessed);
+        }
+        let graph = pp.include_graph();
+        graph.report_cycles(&context);
+        graph.report_unused_includes(&context, &annotations, &tree);
+        graph.report_missing_include_guards(&context, &root);
+    }
+
     println!("============================================================");
     let errors = context.errors().iter().filter(|each| each.severity() <= dm::Severity::Info).count();
     println!("Found {errors} diagnostics");
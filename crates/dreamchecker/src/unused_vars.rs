@@ -0,0 +1,137 @@
+use dm::ast::*;
+use dm::{Context, DMError, Location, Severity};
+
+/**
+ * Checks for local vars which are declared but never read.
+ * Assigning to a var does not count as reading it, but incrementing or
+ * decrementing it does, as does taking a reference to it or using it inside
+ * an embedded expression. Loop vars are never reported.
+ */
+pub fn check_unused_vars(block: &[Spanned<Statement>], context: &Context) {
+    let mut finder = UnusedVarFinder::default();
+    finder.visit_block(block);
+    for local in finder.locals {
+        if local.report && !local.read {
+            DMError::new(local.location, format!("unused local var: {:?}", local.name))
+                .with_component(dm::Component::DreamChecker)
+                .set_severity(Severity::Warning)
+                .with_errortype("unused_var")
+                .register(context);
+        }
+    }
+}
+
+struct Local<'ast> {
+    name: &'ast str,
+    location: Location,
+    report: bool,
+    read: bool,
+}
+
+#[derive(Default)]
+struct UnusedVarFinder<'ast> {
+    locals: Vec<Local<'ast>>,
+    // indices into `locals` for the vars declared in each open scope
+    scopes: Vec<Vec<usize>>,
+}
+
+impl<'ast> UnusedVarFinder<'ast> {
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Vec::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &'ast str, location: Location, report: bool) {
+        let index = self.locals.len();
+        self.locals.push(Local { name, location, report, read: false });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(index);
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Local<'ast>> {
+        let index = self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|&index| self.locals[index].name == name)?;
+        Some(&mut self.locals[index])
+    }
+}
+
+impl<'ast> Visitor<'ast> for UnusedVarFinder<'ast> {
+    fn visit_block(&mut self, block: &'ast [Spanned<Statement>]) {
+        self.scoped(|this| walk_block(this, block));
+    }
+
+    fn visit_statement(&mut self, location: Location, statement: &'ast Statement) {
+        match statement {
+            Statement::Var(var) => {
+                // the initializer may read a var of the same name from outside
+                if let Some(value) = &var.value {
+                    self.visit_expression(value);
+                }
+                self.declare(&var.name, location, true);
+            }
+            Statement::Vars(vars) => {
+                for var in vars {
+                    if let Some(value) = &var.value {
+                        self.visit_expression(value);
+                    }
+                    self.declare(&var.name, location, true);
+                }
+            }
+            Statement::ForLoop { .. } => {
+                self.scoped(|this| walk_statement(this, location, statement));
+            }
+            Statement::ForList(for_list) => {
+                if let Some(in_list) = &for_list.in_list {
+                    self.visit_expression(in_list);
+                }
+                self.scoped(|this| {
+                    if for_list.var_type.is_some() {
+                        this.declare(&for_list.name, location, false);
+                    }
+                    this.visit_block(&for_list.block);
+                });
+            }
+            Statement::ForRange(for_range) => {
+                self.visit_expression(&for_range.start);
+                self.visit_expression(&for_range.end);
+                if let Some(step) = &for_range.step {
+                    self.visit_expression(step);
+                }
+                self.scoped(|this| {
+                    if for_range.var_type.is_some() {
+                        this.declare(&for_range.name, location, false);
+                    }
+                    this.visit_block(&for_range.block);
+                });
+            }
+            _ => walk_statement(self, location, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        match expression {
+            // assigning directly to a var doesn't read it
+            Expression::AssignOp { lhs, rhs, .. } if is_bare_ident(lhs) => self.visit_expression(rhs),
+            _ => walk_expression(self, expression),
+        }
+    }
+
+    fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+        match &term.elem {
+            Term::Ident(name) => {
+                if let Some(local) = self.lookup(name) {
+                    local.read = true;
+                }
+            }
+            _ => walk_term(self, term),
+        }
+    }
+}
+
+fn is_bare_ident(expression: &Expression) -> bool {
+    matches!(expression, Expression::Base { term, follow } if follow.is_empty() && matches!(term.elem, Term::Ident(_)))
+}
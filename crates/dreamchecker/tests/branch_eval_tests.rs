@@ -35,6 +35,51 @@ fn if_else() {
     check_errors_match(code, IF_ELSE_ERRORS);
 }
 
+pub const RETURN_ERRORS: &[(u32, u16, &str)] = &[
+    (3, 5, "possible unreachable code here"),
+];
+
+#[test]
+fn code_after_return() {
+    let code = r##"
+/proc/test()
+    return
+    world.log << "unreachable"
+"##.trim();
+    check_errors_match(code, RETURN_ERRORS);
+}
+
+pub const JUMP_ERRORS: &[(u32, u16, &str)] = &[
+    (4, 9, "possible unreachable code here"),
+    (8, 13, "possible unreachable code here"),
+    (13, 9, "possible unreachable code here"),
+    (15, 5, "possible unreachable code here"),
+];
+
+#[test]
+fn code_after_jumps() {
+    let code = r##"
+/proc/test()
+    for(var/i in 1 to 10)
+        break
+        world.log << "after break"
+    for(var/i in 1 to 10)
+        if(prob(50))
+            continue
+            world.log << "after continue"
+        if(prob(50))
+            break
+        else
+            return
+        world.log << "after both arms"
+    goto done
+    world.log << "after goto"
+    done:
+    world.log << "at label"
+"##.trim();
+    check_errors_match(code, JUMP_ERRORS);
+}
+
 pub const IF_ARMS_ERRORS: &[(u32, u16, &str)] = &[
     (2, 7, "control flow condition is a static term"),
     (2, 7, "if condition is always true"),
@@ -1,11 +1,13 @@
 extern crate dreamchecker as dc;
 
-use dc::test_helpers::check_errors_match;
+use dc::test_helpers::{check_errors_match, NO_ERRORS};
 
 pub const LOCAL_SCOPE_ERRORS: &[(u32, u16, &str)] = &[
     (7, 9, "undefined var: \"bar\""),
     (9, 5, "undefined var: \"bar\""),
     (13, 5, "undefined var: \"bar\""),
+    (4, 9, "unused local var: \"bar\""),
+    (12, 9, "unused local var: \"bar\""),
 ];
 
 #[test]
@@ -27,3 +29,57 @@ fn local_scope() {
 "##.trim();
     check_errors_match(code, LOCAL_SCOPE_ERRORS);
 }
+
+pub const UNUSED_LOCAL_ERRORS: &[(u32, u16, &str)] = &[
+    (2, 5, "unused local var: \"foo\""),
+    (3, 5, "unused local var: \"bar\""),
+];
+
+#[test]
+fn unused_local() {
+    let code = r##"
+/proc/test()
+    var/foo = 1
+    var/bar
+"##.trim();
+    check_errors_match(code, UNUSED_LOCAL_ERRORS);
+}
+
+#[test]
+fn used_local() {
+    let code = r##"
+/proc/takes_ref(ref)
+    return ref
+
+/proc/test()
+    var/foo = 1
+    var/bar = 2
+    var/baz = 3
+    var/list/items = list()
+    for(var/item in items)
+        return
+    world.log << "[foo]"
+    takes_ref(&bar)
+    baz++
+"##.trim();
+    check_errors_match(code, NO_ERRORS);
+}
+
+pub const WRITE_ONLY_LOCAL_ERRORS: &[(u32, u16, &str)] = &[
+    (2, 5, "unused local var: \"foo\""),
+    (6, 9, "unused local var: \"foo\""),
+];
+
+#[test]
+fn write_only_local() {
+    let code = r##"
+/proc/test()
+    var/foo = 1
+    foo = 2
+    foo += 3
+    if(prob(50))
+        var/foo = 5
+        foo = 4
+"##.trim();
+    check_errors_match(code, WRITE_ONLY_LOCAL_ERRORS);
+}
@@ -17,6 +17,29 @@ fn no_parent() {
     check_errors_match(code, NO_PARENT_ERRORS);
 }
 
+#[test]
+fn parent_call_in_override() {
+    let code = r##"
+/mob/proc/test(a)
+    return a
+/mob/child/test(a)
+    ..()
+    return ..(a)
+"##.trim();
+    check_errors_match(code, dc::test_helpers::NO_ERRORS);
+}
+
+#[test]
+fn parent_call_in_global_proc() {
+    let code = r##"
+/proc/test()
+    return ..()
+"##.trim();
+    check_errors_match(code, &[
+        (2, 12, "proc has no parent: /proc/test"),
+    ]);
+}
+
 #[test]
 fn return_type() {
     let code = r##"
@@ -52,3 +75,61 @@ fn return_type_failure() {
 "##.trim();
     check_errors_match(code, RETURN_TYPE_FAILURE_ERRORS);
 }
+
+pub const TOO_MANY_ARGUMENTS_ERRORS: &[(u32, u16, &str)] = &[
+    (9, 5, "too many arguments to /proc/pair: given 3, takes at most 2"),
+    (10, 5, "too many arguments to /datum/proc/act: given 2, takes at most 1"),
+];
+
+#[test]
+fn too_many_arguments() {
+    let code = r##"
+/proc/pair(a, b = 2)
+/proc/spread(...)
+/proc/counted()
+    return args.len
+/datum/proc/act(a)
+/datum/proc/test()
+    pair(1)
+    pair(1, b = 3)
+    pair(1, 2, 3)
+    act(1, 2)
+    spread(1, 2, 3)
+    counted(1, 2)
+    pair(arglist(list(1, 2, 3)))
+"##.trim();
+    check_errors_match(code, TOO_MANY_ARGUMENTS_ERRORS);
+}
+
+#[test]
+fn override_takes_more_arguments() {
+    let code = r##"
+/datum/proc/act(a)
+/datum/child/act(a, b)
+/datum/proc/test()
+    act(1, 2)
+"##.trim();
+    check_errors_match(code, dc::test_helpers::NO_ERRORS);
+}
+
+pub const DYNAMIC_CALL_ERRORS: &[(u32, u16, &str)] = &[
+    (4, 5, "too many arguments to /datum/proc/act: given 2, takes at most 1"),
+    (5, 5, "too many arguments to /proc/pair: given 3, takes at most 2"),
+];
+
+#[test]
+fn dynamic_call_arguments() {
+    // procs named by a string literal or a path are checked like direct
+    // calls, while anything else can't be known until runtime
+    let code = r##"
+/proc/pair(a, b)
+/datum/proc/act(a)
+/datum/proc/test(name)
+    call(src, "act")(1, 2)
+    call(/proc/pair)(1, 2, 3)
+    call(src, "act")(1)
+    call(src, name)(1, 2, 3)
+    call("library.dll", "act")(1, 2)
+"##.trim();
+    check_errors_match(code, DYNAMIC_CALL_ERRORS);
+}
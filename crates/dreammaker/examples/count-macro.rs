@@ -0,0 +1,28 @@
+//! Count how many times a macro is expanded in a file.
+//!
+//! Usage: `count-macro <file> <macro>`
+
+extern crate dreammaker as dm;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(file), Some(name)) = (args.next(), args.next()) else {
+        eprintln!("usage: count-macro <file> <macro>");
+        std::process::exit(1);
+    };
+
+    let context = dm::Context::default();
+    let mut pp = dm::preprocessor::Preprocessor::new(&context, file.into())
+        .expect("i/o error opening file");
+
+    // Every token of one expansion shares the location of the macro's use.
+    let mut count = 0;
+    let mut last_use = None;
+    for expanded in pp.expanded_tokens() {
+        if expanded.macro_name.as_deref() == Some(name.as_str()) && last_use != Some(expanded.token.location) {
+            last_use = Some(expanded.token.location);
+            count += 1;
+        }
+    }
+    println!("{name}: {count}");
+}
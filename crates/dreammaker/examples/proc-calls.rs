@@ -0,0 +1,55 @@
+//! Print how often each proc is called by name across a project.
+
+extern crate dreammaker as dm;
+
+use std::collections::BTreeMap;
+
+use dm::ast::*;
+
+#[derive(Default)]
+struct CallCollector<'ast> {
+    calls: BTreeMap<&'ast str, usize>,
+}
+
+impl<'ast> Visitor<'ast> for CallCollector<'ast> {
+    fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+        if let Term::Call(name, _) | Term::GlobalCall(name, _) = &term.elem {
+            *self.calls.entry(name).or_default() += 1;
+        }
+        walk_term(self, term);
+    }
+
+    fn visit_follow(&mut self, follow: &'ast Spanned<Follow>) {
+        if let Follow::Call(_, name, _) = &follow.elem {
+            *self.calls.entry(name).or_default() += 1;
+        }
+        walk_follow(self, follow);
+    }
+}
+
+fn main() {
+    let context = dm::Context::default();
+    let env = dm::detect_environment_default()
+        .expect("error detecting .dme")
+        .expect("no .dme found");
+    let pp = dm::preprocessor::Preprocessor::new(&context, env)
+        .expect("i/o error opening .dme");
+    let indents = dm::indents::IndentProcessor::new(&context, pp);
+    let mut parser = dm::parser::Parser::new(&context, indents);
+    parser.enable_procs();
+    let ot = parser.parse_object_tree();
+
+    let mut collector = CallCollector::default();
+    for ty in ot.iter_types() {
+        for proc in ty.get().procs.values() {
+            for value in proc.value.iter() {
+                if let Some(code) = &value.code {
+                    collector.visit_proc(&value.parameters, code);
+                }
+            }
+        }
+    }
+    for (name, count) in collector.calls {
+        println!("{count}\t{name}");
+    }
+}
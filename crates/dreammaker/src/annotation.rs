@@ -1,18 +1,25 @@
 //! Data structures for the parser to output mappings from input ranges to AST
 //! elements at those positions.
 
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 
 use crate::docs::DocCollection;
+use foldhash::HashMap;
 use interval_tree::{range, IntervalTree, RangeInclusive, RangePairIter};
-use serde::Serialize;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::ast::*;
-use super::Location;
+use super::objtree::{ObjectTree, ProcRef};
+use super::preprocessor::IncludeKind;
+use super::{FileId, Location};
 
 pub type Iter<'a> = RangePairIter<'a, Location, Annotation>;
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Annotation {
     // contextual information
     TreeBlock(Vec<Ident>),
@@ -28,19 +35,34 @@ pub enum Annotation {
     UnscopedVar(Ident),
     ScopedCall(Vec<Ident>, Ident),
     ScopedVar(Vec<Ident>, Ident),
-    ParentCall,  // ..
+    // scoped accesses through `:` or `?:`, which DM doesn't type-check
+    LooseScopedCall(Vec<Ident>, Ident),
+    LooseScopedVar(Vec<Ident>, Ident),
+    // a global var or proc named through `global.` or `::`, which is
+    // resolved with `ObjectTree::resolve_global_var` and `resolve_global_proc`
+    GlobalVar(Ident),
+    GlobalCall(Ident),
+    // a `..()` call; `args_range` covers its parenthesized arguments, unless
+    // there are none and the caller's own arguments are forwarded
+    ParentCall { args_range: Option<std::ops::Range<Location>> },
     ReturnVal,  // .
     InSequence(usize),  // where in TreePath or TypePath is this ident
+    // the name of a `name:` label and of the label a `goto name` jumps to,
+    // which is looked for in the enclosing proc body
+    LabelDefinition(Ident),
+    GotoTarget(Ident),
 
     // a macro is called here, which is defined at this location
     MacroDefinition(Ident),
+    // `docs` is resolved with `AnnotationTree::macro_docs`, and is `None` if
+    // the macro has no docs
     MacroUse {
         name: String,
         definition_location: Location,
-        docs: Option<Rc<DocCollection>>,
+        docs: Option<MacroId>,
     },
 
-    Include(std::path::PathBuf),
+    Include { path: std::path::PathBuf, kind: IncludeKind },
     Resource(std::path::PathBuf),
 
     // error annotations, mostly for autocompletion
@@ -50,12 +72,128 @@ pub enum Annotation {
 
     ProcArguments(Vec<Ident>, String, usize),  // Vec empty for unscoped call
     ProcArgument(usize),  // where in the prog arguments we are
+    NewExpr { type_source: NewTypeSource, args_range: Option<std::ops::Range<Location>> },
+    // a `call()()`; `proc_name` is known if given by a string literal or a
+    // proc path, and `args_range` covers the arguments passed to the proc
+    DynamicCall { proc_name: Option<Ident>, args_range: std::ops::Range<Location> },
+    // an element of a `list()` or `alist()` literal, including any `key =`
+    ListElement { index: usize, range: std::ops::Range<Location> },
     ReturnOperation(std::ops::Range<Location>),
+    // an expression embedded in a string with `[...]`
+    StringInterpolation { expr_range: std::ops::Range<Location> },
+    // spans the whole expression; `range` covers just the operator
+    BinaryOperation { op: BinaryOp, range: std::ops::Range<Location> },
+    // an assignment, including compound forms like `+=`; spans the whole
+    // expression, `range` covers just the operator
+    AssignOperation { op: AssignOp, range: std::ops::Range<Location> },
+    // an `in` operation, which is annotated instead of `BinaryOperation`;
+    // spans the whole expression, `range` covers just the `in`
+    InOperation { range: std::ops::Range<Location> },
+    TernaryOperation { question: std::ops::Range<Location>, colon: std::ops::Range<Location> },
     ReturnStatement{ returned_value: Vec<Annotation> },
+    // spans a whole switch statement, its `if` cases, and its `else` case
+    Switch,
+    SwitchCase { range: std::ops::Range<Location> },  // `range` covers the case's values
+    SwitchDefault,
+    // spans a whole `spawn` statement; `delay_range` covers the delay, if
+    // one is given between the parentheses
+    SpawnBlock { delay_range: Option<std::ops::Range<Location>>, body_range: std::ops::Range<Location> },
+    // code skipped by conditional compilation
+    InactiveBranch { range: std::ops::Range<Location> },
+    // spans an `#if`-family directive; `condition_range` covers what it
+    // tests, and `active` is whether the code following it is compiled
+    PreprocessorConditional { kind: ConditionalKind, condition_range: Option<std::ops::Range<Location>>, active: bool },
+    // an `// SDMM:` directive comment; `range` covers the code it applies to
+    LintControl { rule: String, enable: bool, range: std::ops::Range<Location> },
 }
 
+/// Where the type constructed by an `Annotation::NewExpr` comes from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NewTypeSource {
+    /// A type path known at compile time, like `new /obj()`. The path is
+    /// empty for `new()` of the implicit type.
+    Static(TypePath),
+    /// An expression evaluated at runtime, like `new type_var()`, so the type
+    /// can't be resolved statically. The range covers the expression.
+    Dynamic(std::ops::Range<Location>),
+}
+
+/// Which directive an `Annotation::PreprocessorConditional` marks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConditionalKind {
+    If,
+    Ifdef,
+    Ifndef,
+    Elif,
+    Else,
+    Endif,
+}
+
+/// A handle to the docs of a macro used in an annotation tree, as found in
+/// `Annotation::MacroUse`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MacroId(u32);
 
 impl Annotation {
+    /// Apply `f` to each macro ID in this annotation.
+    fn map_macro_ids<F: FnMut(MacroId) -> MacroId>(&mut self, f: &mut F) {
+        match self {
+            Annotation::MacroUse { docs: Some(id), .. } => *id = f(*id),
+            Annotation::ReturnStatement { returned_value } => {
+                for each in returned_value.iter_mut() {
+                    each.map_macro_ids(f);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Apply `f` to each location held by this annotation.
+    fn map_locations<F: FnMut(Location) -> Location>(&mut self, f: &mut F) {
+        let mut map_range = |range: &mut std::ops::Range<Location>| {
+            *range = f(range.start)..f(range.end);
+        };
+        match self {
+            Annotation::ParentCall { args_range: Some(range) }
+            | Annotation::DynamicCall { args_range: range, .. }
+            | Annotation::ListElement { range, .. }
+            | Annotation::ReturnOperation(range)
+            | Annotation::StringInterpolation { expr_range: range }
+            | Annotation::BinaryOperation { range, .. }
+            | Annotation::AssignOperation { range, .. }
+            | Annotation::InOperation { range }
+            | Annotation::SwitchCase { range }
+            | Annotation::InactiveBranch { range }
+            | Annotation::PreprocessorConditional { condition_range: Some(range), .. }
+            | Annotation::LintControl { range, .. } => map_range(range),
+            Annotation::TernaryOperation { question, colon } => {
+                map_range(question);
+                map_range(colon);
+            }
+            Annotation::NewExpr { type_source, args_range } => {
+                if let NewTypeSource::Dynamic(range) = type_source {
+                    map_range(range);
+                }
+                if let Some(args_range) = args_range {
+                    map_range(args_range);
+                }
+            }
+            Annotation::SpawnBlock { delay_range, body_range } => {
+                if let Some(delay_range) = delay_range {
+                    map_range(delay_range);
+                }
+                map_range(body_range);
+            }
+            Annotation::MacroUse { definition_location, .. } => *definition_location = f(*definition_location),
+            Annotation::ReturnStatement { returned_value } => {
+                for each in returned_value.iter_mut() {
+                    each.map_locations(f);
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn resolved(self, annotation_tree: &AnnotationTree) -> Annotation {
         match self {
             Self::ReturnOperation(range) => {
@@ -75,6 +213,22 @@ impl Annotation {
 pub struct AnnotationTree {
     tree: IntervalTree<Location, Annotation>,
     len: usize,
+    /// The docs of the macros used, indexed by `MacroId`.
+    macro_docs: Vec<Rc<DocCollection>>,
+    /// Maps the address of each entry in `macro_docs` back to its ID.
+    macro_ids: HashMap<usize, MacroId>,
+}
+
+/// The size and shape of an `AnnotationTree`, as found by
+/// [`AnnotationTree::stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeStats {
+    /// The number of annotations.
+    pub len: usize,
+    /// The number of distinct ranges annotated, each of which is one node.
+    pub node_count: usize,
+    /// The length of the longest path from the root to a leaf.
+    pub max_depth: usize,
 }
 
 impl Default for AnnotationTree {
@@ -82,11 +236,155 @@ impl Default for AnnotationTree {
         AnnotationTree {
             tree: IntervalTree::new(),
             len: 0,
+            macro_docs: Vec::new(),
+            macro_ids: HashMap::default(),
         }
     }
 }
 
+impl Serialize for AnnotationTree {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        struct Annotations<'a>(&'a AnnotationTree);
+
+        impl Serialize for Annotations<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter())
+            }
+        }
+
+        let mut s = serializer.serialize_struct("AnnotationTree", 2)?;
+        s.serialize_field("annotations", &Annotations(self))?;
+        s.serialize_field("macro_docs", &self.macro_docs)?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for AnnotationTree {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        AnnotationTree::from_serialized(deserializer)
+    }
+}
+
 impl AnnotationTree {
+    /// Rebuild a tree from the annotations and macro docs written by its
+    /// `Serialize` implementation.
+    pub fn from_serialized<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AnnotationTree, D::Error> {
+        #[derive(Deserialize)]
+        struct Serialized {
+            annotations: Vec<(RangeInclusive<Location>, Annotation)>,
+            macro_docs: Vec<Rc<DocCollection>>,
+        }
+
+        let serialized = Serialized::deserialize(deserializer)?;
+        let mut result = AnnotationTree::default();
+        for (place, value) in serialized.annotations {
+            result.tree.insert(place, value);
+            result.len += 1;
+        }
+        for docs in serialized.macro_docs {
+            result.intern_macro_docs(docs);
+        }
+        Ok(result)
+    }
+
+    /// Add the docs of a macro to this tree's side table, returning the ID
+    /// to store in its `MacroUse` annotations.
+    ///
+    /// Interning the same `Rc` again returns the same ID.
+    pub fn intern_macro_docs(&mut self, docs: Rc<DocCollection>) -> MacroId {
+        let next = MacroId(self.macro_docs.len() as u32);
+        let macro_docs = &mut self.macro_docs;
+        *self.macro_ids.entry(Rc::as_ptr(&docs) as usize).or_insert_with(|| {
+            macro_docs.push(docs);
+            next
+        })
+    }
+
+    /// Look up the docs of a macro used in this tree.
+    pub fn macro_docs(&self, id: MacroId) -> Option<&DocCollection> {
+        self.macro_docs.get(id.0 as usize).map(|docs| &**docs)
+    }
+
+    /// Write every annotation to `writer` as JSON, for use by external tools.
+    ///
+    /// The output is an array with one object per annotation, in the order
+    /// of `iter()`:
+    ///
+    /// ```json
+    /// [
+    ///   {
+    ///     "start": { "file": 1, "line": 1, "column": 2 },
+    ///     "end": { "file": 1, "line": 1, "column": 5 },
+    ///     "annotation": { "UnscopedVar": "foo" }
+    ///   }
+    /// ]
+    /// ```
+    ///
+    /// `end` is exclusive. `file` is the context's file ID, and lines and
+    /// columns count from 1. `annotation` is externally tagged, so unit
+    /// variants such as `"Switch"` are bare strings and others are objects
+    /// with the variant name as their only key. Fields are named as in
+    /// `Annotation`, and tuple variants are arrays. The `docs` of a `MacroUse`
+    /// are written out in full rather than as a `MacroId`.
+    pub fn to_json(&self, writer: impl io::Write) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct JsonLocation {
+            file: FileId,
+            line: u32,
+            column: u16,
+        }
+
+        // `MacroUse` is written with its docs in place of its `MacroId`.
+        #[derive(Serialize)]
+        enum JsonMacroUse<'a> {
+            MacroUse {
+                name: &'a str,
+                definition_location: Location,
+                docs: Option<&'a DocCollection>,
+            },
+        }
+
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum JsonAnnotation<'a> {
+            MacroUse(JsonMacroUse<'a>),
+            Other(&'a Annotation),
+        }
+
+        #[derive(Serialize)]
+        struct JsonEntry<'a> {
+            start: JsonLocation,
+            end: JsonLocation,
+            annotation: JsonAnnotation<'a>,
+        }
+
+        let json_location = |loc: Location| JsonLocation {
+            file: loc.file,
+            line: loc.line,
+            column: loc.column,
+        };
+        let entries: Vec<_> = self.iter()
+            .map(|(place, annotation)| JsonEntry {
+                start: json_location(place.start),
+                end: json_location(place.end.succ()),
+                annotation: match annotation {
+                    Annotation::MacroUse { name, definition_location, docs } => JsonAnnotation::MacroUse(JsonMacroUse::MacroUse {
+                        name,
+                        definition_location: *definition_location,
+                        docs: docs.and_then(|id| self.macro_docs(id)),
+                    }),
+                    other => JsonAnnotation::Other(other),
+                },
+            })
+            .collect();
+        serde_json::to_writer(writer, &entries)?;
+        Ok(())
+    }
+
     pub fn insert(&mut self, place: std::ops::Range<Location>, value: Annotation) {
         self.tree.insert(range(place.start, place.end.pred()), value);
         self.len += 1;
@@ -94,7 +392,99 @@ impl AnnotationTree {
 
     pub fn merge(&mut self, other: AnnotationTree) {
         self.len += other.len;
-        self.tree.merge(other.tree);
+        if other.macro_docs.is_empty() {
+            self.tree.merge(other.tree);
+            return;
+        }
+        let ids: Vec<_> = other.macro_docs.into_iter().map(|docs| self.intern_macro_docs(docs)).collect();
+        for (place, mut value) in other.tree {
+            value.map_macro_ids(&mut |id| ids[id.0 as usize]);
+            self.tree.insert(place, value);
+        }
+    }
+
+    /// Replace the annotations inside `region` with those of `replacement`.
+    ///
+    /// Every annotation whose range lies entirely within `region` is removed
+    /// before `replacement` is merged in. Annotations which straddle either
+    /// edge of the region, such as the enclosing `TreeBlock` or `ProcBody` of
+    /// an edited statement, are left untouched.
+    pub fn splice(&mut self, region: std::ops::Range<Location>, replacement: AnnotationTree) {
+        let region = range(region.start, region.end.pred());
+        self.remove_where(region, |place| place.start >= region.start && place.end <= region.end);
+        self.merge(replacement);
+    }
+
+    /// Move the annotations at and after `pivot` by `line_delta` lines, as
+    /// after inserting or deleting whole lines above them.
+    ///
+    /// Locations on the pivot line also move by `col_delta_on_pivot_line`
+    /// columns. Locations before the pivot and in other files don't move, so
+    /// an annotation straddling the pivot, such as the `ProcBody` around the
+    /// edit, keeps its start and has its end moved. This is only correct for
+    /// edits which don't change how the code parses, such as inserting blank
+    /// lines; anything else should be re-parsed.
+    pub fn shift_after(&mut self, pivot: Location, line_delta: i64, col_delta_on_pivot_line: i64) {
+        let mut shift = |loc: Location| {
+            if loc.file != pivot.file || loc < pivot {
+                return loc;
+            }
+            let mut column = i64::from(loc.column);
+            if loc.line == pivot.line {
+                column += col_delta_on_pivot_line;
+            }
+            Location {
+                file: loc.file,
+                line: (i64::from(loc.line) + line_delta).clamp(0, i64::from(u32::MAX)) as u32,
+                column: column.clamp(0, i64::from(u16::MAX)) as u16,
+            }
+        };
+
+        let rest_of_file = range(pivot, Location { file: pivot.file, line: !0, column: !0 });
+        let mut moved: Vec<(RangeInclusive<Location>, Vec<Annotation>)> = Vec::new();
+        for (place, _) in self.get_range_raw(rest_of_file) {
+            if moved.last().is_none_or(|(last, _)| *last != place) {
+                moved.push((place, self.tree.get(place).unwrap_or_default().to_vec()));
+            }
+        }
+        for (place, _) in moved.iter() {
+            self.tree.remove(*place);
+        }
+        for (place, values) in moved {
+            let place = range(shift(place.start), shift(place.end));
+            for mut value in values {
+                value.map_locations(&mut shift);
+                self.tree.insert(place, value);
+            }
+        }
+    }
+
+    /// Remove every annotation which starts in the given file.
+    ///
+    /// Annotations should never span files, but if one does, it is removed
+    /// along with the file it starts in.
+    pub fn remove_file(&mut self, file: FileId) {
+        let whole_file = range(
+            Location { file, line: 0, column: 0 },
+            Location { file, line: !0, column: !0 },
+        );
+        self.remove_where(whole_file, |place| place.start.file == file);
+    }
+
+    /// Remove the annotations overlapping `place` whose range passes `filter`.
+    fn remove_where<F>(&mut self, place: RangeInclusive<Location>, filter: F)
+    where
+        F: Fn(&RangeInclusive<Location>) -> bool,
+    {
+        let mut matching: Vec<RangeInclusive<Location>> = self.get_range_raw(place)
+            .map(|(place, _)| place)
+            .filter(filter)
+            .collect();
+        matching.dedup();
+        for place in matching {
+            self.len -= self.tree.get(place).map_or(0, |values| values.len());
+            self.tree.remove(place);
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -105,10 +495,25 @@ impl AnnotationTree {
         self.len == 0
     }
 
+    /// Measure the size and shape of the underlying tree.
+    pub fn stats(&self) -> TreeStats {
+        TreeStats {
+            len: self.len,
+            node_count: self.tree.node_count(),
+            max_depth: self.tree.height(),
+        }
+    }
+
     pub fn iter(&self) -> Iter {
         self.tree.iter()
     }
 
+    /// Iterate over the annotations whose ranges start at or after `start`,
+    /// in order, without scanning those before it.
+    pub fn iter_from(&self, start: Location) -> Iter<'_> {
+        self.tree.iter_from(start)
+    }
+
     pub fn get_location(&self, loc: Location) -> Iter {
         self.tree.range(range(loc.pred(), loc))
     }
@@ -120,4 +525,362 @@ impl AnnotationTree {
     pub fn get_range_raw(&self, place: RangeInclusive<Location>) -> Iter {
         self.tree.range(place)
     }
+
+    /// Collect the annotations intersecting the given range which pass the
+    /// filter, sorted by start and then end, with duplicates removed.
+    pub fn overlapping(
+        &self,
+        place: std::ops::Range<Location>,
+        filter: impl Fn(&Annotation) -> bool,
+    ) -> Vec<(std::ops::Range<Location>, &Annotation)> {
+        let mut found: Vec<(std::ops::Range<Location>, &Annotation)> = self.get_range(place)
+            .filter(|(_, annotation)| filter(annotation))
+            .map(|(place, annotation)| (place.start..place.end.succ(), annotation))
+            .collect();
+        found.sort_by_key(|(place, _)| (place.start, place.end));
+
+        // duplicates now share a run of equal ranges, which is short
+        let mut result: Vec<(std::ops::Range<Location>, &Annotation)> = Vec::with_capacity(found.len());
+        let mut run_start = 0;
+        for (place, annotation) in found {
+            if result.last().is_none_or(|(last, _)| *last != place) {
+                run_start = result.len();
+            }
+            if !result[run_start..].iter().any(|(_, each)| *each == annotation) {
+                result.push((place, annotation));
+            }
+        }
+        result
+    }
+
+    /// Compare against a newer tree, reporting the annotations which only
+    /// appear in `other` as added and those which only appear in `self` as
+    /// removed.
+    ///
+    /// Annotations are matched by range and value, so an annotation which
+    /// merely moved is reported as both removed and added. `MacroUse`
+    /// annotations are compared by their docs, and the `MacroId`s in the
+    /// result belong to the tree each annotation came from.
+    pub fn diff(&self, other: &AnnotationTree) -> AnnotationDiff {
+        let same = |old: &Annotation, new: &Annotation| match (old, new) {
+            (
+                Annotation::MacroUse { name: old_name, definition_location: old_location, docs: old_docs },
+                Annotation::MacroUse { name: new_name, definition_location: new_location, docs: new_docs },
+            ) => {
+                old_name == new_name
+                    && old_location == new_location
+                    && old_docs.and_then(|id| self.macro_docs(id)) == new_docs.and_then(|id| other.macro_docs(id))
+            }
+            _ => old == new,
+        };
+
+        let mut unmatched: BTreeMap<(Location, Location), Vec<&Annotation>> = BTreeMap::new();
+        for (place, annotation) in self.iter() {
+            unmatched.entry((place.start, place.end)).or_default().push(annotation);
+        }
+
+        let mut diff = AnnotationDiff::default();
+        for (place, annotation) in other.iter() {
+            let candidates = unmatched.entry((place.start, place.end)).or_default();
+            match candidates.iter().position(|&each| same(each, annotation)) {
+                Some(idx) => {
+                    candidates.swap_remove(idx);
+                }
+                None => diff.added.push((place.start..place.end.succ(), annotation.clone())),
+            }
+        }
+        for ((start, end), annotations) in unmatched {
+            for annotation in annotations {
+                diff.removed.push((start..end.succ(), annotation.clone()));
+            }
+        }
+        diff
+    }
+
+    /// Find the innermost proc body containing the given location.
+    ///
+    /// Returns the proc's path and its index among the overrides of that proc
+    /// on its type.
+    pub fn enclosing_proc(&self, loc: Location) -> Option<(&[Ident], usize)> {
+        self.innermost(loc, |_, annotation| match annotation {
+            Annotation::ProcBody(path, idx) => Some((&path[..], *idx)),
+            _ => None,
+        })
+    }
+
+    /// Find the proc which a `ParentCall` or `ReturnVal` annotation at the
+    /// given location refers to, using the object tree the annotations were
+    /// made alongside.
+    ///
+    /// `..` resolves to the definition it calls: the previous override on the
+    /// same type, or else the nearest one on a parent type. `.` resolves to
+    /// the enclosing proc, whose declaration holds its return type. Other
+    /// annotations resolve to nothing.
+    pub fn proc_target<'o>(&self, loc: Location, annotation: &Annotation, tree: &'o ObjectTree) -> Option<ProcRef<'o>> {
+        let (path, idx) = self.enclosing_proc(loc)?;
+        let (name, mut type_path) = path.split_last()?;
+        if let Some((kwd, rest)) = type_path.split_last()
+            && (kwd == "proc" || kwd == "verb")
+        {
+            type_path = rest;
+        }
+        let enclosing = tree.type_by_path(type_path)?.get_self_proc(name, idx)?;
+        match annotation {
+            Annotation::ParentCall { .. } => enclosing.parent_proc(),
+            Annotation::ReturnVal => Some(enclosing),
+            _ => None,
+        }
+    }
+
+    /// Check a `ParentCall` annotation at the given location against the
+    /// definition it calls, as found by `proc_target`.
+    ///
+    /// Returns `None` if the enclosing proc has no parent to call. Otherwise
+    /// returns the definition and whether the call's arguments fit it: `..()`
+    /// forwards the caller's own arguments and always fits, while `..(args)`
+    /// fits unless it passes more arguments than the definition has
+    /// parameters. Definitions taking `...` accept any number, but those
+    /// which read `args` directly are not detected.
+    pub fn parent_call<'o>(&self, loc: Location, annotation: &Annotation, tree: &'o ObjectTree) -> Option<(ProcRef<'o>, bool)> {
+        let Annotation::ParentCall { args_range } = annotation else { return None };
+        let target = self.proc_target(loc, annotation, tree)?;
+        let Some(args_range) = args_range else {
+            return Some((target, true));
+        };
+        let given = self.iter_from(args_range.start)
+            .take_while(|(place, _)| place.start == args_range.start)
+            .find_map(|(_, annotation)| match annotation {
+                Annotation::ProcArguments(parents, name, count) if parents.is_empty() && name == ".." => Some(*count),
+                _ => None,
+            })?;
+        let parameters = &target.get().parameters;
+        let fits = given <= parameters.len() || parameters.iter().any(|param| param.name == "...");
+        Some((target, fits))
+    }
+
+    /// Find the label which a `GotoTarget` annotation at the given location
+    /// jumps to, returning the range of its name.
+    ///
+    /// Only labels in the innermost proc body containing the `goto` are
+    /// considered.
+    pub fn goto_label(&self, loc: Location) -> Option<std::ops::Range<Location>> {
+        let name = self.innermost(loc, |_, annotation| match annotation {
+            Annotation::GotoTarget(name) => Some(name),
+            _ => None,
+        })?;
+        let body = self.innermost(loc, |place, annotation| match annotation {
+            Annotation::ProcBody(..) => Some(*place),
+            _ => None,
+        })?;
+        self.get_range_raw(body).find_map(|(place, annotation)| match annotation {
+            Annotation::LabelDefinition(label) if label == name => Some(place.start..place.end.succ()),
+            _ => None,
+        })
+    }
+
+    /// Find the innermost type block containing the given location.
+    ///
+    /// Only `TreeBlock` annotations are considered, as `TypePath` annotations
+    /// mark path literals rather than enclosing definitions.
+    pub fn enclosing_type(&self, loc: Location) -> Option<&[Ident]> {
+        self.innermost(loc, |_, annotation| match annotation {
+            Annotation::TreeBlock(path) => Some(&path[..]),
+            _ => None,
+        })
+    }
+
+    /// Outline the innermost `switch` statement containing the given location.
+    ///
+    /// Cases of `switch` statements nested inside that one are not included.
+    pub fn enclosing_switch(&self, loc: Location) -> Option<SwitchOutline> {
+        let switch = self.innermost_switch(loc)?;
+        let mut outline = SwitchOutline::default();
+        for (place, annotation) in self.get_range_raw(switch) {
+            if place.start < switch.start || place.end > switch.end || self.innermost_switch(place.start) != Some(switch) {
+                continue;
+            }
+            match annotation {
+                Annotation::SwitchCase { range } => outline.cases.push(range.clone()),
+                Annotation::SwitchDefault => outline.default = Some(place.start..place.end.succ()),
+                _ => {}
+            }
+        }
+        Some(outline)
+    }
+
+    fn innermost_switch(&self, loc: Location) -> Option<RangeInclusive<Location>> {
+        self.innermost(loc, |place, annotation| match annotation {
+            Annotation::Switch => Some(*place),
+            _ => None,
+        })
+    }
+
+    /// Check every `Include` and `Resource` annotation against the files
+    /// under `root`, which relative paths are resolved against.
+    pub fn resolve_resources(&self, root: &Path) -> Vec<(std::ops::Range<Location>, ResourceStatus)> {
+        self.iter()
+            .filter_map(|(place, annotation)| match annotation {
+                Annotation::Include { path, .. } | Annotation::Resource(path) => {
+                    Some((place.start..place.end.succ(), resolve_resource(root, path)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Of the annotations covering `loc` which `f` accepts, pick the one with
+    /// the narrowest range, preferring the later-starting range on ties.
+    fn innermost<'a, T, F>(&'a self, loc: Location, mut f: F) -> Option<T>
+    where
+        F: FnMut(&RangeInclusive<Location>, &'a Annotation) -> Option<T>,
+    {
+        let mut best: Option<(RangeInclusive<Location>, T)> = None;
+        for (place, annotation) in self.get_location(loc) {
+            let Some(value) = f(&place, annotation) else {
+                continue
+            };
+            let replace = match best {
+                None => true,
+                Some((ref prev, _)) => match range_width(&place).cmp(&range_width(prev)) {
+                    std::cmp::Ordering::Less => true,
+                    std::cmp::Ordering::Equal => place.start >= prev.start,
+                    std::cmp::Ordering::Greater => false,
+                },
+            };
+            if replace {
+                best = Some((place, value));
+            }
+        }
+        best.map(|(_, value)| value)
+    }
+}
+
+/// Get the source text covered by an annotation's range, as passed to
+/// [`AnnotationTree::insert`], from a map of each file's source.
+///
+/// The range is end-exclusive; ranges read back out of the tree are
+/// inclusive and should be converted with `place.start..place.end.succ()`.
+/// Columns past the end of a line are clamped to it. Returns `None` if the
+/// file is missing, the range spans files or runs backwards, or a boundary
+/// falls inside a multi-byte character.
+pub fn source_text<T, S>(
+    sources: &std::collections::HashMap<FileId, T, S>,
+    place: std::ops::Range<Location>,
+) -> Option<&str>
+where
+    T: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    if place.start.file != place.end.file || place.end < place.start {
+        return None;
+    }
+    let source = sources.get(&place.start.file)?.as_ref();
+    let start = source_offset(source, place.start);
+    let end = source_offset(source, place.end);
+    source.get(start..end.max(start))
+}
+
+/// Find the byte offset of a location in a file's source.
+fn source_offset(source: &str, loc: Location) -> usize {
+    let mut line_start = 0;
+    for _ in 1..loc.line {
+        match source[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return source.len(),
+        }
+    }
+    let line_len = source[line_start..].find('\n').unwrap_or(source.len() - line_start);
+    line_start + (loc.column.max(1) as usize - 1).min(line_len)
+}
+
+/// The changes between two annotation trees, as found by
+/// [`AnnotationTree::diff`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnnotationDiff {
+    pub added: Vec<(std::ops::Range<Location>, Annotation)>,
+    pub removed: Vec<(std::ops::Range<Location>, Annotation)>,
+}
+
+impl AnnotationDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The cases of a `switch` statement, as found by
+/// [`AnnotationTree::enclosing_switch`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SwitchOutline {
+    /// The range of each `if` case's values, in source order.
+    pub cases: Vec<std::ops::Range<Location>>,
+    /// The range of the `else` case, if there is one.
+    pub default: Option<std::ops::Range<Location>>,
+}
+
+/// Whether the file referenced by an `Include` or `Resource` annotation exists.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResourceStatus {
+    /// The path names a readable file.
+    Found,
+    /// No readable file matches the path, even ignoring case.
+    Missing,
+    /// A readable file matches only when ignoring case. This works with
+    /// BYOND on Windows but breaks on case-sensitive filesystems. Holds the
+    /// path as it appears on disk.
+    CaseMismatch(PathBuf),
+}
+
+fn resolve_resource(root: &Path, path: &Path) -> ResourceStatus {
+    // DM accepts backslashes as separators on every platform.
+    let path = PathBuf::from(path.to_string_lossy().replace('\\', "/"));
+
+    let mut current = root.to_owned();
+    let mut mismatched = false;
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            // Absolute prefixes replace the root, `..` and `.` are literal.
+            current.push(component);
+            continue;
+        };
+        // Look for the exact name first, as `exists()` ignores case on
+        // case-insensitive filesystems.
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            return ResourceStatus::Missing;
+        };
+        let mut exact = false;
+        let mut folded = None;
+        for entry in entries.flatten() {
+            let entry_name = entry.file_name();
+            if entry_name == name {
+                exact = true;
+                break;
+            } else if folded.is_none() && entry_name.to_string_lossy().to_lowercase() == name.to_string_lossy().to_lowercase() {
+                folded = Some(entry_name);
+            }
+        }
+        if exact {
+            current.push(name);
+        } else if let Some(folded) = folded {
+            mismatched = true;
+            current.push(folded);
+        } else {
+            return ResourceStatus::Missing;
+        }
+    }
+
+    if !current.is_file() || std::fs::File::open(&current).is_err() {
+        ResourceStatus::Missing
+    } else if mismatched {
+        ResourceStatus::CaseMismatch(current)
+    } else {
+        ResourceStatus::Found
+    }
+}
+
+/// Approximate the size of a range as a (lines, columns) pair.
+fn range_width(place: &RangeInclusive<Location>) -> (u32, i32) {
+    (
+        place.end.line.saturating_sub(place.start.line),
+        i32::from(place.end.column) - i32::from(place.start.column),
+    )
 }
@@ -7,7 +7,7 @@ use std::iter::FromIterator;
 use get_size::GetSize;
 use get_size_derive::GetSize;
 use phf::phf_map;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::error::Location;
 
@@ -77,6 +77,11 @@ impl UnaryOp {
             Dereference => "*",
         }
     }
+
+    /// Check whether this operator is written before its operand.
+    pub fn is_prefix(self) -> bool {
+        !matches!(self, UnaryOp::PostIncr | UnaryOp::PostDecr)
+    }
 }
 
 /// The DM path operators.
@@ -101,7 +106,27 @@ impl Serialize for PathOp {
     }
 }
 
+impl<'de> Deserialize<'de> for PathOp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de> {
+        let name = String::deserialize(deserializer)?;
+        PathOp::from_name(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid path operator: {name:?}")))
+    }
+}
+
 impl PathOp {
+    /// Attempt to convert a string to a path operator.
+    pub fn from_name(name: &str) -> Option<PathOp> {
+        match name {
+            "/" => Some(PathOp::Slash),
+            "." => Some(PathOp::Dot),
+            ":" => Some(PathOp::Colon),
+            _ => None,
+        }
+    }
+
     pub fn name(self) -> &'static str {
         match self {
             PathOp::Slash => "/",
@@ -118,7 +143,7 @@ impl fmt::Display for PathOp {
 }
 
 /// The binary operators.
-#[derive(Copy, Clone, PartialEq, Eq, Debug, GetSize, Serialize)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, GetSize, Serialize, Deserialize)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -181,7 +206,7 @@ impl fmt::Display for BinaryOp {
 }
 
 /// The assignment operators, including augmented assignment.
-#[derive(Copy, Clone, PartialEq, Eq, Debug, GetSize, Serialize)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, GetSize, Serialize, Deserialize)]
 pub enum AssignOp {
     Assign,
     AddAssign,
@@ -293,6 +318,12 @@ pub enum PropertyAccessKind {
 }
 
 impl PropertyAccessKind {
+    /// Whether this access skips checking that the member exists, as `:`
+    /// and `?:` do.
+    pub fn is_loose(self) -> bool {
+        matches!(self, PropertyAccessKind::Colon | PropertyAccessKind::SafeColon)
+    }
+
     pub fn name(self) -> &'static str {
         match self {
             PropertyAccessKind::Dot => ".",
@@ -818,6 +849,21 @@ impl From<TypePath> for Prefab {
     }
 }
 
+impl fmt::Display for Prefab {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", FormatTypePath(&self.path))?;
+        let mut first = true;
+        for (k, v) in self.vars.iter() {
+            write!(f, "{}{} = {}", if first { " {" } else { "; " }, k, v)?;
+            first = false;
+        }
+        if !first {
+            f.write_str("}")?;
+        }
+        Ok(())
+    }
+}
+
 /// Formatting helper for variable arrays.
 pub struct FormatVars<'a, T>(pub &'a T);
 
@@ -877,7 +923,15 @@ pub enum Expression {
         if_: Box<Expression>,
         /// The value otherwise.
         else_: Box<Expression>,
-    }
+    },
+    /// A placeholder for an expression which failed to parse, produced only
+    /// in lenient mode.
+    Error {
+        /// Where the failed expression began.
+        location: Location,
+        /// A short description of the parse error.
+        message: Ident2,
+    },
 }
 
 impl Expression {
@@ -970,9 +1024,23 @@ impl Expression {
                     else_.is_truthy()
                 }
             }
+            Expression::Error { .. } => None,
         }
     }
 
+    /// If this expression is an `in` operation, return the value tested and
+    /// what it's tested against.
+    pub fn as_in(&self) -> Option<(&Expression, InOperand<'_>)> {
+        let Expression::BinaryOp { op: BinaryOp::In, lhs, rhs } = self else {
+            return None;
+        };
+        let operand = match &**rhs {
+            Expression::BinaryOp { op: BinaryOp::To, lhs: low, rhs: high } => InOperand::Range { low, high },
+            list => InOperand::List(list),
+        };
+        Some((lhs, operand))
+    }
+
     pub fn nameof(&self) -> Option<&str> {
         match self {
             Expression::Base { term, follow } => {
@@ -987,6 +1055,16 @@ impl Expression {
     }
 }
 
+/// The right-hand side of an `in` operation, as returned by
+/// [`Expression::as_in`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InOperand<'a> {
+    /// `x in list`, a membership test.
+    List(&'a Expression),
+    /// `x in low to high`, a range check.
+    Range { low: &'a Expression, high: &'a Expression },
+}
+
 impl From<Term> for Expression {
     fn from(term: Term) -> Expression {
         match term {
@@ -999,6 +1077,130 @@ impl From<Term> for Expression {
     }
 }
 
+/// Formats the expression as DM source, adding only the parentheses needed
+/// for it to parse back to the same tree.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Base { term, follow } => fmt_base(f, &term.elem, follow),
+            // `x in 1 to 5` is the only place the parser accepts `to`.
+            Expression::BinaryOp { op: BinaryOp::In, lhs, rhs } if matches!(**rhs, Expression::BinaryOp { op: BinaryOp::To, .. }) => {
+                let Expression::BinaryOp { lhs: low, rhs: high, .. } = &**rhs else { unreachable!() };
+                write!(f, "{} in {} to {}",
+                    Operand(lhs, lhs.strength() > STRENGTH_IN),
+                    Operand(low, low.strength() >= STRENGTH_IN),
+                    Operand(high, high.strength() >= STRENGTH_IN))
+            }
+            Expression::BinaryOp { op, lhs, rhs } => {
+                let strength = binary_strength(*op);
+                write!(f, "{} {} {}", Operand(lhs, lhs.strength() > strength), op, Operand(rhs, rhs.strength() >= strength))
+            }
+            Expression::AssignOp { op, lhs, rhs } => {
+                // Assignment binds to the right.
+                write!(f, "{} {} {}", Operand(lhs, lhs.strength() >= STRENGTH_ASSIGN), op, Operand(rhs, rhs.strength() > STRENGTH_ASSIGN))
+            }
+            Expression::TernaryOp { cond, if_, else_ } => {
+                write!(f, "{} ? {} : {}",
+                    Operand(cond, cond.strength() >= STRENGTH_CONDITIONAL),
+                    Operand(if_, if_.strength() > STRENGTH_CONDITIONAL),
+                    Operand(else_, else_.strength() > STRENGTH_CONDITIONAL))
+            }
+            Expression::Error { message, .. } => write!(f, "/* error: {message} */"),
+        }
+    }
+}
+
+// Binding strengths for formatting, mirroring the parser's operator table.
+// Lower numbers bind tighter.
+const STRENGTH_CONDITIONAL: u8 = 12;
+const STRENGTH_ASSIGN: u8 = 13;
+const STRENGTH_IN: u8 = 14;
+
+fn binary_strength(op: BinaryOp) -> u8 {
+    use self::BinaryOp::*;
+    match op {
+        Pow => 1,
+        Mul | Div | Mod | FloatMod => 2,
+        Add | Sub => 3,
+        Less | Greater | LessEq | GreaterEq | LessOrGreater => 4,
+        LShift | RShift => 5,
+        Eq | NotEq | Equiv | NotEquiv => 6,
+        BitAnd => 7,
+        BitXor => 8,
+        BitOr => 9,
+        And => 10,
+        Or => 11,
+        In | To => STRENGTH_IN,
+    }
+}
+
+impl Expression {
+    fn strength(&self) -> u8 {
+        match self {
+            Expression::Base { .. } | Expression::Error { .. } => 0,
+            Expression::BinaryOp { op, .. } => binary_strength(*op),
+            Expression::AssignOp { .. } => STRENGTH_ASSIGN,
+            Expression::TernaryOp { .. } => STRENGTH_CONDITIONAL,
+        }
+    }
+}
+
+/// A formatting wrapper which parenthesizes an operand if needed.
+struct Operand<'a>(&'a Expression, bool);
+
+impl<'a> fmt::Display for Operand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Format a term and its follows. Prefix unary operators come last in the
+/// follows but are written first, so the operand of anything following one
+/// is parenthesized.
+fn fmt_base(f: &mut fmt::Formatter, term: &Term, follow: &[Spanned<Follow>]) -> fmt::Result {
+    let Some((last, rest)) = follow.split_last() else {
+        return write!(f, "{term}");
+    };
+    let base = FormatBase(term, rest);
+    match last.elem {
+        Follow::Unary(op) if op.is_prefix() => write!(f, "{}", op.around(&base)),
+        Follow::Unary(op) if rest.last().is_some_and(|prev| prev.elem.is_prefix_unary()) => {
+            write!(f, "{}", op.around(&format_args!("({base})")))
+        }
+        Follow::Unary(op) => write!(f, "{}", op.around(&base)),
+        ref other if rest.last().is_some_and(|prev| prev.elem.is_prefix_unary()) => write!(f, "({base}){other}"),
+        ref other => write!(f, "{base}{other}"),
+    }
+}
+
+struct FormatBase<'a>(&'a Term, &'a [Spanned<Follow>]);
+
+impl<'a> fmt::Display for FormatBase<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_base(f, self.0, self.1)
+    }
+}
+
+/// Formatting helper for comma-separated argument lists.
+struct FormatArgs<'a>(&'a [Expression]);
+
+impl<'a> fmt::Display for FormatArgs<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("(")?;
+        for (i, arg) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        f.write_str(")")
+    }
+}
+
 /// The structure of a term, the basic building block of the AST.
 #[allow(non_camel_case_types)]
 #[derive(Clone, PartialEq, Debug, GetSize, Serialize)]
@@ -1092,6 +1294,16 @@ pub enum Term {
 }
 
 impl Term {
+    /// If this term calls the builtin `sleep` or `sleep_offline`, which
+    /// block the calling proc, return the call's arguments.
+    pub fn as_sleep_call(&self) -> Option<&[Expression]> {
+        match self {
+            Term::Call(name, args) | Term::GlobalCall(name, args)
+                if name.as_str() == "sleep" || name.as_str() == "sleep_offline" => Some(args),
+            _ => None,
+        }
+    }
+
     pub fn is_static(&self) -> bool {
         matches!(self,
             Term::Null
@@ -1184,12 +1396,202 @@ impl From<Expression> for Term {
     }
 }
 
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Null => f.write_str("null"),
+            Term::Int(i) => write!(f, "{i}"),
+            Term::Float(n) if n.is_nan() => f.write_str("1.#IND"),
+            Term::Float(n) if n.is_infinite() => f.write_str(if *n < 0.0 { "-1.#INF" } else { "1.#INF" }),
+            // Debug formatting always includes a point or exponent, so the
+            // literal still reads as a float.
+            Term::Float(n) => write!(f, "{n:?}"),
+            Term::Ident(i) => f.write_str(i),
+            Term::String(s) => {
+                f.write_str("\"")?;
+                write_string_part(f, s)?;
+                f.write_str("\"")
+            }
+            Term::Resource(s) => {
+                f.write_str("'")?;
+                for ch in s.chars() {
+                    if ch == '\'' || ch == '\\' {
+                        f.write_str("\\")?;
+                    }
+                    write!(f, "{ch}")?;
+                }
+                f.write_str("'")
+            }
+            Term::As(input_type) if input_type.is_empty() => f.write_str("as()"),
+            Term::As(input_type) => write!(f, "as({input_type})"),
+            Term::__PROC__ => f.write_str("__PROC__"),
+            Term::__TYPE__ => f.write_str("__TYPE__"),
+            Term::__IMPLIED_TYPE__ => f.write_str("__IMPLIED_TYPE__"),
+            Term::Expr(expr) => write!(f, "({expr})"),
+            Term::Prefab(prefab) => write!(f, "{prefab}"),
+            Term::InterpString(first, parts) => {
+                f.write_str("\"")?;
+                write_string_part(f, first)?;
+                for (expr, part) in parts.iter() {
+                    match expr {
+                        Some(expr) => write!(f, "[{expr}]")?,
+                        None => f.write_str("[]")?,
+                    }
+                    write_string_part(f, part)?;
+                }
+                f.write_str("\"")
+            }
+            Term::Call(name, args) => write!(f, "{}{}", name, FormatArgs(args)),
+            Term::SelfCall(args) => write!(f, ".{}", FormatArgs(args)),
+            Term::ParentCall(args) => write!(f, "..{}", FormatArgs(args)),
+            Term::NewImplicit { args } => {
+                f.write_str("new")?;
+                fmt_new_args(f, args.as_deref())
+            }
+            Term::NewPrefab { prefab, args } => {
+                write!(f, "new {prefab}")?;
+                fmt_new_args(f, args.as_deref())
+            }
+            Term::NewMiniExpr { expr, args } => {
+                write!(f, "new {expr}")?;
+                fmt_new_args(f, args.as_deref())
+            }
+            Term::List(args) => write!(f, "list{}", FormatArgs(args)),
+            Term::Input { args, input_type, in_list } => {
+                write!(f, "input{}", FormatArgs(args))?;
+                if let Some(input_type) = input_type {
+                    write!(f, " as {input_type}")?;
+                }
+                if let Some(in_list) = in_list {
+                    write!(f, " in {in_list}")?;
+                }
+                Ok(())
+            }
+            Term::Locate { args, in_list } => {
+                write!(f, "locate{}", FormatArgs(args))?;
+                if let Some(in_list) = in_list {
+                    write!(f, " in {in_list}")?;
+                }
+                Ok(())
+            }
+            Term::Pick(args) => {
+                f.write_str("pick(")?;
+                for (i, (weight, value)) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    if let Some(weight) = weight {
+                        write!(f, "{weight}; ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str(")")
+            }
+            Term::DynamicCall(call_args, args) => write!(f, "call{}{}", FormatArgs(call_args), FormatArgs(args)),
+            Term::ExternalCall { library, function, args } => {
+                f.write_str("call_ext(")?;
+                if let Some(library) = library {
+                    write!(f, "{library}, ")?;
+                }
+                write!(f, "{}){}", function, FormatArgs(args))
+            }
+            Term::GlobalIdent(name) => write!(f, "::{name}"),
+            Term::GlobalCall(name, args) => write!(f, "::{}{}", name, FormatArgs(args)),
+        }
+    }
+}
+
+fn fmt_new_args(f: &mut fmt::Formatter, args: Option<&[Expression]>) -> fmt::Result {
+    match args {
+        Some(args) => write!(f, "{}", FormatArgs(args)),
+        None => Ok(()),
+    }
+}
+
+/// Write the literal text of a string, escaping any quotes, brackets, or
+/// newlines which were not already escaped in the source.
+fn write_string_part(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    let mut backslash = false;
+    for ch in text.chars() {
+        if backslash {
+            backslash = false;
+        } else {
+            match ch {
+                '\\' => backslash = true,
+                '"' | '[' => f.write_str("\\")?,
+                '\n' => {
+                    f.write_str("\\n")?;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        write!(f, "{ch}")?;
+    }
+    Ok(())
+}
+
 #[derive(Clone, PartialEq, Debug, GetSize, Serialize)]
 pub struct MiniExpr {
     pub ident: Ident2,
     pub fields: Box<[Field]>,
 }
 
+impl fmt::Display for MiniExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.ident)?;
+        for field in self.fields.iter() {
+            write!(f, "{field}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The proc a `call()()` calls, as far as can be told without evaluating it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DynamicCallTarget<'a> {
+    /// `call(src, "name")`, the proc of `src` named by a string literal.
+    Named(&'a Expression, &'a str),
+    /// `call(/type/proc/name)` or `call(src, /type/proc/name)`, a proc given
+    /// by its path.
+    Path(&'a Prefab),
+    /// The proc can't be known until runtime, or this calls into a library.
+    Unknown,
+}
+
+impl<'a> DynamicCallTarget<'a> {
+    /// Find the target from the first argument list of a `call()()`.
+    pub fn from_call_args(call_args: &'a [Expression]) -> DynamicCallTarget<'a> {
+        let proc_path = |expr: &'a Expression| match expr.as_term() {
+            Some(Term::Prefab(prefab)) if prefab.vars.is_empty() && prefab.path.len() >= 2
+                && ProcDeclKind::from_name(&prefab.path[prefab.path.len() - 2].1).is_some() => Some(prefab),
+            _ => None,
+        };
+        if let [path] | [_, path] = call_args
+            && let Some(prefab) = proc_path(path)
+        {
+            return DynamicCallTarget::Path(prefab);
+        }
+        match call_args {
+            // `call("library", "function")` is the old form of `call_ext`
+            [src, name] if !matches!(src.as_term(), Some(Term::String(_))) => match name.as_term() {
+                Some(Term::String(name)) => DynamicCallTarget::Named(src, name),
+                _ => DynamicCallTarget::Unknown,
+            },
+            _ => DynamicCallTarget::Unknown,
+        }
+    }
+
+    /// The name of the proc called, if known.
+    pub fn proc_name(self) -> Option<&'a str> {
+        match self {
+            DynamicCallTarget::Named(_, name) => Some(name),
+            DynamicCallTarget::Path(prefab) => prefab.path.last().map(|(_, name)| name.as_str()),
+            DynamicCallTarget::Unknown => None,
+        }
+    }
+}
+
 /// An expression part which is applied to a term or another follow.
 #[derive(Debug, Clone, PartialEq, GetSize, Serialize)]
 pub enum Follow {
@@ -1222,6 +1624,27 @@ impl Follow {
             _ => None,
         }
     }
+
+    fn is_prefix_unary(&self) -> bool {
+        matches!(self, Follow::Unary(op) if op.is_prefix())
+    }
+}
+
+/// Formats the follow as it appears after its operand. Unary operators are
+/// written by name alone.
+impl fmt::Display for Follow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Follow::Index(ListAccessKind::Normal, expr) => write!(f, "[{expr}]"),
+            Follow::Index(ListAccessKind::Safe, expr) => write!(f, "?[{expr}]"),
+            Follow::Field(kind, name) => write!(f, "{kind}{name}"),
+            Follow::Call(kind, name, args) => write!(f, "{}{}{}", kind, name, FormatArgs(args)),
+            Follow::Unary(op) => f.write_str(op.name()),
+            Follow::StaticField(name) => write!(f, "::{name}"),
+            // The arguments of a proc reference are not kept.
+            Follow::ProcReference(name) => write!(f, "::{name}()"),
+        }
+    }
 }
 
 /// Like a `Follow` but only supports field accesses.
@@ -1237,6 +1660,12 @@ impl From<Field> for Follow {
     }
 }
 
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.kind, self.ident)
+    }
+}
+
 /// A parameter declaration in the header of a proc.
 #[derive(Debug, Clone, PartialEq, Default, GetSize)]
 pub struct Parameter {
@@ -1274,6 +1703,16 @@ impl Serialize for VarType {
     }
 }
 
+impl<'de> Deserialize<'de> for VarType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de> {
+        // The serialized form is the `Display` form, which omits the input type.
+        let path = String::deserialize(deserializer)?;
+        Ok(path.split('/').filter(|s| !s.is_empty()).map(ToOwned::to_owned).collect())
+    }
+}
+
 impl VarType {
     #[inline]
     pub fn is_const_evaluable(&self) -> bool {
@@ -1441,6 +1880,12 @@ pub enum Statement {
     },
     Del(Expression),
     Crash(Option<Expression>),
+    /// A placeholder for a statement which failed to parse, produced only in
+    /// lenient mode.
+    Error {
+        location: Location,
+        message: Ident2,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, GetSize)]
@@ -1477,6 +1922,285 @@ pub struct ForRangeStatement {
     pub block: Block,
 }
 
+// ----------------------------------------------------------------------------
+// Traversal
+
+/// A visitor over proc bodies and the expressions within them.
+///
+/// Each method defaults to calling the matching `walk_*` function, which
+/// visits the node's children. Implementors override only the nodes they care
+/// about, calling `walk_*` from the override to keep descending.
+pub trait Visitor<'ast> {
+    fn visit_proc(&mut self, parameters: &'ast [Parameter], block: &'ast [Spanned<Statement>]) {
+        walk_proc(self, parameters, block)
+    }
+
+    fn visit_block(&mut self, block: &'ast [Spanned<Statement>]) {
+        walk_block(self, block)
+    }
+
+    /// Visit a statement. Statements nested in `for` headers have no span of
+    /// their own and are given the location of the loop.
+    fn visit_statement(&mut self, location: Location, statement: &'ast Statement) {
+        walk_statement(self, location, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+        walk_term(self, term)
+    }
+
+    fn visit_follow(&mut self, follow: &'ast Spanned<Follow>) {
+        walk_follow(self, follow)
+    }
+
+    fn visit_prefab(&mut self, prefab: &'ast Prefab) {
+        walk_prefab(self, prefab)
+    }
+}
+
+pub fn walk_proc<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, parameters: &'ast [Parameter], block: &'ast [Spanned<Statement>]) {
+    for parameter in parameters {
+        if let Some(default) = &parameter.default {
+            visitor.visit_expression(default);
+        }
+        if let Some(in_list) = &parameter.in_list {
+            visitor.visit_expression(in_list);
+        }
+    }
+    visitor.visit_block(block);
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, block: &'ast [Spanned<Statement>]) {
+    for statement in block {
+        visitor.visit_statement(statement.location, &statement.elem);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, location: Location, statement: &'ast Statement) {
+    match statement {
+        Statement::Expr(expr) |
+        Statement::Throw(expr) |
+        Statement::Del(expr) => visitor.visit_expression(expr),
+        Statement::Return(expr) |
+        Statement::Crash(expr) => {
+            if let Some(expr) = expr {
+                visitor.visit_expression(expr);
+            }
+        }
+        Statement::While { condition, block } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(block);
+        }
+        Statement::DoWhile { block, condition } => {
+            visitor.visit_block(block);
+            visitor.visit_expression(&condition.elem);
+        }
+        Statement::If { arms, else_arm } => {
+            for (condition, block) in arms {
+                visitor.visit_expression(&condition.elem);
+                visitor.visit_block(block);
+            }
+            if let Some(block) = else_arm {
+                visitor.visit_block(block);
+            }
+        }
+        Statement::ForInfinite { block } => visitor.visit_block(block),
+        Statement::ForLoop { init, test, inc, block } => {
+            if let Some(init) = init {
+                visitor.visit_statement(location, init);
+            }
+            if let Some(test) = test {
+                visitor.visit_expression(test);
+            }
+            if let Some(inc) = inc {
+                visitor.visit_statement(location, inc);
+            }
+            visitor.visit_block(block);
+        }
+        Statement::ForList(for_list) => {
+            if let Some(in_list) = &for_list.in_list {
+                visitor.visit_expression(in_list);
+            }
+            visitor.visit_block(&for_list.block);
+        }
+        Statement::ForRange(for_range) => {
+            visitor.visit_expression(&for_range.start);
+            visitor.visit_expression(&for_range.end);
+            if let Some(step) = &for_range.step {
+                visitor.visit_expression(step);
+            }
+            visitor.visit_block(&for_range.block);
+        }
+        Statement::Var(var) => {
+            if let Some(value) = &var.value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Vars(vars) => {
+            for var in vars {
+                if let Some(value) = &var.value {
+                    visitor.visit_expression(value);
+                }
+            }
+        }
+        Statement::Setting { value, .. } => visitor.visit_expression(value),
+        Statement::Spawn { delay, block } => {
+            if let Some(delay) = delay {
+                visitor.visit_expression(delay);
+            }
+            visitor.visit_block(block);
+        }
+        Statement::Switch { input, cases, default } => {
+            visitor.visit_expression(input);
+            for (case_list, block) in cases.iter() {
+                for case in case_list.elem.iter() {
+                    match case {
+                        Case::Exact(expr) => visitor.visit_expression(expr),
+                        Case::Range(start, end) => {
+                            visitor.visit_expression(start);
+                            visitor.visit_expression(end);
+                        }
+                    }
+                }
+                visitor.visit_block(block);
+            }
+            if let Some(block) = default {
+                visitor.visit_block(block);
+            }
+        }
+        Statement::TryCatch { try_block, catch_block, .. } => {
+            visitor.visit_block(try_block);
+            visitor.visit_block(catch_block);
+        }
+        Statement::Label { block, .. } => visitor.visit_block(block),
+        Statement::Continue(_) |
+        Statement::Break(_) |
+        Statement::Goto(_) |
+        Statement::Error { .. } => {}
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expression: &'ast Expression) {
+    match expression {
+        Expression::Base { term, follow } => {
+            visitor.visit_term(term);
+            for each in follow.iter() {
+                visitor.visit_follow(each);
+            }
+        }
+        Expression::BinaryOp { lhs, rhs, .. } |
+        Expression::AssignOp { lhs, rhs, .. } => {
+            visitor.visit_expression(lhs);
+            visitor.visit_expression(rhs);
+        }
+        Expression::TernaryOp { cond, if_, else_ } => {
+            visitor.visit_expression(cond);
+            visitor.visit_expression(if_);
+            visitor.visit_expression(else_);
+        }
+        Expression::Error { .. } => {}
+    }
+}
+
+pub fn walk_term<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, term: &'ast Spanned<Term>) {
+    fn all<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, exprs: &'ast [Expression]) {
+        for expr in exprs {
+            visitor.visit_expression(expr);
+        }
+    }
+
+    match &term.elem {
+        Term::Expr(expr) => visitor.visit_expression(expr),
+        Term::Prefab(prefab) => visitor.visit_prefab(prefab),
+        Term::InterpString(_, parts) => {
+            for (expr, _) in parts.iter() {
+                if let Some(expr) = expr {
+                    visitor.visit_expression(expr);
+                }
+            }
+        }
+        Term::Call(_, args) |
+        Term::SelfCall(args) |
+        Term::ParentCall(args) |
+        Term::List(args) |
+        Term::GlobalCall(_, args) => all(visitor, args),
+        Term::NewImplicit { args } |
+        Term::NewMiniExpr { args, .. } => {
+            if let Some(args) = args {
+                all(visitor, args);
+            }
+        }
+        Term::NewPrefab { prefab, args } => {
+            visitor.visit_prefab(prefab);
+            if let Some(args) = args {
+                all(visitor, args);
+            }
+        }
+        Term::Input { args, in_list, .. } |
+        Term::Locate { args, in_list } => {
+            all(visitor, args);
+            if let Some(in_list) = in_list {
+                visitor.visit_expression(in_list);
+            }
+        }
+        Term::Pick(args) => {
+            for (weight, value) in args.iter() {
+                if let Some(weight) = weight {
+                    visitor.visit_expression(weight);
+                }
+                visitor.visit_expression(value);
+            }
+        }
+        Term::DynamicCall(call_args, args) => {
+            all(visitor, call_args);
+            all(visitor, args);
+        }
+        Term::ExternalCall { library, function, args } => {
+            if let Some(library) = library {
+                visitor.visit_expression(library);
+            }
+            visitor.visit_expression(function);
+            all(visitor, args);
+        }
+        Term::Null |
+        Term::Int(_) |
+        Term::Float(_) |
+        Term::Ident(_) |
+        Term::String(_) |
+        Term::Resource(_) |
+        Term::As(_) |
+        Term::__PROC__ |
+        Term::__TYPE__ |
+        Term::__IMPLIED_TYPE__ |
+        Term::GlobalIdent(_) => {}
+    }
+}
+
+pub fn walk_follow<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, follow: &'ast Spanned<Follow>) {
+    match &follow.elem {
+        Follow::Index(_, expr) => visitor.visit_expression(expr),
+        Follow::Call(_, _, args) => {
+            for arg in args.iter() {
+                visitor.visit_expression(arg);
+            }
+        }
+        Follow::Field(..) |
+        Follow::Unary(_) |
+        Follow::StaticField(_) |
+        Follow::ProcReference(_) => {}
+    }
+}
+
+pub fn walk_prefab<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, prefab: &'ast Prefab) {
+    for (_, value) in prefab.vars.iter() {
+        visitor.visit_expression(value);
+    }
+}
+
 // ----------------------------------------------------------------------------
 // Miscellaneous
 
@@ -1493,6 +2217,26 @@ pub const KNOWN_SETTING_NAMES: &[&str] = &[
     "waitfor",
 ];
 
+/// The `set` statements at the head of a proc body. Only these count as the
+/// proc's settings, so the scan stops at the first statement of any other kind.
+pub fn leading_settings(block: &[Spanned<Statement>]) -> impl Iterator<Item = &Spanned<Statement>> {
+    block.iter().take_while(|statement| matches!(statement.elem, Statement::Setting { .. }))
+}
+
+/// The type path given by `set SpacemanDMM_return_type = /path` among the
+/// leading settings of a proc body.
+pub fn return_type_setting(block: &[Spanned<Statement>]) -> Option<&TypePath> {
+    leading_settings(block).find_map(|statement| match &statement.elem {
+        Statement::Setting { name, mode: SettingMode::Assign, value } if name == "SpacemanDMM_return_type" => {
+            match value.as_term() {
+                Some(Term::Prefab(fab)) => Some(&fab.path),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
 // TODO: maybe put this somewhere more suitable?
 pub static VALID_FILTER_TYPES: phf::Map<&'static str, &[&str]> = phf_map! {
     "alpha" => &[ "x", "y", "icon", "render_source", "flags" ],
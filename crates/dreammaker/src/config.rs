@@ -20,6 +20,7 @@ pub struct Config {
     display: WarningDisplay,
     diagnostics: HashMap<String, WarningLevel>,
     pub code_standards: CodeStandards,
+    pub parser: Parser,
 
     // tool-specific configuration
     pub langserver: Langserver,
@@ -47,6 +48,90 @@ pub struct Langserver {
 pub struct CodeStandards {
     pub disallow_relative_proc_definitions: bool,
     pub disallow_relative_type_definitions: bool,
+    pub naming: NamingRules,
+    pub empty_blocks: EmptyBlockRules,
+    /// Tags such as `TODO` which mark a line comment starting with them to be
+    /// reported, none by default.
+    pub comment_tags: Vec<String>,
+    /// Whether dreamchecker checks the include graph.
+    pub check_includes: bool,
+}
+
+/// Naming conventions to enforce for each kind of symbol, none by default
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct NamingRules {
+    pub macros: Option<NamingCase>,
+    pub types: Option<NamingCase>,
+    pub vars: Option<NamingCase>,
+    pub procs: Option<NamingCase>,
+    pub verbs: Option<NamingCase>,
+}
+
+/// Kinds of empty blocks to warn about, none by default
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct EmptyBlockRules {
+    /// Procs with an empty body. Procs declared without a body at all, like
+    /// `/mob/proc/attack()`, are meant to be overridden and never warned about.
+    pub procs: bool,
+    /// `if`, `else if`, and `else` blocks.
+    pub ifs: bool,
+    /// `while` loops.
+    pub whiles: bool,
+}
+
+/// A convention for the case of names
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all(deserialize = "snake_case"))]
+pub enum NamingCase {
+    /// Uppercase letters, digits, and underscores, like `MAX_HEALTH`
+    UpperCase,
+    /// Lowercase letters, digits, and underscores, like `take_damage`
+    SnakeCase,
+}
+
+impl NamingCase {
+    /// Test whether a name follows this convention.
+    pub fn matches(self, name: &str) -> bool {
+        name.chars().all(|ch| ch == '_' || ch.is_ascii_digit() || match self {
+            NamingCase::UpperCase => ch.is_ascii_uppercase(),
+            NamingCase::SnakeCase => ch.is_ascii_lowercase(),
+        })
+    }
+
+    /// Return how this convention is written in diagnostics.
+    pub fn name(self) -> &'static str {
+        match self {
+            NamingCase::UpperCase => "UPPER_CASE",
+            NamingCase::SnakeCase => "snake_case",
+        }
+    }
+}
+
+/// Parser config options
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Parser {
+    /// How deeply expressions and blocks may nest before parsing gives up.
+    pub max_nesting_depth: usize,
+    /// How deeply macros may expand into other macros before the use which
+    /// would go deeper is left unexpanded. Enforced by the preprocessor, but
+    /// kept here beside the other depth limit.
+    pub max_macro_expansion_depth: usize,
+    /// Directories searched in order for `#include <...>` libraries. Relative
+    /// directories are taken relative to the environment's directory.
+    pub library_dirs: Vec<PathBuf>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser {
+            max_nesting_depth: 256,
+            max_macro_expansion_depth: 256,
+            library_dirs: Vec::new(),
+        }
+    }
 }
 
 /// DMDoc config options
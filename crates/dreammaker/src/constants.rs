@@ -1,5 +1,7 @@
 //! The constant folder/evaluator, used by the preprocessor and object tree.
+use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops;
 use std::path::Path;
 
@@ -99,6 +101,18 @@ impl Constant {
     fn from_bit_op(x: u32) -> Constant {
         Constant::Float((x & Constant::BIT_MASK) as f32)
     }
+
+    /// Convert an operand of a bitwise operator to its integer value.
+    ///
+    /// BYOND truncates to 24 bits, so negative values wrap around rather than
+    /// clamping to zero. Non-integers are rejected.
+    fn to_bit_operand(f: f32) -> Option<u32> {
+        if f.fract() == 0. {
+            Some((f as i32) as u32 & Constant::BIT_MASK)
+        } else {
+            None
+        }
+    }
 }
 
 // Manual Hash and Eq impls using OrderedFloat, so that we get the desired
@@ -224,6 +238,45 @@ impl Constant {
         self.as_path_str().map(Path::new)
     }
 
+    /// Read a color as folded from `rgb()` or written as a `"#rrggbb"` or
+    /// `"#rrggbbaa"` string. Colors without alpha are fully opaque.
+    pub fn as_color(&self) -> Option<[u8; 4]> {
+        let hex = self.as_str()?.strip_prefix('#')?;
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            return None;
+        }
+        let mut color = [255; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(color)
+    }
+
+    /// Read the six components of a `matrix()` call, in argument order.
+    ///
+    /// `matrix()` with no arguments is the identity. Other forms, such as
+    /// copying another matrix or the scale and rotate shorthands, aren't
+    /// understood.
+    pub fn as_matrix(&self) -> Option<[f32; 6]> {
+        let Constant::Call(ConstFn::Matrix, ref args) = *self else {
+            return None;
+        };
+        match args.len() {
+            0 => Some([1., 0., 0., 0., 1., 0.]),
+            6 => {
+                let mut matrix = [0.; 6];
+                for (component, (arg, kwarg)) in matrix.iter_mut().zip(args.iter()) {
+                    if kwarg.is_some() {
+                        return None;
+                    }
+                    *component = arg.to_float()?;
+                }
+                Some(matrix)
+            }
+            _ => None,
+        }
+    }
+
     // ------------------------------------------------------------------------
     // Comparisons
 
@@ -440,7 +493,269 @@ impl Expression {
             location,
             ty: NodeIndex::new(0),
             defines: None,
-        }.expr(self, None)
+            cache: None,
+            depends_on_type: false,
+        }.expr(&self, None)
+    }
+
+    /// Evaluate this expression in the absence of any surrounding context,
+    /// reusing an earlier result from the cache if there is one.
+    pub fn cached_evaluate(self, location: Location, cache: &mut ConstantCache) -> Result<Constant, DMError> {
+        ConstantFolder {
+            context: None,
+            tree: None,
+            location,
+            ty: NodeIndex::new(0),
+            defines: None,
+            cache: Some(cache),
+            depends_on_type: false,
+        }.cached_expr(self, None)
+    }
+}
+
+impl Expression {
+    /// Check whether this expression is a compile-time constant, as seen from
+    /// the global scope, without evaluating it.
+    ///
+    /// Macros have already been expanded by the time an expression is parsed,
+    /// so a constant macro is judged by what it expanded to.
+    pub fn is_const(&self, tree: &ObjectTree) -> bool {
+        self.is_const_in(tree.root())
+    }
+
+    /// Check whether this expression is a compile-time constant, resolving
+    /// var references against the given type, without evaluating it.
+    ///
+    /// Literals, `const` vars, type paths and operators over constant operands
+    /// are constant. Proc calls other than the builtins which the constant
+    /// folder knows about, and references to non-`const` vars, are not.
+    pub fn is_const_in(&self, ty: TypeRef) -> bool {
+        match self {
+            Expression::Base { term, follow } => {
+                term_is_const(&term.elem, ty) && follow_is_const(&term.elem, follow, ty)
+            }
+            Expression::BinaryOp { lhs, rhs, .. } => lhs.is_const_in(ty) && rhs.is_const_in(ty),
+            Expression::TernaryOp { cond, if_, else_ } => {
+                cond.is_const_in(ty) && if_.is_const_in(ty) && else_.is_const_in(ty)
+            }
+            Expression::AssignOp { .. } | Expression::Error { .. } => false,
+        }
+    }
+}
+
+/// Text macros like `\the` depend on the embedded values at runtime.
+fn has_text_macro(text: &str) -> bool {
+    text.split('\\').skip(1).any(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+fn term_is_const(term: &Term, ty: TypeRef) -> bool {
+    let args_are_const = |args: &[Expression]| args.iter().all(|arg| match arg {
+        // associations in argument lists have name keys
+        Expression::AssignOp { op: AssignOp::Assign, lhs, rhs } => {
+            (matches!(lhs.as_term(), Some(Term::Ident(_))) || lhs.is_const_in(ty)) && rhs.is_const_in(ty)
+        }
+        other => other.is_const_in(ty),
+    });
+    let prefab_is_const = |prefab: &Prefab| prefab.vars.iter().all(|(_, value)| value.is_const_in(ty));
+
+    match term {
+        Term::Null
+        | Term::Int(_)
+        | Term::Float(_)
+        | Term::String(_)
+        | Term::Resource(_)
+        | Term::__TYPE__ => true,
+        Term::Expr(expr) => expr.is_const_in(ty),
+        Term::Prefab(prefab) => prefab_is_const(prefab),
+        Term::InterpString(first, parts) => {
+            !has_text_macro(first)
+                && parts.iter().all(|(expr, text)| {
+                    !has_text_macro(text) && expr.as_ref().is_none_or(|expr| expr.is_const_in(ty))
+                })
+        }
+        Term::List(args) => args_are_const(args),
+        Term::NewPrefab { prefab, args } => prefab_is_const(prefab) && args.as_deref().is_none_or(args_are_const),
+        Term::NewImplicit { args } => args.as_deref().is_none_or(args_are_const),
+        Term::Call(ident, args) => match &**ident {
+            "matrix" | "newlist" | "icon" | "sound" | "filter" | "file" | "generator"
+            | "sin" | "cos" | "arcsin" | "arccos" | "rgb"
+            | "min" | "max" | "abs" | "round" | "sqrt" => args_are_const(args),
+            "nameof" => true,
+            _ => false,
+        },
+        Term::Ident(ident) => match ident.as_str() {
+            "type" => true,
+            "parent_type" => ty.parent_type().is_some(),
+            name => ty.get_var_declaration(name).is_some_and(|decl| decl.var_type.flags.is_const()),
+        },
+        _ => false,
+    }
+}
+
+fn follow_is_const(term: &Term, follow: &[Spanned<Follow>], ty: TypeRef) -> bool {
+    // `/path::NAME` reads a var off of another type, which must also be const
+    let mut static_type = match term {
+        Term::Prefab(prefab) if prefab.vars.is_empty() => ty.navigate_path(&prefab.path).map(|nav| nav.ty()),
+        Term::Ident(ident) if ident == "type" => Some(ty),
+        Term::Ident(ident) if ident == "parent_type" => ty.parent_type(),
+        Term::__TYPE__ => Some(ty),
+        _ => None,
+    };
+    for each in follow {
+        match &each.elem {
+            Follow::Unary(UnaryOp::Neg | UnaryOp::BitNot | UnaryOp::Not) => static_type = None,
+            Follow::StaticField(name) => {
+                let Some(owner) = static_type.take() else { return false };
+                if !owner.get_var_declaration(name).is_some_and(|decl| decl.var_type.flags.is_const()) {
+                    return false;
+                }
+            }
+            Follow::ProcReference(name) => {
+                let Some(owner) = static_type.take() else { return false };
+                if owner.get_proc(name).is_none() {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Memoized results of constant evaluation.
+///
+/// Entries are keyed by the structure of the expression and its type hint,
+/// ignoring locations. Results which depend on the type an expression is
+/// evaluated on, such as var references and relative paths, are never stored,
+/// so a cache is safe to share across every type in one object tree. Values
+/// read from elsewhere in the tree are assumed fixed, so a cache must not
+/// outlive the tree it was used with.
+#[derive(Debug, Default)]
+pub struct ConstantCache {
+    entries: HashMap<u64, Vec<CacheEntry>>,
+    hits: usize,
+    misses: usize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    expression: Expression,
+    type_hint: Option<TreePath>,
+    value: Constant,
+}
+
+/// Hashes the shape and leaf values of an expression, skipping locations so
+/// that the hash agrees with `Expression`'s equality.
+struct StructuralHasher<H>(H);
+
+impl<H: Hasher> StructuralHasher<H> {
+    fn float(&mut self, value: f32) {
+        // 0.0 and -0.0 compare equal, so they must hash alike
+        if value == 0.0 { 0 } else { value.to_bits() }.hash(&mut self.0);
+    }
+}
+
+impl<'ast, H: Hasher> Visitor<'ast> for StructuralHasher<H> {
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        std::mem::discriminant(expression).hash(&mut self.0);
+        match expression {
+            Expression::Base { follow, .. } => follow.len().hash(&mut self.0),
+            Expression::BinaryOp { op, .. } => std::mem::discriminant(op).hash(&mut self.0),
+            Expression::AssignOp { op, .. } => std::mem::discriminant(op).hash(&mut self.0),
+            Expression::TernaryOp { .. } | Expression::Error { .. } => {}
+        }
+        walk_expression(self, expression)
+    }
+
+    fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+        std::mem::discriminant(&term.elem).hash(&mut self.0);
+        match &term.elem {
+            Term::Int(value) => value.hash(&mut self.0),
+            Term::Float(value) => self.float(*value),
+            Term::Ident(text) | Term::String(text) | Term::Resource(text) => text.as_str().hash(&mut self.0),
+            Term::GlobalIdent(name) => name.as_str().hash(&mut self.0),
+            Term::Call(name, args) | Term::GlobalCall(name, args) => {
+                name.as_str().hash(&mut self.0);
+                args.len().hash(&mut self.0);
+            }
+            Term::InterpString(first, parts) => {
+                first.as_str().hash(&mut self.0);
+                for (expr, text) in parts.iter() {
+                    expr.is_some().hash(&mut self.0);
+                    text[..].hash(&mut self.0);
+                }
+            }
+            Term::List(args) | Term::SelfCall(args) | Term::ParentCall(args) => args.len().hash(&mut self.0),
+            Term::NewImplicit { args } | Term::NewPrefab { args, .. } => args.as_ref().map(|args| args.len()).hash(&mut self.0),
+            _ => {}
+        }
+        walk_term(self, term)
+    }
+
+    fn visit_follow(&mut self, follow: &'ast Spanned<Follow>) {
+        std::mem::discriminant(&follow.elem).hash(&mut self.0);
+        match &follow.elem {
+            Follow::Field(_, name) | Follow::Call(_, name, _) | Follow::StaticField(name) | Follow::ProcReference(name) => {
+                name.as_str().hash(&mut self.0)
+            }
+            Follow::Unary(op) => std::mem::discriminant(op).hash(&mut self.0),
+            Follow::Index(..) => {}
+        }
+        walk_follow(self, follow)
+    }
+
+    fn visit_prefab(&mut self, prefab: &'ast Prefab) {
+        prefab.path.hash(&mut self.0);
+        for (name, _) in prefab.vars.iter() {
+            name.as_str().hash(&mut self.0);
+        }
+        walk_prefab(self, prefab)
+    }
+}
+
+impl ConstantCache {
+    /// The number of evaluations answered from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of evaluations which had to be computed.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// The number of stored results.
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    /// Whether no results are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn key(expression: &Expression, type_hint: Option<&TreePath>) -> u64 {
+        let mut hasher = StructuralHasher(std::collections::hash_map::DefaultHasher::new());
+        hasher.visit_expression(expression);
+        type_hint.hash(&mut hasher.0);
+        hasher.0.finish()
+    }
+
+    fn get(&mut self, key: u64, expression: &Expression, type_hint: Option<&TreePath>) -> Option<Constant> {
+        let found = self.entries.get(&key)?.iter()
+            .find(|entry| entry.expression == *expression && entry.type_hint.as_ref() == type_hint)?
+            .value
+            .clone();
+        self.hits += 1;
+        Some(found)
+    }
+
+    fn insert(&mut self, key: u64, expression: Expression, type_hint: Option<&TreePath>, value: Constant) {
+        self.entries.entry(key).or_default().push(CacheEntry {
+            expression,
+            type_hint: type_hint.cloned(),
+            value,
+        });
     }
 }
 
@@ -452,11 +767,14 @@ pub fn preprocessor_evaluate(location: Location, expr: Expression, defines: &Def
         location,
         ty: NodeIndex::new(0),
         defines: Some(defines),
-    }.expr(expr, None)
+        cache: None,
+        depends_on_type: false,
+    }.expr(&expr, None)
 }
 
 /// Evaluate all the type-level variables in an object tree into constants.
 pub(crate) fn evaluate_all(context: &Context, tree: &mut ObjectTree) {
+    let mut cache = ConstantCache::default();
     for ty in tree.node_indices() {
         let keys: Vec<String> = tree[ty].vars.keys().cloned().collect();
         for key in keys {
@@ -468,7 +786,7 @@ pub(crate) fn evaluate_all(context: &Context, tree: &mut ObjectTree) {
             {
                 continue;  // skip non-constant-evaluable vars
             }
-            match constant_ident_lookup(tree, ty, &key, false, Some(context)) {
+            match constant_ident_lookup(tree, ty, &key, false, Some(context), Some(&mut cache)) {
                 Err(err) => context.register_error(err),
                 Ok(ConstLookup::Found(_)) => {}
                 Ok(ConstLookup::Continue(_)) => {
@@ -497,6 +815,7 @@ fn constant_ident_lookup(
     ident: &str,
     must_be_const: bool,
     context: Option<&Context>,
+    cache: Option<&mut ConstantCache>,
 ) -> Result<ConstLookup, DMError> {
     // try to read the currently-set value if we can and
     // substitute that in, otherwise try to evaluate it.
@@ -552,7 +871,9 @@ fn constant_ident_lookup(
         defines: None,
         location,
         ty,
-    }.expr(expr, if type_hint.is_empty() { None } else { Some(&type_hint) })?;
+        cache,
+        depends_on_type: false,
+    }.cached_expr(expr, if type_hint.is_empty() { None } else { Some(&type_hint) })?;
     // and store it into 'value', then return it
     let var = tree[ty].vars.get_mut(ident).unwrap();
     var.value.constant = Some(value.clone());
@@ -566,6 +887,9 @@ struct ConstantFolder<'a> {
     defines: Option<&'a DefineMap>,
     location: Location,
     ty: NodeIndex,
+    cache: Option<&'a mut ConstantCache>,
+    // whether the result so far relies on `ty`, and so can't be cached
+    depends_on_type: bool,
 }
 
 impl<'a> HasLocation for ConstantFolder<'a> {
@@ -575,7 +899,26 @@ impl<'a> HasLocation for ConstantFolder<'a> {
 }
 
 impl<'a> ConstantFolder<'a> {
-    fn expr(&mut self, expression: Expression, type_hint: Option<&TreePath>) -> Result<Constant, DMError> {
+    fn cached_expr(&mut self, expression: Expression, type_hint: Option<&TreePath>) -> Result<Constant, DMError> {
+        let Some(cache) = self.cache.as_deref_mut() else {
+            return self.expr(&expression, type_hint);
+        };
+        let key = ConstantCache::key(&expression, type_hint);
+        if let Some(value) = cache.get(key, &expression, type_hint) {
+            return Ok(value);
+        }
+        cache.misses += 1;
+
+        let value = self.expr(&expression, type_hint)?;
+        if !self.depends_on_type
+            && let Some(cache) = self.cache.as_deref_mut()
+        {
+            cache.insert(key, expression, type_hint, value.clone());
+        }
+        Ok(value)
+    }
+
+    fn expr(&mut self, expression: &Expression, type_hint: Option<&TreePath>) -> Result<Constant, DMError> {
         Ok(match expression {
             Expression::Base { term, follow } => {
                 let base_type_hint = if follow.is_empty() {
@@ -583,30 +926,32 @@ impl<'a> ConstantFolder<'a> {
                 } else {
                     None
                 };
-                let mut term = self.term(term.elem, base_type_hint)?;
-                for each in Vec::from(follow).into_iter() {
-                    term = self.follow(term, each.elem)?;
+                let mut term = self.term(&term.elem, base_type_hint)?;
+                for each in follow.iter() {
+                    term = self.follow(term, &each.elem)?;
                 }
                 term
             },
             Expression::BinaryOp { op, lhs, rhs } => {
-                let lhs = self.expr(*lhs, None)?;
-                let rhs = self.expr(*rhs, None)?;
-                self.binary(lhs, rhs, op)?
+                let lhs = self.expr(lhs, None)?;
+                let rhs = self.expr(rhs, None)?;
+                self.binary(lhs, rhs, *op)?
             },
             Expression::TernaryOp { cond, if_, else_ } => {
-                match self.expr(*cond, None)?.to_bool() {
-                    true => self.expr(*if_, type_hint)?,
-                    false => self.expr(*else_, type_hint)?,
+                match self.expr(cond, None)?.to_bool() {
+                    true => self.expr(if_, type_hint)?,
+                    false => self.expr(else_, type_hint)?,
                 }
             },
             Expression::AssignOp { .. } => return Err(self.error("non-constant assignment")),
+            // The parse error has already been reported.
+            Expression::Error { .. } => Constant::Null(None),
         })
     }
 
     /// list of expressions, keyword arguments disallowed
     #[allow(dead_code)]
-    fn expr_vec(&mut self, v: Vec<Expression>) -> Result<Vec<Constant>, DMError> {
+    fn expr_vec(&mut self, v: &[Expression]) -> Result<Vec<Constant>, DMError> {
         let mut out = Vec::with_capacity(v.len());
         for each in v {
             out.push(self.expr(each, None)?);
@@ -615,29 +960,32 @@ impl<'a> ConstantFolder<'a> {
     }
 
     /// arguments or keyword arguments
-    fn arguments(&mut self, v: Box<[Expression]>) -> Result<Box<Arguments>, DMError> {
+    fn arguments(&mut self, v: &[Expression]) -> Result<Box<Arguments>, DMError> {
         let mut out = Vec::with_capacity(v.len());
-        for each in Vec::from(v).into_iter() {
+        for each in v {
             out.push(match each {
                 // handle associations
                 Expression::AssignOp {
                     op: AssignOp::Assign,
                     lhs,
                     rhs,
-                } => {
-                    let key = match Term::from(*lhs) {
-                        Term::Ident(ident) => Constant::String(ident.into()),
-                        other => self.term(other, None)?,
-                    };
-                    (key, Some(self.expr(*rhs, None)?))
-                },
+                } => (self.association_key(lhs)?, Some(self.expr(rhs, None)?)),
                 key => (self.expr(key, None)?, None),
             });
         }
         Ok(out.into())
     }
 
-    fn follow(&mut self, term: Constant, follow: Follow) -> Result<Constant, DMError> {
+    /// The key of an association, where a bare identifier is a string.
+    fn association_key(&mut self, lhs: &Expression) -> Result<Constant, DMError> {
+        match lhs.as_term() {
+            Some(Term::Ident(ident)) => Ok(Constant::String(ident.as_str().into())),
+            Some(Term::Expr(inner)) => self.association_key(inner),
+            _ => self.expr(lhs, None),
+        }
+    }
+
+    fn follow(&mut self, term: Constant, follow: &Follow) -> Result<Constant, DMError> {
         match (term, follow) {
             // Meant to handle the GLOB.SCI_FREQ case:
             //     /datum/globals/var/const/SCI_FREQ = 1351
@@ -652,11 +1000,11 @@ impl<'a> ConstantFolder<'a> {
                     full_path.push_str(each);
                 }
                 match self.tree.as_mut().and_then(|t| t.find(&full_path)).map(|t| t.index()) {
-                    Some(idx) => self.recursive_lookup(idx, &field_name, true),
+                    Some(idx) => self.recursive_lookup(idx, field_name, true),
                     None => Err(self.error(format!("unknown typepath {full_path}"))),
                 }
             }
-            (term, Follow::Unary(op)) => self.unary(term, op),
+            (term, Follow::Unary(op)) => self.unary(term, *op),
             (term, Follow::StaticField(field)) => {
                 let Constant::Prefab(read_from) = term else {
                     return Err(self.error(format!("non typepath {term} used with ::")))
@@ -670,7 +1018,7 @@ impl<'a> ConstantFolder<'a> {
                 let Some(real_type) = tree.find(FormatTreePath(&read_from.path).to_string().as_str()) else {
                     return Err(self.error(format!("{} was not a valid type", FormatTreePath(&read_from.path))))
                 };
-                self.recursive_lookup(real_type.index(), &field, false)
+                self.recursive_lookup(real_type.index(), field, false)
             },
             (term, Follow::ProcReference(field)) => {
                 let Constant::Prefab(read_from) = term else {
@@ -685,7 +1033,7 @@ impl<'a> ConstantFolder<'a> {
                 let Some(real_type) = tree.find(FormatTreePath(&read_from.path).to_string().as_str()) else {
                     return Err(self.error(format!("{} was not a valid type", FormatTreePath(&read_from.path))))
                 };
-                self.proc_ref_lookup(real_type.index(), &field)
+                self.proc_ref_lookup(real_type.index(), field)
             },
             (term, follow) => Err(self.error(format!("non-constant expression follower: {term} {follow:?}"))),
         }
@@ -697,7 +1045,10 @@ impl<'a> ConstantFolder<'a> {
         Ok(match (op, term) {
             // int ops
             (UnaryOp::Neg, Float(i)) => Float(-i),
-            (UnaryOp::BitNot, Float(f)) => Constant::from_bit_op(!(f as u32)),
+            (UnaryOp::BitNot, Float(f)) => match Constant::to_bit_operand(f) {
+                Some(bits) => Constant::from_bit_op(!bits),
+                None => return Err(self.error(format!("non-integer operand to bitwise operation: {}", op.around(&Float(f))))),
+            },
             (UnaryOp::Not, c) => Constant::from(!c.to_bool()),
             // float ops
             // unsupported
@@ -742,18 +1093,24 @@ impl<'a> ConstantFolder<'a> {
         }
 
         macro_rules! integer {
-            ($name:ident $oper:tt) => {
+            ($name:ident |$lhs:ident, $rhs:ident| $value:expr) => {
                 match (op, lhs, rhs) {
-                    (BinaryOp::$name, Float(lhs), Float(rhs)) => return Ok(Constant::from_bit_op((lhs as u32) $oper (rhs as u32))),
+                    (BinaryOp::$name, Float(lhs_), Float(rhs_)) => {
+                        return match (Constant::to_bit_operand(lhs_), Constant::to_bit_operand(rhs_)) {
+                            (Some($lhs), Some($rhs)) => Ok(Constant::from_bit_op($value)),
+                            _ => Err(self.error(format!("non-integer operand to bitwise operation: {} {op} {}", Float(lhs_), Float(rhs_)))),
+                        };
+                    }
                     (_, lhs_, rhs_) => { lhs = lhs_; rhs = rhs_; }
                 }
             }
         }
-        integer!(BitOr |);
-        integer!(BitAnd &);
-        integer!(BitXor ^);
-        integer!(LShift <<);
-        integer!(RShift >>);
+        integer!(BitOr |lhs, rhs| lhs | rhs);
+        integer!(BitAnd |lhs, rhs| lhs & rhs);
+        integer!(BitXor |lhs, rhs| lhs ^ rhs);
+        // shifting past the width of the value leaves nothing behind
+        integer!(LShift |lhs, rhs| lhs.checked_shl(rhs).unwrap_or(0));
+        integer!(RShift |lhs, rhs| lhs.checked_shr(rhs).unwrap_or(0));
 
         match (op, lhs, rhs) {
             (BinaryOp::Add, String(lhs), String(rhs)) => Ok(String((std::string::String::from(lhs) + &rhs).into())),
@@ -765,11 +1122,11 @@ impl<'a> ConstantFolder<'a> {
         }
     }
 
-    fn term(&mut self, term: Term, type_hint: Option<&TreePath>) -> Result<Constant, DMError> {
+    fn term(&mut self, term: &Term, type_hint: Option<&TreePath>) -> Result<Constant, DMError> {
         Ok(match term {
             Term::Null => Constant::Null(type_hint.cloned()),
             Term::NewPrefab { prefab, args } => Constant::New {
-                type_: Some(Box::new(self.prefab(*prefab)?)),
+                type_: Some(Box::new(self.prefab(prefab)?)),
                 args: match args {
                     Some(args) => Some(self.arguments(args)?),
                     None => None,
@@ -784,7 +1141,7 @@ impl<'a> ConstantFolder<'a> {
             },
             Term::NewMiniExpr { .. } => return Err(self.error("non-constant new expression")),
             Term::List(vec) => Constant::List(self.arguments(vec)?),
-            Term::Call(ident, args) => match &*ident {
+            Term::Call(ident, args) => match ident.as_str() {
                 // constructors which remain as they are
                 "matrix" => Constant::Call(ConstFn::Matrix, self.arguments(args)?),
                 "newlist" => Constant::Call(ConstFn::Newlist, self.arguments(args)?),
@@ -798,6 +1155,23 @@ impl<'a> ConstantFolder<'a> {
                 "cos" => self.trig_op(args, f32::cos)?,
                 "arcsin" => self.trig_op(args, f32::asin)?,
                 "arccos" => self.trig_op(args, f32::acos)?,
+                "min" => self.extremum(ident, args, f32::min)?,
+                "max" => self.extremum(ident, args, f32::max)?,
+                "abs" => Constant::Float(self.numeric_args(ident, args, 1..=1)?[0].abs()),
+                "sqrt" => {
+                    let value = self.numeric_args(ident, args, 1..=1)?[0];
+                    if value < 0. {
+                        return Err(self.error(format!("sqrt() of negative number {value}")));
+                    }
+                    Constant::Float(value.sqrt())
+                }
+                "round" => match *self.numeric_args(ident, args, 1..=2)? {
+                    // with no multiple to round to, BYOND rounds down
+                    [value] => Constant::Float(value.floor()),
+                    [_, 0.] => return Err(self.error("round() to a multiple of 0")),
+                    [value, multiple] => Constant::Float((value / multiple + 0.5).floor() * multiple),
+                    _ => unreachable!(),
+                },
                 "rgb" => Constant::String(self.rgb(args)?.into()),
                 "defined" if self.defines.is_some() => {
                     let defines = self.defines.unwrap();  // annoying, but keeps the match clean
@@ -838,12 +1212,13 @@ impl<'a> ConstantFolder<'a> {
                 // other functions are no-goes
                 _ => return Err(self.error(format!("non-constant function call: {ident}"))),
             },
-            Term::Prefab(prefab) => Constant::Prefab(Box::new(self.prefab(*prefab)?)),
+            Term::Prefab(prefab) => Constant::Prefab(Box::new(self.prefab(prefab)?)),
             Term::Ident(ident) => match ident.as_str() {
                 // We need to handle type and parent_type here
                 // They technically resolve to their respective values only in type defs when using ::
                 // But that's annoying so let's not
                 "type" => {
+                    self.depends_on_type = true;
                     if let Some(obj_tree) = &self.tree {
                         let typeval = TypeRef::new(obj_tree, self.ty).get();
                         let pop = Pop::from(typeval.path.split('/').filter(|elem| !elem.is_empty()).map(|segment| segment.to_string()).collect::<TreePath>());
@@ -853,6 +1228,7 @@ impl<'a> ConstantFolder<'a> {
                     }
                 },
                 "parent_type" => {
+                    self.depends_on_type = true;
                     if let Some(obj_tree) = &self.tree {
                         let typeref = TypeRef::new(obj_tree, self.ty);
                         let Some(parent_type) = typeref.parent_type() else {
@@ -866,12 +1242,31 @@ impl<'a> ConstantFolder<'a> {
                 }
                 _ => self.ident(ident, false)?,
             },
-            Term::String(v) => Constant::String(v.into()),
-            Term::Resource(v) => Constant::Resource(v.into()),
-            Term::Int(v) => Constant::Float(v as f32),
-            Term::Float(v) => Constant::from(v),
-            Term::Expr(expr) => self.expr(*expr, type_hint)?,
+            Term::String(v) => Constant::String(v.as_str().into()),
+            Term::InterpString(first, parts) => {
+                if has_text_macro(first) || parts.iter().any(|(_, text)| has_text_macro(text)) {
+                    return Err(self.error("non-constant string interpolation: text macros are not supported"));
+                }
+                let mut result = first.as_str().to_owned();
+                for (expr, text) in parts.iter() {
+                    if let Some(expr) = expr {
+                        match self.expr(expr, None)? {
+                            Constant::String(embedded) => result.push_str(&embedded),
+                            Constant::Float(value) => result.push_str(&crate::lexer::FormatFloat(value).to_string()),
+                            Constant::Null(_) => {}
+                            other => return Err(self.error(format!("non-constant string interpolation: [{other}]"))),
+                        }
+                    }
+                    result.push_str(text);
+                }
+                Constant::String(result.into())
+            }
+            Term::Resource(v) => Constant::Resource(v.as_str().into()),
+            Term::Int(v) => Constant::Float(*v as f32),
+            Term::Float(v) => Constant::from(*v),
+            Term::Expr(expr) => self.expr(expr, type_hint)?,
             Term::__TYPE__ => {
+                self.depends_on_type = true;
                 if let Some(obj_tree) = &self.tree {
                     let typeval = TypeRef::new(obj_tree, self.ty).get();
                     let pop = Pop::from(typeval.path.split('/').filter(|elem| !elem.is_empty()).map(|segment| segment.to_string()).collect::<TreePath>());
@@ -891,18 +1286,68 @@ impl<'a> ConstantFolder<'a> {
         })
     }
 
-    fn trig_op(&mut self, args: Box<[Expression]>, op: fn(f32) -> f32) -> Result<Constant, DMError> {
+    fn trig_op(&mut self, args: &[Expression], op: fn(f32) -> f32) -> Result<Constant, DMError> {
         if args.len() != 1 {
             Err(self.error(format!("trig function requires exactly 1 argument, instead found {}", args.len())))
-        } else if let Some(f) = self.expr(Vec::from(args).swap_remove(0), None)?.to_float() {
+        } else if let Some(f) = self.expr(&args[0], None)?.to_float() {
             Ok(Constant::Float(op(f)))
         } else {
             Err(self.error("trig function requires numeric argument"))
         }
     }
 
-    fn prefab(&mut self, prefab: Prefab) -> Result<Pop, DMError> {
-        let vars = self.vars(prefab.vars.into())?;
+    /// Evaluate the arguments of a math function, which must all be numbers.
+    fn numeric_args(&mut self, name: &str, args: &[Expression], count: std::ops::RangeInclusive<usize>) -> Result<Vec<f32>, DMError> {
+        if !count.contains(&args.len()) {
+            let expected = match (count.start(), count.end()) {
+                (1, 1) => "exactly 1 argument".to_owned(),
+                (start, end) if start == end => format!("exactly {start} arguments"),
+                (start, end) => format!("{start} to {end} arguments"),
+            };
+            return Err(self.error(format!("{name}() requires {expected}, instead found {}", args.len())));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            match self.expr(arg, None)? {
+                Constant::Float(f) => values.push(f),
+                other => return Err(self.error(format!("non-numeric argument to {name}(): {other}"))),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluate `min()` or `max()`, which take any number of arguments, or a
+    /// single list whose elements are compared.
+    fn extremum(&mut self, name: &str, args: &[Expression], op: fn(f32, f32) -> f32) -> Result<Constant, DMError> {
+        let values = if args.len() == 1 {
+            match self.expr(&args[0], None)? {
+                Constant::Float(f) => vec![Constant::Float(f)],
+                Constant::List(list) => list.iter().map(|(key, _)| key.clone()).collect(),
+                other => vec![other],
+            }
+        } else {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                values.push(self.expr(arg, None)?);
+            }
+            values
+        };
+
+        let mut result = None;
+        for value in values {
+            let Constant::Float(f) = value else {
+                return Err(self.error(format!("non-numeric argument to {name}(): {value}")));
+            };
+            result = Some(result.map_or(f, |prev| op(prev, f)));
+        }
+        match result {
+            Some(f) => Ok(Constant::Float(f)),
+            None => Err(self.error(format!("{name}() requires at least 1 argument"))),
+        }
+    }
+
+    fn prefab(&mut self, prefab: &Prefab) -> Result<Pop, DMError> {
+        let vars = self.vars(&prefab.vars)?;
 
         // If the path is all slashes, it's absolute, and doesn't need to be
         // further resolved.
@@ -912,6 +1357,7 @@ impl<'a> ConstantFolder<'a> {
         }
 
         // Otherwise, resolve it against our object tree, then stringify it.
+        self.depends_on_type = true;
         let tree = match self.tree.as_ref() {
             Some(tree) => tree,
             None => return Err(self.error(format!(
@@ -930,19 +1376,20 @@ impl<'a> ConstantFolder<'a> {
         Ok(Pop { path, vars })
     }
 
-    fn vars(&mut self, input: Vec<(Ident2, Expression)>) -> Result<IndexMap<Ident, Constant, RandomState>, DMError> {
+    fn vars(&mut self, input: &[(Ident2, Expression)]) -> Result<IndexMap<Ident, Constant, RandomState>, DMError> {
         // Visit the vars recursively.
         let mut vars = IndexMap::with_hasher(RandomState::default());
         for (k, v) in input {
             // TODO: find a type annotation by looking up 'k' on the prefab's type
-            vars.insert(String::from(k), self.expr(v, None)?);
+            vars.insert(k.as_str().to_owned(), self.expr(v, None)?);
         }
         Ok(vars)
     }
 
-    fn ident(&mut self, ident: Ident, must_be_const: bool) -> Result<Constant, DMError> {
+    fn ident(&mut self, ident: &str, must_be_const: bool) -> Result<Constant, DMError> {
+        self.depends_on_type = true;
         let ty = self.ty;
-        self.recursive_lookup(ty, &ident, must_be_const)
+        self.recursive_lookup(ty, ident, must_be_const)
     }
 
     fn recursive_lookup(&mut self, ty: NodeIndex, ident: &str, must_be_const: bool) -> Result<Constant, DMError> {
@@ -953,7 +1400,7 @@ impl<'a> ConstantFolder<'a> {
                 return Err(self.error(format!("cannot reference variable {ident:?} in this context")));
             }
             let tree = self.tree.as_mut().unwrap();
-            match constant_ident_lookup(tree, ty, ident, must_be_const, self.context)
+            match constant_ident_lookup(tree, ty, ident, must_be_const, self.context, self.cache.as_deref_mut())
                 .map_err(|e| e.with_location(location))?
             {
                 ConstLookup::Found(v) => return Ok(v),
@@ -979,7 +1426,7 @@ impl<'a> ConstantFolder<'a> {
         Ok(Constant::Prefab(Box::new(Pop::from(Box::from(path_elements)))))
     }
 
-    fn rgb(&mut self, args: Box<[Expression]>) -> Result<String, DMError> {
+    fn rgb(&mut self, args: &[Expression]) -> Result<String, DMError> {
         enum ColorSpace {
             Rgb = 0,
             Hsv = 1,
@@ -4,12 +4,15 @@ use std::fmt;
 
 use get_size::GetSize;
 use get_size_derive::GetSize;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// A collection of documentation comments targeting the same item.
-#[derive(Default, Clone, Debug, PartialEq, GetSize, Serialize)]
+#[derive(Default, Clone, Debug, PartialEq, GetSize, Serialize, Deserialize)]
 pub struct DocCollection {
     elems: Vec<DocComment>,
+    // Only object tree builtins carry reference hashes, and those are never
+    // round-tripped, so there is no need to allocate a `&'static str` here.
+    #[serde(skip_deserializing)]
     pub builtin_docs: BuiltinDocs,
 }
 
@@ -89,6 +92,29 @@ impl Serialize for DocComment {
     }
 }
 
+impl<'de> Deserialize<'de> for DocComment {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de> {
+        let string = String::deserialize(deserializer)?;
+        let (kind, target, text) = if let Some(rest) = string.strip_prefix("/**") {
+            (CommentKind::Block, DocTarget::FollowingItem, rest.strip_suffix("*/"))
+        } else if let Some(rest) = string.strip_prefix("/*!") {
+            (CommentKind::Block, DocTarget::EnclosingItem, rest.strip_suffix("*/"))
+        } else if let Some(rest) = string.strip_prefix("///") {
+            (CommentKind::Line, DocTarget::FollowingItem, Some(rest))
+        } else if let Some(rest) = string.strip_prefix("//!") {
+            (CommentKind::Line, DocTarget::EnclosingItem, Some(rest))
+        } else {
+            (CommentKind::Line, DocTarget::FollowingItem, None)
+        };
+        match text {
+            Some(text) => Ok(DocComment { kind, target, text: text.to_owned() }),
+            None => Err(serde::de::Error::custom(format!("malformed doc comment: {string:?}"))),
+        }
+    }
+}
+
 impl DocComment {
     /// Construct an empty DocComment with the given properties.
     pub fn new(kind: CommentKind, target: DocTarget) -> DocComment {
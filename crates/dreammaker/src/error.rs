@@ -3,13 +3,14 @@
 use std::cell::{Ref, RefCell, RefMut};
 use foldhash::HashMap;
 use serde::ser::SerializeStruct;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::{error, fmt, io};
 
 use get_size::GetSize;
 use get_size_derive::GetSize;
 use termcolor::{Color, ColorSpec};
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::config::Config;
 
@@ -25,6 +26,14 @@ impl Serialize for FileId {
     }
 }
 
+impl<'de> Deserialize<'de> for FileId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        u16::deserialize(deserializer).map(FileId)
+    }
+}
+
 impl GetSize for FileId {}
 
 const FILEID_BUILTINS: FileId = FileId(0x0000);
@@ -56,6 +65,8 @@ pub struct Context {
     /// Warning config
     config: Config,
     print_severity: Option<Severity>,
+    /// In-source directives enabling or disabling diagnostics.
+    lint_controls: RefCell<Vec<LintControl>>,
 
     io_time: std::cell::Cell<std::time::Duration>,
 }
@@ -158,6 +169,11 @@ impl Context {
         &self.config
     }
 
+    /// Replace the configuration, as though it had been read from a file.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
     /// Set a severity at and above which errors will be printed immediately.
     pub fn set_print_severity(&mut self, print_severity: Option<Severity>) {
         self.print_severity = print_severity;
@@ -186,6 +202,9 @@ impl Context {
         let Some(error) = self.config.set_configured_severity(error) else {
             return // errortype is disabled
         };
+        if self.is_suppressed(&error) {
+            return
+        }
         // ignore errors with severity above configured level
         if !self.config.registerable_error(&error) {
             return
@@ -200,11 +219,70 @@ impl Context {
         self.errors.borrow_mut().push(error);
     }
 
+    /// Record an in-source lint control directive.
+    ///
+    /// A directive which disables a rule also removes matching diagnostics
+    /// which were registered before it was seen.
+    pub fn register_lint_control(&self, control: LintControl) {
+        let enable = control.enable;
+        self.lint_controls.borrow_mut().push(control);
+        if !enable {
+            self.errors.borrow_mut().retain(|error| !self.is_suppressed(error));
+        }
+    }
+
+    /// Forget the lint control directives found in a file, before it is
+    /// lexed again.
+    pub fn clear_lint_controls(&self, file: FileId) {
+        self.lint_controls.borrow_mut().retain(|control| control.directive.start.file != file);
+    }
+
+    /// Access the list of lint control directives seen so far.
+    pub fn lint_controls(&self) -> Ref<'_, [LintControl]> {
+        Ref::map(self.lint_controls.borrow(), |x| &**x)
+    }
+
+    /// Check whether a diagnostic is disabled by a lint control directive.
+    ///
+    /// Of the directives for its errortype covering its location, the one
+    /// with the narrowest range wins, preferring the later one on ties.
+    pub fn is_suppressed(&self, error: &DMError) -> bool {
+        let Some(errortype) = error.errortype() else {
+            return false
+        };
+        let location = error.location();
+        let controls = self.lint_controls.borrow();
+        let mut best: Option<&LintControl> = None;
+        for control in controls.iter() {
+            if control.rule != errortype || !control.range.contains(&location) {
+                continue;
+            }
+            if best.is_none_or(|prev| control.lines() <= prev.lines()) {
+                best = Some(control);
+            }
+        }
+        best.is_some_and(|control| !control.enable)
+    }
+
     /// Access the list of diagnostics generated so far.
     pub fn errors(&self) -> Ref<[DMError]> {
         Ref::map(self.errors.borrow(), |x| &**x)
     }
 
+    /// Access the diagnostics generated so far, sorted by location.
+    ///
+    /// Each phase registers its diagnostics as it runs, so the list is not
+    /// otherwise in order. Diagnostics at the same location keep the order in
+    /// which they were registered. The list itself is left as it is.
+    pub fn sorted_errors(&self) -> Vec<Ref<'_, DMError>> {
+        let errors = self.errors();
+        let mut order: Vec<usize> = (0..errors.len()).collect();
+        order.sort_by_key(|&i| errors[i].location());
+        order.into_iter()
+            .map(|i| Ref::map(Ref::clone(&errors), |errors| &errors[i]))
+            .collect()
+    }
+
     /// Mutably access the diagnostics list. Dangerous.
     #[doc(hidden)]
     pub fn errors_mut(&self) -> RefMut<Vec<DMError>> {
@@ -284,6 +362,63 @@ impl Context {
 // ----------------------------------------------------------------------------
 // Location handling
 
+/// An in-source directive such as `// SDMM: disable unused_var`, turning a
+/// diagnostic off or back on.
+///
+/// `disable` and `enable` cover the line the comment is on, while
+/// `disable-file` and `enable-file` cover the whole file. Several rules may
+/// be listed, separated by commas or spaces, and `-` may be written for `_`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintControl {
+    /// The errortype this directive applies to.
+    pub rule: String,
+    pub enable: bool,
+    /// The region of code this directive applies to.
+    pub range: Range<Location>,
+    /// The location of the comment containing the directive.
+    pub directive: Range<Location>,
+}
+
+impl LintControl {
+    /// Parse the text of a line comment, after the `//`, as lint control
+    /// directives.
+    ///
+    /// Returns `None` if the comment isn't a directive at all, and an error
+    /// if it is one but can't be understood.
+    pub fn parse(directive: Range<Location>, text: &str) -> Option<Result<Vec<LintControl>, DMError>> {
+        let text = text.trim_start().strip_prefix("SDMM:")?;
+        let mut words = text.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty());
+        let file = directive.start.file;
+        let line = directive.start.line;
+        let (enable, range) = match words.next() {
+            Some("disable") => (false, Location { file, line, column: 0 }..Location { file, line: line + 1, column: 0 }),
+            Some("enable") => (true, Location { file, line, column: 0 }..Location { file, line: line + 1, column: 0 }),
+            Some("disable-file") => (false, Location { file, line: 0, column: 0 }..Location { file, line: !0, column: !0 }),
+            Some("enable-file") => (true, Location { file, line: 0, column: 0 }..Location { file, line: !0, column: !0 }),
+            _ => {
+                return Some(Err(DMError::new(directive.start, "unknown SDMM directive")
+                    .set_severity(Severity::Warning)
+                    .with_note(directive.start, "expected 'disable', 'enable', 'disable-file', or 'enable-file'")));
+            }
+        };
+        let controls: Vec<_> = words.map(|rule| LintControl {
+            rule: rule.replace('-', "_"),
+            enable,
+            range: range.clone(),
+            directive: directive.clone(),
+        }).collect();
+        if controls.is_empty() {
+            return Some(Err(DMError::new(directive.start, "SDMM directive names no rules")
+                .set_severity(Severity::Warning)));
+        }
+        Some(Ok(controls))
+    }
+
+    fn lines(&self) -> u32 {
+        self.range.end.line - self.range.start.line
+    }
+}
+
 /// File, line, and column information for an error.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Default, GetSize)]
 pub struct Location {
@@ -291,7 +426,7 @@ pub struct Location {
     pub file: FileId,
     /// The line number, starting at 1.
     pub line: u32,
-    /// The column number, starting at 1.
+    /// The column number, starting at 1, counted as `lexer::COLUMN_UNIT`.
     pub column: u16,
 }
 
@@ -307,6 +442,26 @@ impl Serialize for Location {
     }
 }
 
+impl<'de> Deserialize<'de> for Location {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        // Mirrors the field names used by the `Serialize` impl above.
+        #[derive(Deserialize)]
+        struct Fields {
+            #[serde(rename = "FileID")]
+            file: FileId,
+            #[serde(rename = "Line")]
+            line: u32,
+            #[serde(rename = "Column")]
+            column: u16,
+        }
+
+        let Fields { file, line, column } = Fields::deserialize(deserializer)?;
+        Ok(Location { file, line, column })
+    }
+}
+
 impl Location {
     pub fn builtins() -> Location {
         Location { file: FILEID_BUILTINS, line: 1, column: 1 }
@@ -318,6 +473,9 @@ impl Location {
     }
 
     /// Return the predecessor of this `Location`.
+    ///
+    /// A zero column steps back to the end of the previous line rather than
+    /// wrapping within the line.
     pub fn pred(mut self) -> Location {
         if self.column != 0 {
             self.column -= 1;
@@ -333,11 +491,40 @@ impl Location {
             self.line = !0;
             self.file.0 -= 1;
         } else {
-            panic!("cannot take pred() of lowest possible Location")
+            // The lowest possible Location is its own predecessor.
         }
         self
     }
 
+    /// Return the successor of this `Location`, the inverse of `pred()`.
+    pub fn succ(mut self) -> Location {
+        if self.column != !0 {
+            self.column += 1;
+        } else if self.line != !0 {
+            self.column = 0;
+            self.line += 1;
+        } else if self.file == FILEID_BAD {
+            // As in pred(), leave the highest possible Location alone.
+        } else {
+            self.column = 0;
+            self.line = 0;
+            self.file.0 += 1;
+        }
+        self
+    }
+
+    /// Build the half-open range from this `Location` up to `end`.
+    pub fn span_to(self, end: Location) -> Range<Location> {
+        debug_assert!(self.file == end.file && self <= end, "bad span {self:?} to {end:?}");
+        self..end
+    }
+
+    /// Check whether `other` is in the same file as this `Location` and at or
+    /// after it, i.e. within a span starting here and running to end of file.
+    pub fn contains(&self, other: Location) -> bool {
+        self.file == other.file && *self <= other
+    }
+
     pub fn add_columns(mut self, num: u16) -> Location {
         self.column += num;
         self
@@ -348,6 +535,121 @@ impl Location {
     }
 }
 
+/// An index of line starts within a file's source text, for converting
+/// `Location`s to and from byte offsets and UTF-16 code units.
+///
+/// `Location` columns count bytes, while the Language Server Protocol counts
+/// UTF-16 code units, so the two disagree on lines containing non-ASCII text.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    file: FileId,
+    text: String,
+    line_starts: Vec<usize>,
+    // the length of the stripped BOM, which byte offsets still count
+    bom: usize,
+}
+
+impl LineIndex {
+    /// Index the given source text, which should be the same text the lexer
+    /// read for `file`.
+    pub fn new(file: FileId, text: &str) -> LineIndex {
+        // The lexer skips a UTF-8 BOM without counting it as a column.
+        let stripped = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let bom = text.len() - stripped.len();
+        let text = stripped.to_owned();
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { file, text, line_starts, bom }
+    }
+
+    /// The file this index was built for.
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// Get the text of a line, starting at 1, without its line ending.
+    fn line(&self, line: u32) -> Option<&str> {
+        let idx = usize::try_from(line).ok()?.checked_sub(1)?;
+        let start = *self.line_starts.get(idx)?;
+        let end = self.line_starts.get(idx + 1).map_or(self.text.len(), |&next| next - 1);
+        Some(&self.text[start..end])
+    }
+
+    /// Convert a byte offset into the text this index was built from to a
+    /// `Location`.
+    ///
+    /// Offsets past the end of the text are clamped to it, and offsets within
+    /// a BOM to the start of the first line.
+    pub fn offset_to_location(&self, offset: usize) -> Location {
+        let offset = offset.saturating_sub(self.bom).min(self.text.len());
+        let idx = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        Location {
+            file: self.file,
+            line: idx as u32 + 1,
+            column: u16::try_from(offset - self.line_starts[idx] + 1).unwrap_or(!0),
+        }
+    }
+
+    /// Convert a `Location` to a byte offset into the text this index was
+    /// built from.
+    ///
+    /// Columns past the end of a line are clamped to it, and lines past the
+    /// end of the text to the end of the text. The location's file is not
+    /// checked.
+    pub fn location_to_offset(&self, location: Location) -> usize {
+        let idx = (location.line as usize).saturating_sub(1);
+        let offset = match self.line_starts.get(idx) {
+            Some(&start) => {
+                let end = self.line_starts.get(idx + 1).map_or(self.text.len(), |&next| next - 1);
+                (start + usize::from(location.column).saturating_sub(1)).min(end)
+            }
+            None => self.text.len(),
+        };
+        offset + self.bom
+    }
+
+    /// Convert a `Location` to a zero-indexed line and UTF-16 column.
+    ///
+    /// Returns `None` if the location belongs to another file or is past the
+    /// end of the text. Columns past the end of a line are clamped to it.
+    pub fn to_utf16(&self, location: Location) -> Option<(u32, u32)> {
+        if location.file != self.file {
+            return None;
+        }
+        let line = self.line(location.line)?;
+        let mut byte = (usize::from(location.column).saturating_sub(1)).min(line.len());
+        while !line.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        let column = line[..byte].encode_utf16().count();
+        Some((location.line - 1, column as u32))
+    }
+
+    /// Convert a zero-indexed line and UTF-16 column to a `Location`.
+    ///
+    /// Returns `None` if the line is past the end of the text. Columns which
+    /// fall past the end of the line or inside a surrogate pair are clamped
+    /// to the nearest preceding character.
+    pub fn from_utf16(&self, line: u32, column: u32) -> Option<Location> {
+        let line = line.checked_add(1)?;
+        let text = self.line(line)?;
+        let mut remaining = column as usize;
+        let mut byte = text.len();
+        for (i, ch) in text.char_indices() {
+            if remaining < ch.len_utf16() {
+                byte = i;
+                break;
+            }
+            remaining -= ch.len_utf16();
+        }
+        Some(Location {
+            file: self.file,
+            line,
+            column: u16::try_from(byte + 1).unwrap_or(!0),
+        })
+    }
+}
+
 /// A trait for types which may yield location information.
 pub(crate) trait HasLocation {
     /// Get the current location of this parsing stage.
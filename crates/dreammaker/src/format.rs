@@ -0,0 +1,291 @@
+//! Canonical source formatter.
+//!
+//! Formatting works line by line over the output of `lexer::lex_file`, so
+//! comments are kept where they were written. Indentation is recomputed the
+//! same way the indentation processor reads it, so the formatted code parses
+//! to the same blocks as the original.
+use std::ops::Range;
+
+use crate::lexer::{lex_file, LexedToken, Punctuation, Token};
+use crate::FileId;
+
+/// Options for `format_source`.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Whether to put spaces around assignment, comparison, and logical
+    /// operators. Other operators keep whatever spacing they were written
+    /// with, as `-` and friends may be unary.
+    pub space_operators: bool,
+    /// The most consecutive blank lines to keep.
+    pub max_blank_lines: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            space_operators: true,
+            max_blank_lines: 2,
+        }
+    }
+}
+
+/// Format DM source code canonically.
+///
+/// * Code is indented with one tab per block.
+/// * Lines continued inside parentheses are indented one tab per open
+///   parenthesis past the line which opened them.
+/// * Spaces inside brackets and before commas and semicolons are removed, and
+///   one space follows each comma and semicolon.
+/// * A `{` which ends a line is preceded by one space.
+/// * Trailing whitespace and excess blank lines are removed.
+///
+/// Preprocessor directives, including multi-line macros, strings with
+/// embedded expressions, `{"..."}` blocks, comments, and code continued with
+/// a backslash are left as written. Source which doesn't lex cleanly is
+/// returned unchanged.
+///
+/// Formatting already-formatted source returns it unchanged.
+pub fn format_source(source: &str, opts: FormatOptions) -> String {
+    let (bom, body) = match source.strip_prefix('\u{feff}') {
+        Some(body) => ("\u{feff}", body),
+        None => ("", source),
+    };
+    let lexed = lex_file(FileId::default(), body);
+    if !lexed.errors.is_empty() {
+        return source.to_owned();
+    }
+
+    let mut formatter = Formatter {
+        source: body,
+        opts: &opts,
+        indents: None,
+        parens: 0,
+        paren_level: 0,
+    };
+    let mut output: Vec<String> = Vec::new();
+    let mut blank_lines = 0;
+    for (tokens, segment) in split_lines(&lexed.tokens) {
+        let line = formatter.line(tokens, segment);
+        if line.is_empty() {
+            blank_lines += 1;
+            if blank_lines > opts.max_blank_lines {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        output.push(line);
+    }
+    while output.last().is_some_and(|line| line.is_empty()) {
+        output.pop();
+    }
+
+    let mut result = String::from(bom);
+    for line in output {
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Split tokens at the newlines which end lines, pairing the tokens of each
+/// line with the source it covers. Newlines inside embedded expressions
+/// don't end the line of the string they're in.
+fn split_lines(tokens: &[LexedToken]) -> Vec<(&[LexedToken], Range<usize>)> {
+    let mut lines = Vec::new();
+    let mut interp_depth = 0usize;
+    let mut first = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token {
+            Token::InterpStringBegin(_) => interp_depth += 1,
+            Token::InterpStringEnd(_) => interp_depth = interp_depth.saturating_sub(1),
+            Token::Punct(Punctuation::Newline) if interp_depth == 0 => {
+                lines.push((&tokens[first..i], start..token.text.start));
+                first = i + 1;
+                start = token.text.end;
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    opts: &'a FormatOptions,
+    // Mirrors the indentation processor: the spaces per indent and the
+    // number of indents, if indented.
+    indents: Option<(usize, usize)>,
+    parens: usize,
+    // The level of the line which opened the outermost parenthesis.
+    paren_level: usize,
+}
+
+impl Formatter<'_> {
+    fn level(&self) -> usize {
+        self.indents.map_or(0, |(_, indents)| indents)
+    }
+
+    fn line(&mut self, tokens: &[LexedToken], segment: Range<usize>) -> String {
+        let text = &self.source[segment.clone()];
+        let Some(first) = tokens.first() else {
+            // blank or only a comment, which the indentation processor never
+            // sees, so place it by its own indentation
+            let comment = text.trim();
+            if comment.is_empty() {
+                return String::new();
+            }
+            let spaces = leading_spaces(text);
+            let level = match self.indents {
+                Some((per_indent, _)) => spaces / per_indent,
+                None => spaces.min(1),
+            };
+            return indent(level) + comment;
+        };
+
+        if first.token == Token::Punct(Punctuation::Hash) {
+            // preprocessor directives are left alone
+            return text.trim_end().to_owned();
+        }
+
+        let lead = &self.source[segment.start..first.text.start];
+        let level = if self.parens > 0 {
+            let closing = first.token == Token::Punct(Punctuation::RParen);
+            self.paren_level + self.parens - usize::from(closing)
+        } else {
+            match first.token {
+                Token::Punct(Punctuation::LBrace) => self.level(),
+                Token::Punct(Punctuation::RBrace) => {
+                    let closing = tokens.iter().take_while(|t| t.token == Token::Punct(Punctuation::RBrace)).count();
+                    self.level().saturating_sub(closing)
+                }
+                _ => self.indent_line(leading_spaces(lead)),
+            }
+        };
+
+        for token in tokens {
+            match token.token {
+                Token::Punct(Punctuation::LBrace) => {
+                    self.indents = Some(match self.indents {
+                        None => (1, 1),
+                        Some((per_indent, indents)) => (per_indent, indents + 1),
+                    });
+                }
+                Token::Punct(Punctuation::RBrace) => {
+                    self.indents = match self.indents {
+                        None | Some((_, 1)) => None,
+                        Some((per_indent, indents)) => Some((per_indent, indents - 1)),
+                    };
+                }
+                Token::Punct(Punctuation::LParen) => {
+                    if self.parens == 0 {
+                        self.paren_level = level;
+                    }
+                    self.parens += 1;
+                }
+                Token::Punct(Punctuation::RParen) => self.parens = self.parens.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        let mut out = indent(level);
+        let comment = lead.trim();
+        if !comment.is_empty() {
+            out.push_str(comment);
+            out.push(' ');
+        }
+        self.tokens(&mut out, tokens);
+        if let Some(last) = tokens.last() {
+            let comment = self.source[last.text.end..segment.end].trim();
+            if !comment.is_empty() {
+                out.push(' ');
+                out.push_str(comment);
+            }
+        }
+        out
+    }
+
+    /// Read the indentation of a line as the indentation processor does,
+    /// returning its level.
+    fn indent_line(&mut self, spaces: usize) -> usize {
+        if spaces == 0 {
+            self.indents = None;
+            return 0;
+        }
+        let (per_indent, indents) = match self.indents {
+            None => (spaces, 1),
+            Some((per_indent, _)) => (per_indent, spaces / per_indent),
+        };
+        self.indents = Some((per_indent, indents));
+        indents
+    }
+
+    fn tokens(&self, out: &mut String, tokens: &[LexedToken]) {
+        let mut prev: Option<&LexedToken> = None;
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            // strings with embedded expressions are copied whole
+            let mut end = i;
+            if let Token::InterpStringBegin(_) = token.token {
+                let mut depth = 0;
+                for (j, each) in tokens.iter().enumerate().skip(i) {
+                    match each.token {
+                        Token::InterpStringBegin(_) => depth += 1,
+                        Token::InterpStringEnd(_) => depth -= 1,
+                        _ => {}
+                    }
+                    end = j;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+
+            if let Some(prev) = prev {
+                let gap = &self.source[prev.text.end..token.text.start];
+                if gap.contains('\n') || !gap.trim().is_empty() {
+                    out.push_str(gap);
+                } else if self.space_between(&prev.token, &token.token, !gap.is_empty(), end + 1 == tokens.len()) {
+                    out.push(' ');
+                }
+            }
+            out.push_str(&self.source[token.text.start..tokens[end].text.end]);
+            prev = Some(&tokens[end]);
+            i = end + 1;
+        }
+    }
+
+    fn space_between(&self, prev: &Token, next: &Token, had_space: bool, next_is_last: bool) -> bool {
+        use self::Punctuation::*;
+        match (prev, next) {
+            (_, Token::Punct(Comma | Semicolon | RParen | RBracket)) => false,
+            (Token::Punct(LParen | LBracket | SafeLBracket), _) => false,
+            (Token::Punct(Comma | Semicolon), _) => true,
+            (_, Token::Punct(LBrace)) if next_is_last => true,
+            _ if self.opts.space_operators && (is_spaced_operator(prev) || is_spaced_operator(next)) => true,
+            _ => had_space,
+        }
+    }
+}
+
+fn is_spaced_operator(token: &Token) -> bool {
+    use self::Punctuation::*;
+    matches!(token, Token::Punct(
+        Assign | AddAssign | SubAssign | MulAssign | DivAssign | ModAssign | FloatModAssign
+        | BitAndAssign | BitOrAssign | BitXorAssign | LShiftAssign | RShiftAssign | AndAssign
+        | OrAssign | AssignInto | Eq | NotEq | Equiv | NotEquiv | Less | Greater | LessEq
+        | GreaterEq | LessGreater | LessOrGreater | And | Or
+    ))
+}
+
+/// Count the spaces and tabs at the start of a line, each as one, as the
+/// indentation processor does.
+fn leading_spaces(text: &str) -> usize {
+    text.bytes().take_while(|&b| b == b' ' || b == b'\t').count()
+}
+
+fn indent(level: usize) -> String {
+    "\t".repeat(level)
+}
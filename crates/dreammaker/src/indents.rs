@@ -21,6 +21,14 @@ pub struct IndentProcessor<'ctx, I> {
     current_spaces: Option<usize>,
     parentheses: usize,
     eof_yielded: bool,
+
+    lenient: bool,
+    // The spaces/tabs leading the current line, and the line which opened the
+    // outermost open parenthesis.
+    line_spaces: usize,
+    paren_spaces: usize,
+    // The spaces/tabs accumulated on a line continued inside parentheses.
+    continuation_spaces: Option<usize>,
 }
 
 impl<'ctx, I> IndentProcessor<'ctx, I> where
@@ -37,9 +45,23 @@ impl<'ctx, I> IndentProcessor<'ctx, I> where
             current_spaces: None,
             parentheses: 0,
             eof_yielded: false,
+            lenient: false,
+            line_spaces: 0,
+            paren_spaces: 0,
+            continuation_spaces: None,
         }
     }
 
+    /// Guess where unclosed parentheses end, rather than continuing them to
+    /// the end of the file.
+    ///
+    /// A line inside parentheses which is indented no further than the line
+    /// that opened them, and which doesn't start with `)`, is taken to start a
+    /// new statement. For use with the parser's lenient mode.
+    pub fn enable_lenient(&mut self) {
+        self.lenient = true;
+    }
+
     #[inline]
     fn inner_next(&mut self) -> Option<LocatedToken> {
         self.inner.next()
@@ -66,6 +88,8 @@ impl<'ctx, I> IndentProcessor<'ctx, I> where
             Token::Punct(Punctuation::Newline) => {
                 if self.parentheses == 0 {
                     self.current_spaces = Some(0);
+                } else if self.lenient {
+                    self.continuation_spaces = Some(0);
                 }
                 // semicolons are placed by the first token on the next line
                 if self.eol_location.is_none() {
@@ -78,11 +102,23 @@ impl<'ctx, I> IndentProcessor<'ctx, I> where
                 if let Some(spaces) = self.current_spaces.as_mut() {
                     *spaces += 1;
                 }
+                if let Some(spaces) = self.continuation_spaces.as_mut() {
+                    *spaces += 1;
+                }
                 return;
             }
             _ => {}
         }
 
+        // in lenient mode, end parentheses which seem to have been left open
+        if let Some(spaces) = self.continuation_spaces.take()
+            && spaces <= self.paren_spaces
+            && read != Token::Punct(Punctuation::RParen)
+        {
+            self.parentheses = 0;
+            self.current_spaces = Some(spaces);
+        }
+
         // handle pre-existing braces
         match read {
             Token::Punct(Punctuation::LBrace) => self.current_spaces = None,
@@ -94,6 +130,7 @@ impl<'ctx, I> IndentProcessor<'ctx, I> where
 
         // handle indentation
         if let Some(spaces) = self.current_spaces.take() {
+            self.line_spaces = spaces;
             let (indents, new_indents);
             match self.current {
                 None => {
@@ -171,6 +208,9 @@ impl<'ctx, I> IndentProcessor<'ctx, I> where
                 };
             }
             Token::Punct(Punctuation::LParen) => {
+                if self.parentheses == 0 {
+                    self.paren_spaces = self.line_spaces;
+                }
                 self.parentheses += 1;
             }
             Token::Punct(Punctuation::RParen) => {
@@ -2,11 +2,12 @@
 use std::borrow::Cow;
 use std::fmt;
 use std::io::Read;
+use std::ops::Range;
 use std::str::FromStr;
 
 use super::ast::Ident;
 use super::docs::*;
-use super::{Context, DMError, FileId, HasLocation, Location, Severity};
+use super::{Context, DMError, FileId, HasLocation, LintControl, Location, Severity};
 
 macro_rules! table {
     (
@@ -447,6 +448,193 @@ impl LocatedToken {
     }
 }
 
+/// A change to source text: the characters in `range` are replaced by `text`.
+///
+/// The range is given in terms of the text before the edit; `range.end` is
+/// the location of the first character which is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<Location>,
+    pub text: String,
+}
+
+impl TextEdit {
+    /// The location in the edited text of the first character after the
+    /// replacement, corresponding to `range.end` in the original.
+    pub fn new_end(&self) -> Location {
+        let mut end = self.range.start;
+        match self.text.rfind('\n') {
+            Some(last) => {
+                end.line += self.text.matches('\n').count() as u32;
+                end.column = (self.text.len() - last) as u16;
+            }
+            None => end.column += self.text.len() as u16,
+        }
+        end
+    }
+
+    /// Map a location at or after `range.end` in the original text to the
+    /// edited text.
+    fn shift(&self, new_end: Location, loc: Location) -> Location {
+        let old_end = self.range.end;
+        if loc.line == old_end.line {
+            Location {
+                file: loc.file,
+                line: new_end.line,
+                column: loc.column - old_end.column + new_end.column,
+            }
+        } else {
+            Location {
+                file: loc.file,
+                line: loc.line - old_end.line + new_end.line,
+                column: loc.column,
+            }
+        }
+    }
+
+    /// The inverse of `shift`, for locations at or after `new_end`.
+    fn unshift(&self, new_end: Location, loc: Location) -> Location {
+        let old_end = self.range.end;
+        if loc.line == new_end.line {
+            Location {
+                file: loc.file,
+                line: old_end.line,
+                column: loc.column - new_end.column + old_end.column,
+            }
+        } else {
+            Location {
+                file: loc.file,
+                line: loc.line - new_end.line + old_end.line,
+                column: loc.column,
+            }
+        }
+    }
+}
+
+/// Find the tokens after which the lexer's state is fully determined by the
+/// token itself: identifiers which begin a line outside of any string
+/// interpolation. Lexing can be restarted just after such a token.
+fn sync_points(tokens: &[LocatedToken]) -> Vec<bool> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut depth = 0usize;
+    let mut line_head = true;
+    for token in tokens {
+        let mut sync = false;
+        match token.token {
+            Token::Punct(Punctuation::Newline) => line_head = depth == 0,
+            Token::Punct(Punctuation::Tab) | Token::Punct(Punctuation::Space) => {}
+            Token::Ident(..) => {
+                sync = line_head;
+                line_head = false;
+            }
+            Token::InterpStringBegin(_) => {
+                depth += 1;
+                line_head = false;
+            }
+            Token::InterpStringEnd(_) => {
+                depth = depth.saturating_sub(1);
+                line_head = false;
+            }
+            _ => line_head = false,
+        }
+        result.push(sync);
+    }
+    result
+}
+
+/// A token along with the exact source text it was read from and the
+/// whitespace and comments around it.
+///
+/// All ranges are byte offsets into the `LexedFile`'s source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexedToken {
+    pub token: Token,
+    pub location: Location,
+    pub text: Range<usize>,
+    /// Whitespace, comments, and line continuations since the previous token.
+    pub leading_trivia: Range<usize>,
+    /// Whitespace and comments between this token and the end of its line.
+    pub trailing_trivia: Range<usize>,
+}
+
+/// The full token stream of a file, for tools such as formatters which must
+/// be able to reproduce the original source.
+///
+/// Concatenating each token's leading trivia, text, and trailing trivia in
+/// order yields the source byte-for-byte. Indentation is kept as trivia
+/// rather than as tokens, but newlines remain tokens as they are significant.
+#[derive(Debug)]
+pub struct LexedFile<'a> {
+    pub file: FileId,
+    pub source: &'a str,
+    pub tokens: Vec<LexedToken>,
+    /// Errors encountered while lexing.
+    pub errors: Vec<DMError>,
+}
+
+impl<'a> LexedFile<'a> {
+    /// The source text of a token.
+    pub fn text(&self, token: &LexedToken) -> &'a str {
+        &self.source[token.text.clone()]
+    }
+
+    /// The source text of a token's leading trivia.
+    pub fn leading_trivia(&self, token: &LexedToken) -> &'a str {
+        &self.source[token.leading_trivia.clone()]
+    }
+
+    /// The source text of a token's trailing trivia.
+    pub fn trailing_trivia(&self, token: &LexedToken) -> &'a str {
+        &self.source[token.trailing_trivia.clone()]
+    }
+}
+
+/// Lex a file, keeping the whitespace and comments which are normally
+/// discarded.
+pub fn lex_file(file: FileId, source: &str) -> LexedFile<'_> {
+    // the lexer skips a BOM without counting it as a column
+    let first_line = if source.starts_with('\u{feff}') { 3 } else { 0 };
+    let line_starts: Vec<usize> = std::iter::once(first_line)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+
+    let context = Context::default();
+    let mut lexer = Lexer::new(&context, file, source.as_bytes());
+    let mut tokens: Vec<LexedToken> = Vec::new();
+    let mut last_end = 0;
+    while let Some(LocatedToken { location, token }) = Iterator::next(&mut lexer) {
+        let end = lexer.input.offset - lexer.next.is_some() as usize;
+        let start = line_starts.get(location.line as usize - 1)
+            .map_or(end, |line| line + location.column as usize - 1)
+            .clamp(last_end, end);
+        if let Token::Punct(Punctuation::Tab | Punctuation::Space) = token {
+            continue;
+        }
+
+        let mut gap = last_end..start;
+        // stuff before a newline belongs to the end of the previous line
+        if let Token::Punct(Punctuation::Newline) = token
+            && let Some(previous) = tokens.last_mut()
+        {
+            previous.trailing_trivia = gap;
+            gap = start..start;
+        }
+        tokens.push(LexedToken {
+            token,
+            location,
+            text: start..end,
+            leading_trivia: gap,
+            trailing_trivia: end..end,
+        });
+        last_end = end;
+    }
+    // the lexer always finishes with a newline at the end of input
+    debug_assert_eq!(last_end, source.len());
+
+    let errors = std::mem::take(&mut *context.errors_mut());
+    LexedFile { file, source, tokens, errors }
+}
+
 fn is_digit(ch: u8) -> bool {
     ch.is_ascii_digit()
 }
@@ -483,6 +671,8 @@ pub fn from_utf8_or_latin1_borrowed(bytes: &[u8]) -> Cow<str> {
 // Used to track nested string interpolations and know when they end.
 #[derive(Debug)]
 struct Interpolation {
+    /// The opening delimiter of the string being interpolated into.
+    start: Location,
     end: &'static [u8],
     bracket_depth: usize,
 }
@@ -494,6 +684,10 @@ enum Directive {
     Hash,
     Ordinary,
     Stringy,
+    // after `#include`, where `<` opens a library path
+    Include,
+    // after the `<` of `#include <...>`
+    LibraryPath,
 }
 
 fn buffer_read<R: Read>(file: FileId, mut read: R) -> Result<Vec<u8>, DMError> {
@@ -530,10 +724,27 @@ pub fn buffer_file(file: FileId, path: &std::path::Path) -> Result<Vec<u8>, DMEr
     Ok(buffer)
 }
 
+/// What the columns of a `Location` count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8 source, so a multi-byte character spans several columns.
+    Bytes,
+    /// Unicode characters.
+    Chars,
+    /// Columns on screen, with tabs expanded to tab stops.
+    Display,
+}
+
+/// What the lexer counts columns in.
+///
+/// Tabs are never expanded, matching how BYOND reports columns, so a line
+/// indented by two tabs starts its code at column 3.
+pub const COLUMN_UNIT: ColumnUnit = ColumnUnit::Bytes;
+
 /// A wrapper for an input stream which tracks line and column numbers.
 ///
-/// All characters, including tabs, are considered to occupy one column
-/// regardless of position.
+/// All bytes, including tabs, are considered to occupy one column regardless
+/// of position. See `COLUMN_UNIT`.
 ///
 /// `io::Error`s are converted to `DMError`s which include the location.
 pub struct LocationTracker<'a> {
@@ -698,6 +909,92 @@ impl<'ctx> Lexer<'ctx> {
         Ok(Lexer::new(context, file, input))
     }
 
+    /// Re-tokenize after an edit, reusing as much of `old_tokens` as possible.
+    ///
+    /// `old_tokens` must be the complete token stream of the text before
+    /// `edit`, and this lexer must be freshly created over the text after it.
+    /// Lexing restarts after the last line-leading identifier which ends
+    /// before the edit and stops once it reaches such an identifier again
+    /// beyond the edit, so an edit inside a text block or block comment
+    /// restarts before the string or comment containing it, and an edit
+    /// which opens one lexes until it is closed, possibly to the end of the
+    /// file.
+    pub fn relex(&mut self, old_tokens: &[LocatedToken], edit: TextEdit) -> Vec<LocatedToken> {
+        let old_sync = sync_points(old_tokens);
+        let new_end = edit.new_end();
+
+        // Find where to restart.
+        let restart = (0..old_tokens.len()).rev().find(|&i| {
+            if !old_sync[i] {
+                return false;
+            }
+            let Token::Ident(ref name, _) = old_tokens[i].token else { return false };
+            let mut after = old_tokens[i].location;
+            after.column += name.len() as u16;
+            after < edit.range.start
+        });
+
+        let mut result = Vec::new();
+        if let Some(i) = restart {
+            result.extend_from_slice(&old_tokens[..=i]);
+            let Token::Ident(ref name, _) = old_tokens[i].token else { unreachable!() };
+            let mut last = old_tokens[i].location;
+            last.column += name.len() as u16 - 1;
+            self.seek(last);
+        }
+
+        // Lex forward until resynchronized.
+        let mut line_head = self.at_line_head;
+        while let Some(token) = Iterator::next(self) {
+            let mut sync = false;
+            match token.token {
+                Token::Punct(Punctuation::Newline) => line_head = self.interp_stack.is_empty(),
+                Token::Punct(Punctuation::Tab) | Token::Punct(Punctuation::Space) => {}
+                Token::Ident(..) => {
+                    sync = line_head && token.location >= new_end;
+                    line_head = false;
+                }
+                _ => line_head = false,
+            }
+            if sync {
+                let old_loc = edit.unshift(new_end, token.location);
+                if let Ok(k) = old_tokens.binary_search_by_key(&old_loc, |t| t.location)
+                    && old_sync[k]
+                    && old_tokens[k].token == token.token
+                {
+                    result.push(token);
+                    result.extend(old_tokens[k + 1..].iter().map(|t| {
+                        LocatedToken::new(edit.shift(new_end, t.location), t.token.clone())
+                    }));
+                    return result;
+                }
+            }
+            result.push(token);
+        }
+        result
+    }
+
+    /// Position the input just after the character at `last`, in a state
+    /// matching having just read a line-leading identifier.
+    fn seek(&mut self, last: Location) {
+        let mut offset = if self.input.inner.starts_with(b"\xEF\xBB\xBF") { 3 } else { 0 };
+        for _ in 1..last.line {
+            match self.input.inner[offset..].iter().position(|&b| b == b'\n') {
+                Some(pos) => offset += pos + 1,
+                None => break,
+            }
+        }
+        self.input.offset = offset + last.column as usize;
+        self.input.location = last;
+        self.input.at_line_end = false;
+        self.next = None;
+        self.final_newline = false;
+        self.at_line_head = false;
+        self.close_allowed = true;
+        self.directive = Directive::None;
+        self.interp_stack.clear();
+    }
+
     pub fn remaining(&self) -> &[u8] {
         self.input.remaining()
     }
@@ -732,6 +1029,8 @@ impl<'ctx> Lexer<'ctx> {
     }
 
     fn skip_block_comments(&mut self) -> Option<Token> {
+        let mut start = self.location();
+        start.column -= 1;
         let mut depth = 1;
         let mut buffer = [0, 0];
         let mut comment_text = Vec::new();
@@ -758,7 +1057,7 @@ impl<'ctx> Lexer<'ctx> {
             match self.next() {
                 Some(val) => buffer[1] = val,
                 None => {
-                    self.context.register_error(self.error("still skipping comments at end of file"));
+                    self.context.register_error(DMError::new(start, "unterminated block comment"));
                     break;
                 }
             }
@@ -785,6 +1084,8 @@ impl<'ctx> Lexer<'ctx> {
 
     fn skip_line_comment(&mut self) -> Option<Token> {
         let mut backslash = false;
+        let mut start = self.location();
+        start.column -= 1;
 
         // read the first character and check for being a comment
         let mut comment = None;
@@ -797,11 +1098,12 @@ impl<'ctx> Lexer<'ctx> {
                 return None;
             }
             Some(b'\\') => backslash = true,
-            _ => {}
+            Some(ch) => comment_text.push(ch),
+            None => {}
         }
 
         while let Some(ch) = self.next() {
-            if ch != b'\r' && ch != b'\n' && comment.is_some() {
+            if ch != b'\r' && ch != b'\n' {
                 comment_text.push(ch);
             }
 
@@ -822,10 +1124,48 @@ impl<'ctx> Lexer<'ctx> {
             }
         }
 
-        comment.map(|mut c| {
-            c.text = from_utf8_or_latin1(comment_text);
-            Token::DocComment(c)
-        })
+        self.check_comment_tag(start, &comment_text);
+        match comment {
+            Some(mut c) => {
+                c.text = from_utf8_or_latin1(comment_text);
+                Some(Token::DocComment(c))
+            }
+            None if comment_text.trim_ascii_start().starts_with(b"SDMM:") => {
+                let text = from_utf8_or_latin1(comment_text);
+                match LintControl::parse(start..self.location(), &text) {
+                    Some(Ok(controls)) => {
+                        for control in controls {
+                            self.context.register_lint_control(control);
+                        }
+                    }
+                    Some(Err(error)) => self.context.register_error(error),
+                    None => {}
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Report a line comment which starts with one of the configured
+    /// `comment_tags`, like `// TODO: ...`, as an informational diagnostic.
+    fn check_comment_tag(&self, start: Location, comment_text: &[u8]) {
+        let tags = &self.context.config().code_standards.comment_tags;
+        if tags.is_empty() {
+            return;
+        }
+        let text = from_utf8_or_latin1_borrowed(comment_text);
+        let text = text.trim();
+        let tagged = tags.iter().any(|tag| {
+            text.strip_prefix(tag.as_str())
+                .is_some_and(|rest| !rest.starts_with(|ch: char| ch.is_ascii_alphanumeric() || ch == '_'))
+        });
+        if tagged {
+            DMError::new(start, text)
+                .set_severity(Severity::Info)
+                .with_errortype("comment_tag")
+                .register(self.context);
+        }
     }
 
     fn read_number_inner(&mut self, first: u8) -> (bool, u32, Cow<'static, str>) {
@@ -974,8 +1314,24 @@ impl<'ctx> Lexer<'ctx> {
         from_utf8_or_latin1(buf)
     }
 
-    fn read_string(&mut self, end: &'static [u8], interp_closed: bool) -> Token {
-        let start_loc = self.location();
+    /// Read the path of an `#include <...>` up to its closing `>`.
+    fn read_library_path(&mut self, start: Location) -> String {
+        let mut buf = Vec::new();
+        loop {
+            match self.next() {
+                Some(b'>') => break,
+                ch @ (Some(b'\n') | None) => {
+                    self.put_back(ch);
+                    self.context.register_error(DMError::new(start, "unterminated library path"));
+                    break;
+                }
+                Some(ch) => buf.push(ch),
+            }
+        }
+        from_utf8_or_latin1(buf)
+    }
+
+    fn read_string(&mut self, start: Location, end: &'static [u8], interp_closed: bool) -> Token {
         let mut buf = Vec::new();
         let mut backslash = false;
         let mut idx = 0;
@@ -985,7 +1341,8 @@ impl<'ctx> Lexer<'ctx> {
             let ch = match self.next() {
                 Some(ch) => ch,
                 None => {
-                    self.context.register_error(DMError::new(start_loc, "unterminated string literal"));
+                    let message = if end == b"\"}" { "unterminated text block" } else { "unterminated string literal" };
+                    self.context.register_error(DMError::new(start, message));
                     break;
                 }
             };
@@ -1023,6 +1380,7 @@ impl<'ctx> Lexer<'ctx> {
                 // `backslash` is false hereafter
                 b'[' => {
                     self.interp_stack.push(Interpolation {
+                        start,
                         end,
                         bracket_depth: 1,
                     });
@@ -1181,6 +1539,10 @@ impl<'ctx> Iterator for Lexer<'ctx> {
                     // always end with a newline
                     if !self.final_newline {
                         self.final_newline = true;
+                        if let Some(interp) = self.interp_stack.first() {
+                            self.context.register_error(DMError::new(interp.start, "unterminated string literal"));
+                            self.interp_stack.clear();
+                        }
                         let mut location = self.location();
                         location.column += 1;
                         return Some(LocatedToken {
@@ -1200,7 +1562,20 @@ impl<'ctx> Iterator for Lexer<'ctx> {
             if self.directive == Directive::Stringy {
                 self.directive = Directive::None;
                 self.put_back(Some(first));
-                return Some(locate(self.read_string(b"\n", false)));
+                return Some(locate(self.read_string(loc, b"\n", false)));
+            }
+            if self.directive == Directive::LibraryPath {
+                self.directive = Directive::Ordinary;
+                self.put_back(Some(first));
+                return Some(locate(String(self.read_library_path(loc))));
+            }
+            if self.directive == Directive::Include {
+                if first == b'<' {
+                    // `#include <...>` is lexed as `<` followed by a string
+                    self.directive = Directive::LibraryPath;
+                    return Some(locate(Punct(Less)));
+                }
+                self.directive = Directive::Ordinary;
             }
 
             let mut punct = self.read_punct(first);
@@ -1225,8 +1600,8 @@ impl<'ctx> Iterator for Lexer<'ctx> {
                     continue;
                 }
                 Some(SingleQuote) => Some(locate(Resource(self.read_resource()))),
-                Some(DoubleQuote) => Some(locate(self.read_string(b"\"", false))),
-                Some(BlockString) => Some(locate(self.read_string(b"\"}", false))),
+                Some(DoubleQuote) => Some(locate(self.read_string(loc, b"\"", false))),
+                Some(BlockString) => Some(locate(self.read_string(loc, b"\"}", false))),
                 Some(lbr @ LBracket | lbr @ SafeLBracket) => {
                     if let Some(interp) = self.interp_stack.last_mut() {
                         interp.bracket_depth += 1;
@@ -1237,7 +1612,7 @@ impl<'ctx> Iterator for Lexer<'ctx> {
                     if let Some(mut interp) = self.interp_stack.pop() {
                         interp.bracket_depth -= 1;
                         if interp.bracket_depth == 0 {
-                            return Some(locate(self.read_string(interp.end, true)));
+                            return Some(locate(self.read_string(interp.start, interp.end, true)));
                         }
                         self.interp_stack.push(interp);
                     }
@@ -1254,8 +1629,10 @@ impl<'ctx> Iterator for Lexer<'ctx> {
                     b'_' | b'a'..=b'z' | b'A'..=b'Z' => {
                         let (ident, ws) = self.read_ident(first);
                         if self.directive == Directive::Hash {
-                            if ident == "warn" || ident == "error" {
+                            if ident == "warn" || ident == "warning" || ident == "error" {
                                 self.directive = Directive::Stringy;
+                            } else if ident == "include" {
+                                self.directive = Directive::Include;
                             } else {
                                 self.directive = Directive::Ordinary;
                             }
@@ -30,6 +30,7 @@ pub mod config;
 pub mod constants;
 pub mod dmi;
 pub mod docs;
+pub mod format;
 pub mod indents;
 pub mod lexer;
 pub mod objtree;
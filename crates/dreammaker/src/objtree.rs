@@ -17,12 +17,14 @@ use serde::{Serialize, Serializer};
 use crate::heap_size_of_index_map;
 
 use super::ast::{
-    ProcReturnType, Block, Expression, Ident, Parameter, PathOp, ProcDeclBuilder, ProcDeclKind, ProcFlags,
-    VarSuffix, VarType, VarTypeBuilder,
+    ProcReturnType, Block, Expression, Follow, Ident, Parameter, PathOp,
+    ProcDeclBuilder, ProcDeclKind, ProcFlags, SettingMode, Spanned, Statement, Term, TypePath, VarSuffix, VarType,
+    VarTypeBuilder, Visitor, return_type_setting, walk_block, walk_expression, walk_follow, walk_proc, walk_statement, walk_term,
 };
+use super::config::EmptyBlockRules;
 use super::constants::Constant;
 use super::docs::DocCollection;
-use super::{Context, DMError, Location, Severity};
+use super::{Context, DMError, FileId, Location, Severity};
 
 // ----------------------------------------------------------------------------
 // Symbol IDs
@@ -84,6 +86,9 @@ pub struct VarValue {
 pub struct TypeVar {
     pub value: VarValue,
     pub declaration: Option<VarDeclaration>,
+    /// Values this var was given on the same type before `value`, in order,
+    /// which `ObjectTree::update_file` falls back to.
+    pub overridden: Vec<VarValue>,
 }
 
 #[derive(Debug, Clone, GetSize)]
@@ -107,8 +112,120 @@ pub struct ProcValue {
     pub parameters: Box<[Parameter]>,
     pub docs: DocCollection,
     pub code: Option<Block>,
+    /// `None` for builtins and for procs declared without a body, like
+    /// `/mob/proc/attack()` alone on a line.
     #[get_size(size_fn = heap_size_of_location_range)]
     pub body_range: Option<Range<Location>>,
+    pub settings: ProcSettings,
+}
+
+impl ProcValue {
+    /// Find the parameter which receives the positional argument at `idx`.
+    ///
+    /// In a varargs proc, every argument from the `...` onwards belongs to
+    /// it. Otherwise, arguments past the declared parameters have none.
+    pub fn parameter_at(&self, idx: usize) -> Option<&Parameter> {
+        let varargs = self.parameters.iter().position(|p| p.name == "...");
+        match varargs {
+            Some(pos) if idx >= pos => self.parameters.get(pos),
+            _ => self.parameters.get(idx),
+        }
+    }
+}
+
+/// The `set` statements at the top level of a proc's body.
+///
+/// Each typed field is `None` if the setting is absent or its value was
+/// invalid. Settings which aren't built in, such as `SpacemanDMM_` directives,
+/// and valid values the typed fields can't represent, such as interpolated
+/// verb names, are kept in `other`.
+#[derive(Debug, Clone, Default, GetSize)]
+pub struct ProcSettings {
+    pub name: Option<String>,
+    pub desc: Option<String>,
+    /// `Some(None)` if the category is set to null, hiding the verb.
+    pub category: Option<Option<String>>,
+    pub hidden: Option<bool>,
+    pub popup_menu: Option<bool>,
+    pub instant: Option<bool>,
+    pub background: Option<bool>,
+    pub waitfor: Option<bool>,
+    pub invisibility: Option<i32>,
+    pub src: Option<(SettingMode, Expression)>,
+    #[get_size(size_fn = heap_size_of_index_map)]
+    pub other: IndexMap<Ident, Expression, RandomState>,
+}
+
+impl ProcSettings {
+    /// Collect the settings from a proc body, reporting invalid values.
+    pub fn from_code(context: &Context, code: &Block) -> ProcSettings {
+        let mut settings = ProcSettings::default();
+        for statement in code.iter() {
+            if let Statement::Setting { ref name, mode, ref value } = statement.elem {
+                settings.set(context, statement.location, name, mode, value);
+            }
+        }
+        settings
+    }
+
+    fn set(&mut self, context: &Context, location: Location, name: &str, mode: SettingMode, value: &Expression) {
+        let flag = match name {
+            "hidden" => &mut self.hidden,
+            "popup_menu" => &mut self.popup_menu,
+            "instant" => &mut self.instant,
+            "background" => &mut self.background,
+            "waitfor" => &mut self.waitfor,
+            "name" | "desc" | "category" => {
+                let text = match value.as_term() {
+                    Some(Term::String(text)) => text.clone(),
+                    Some(Term::Null) if name == "category" => {
+                        self.category = Some(None);
+                        return;
+                    }
+                    Some(Term::InterpString(..)) => {
+                        self.other.insert(name.to_owned(), value.clone());
+                        return;
+                    }
+                    _ => return invalid_setting(context, location, format!("set {name} must have a string value")),
+                };
+                match name {
+                    "name" => self.name = Some(text),
+                    "desc" => self.desc = Some(text),
+                    _ => self.category = Some(Some(text)),
+                }
+                return;
+            }
+            "invisibility" => {
+                match value.as_term() {
+                    Some(&Term::Int(i)) if (0..=100).contains(&i) => self.invisibility = Some(i),
+                    _ => invalid_setting(context, location, "set invisibility must be 0-100"),
+                }
+                return;
+            }
+            "src" => {
+                self.src = Some((mode, value.clone()));
+                return;
+            }
+            _ => {
+                self.other.insert(name.to_owned(), value.clone());
+                return;
+            }
+        };
+        match value.as_term() {
+            Some(Term::Int(0)) => *flag = Some(false),
+            Some(Term::Int(1)) => *flag = Some(true),
+            Some(Term::Ident(i)) if i == "FALSE" => *flag = Some(false),
+            Some(Term::Ident(i)) if i == "TRUE" => *flag = Some(true),
+            _ => invalid_setting(context, location, format!("set {name} must be 0/1/TRUE/FALSE")),
+        }
+    }
+}
+
+fn invalid_setting<S: Into<String>>(context: &Context, location: Location, message: S) {
+    DMError::new(location, message)
+        .set_severity(Severity::Warning)
+        .with_errortype("invalid_set_value")
+        .register(context);
 }
 
 #[derive(Debug, Clone, Default, GetSize)]
@@ -174,6 +291,12 @@ impl Type {
         self.path.is_empty()
     }
 
+    /// The number of segments in this type's path, 0 for the root and 2 for
+    /// `/obj/item`.
+    pub fn depth(&self) -> usize {
+        self.path.matches('/').count()
+    }
+
     pub fn pretty_path(&self) -> &str {
         if self.is_root() {
             "(global)"
@@ -221,6 +344,24 @@ pub fn subpath(path: &str, parent: &str) -> bool {
     path == &parent[..parent.len() - 1] || path.starts_with(parent)
 }
 
+/// Split a path glob into its segments, ignoring empty ones.
+fn glob_segments(pattern: &str) -> Vec<String> {
+    pattern.split('/').filter(|s| !s.is_empty()).map(ToOwned::to_owned).collect()
+}
+
+fn glob_matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| glob_matches(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => (first == "*" || first == segment) && glob_matches(rest, path),
+            None => false,
+        },
+    }
+}
+
 // ----------------------------------------------------------------------------
 // Type references
 
@@ -430,6 +571,65 @@ impl<'a> TypeRef<'a> {
         self.get().get_var_declaration(name, self.tree)
     }
 
+    /// Find the declaration of a var as seen from this type.
+    pub fn var_ref(self, name: &str) -> Option<VarRef<'a>> {
+        let mut current = Some(self);
+        while let Some(ty) = current {
+            if let Some((name, var)) = ty.get().vars.get_key_value(name)
+                && let Some(declaration) = var.declaration.as_ref()
+            {
+                return Some(VarRef { ty, name, declaration });
+            }
+            current = ty.parent_type();
+        }
+        None
+    }
+
+    /// Find the type which supplies the effective value of a var, along with
+    /// the var's declaration.
+    ///
+    /// This is the nearest type, starting from this one, which gives the var
+    /// an initializer, even if a closer type redeclares the var without one.
+    /// If no type gives it a value, the type with the nearest declaration is
+    /// returned instead.
+    pub fn effective_var(self, name: &str) -> Option<(TypeRef<'a>, &'a VarDeclaration)> {
+        let mut declared = None;
+        let mut current = Some(self);
+        while let Some(ty) = current {
+            if let Some(var) = ty.get().vars.get(name) {
+                if declared.is_none() && let Some(decl) = var.declaration.as_ref() {
+                    declared = Some((ty, decl));
+                }
+                if var.value.expression.is_some() {
+                    let declaration = match declared {
+                        Some((_, decl)) => decl,
+                        None => ty.get_var_declaration(name)?,
+                    };
+                    return Some((ty, declaration));
+                }
+            }
+            current = ty.parent_type();
+        }
+        declared
+    }
+
+    /// Iterate over the vars declared or overridden on this type, along with
+    /// their declarations and, if their initializers are constant, values.
+    ///
+    /// Vars without an initializer have a constant null value.
+    pub fn vars_with_values(self) -> impl Iterator<Item=(&'a Ident, &'a VarDeclaration, Option<Constant>)> + use<'a> {
+        self.get().vars.iter().filter_map(move |(name, var)| {
+            let declaration = self.get_var_declaration(name)?;
+            let constant = match (&var.value.constant, &var.value.expression) {
+                (Some(constant), _) => Some(constant.clone()),
+                // static and tmp vars are skipped by tree-wide evaluation
+                (None, Some(expression)) => expression.clone().simple_evaluate(var.value.location).ok(),
+                (None, None) => Some(Constant::Null(None)),
+            };
+            Some((name, declaration, constant))
+        })
+    }
+
     pub fn get_proc(self, name: &str) -> Option<ProcRef<'a>> {
         let mut current: Option<TypeRef<'a>> = Some(self);
         while let Some(ty) = current {
@@ -446,6 +646,12 @@ impl<'a> TypeRef<'a> {
         None
     }
 
+    /// Iterate over the definitions of a proc visible from this type, from
+    /// the most specific override up to the original definition.
+    pub fn proc_chain(self, name: &str) -> impl Iterator<Item = ProcRef<'a>> {
+        std::iter::successors(self.get_proc(name), |proc| proc.parent_proc())
+    }
+
     pub fn get_proc_declaration(self, name: &str) -> Option<&'a ProcDeclaration> {
         let mut current: Option<TypeRef<'a>> = Some(self);
         while let Some(ty) = current {
@@ -459,6 +665,18 @@ impl<'a> TypeRef<'a> {
         None
     }
 
+    /// Look up one definition of a proc on this type, by its index among the
+    /// definitions and overrides of that proc on this type alone.
+    pub fn get_self_proc(self, name: &str, idx: usize) -> Option<ProcRef<'a>> {
+        let (name, type_proc) = self.get().procs.get_key_value(name)?;
+        (idx < type_proc.value.len()).then_some(ProcRef {
+            ty: self,
+            list: &type_proc.value,
+            name,
+            idx,
+        })
+    }
+
     pub fn iter_self_procs(self) -> impl Iterator<Item=ProcRef<'a>> {
         self.get().procs.iter().flat_map(move |(name, type_proc)| {
             let list = &type_proc.value;
@@ -598,6 +816,11 @@ impl<'a> ProcRef<'a> {
         }
     }
 
+    /// Iterate over this proc and each proc it overrides, following `..()`.
+    pub fn proc_chain(self) -> impl Iterator<Item = ProcRef<'a>> {
+        std::iter::successors(Some(self), |proc| proc.parent_proc())
+    }
+
     /// Returns whether this is the public-facing version (final override) of this proc.
     pub fn is_externally_visible(self) -> bool {
         self.idx + 1 == self.list.len()
@@ -608,11 +831,52 @@ impl<'a> ProcRef<'a> {
         self.parameters.iter().any(|p| p.name == "...")
     }
 
+    /// Get the statements of this proc's body.
+    ///
+    /// Bodies are only kept if the tree was parsed with
+    /// `Parser::enable_procs`, and until `ObjectTree::drop_code`. Builtins
+    /// and procs declared without a body, like `/mob/proc/attack()` alone on
+    /// a line, have none.
+    pub fn body(self) -> Option<&'a [Spanned<Statement>]> {
+        let proc = self.get();
+        proc.body_range.as_ref()?;
+        proc.code.as_deref()
+    }
+
     /// Get the declaration corresponding to this proc reference.
     pub fn get_declaration(self) -> Option<&'a ProcDeclaration> {
         self.ty.get_proc_declaration(self.name)
     }
 
+    /// Get whether this proc was declared as a `proc` or a `verb`.
+    ///
+    /// Overrides take the kind of the declaration they override.
+    pub fn kind(self) -> Option<ProcDeclKind> {
+        self.get_declaration().map(|decl| decl.kind)
+    }
+
+    /// Get the type this proc is hinted to return, if any.
+    ///
+    /// `set SpacemanDMM_return_type = /path`, as written by the `RETURN_TYPE`
+    /// macro, is looked for in this proc and each proc it overrides, which
+    /// requires that proc bodies were parsed. Otherwise an `as /path` or
+    /// `as obj` on the declaration is used.
+    pub fn return_type(self) -> Option<TypePath> {
+        for proc in self.proc_chain() {
+            if let Some(path) = proc.get().code.as_deref().and_then(return_type_setting) {
+                return Some(path.clone());
+            }
+        }
+
+        let path: Vec<Ident> = match self.get_declaration()?.return_type {
+            ProcReturnType::TypePath(ref path) => path.clone(),
+            ProcReturnType::InputType(input_type) => {
+                input_type.to_typepath()?.split('/').skip(1).map(ToOwned::to_owned).collect()
+            }
+        };
+        Some(path.into_iter().map(|name| (PathOp::Slash, name)).collect())
+    }
+
     /// Recursively visit this and all public-facing procs which override it.
     pub fn recurse_children<F: FnMut(ProcRef<'a>)>(self, f: &mut F) {
         self.ty.recurse(&mut move |ty| {
@@ -667,6 +931,383 @@ impl<'a> std::hash::Hash for ProcRef<'a> {
     }
 }
 
+// ----------------------------------------------------------------------------
+// Call sites
+
+/// The proc named by a call site.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Callee {
+    /// A call by name. `.()` and `..()` calls name the calling proc itself.
+    Named(Ident),
+    /// A `call()()`, whose target can't be known statically.
+    Unresolved,
+}
+
+/// A proc call found in the body of a proc, as found by
+/// [`ObjectTree::call_sites`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallSite<'a> {
+    /// The proc definition containing the call.
+    pub caller: ProcRef<'a>,
+    pub callee: Callee,
+    pub location: Location,
+    /// Whether the call is on some other value, as in `a.foo()`.
+    pub is_scoped: bool,
+}
+
+struct CallSiteVisitor<'a> {
+    caller: ProcRef<'a>,
+    sites: Vec<CallSite<'a>>,
+}
+
+impl CallSiteVisitor<'_> {
+    fn push(&mut self, callee: Callee, location: Location, is_scoped: bool) {
+        self.sites.push(CallSite { caller: self.caller, callee, location, is_scoped });
+    }
+}
+
+impl<'ast> Visitor<'ast> for CallSiteVisitor<'_> {
+    fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+        match &term.elem {
+            Term::Call(name, _) | Term::GlobalCall(name, _) => {
+                self.push(Callee::Named(name.to_string()), term.location, false);
+            }
+            Term::SelfCall(_) | Term::ParentCall(_) => {
+                self.push(Callee::Named(self.caller.name().to_owned()), term.location, false);
+            }
+            Term::DynamicCall(..) => self.push(Callee::Unresolved, term.location, false),
+            _ => {}
+        }
+        walk_term(self, term);
+    }
+
+    fn visit_follow(&mut self, follow: &'ast Spanned<Follow>) {
+        if let Follow::Call(_, name, _) = &follow.elem {
+            self.push(Callee::Named(name.to_string()), follow.location, true);
+        }
+        walk_follow(self, follow);
+    }
+}
+
+struct EmptyBlockVisitor<'a> {
+    rules: &'a EmptyBlockRules,
+    found: Vec<(Location, String)>,
+}
+
+impl<'ast> Visitor<'ast> for EmptyBlockVisitor<'_> {
+    fn visit_statement(&mut self, location: Location, statement: &'ast Statement) {
+        match statement {
+            Statement::If { arms, else_arm } if self.rules.ifs => {
+                for (condition, block) in arms {
+                    if block.is_empty() {
+                        self.found.push((condition.location, "empty if block".to_owned()));
+                    }
+                }
+                if else_arm.as_ref().is_some_and(|block| block.is_empty()) {
+                    self.found.push((location, "empty else block".to_owned()));
+                }
+            }
+            Statement::While { block, .. } if self.rules.whiles && block.is_empty() => {
+                self.found.push((location, "empty while loop".to_owned()));
+            }
+            _ => {}
+        }
+        walk_statement(self, location, statement);
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Var references
+
+/// A declared var, as found by [`TypeRef::var_ref`].
+#[derive(Clone, Copy, Debug)]
+pub struct VarRef<'a> {
+    /// The type holding the declaration.
+    ty: TypeRef<'a>,
+    name: &'a str,
+    declaration: &'a VarDeclaration,
+}
+
+impl<'a> VarRef<'a> {
+    /// The type the var is declared on.
+    pub fn ty(self) -> TypeRef<'a> {
+        self.ty
+    }
+
+    pub fn name(self) -> &'a str {
+        self.name
+    }
+
+    pub fn declaration(self) -> &'a VarDeclaration {
+        self.declaration
+    }
+
+    /// Whether `declaration` is this var's declaration.
+    fn is(self, declaration: &VarDeclaration) -> bool {
+        std::ptr::eq(self.declaration, declaration)
+    }
+}
+
+/// How a var is used at a reference found by [`ObjectTree::var_references`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    /// The target of an assignment, including operators like `+=`, or the
+    /// loop variable of a `for` which doesn't declare its own.
+    Write,
+}
+
+struct VarReferenceVisitor<'a> {
+    var: VarRef<'a>,
+    tree: &'a ObjectTree,
+    /// The type of `src` in the proc being walked.
+    src: TypeRef<'a>,
+    /// Local vars in scope and their declared type paths, innermost last.
+    scopes: Vec<Vec<(&'a str, &'a [Ident])>>,
+    references: Vec<(Location, AccessKind)>,
+}
+
+impl<'a> VarReferenceVisitor<'a> {
+    fn local(&self, name: &str) -> Option<&'a [Ident]> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(local, _)| *local == name).map(|&(_, ty)| ty)
+    }
+
+    fn declare(&mut self, name: &'a str, ty: &'a [Ident]) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name, ty));
+        }
+    }
+
+    fn static_type(&self, path: &[Ident]) -> Option<TypeRef<'a>> {
+        if path.is_empty() {
+            return None;
+        }
+        self.tree.type_by_path(path)
+    }
+
+    /// Record a reference to `name` on `ty` if it is the var being searched
+    /// for, returning the declared type of whatever var it names.
+    fn member(&mut self, ty: TypeRef<'a>, name: &str, location: Location, write: bool) -> Option<TypeRef<'a>> {
+        let declaration = ty.get_var_declaration(name)?;
+        if self.var.is(declaration) {
+            self.references.push((location, if write { AccessKind::Write } else { AccessKind::Read }));
+        }
+        self.static_type(&declaration.var_type.type_path)
+    }
+
+    /// Record a use of an unscoped name, which a local var shadows.
+    fn unscoped(&mut self, name: &str, location: Location, write: bool) -> Option<TypeRef<'a>> {
+        match self.local(name) {
+            Some(path) => self.static_type(path),
+            None => match name {
+                "src" => Some(self.src),
+                "usr" => self.tree.find("/mob"),
+                "global" => Some(self.tree.root()),
+                _ => self.member(self.src, name, location, write),
+            },
+        }
+    }
+
+    /// Walk a `for` over a list or range, whose loop variable is either
+    /// declared for the loop or written to on each iteration.
+    fn for_each(&mut self, location: Location, statement: &'a Statement, var_type: &'a Option<VarType>, name: &'a str) {
+        self.scopes.push(Vec::new());
+        match var_type {
+            Some(var_type) => self.declare(name, &var_type.type_path),
+            None => {
+                self.unscoped(name, location, true);
+            }
+        }
+        walk_statement(self, location, statement);
+        self.scopes.pop();
+    }
+
+    /// Walk an expression which is read, or written if `write` is set.
+    fn access(&mut self, expression: &'a Expression, write: bool) {
+        let Expression::Base { term, follow } = expression else {
+            return self.visit_expression(expression);
+        };
+        let mut current = match &term.elem {
+            Term::Ident(name) => self.unscoped(name, term.location, write && follow.is_empty()),
+            _ => {
+                walk_term(self, term);
+                None
+            }
+        };
+        for (i, each) in follow.iter().enumerate() {
+            current = match (&each.elem, current) {
+                (Follow::Field(_, name), Some(ty)) => self.member(ty, name, each.location, write && i + 1 == follow.len()),
+                _ => None,
+            };
+            walk_follow(self, each);
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for VarReferenceVisitor<'a> {
+    fn visit_proc(&mut self, parameters: &'a [Parameter], block: &'a [Spanned<Statement>]) {
+        self.scopes.push(parameters.iter().map(|param| (param.name.as_str(), &param.var_type.type_path[..])).collect());
+        walk_proc(self, parameters, block);
+        self.scopes.pop();
+    }
+
+    fn visit_block(&mut self, block: &'a [Spanned<Statement>]) {
+        self.scopes.push(Vec::new());
+        walk_block(self, block);
+        self.scopes.pop();
+    }
+
+    fn visit_statement(&mut self, location: Location, statement: &'a Statement) {
+        match statement {
+            Statement::Var(var) => {
+                walk_statement(self, location, statement);
+                self.declare(&var.name, &var.var_type.type_path);
+            }
+            Statement::Vars(vars) => {
+                for var in vars.iter() {
+                    if let Some(value) = &var.value {
+                        self.visit_expression(value);
+                    }
+                    self.declare(&var.name, &var.var_type.type_path);
+                }
+            }
+            Statement::ForLoop { .. } => {
+                // vars declared by the initializer are scoped to the loop
+                self.scopes.push(Vec::new());
+                walk_statement(self, location, statement);
+                self.scopes.pop();
+            }
+            Statement::ForList(for_list) => self.for_each(location, statement, &for_list.var_type, &for_list.name),
+            Statement::ForRange(for_range) => self.for_each(location, statement, &for_range.var_type, &for_range.name),
+            Statement::TryCatch { try_block, catch_params, catch_block } => {
+                self.visit_block(try_block);
+                self.scopes.push(catch_params.iter().filter_map(|param| Some((param.last()?.as_str(), &[][..]))).collect());
+                self.visit_block(catch_block);
+                self.scopes.pop();
+            }
+            _ => walk_statement(self, location, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &'a Expression) {
+        match expression {
+            Expression::Base { .. } => self.access(expression, false),
+            Expression::AssignOp { lhs, rhs, .. } => {
+                self.access(lhs, true);
+                self.visit_expression(rhs);
+            }
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Completion
+
+/// How closely a completion candidate matches what has been typed so far,
+/// best first. Case and non-alphanumeric characters are ignored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompletionRank {
+    /// The candidate starts with the query.
+    Prefix,
+    /// The candidate contains the query.
+    Substring,
+    /// The candidate contains the query's characters in order.
+    Subsequence,
+}
+
+impl CompletionRank {
+    pub fn of(candidate: &str, query: &str) -> Option<CompletionRank> {
+        fn simplify(s: &str) -> impl Iterator<Item=char> + Clone + '_ {
+            s.chars().flat_map(|c| c.to_lowercase()).filter(|c| c.is_alphanumeric())
+        }
+        fn starts_with(mut candidate: impl Iterator<Item=char>, query: impl Iterator<Item=char>) -> bool {
+            query.into_iter().all(|q| candidate.next() == Some(q))
+        }
+
+        let (mut candidate, query) = (simplify(candidate), simplify(query));
+        if starts_with(candidate.clone(), query.clone()) {
+            return Some(CompletionRank::Prefix);
+        }
+        let mut rest = candidate.clone();
+        while rest.next().is_some() {
+            if starts_with(rest.clone(), query.clone()) {
+                return Some(CompletionRank::Substring);
+            }
+        }
+        if query.into_iter().all(|q| candidate.any(|c| c == q)) {
+            return Some(CompletionRank::Subsequence);
+        }
+        None
+    }
+}
+
+/// A var or proc available on a type, as found by
+/// [`ObjectTree::complete_member`].
+#[derive(Clone, Copy, Debug)]
+pub enum Member<'a> {
+    /// A var, along with the most specific type which declares or overrides it.
+    Var(TypeRef<'a>, &'a str),
+    Proc(ProcRef<'a>),
+}
+
+impl<'a> Member<'a> {
+    pub fn name(&self) -> &'a str {
+        match *self {
+            Member::Var(_, name) => name,
+            Member::Proc(proc) => proc.name,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Type keywords
+
+/// A var which names a type relative to the type of the code using it, as
+/// resolved by [`ObjectTree::resolve_type_keyword`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TypeKeyword {
+    /// `type` or `src.type`.
+    Type,
+    /// `parent_type` or `src.parent_type`.
+    ParentType,
+}
+
+impl TypeKeyword {
+    /// Attempt to convert a var name to a type keyword.
+    pub fn from_name(name: &str) -> Option<TypeKeyword> {
+        match name {
+            "type" => Some(TypeKeyword::Type),
+            "parent_type" => Some(TypeKeyword::ParentType),
+            _ => None,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Symbol index
+
+/// What sort of declaration a [`SymbolEntry`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Type,
+    Var,
+    Proc(ProcDeclKind),
+}
+
+/// A type, var, or proc declared in the object tree, as listed by
+/// [`ObjectTree::symbols`].
+#[derive(Clone, Debug)]
+pub struct SymbolEntry<'a> {
+    /// The full path, such as `/obj/item/var/name` or `/mob/verb/say`.
+    pub path: String,
+    pub kind: SymbolKind,
+    pub id: SymbolId,
+    /// Where the symbol is declared.
+    pub location: Location,
+    pub docs: Option<&'a DocCollection>,
+}
+
 // ----------------------------------------------------------------------------
 // The object tree itself
 
@@ -674,6 +1315,9 @@ impl<'a> std::hash::Hash for ProcRef<'a> {
 pub struct ObjectTree {
     graph: Vec<Type>,
     types: BTreeMap<String, NodeIndex>,
+    // what the checks run on finishing the tree last reported, so that
+    // `update_file` can replace it
+    finish_errors: Vec<(Location, String)>,
 }
 
 impl ObjectTree {
@@ -694,6 +1338,17 @@ impl ObjectTree {
         self.node_indices().map(move |idx| TypeRef::new(self, idx))
     }
 
+    /// List every type in canonical path order, lexicographic by segment,
+    /// so that each type is followed by all of its subpaths.
+    ///
+    /// As `/` sorts before every character allowed in a path segment, this
+    /// is the same as comparing paths as strings.
+    pub fn types_sorted(&self) -> Vec<TypeRef<'_>> {
+        let mut types: Vec<_> = self.iter_types().collect();
+        types.sort_by(|a, b| a.path.cmp(&b.path));
+        types
+    }
+
     pub fn root(&self) -> TypeRef {
         TypeRef::new(self, NodeIndex::new(0))
     }
@@ -761,6 +1416,357 @@ impl ObjectTree {
         }
     }
 
+    /// Resolve the type named by the idents before a scoped access, as
+    /// recorded by `Annotation::ScopedCall` and `Annotation::ScopedVar` and
+    /// their loose counterparts.
+    ///
+    /// `scope` is the type whose code contains the access. The first ident
+    /// may be `src`, `usr`, `global`, `args`, an absolute path like
+    /// `/datum`, or a var on `scope`; each later ident is a var on the type
+    /// found so far. Locals and parameters aren't known to the tree, so
+    /// chains starting with one of those don't resolve.
+    pub fn resolve_scope<'a>(&'a self, scope: TypeRef<'a>, base: &[Ident]) -> Option<TypeRef<'a>> {
+        let mut base = base.iter();
+        let Some(first) = base.next() else {
+            return Some(scope);
+        };
+        let mut current = match first.as_str() {
+            "src" => scope,
+            "usr" => self.find("/mob")?,
+            "global" => self.root(),
+            "args" => self.find("/list")?,
+            path if path.starts_with('/') => self.find(path)?,
+            var => self.type_by_path(scope.get_var_declaration(var)?.var_type.type_path.iter())?,
+        };
+        for var in base {
+            current = self.type_by_path(current.get_var_declaration(var)?.var_type.type_path.iter())?;
+        }
+        Some(current)
+    }
+
+    /// Resolve `type` or `parent_type` as used in code on the type at
+    /// `enclosing`, as found by `AnnotationTree::enclosing_type`.
+    ///
+    /// Global code and `/datum` have no type or parent type respectively, so
+    /// those don't resolve.
+    pub fn resolve_type_keyword(&self, enclosing: &[Ident], kw: TypeKeyword) -> Option<TypeRef<'_>> {
+        let ty = self.type_by_path(enclosing).filter(|ty| !ty.is_root())?;
+        match kw {
+            TypeKeyword::Type => Some(ty),
+            TypeKeyword::ParentType => ty.parent_type_without_root(),
+        }
+    }
+
+    /// Resolve a scoped proc call like `a.b.foo()` to the most-derived
+    /// definition of `foo` visible on the type of `a.b`.
+    ///
+    /// See `resolve_scope` for how `base` is interpreted. Global procs are
+    /// only found when the scope is `global`.
+    pub fn resolve_scoped_call<'a>(&'a self, scope: TypeRef<'a>, base: &[Ident], name: &Ident) -> Option<ProcRef<'a>> {
+        let ty = self.resolve_scope(scope, base)?;
+        ty.get_proc(name).filter(|proc| ty.is_root() || !proc.ty().is_root())
+    }
+
+    /// Resolve a scoped var access like `a.b.foo` to the most-derived type
+    /// which sets `foo`, along with that type's entry for it.
+    ///
+    /// See `resolve_scope` for how `base` is interpreted. Global vars are
+    /// only found when the scope is `global`.
+    pub fn resolve_scoped_var<'a>(&'a self, scope: TypeRef<'a>, base: &[Ident], name: &Ident) -> Option<(TypeRef<'a>, &'a TypeVar)> {
+        let ty = self.resolve_scope(scope, base)?;
+        if ty.is_root() {
+            return ty.get().vars.get(name).map(|var| (ty, var));
+        }
+        let mut current = Some(ty);
+        while let Some(ty) = current {
+            if let Some(var) = ty.get().vars.get(name) {
+                return Some((ty, var));
+            }
+            current = ty.parent_type_without_root();
+        }
+        None
+    }
+
+    /// Resolve a global var named as `global.name` or `::name` to its entry
+    /// on the root.
+    pub fn resolve_global_var(&self, name: &str) -> Option<&TypeVar> {
+        self.root().get().vars.get(name)
+    }
+
+    /// Resolve a global proc called as `global.name()` or `::name()`.
+    pub fn resolve_global_proc(&self, name: &str) -> Option<ProcRef<'_>> {
+        self.root().get_proc(name)
+    }
+
+    /// Find the types whose paths match a glob pattern like
+    /// `/obj/item/*/sword` or `/obj/item/weapon/**`.
+    ///
+    /// A `*` segment matches exactly one path segment and a `**` segment
+    /// matches any number of them, including none, so `/obj/**` matches
+    /// `/obj` itself. The pattern `/` matches only the root.
+    pub fn matching_paths<'a>(&'a self, pattern: &str) -> impl Iterator<Item=TypeRef<'a>> + use<'a> {
+        self.matching_paths_by(glob_segments(pattern))
+    }
+
+    /// Find the procs whose paths match a glob pattern like
+    /// `/**/proc/attack` or `/mob/*/Login`.
+    ///
+    /// The last segment names the proc and may be `*`; the rest is matched
+    /// against type paths as in `matching_paths`, with an optional `proc` or
+    /// `verb` segment before the name. Every definition or override on a
+    /// matching type is included.
+    pub fn matching_procs<'a>(&'a self, pattern: &str) -> impl Iterator<Item=ProcRef<'a>> + use<'a> {
+        let mut pattern = glob_segments(pattern);
+        let name = pattern.pop().unwrap_or_default();
+        if matches!(pattern.last().map(String::as_str), Some("proc" | "verb")) {
+            pattern.pop();
+        }
+        self.matching_paths_by(pattern)
+            .flat_map(|ty| ty.iter_self_procs())
+            .filter(move |proc| name == "*" || proc.name() == name)
+    }
+
+    /// Find every definition of a proc on the types which inherit from
+    /// `base_type`, not including `base_type` itself.
+    ///
+    /// Types are related by `parent_type`, as with `is_subtype_of`. If the
+    /// proc isn't declared on or inherited by `base_type`, nothing is found.
+    pub fn overrides_of<'a>(&'a self, base_type: TypeRef<'a>, proc_name: &Ident) -> Vec<ProcRef<'a>> {
+        if base_type.get_proc(proc_name).is_none() {
+            return Vec::new();
+        }
+        self.iter_types()
+            .filter(|ty| *ty != base_type && ty.is_subtype_of(base_type.get()))
+            .flat_map(|ty| {
+                let count = ty.get().procs.get(proc_name).map_or(0, |proc| proc.value.len());
+                (0..count).filter_map(move |idx| ty.get_self_proc(proc_name, idx))
+            })
+            .collect()
+    }
+
+    /// List every type alongside the types which directly inherit from it,
+    /// starting with the root.
+    ///
+    /// Types are related by `parent_type`, so a type whose `parent_type` is
+    /// overridden is listed under that type rather than its parent path, and
+    /// each type other than the root is a child of exactly one other. Types
+    /// and children are in the order they were first declared.
+    pub fn hierarchy(&self) -> Vec<(TypeRef<'_>, Vec<TypeRef<'_>>)> {
+        let mut children = vec![Vec::new(); self.graph.len()];
+        for ty in self.iter_types() {
+            if let Some(parent) = ty.parent_type_index() {
+                children[parent.index()].push(ty);
+            }
+        }
+        self.iter_types().zip(children).collect()
+    }
+
+    /// Find every proc call in the proc bodies of this tree, for building a
+    /// call graph.
+    ///
+    /// Only procs whose code was kept when parsing are searched. Callees are
+    /// given by name, as resolving them depends on the type of the value
+    /// they are called on.
+    pub fn call_sites(&self) -> impl Iterator<Item=CallSite<'_>> {
+        self.iter_types()
+            .flat_map(|ty| ty.iter_self_procs())
+            .flat_map(|proc| {
+                let mut visitor = CallSiteVisitor { caller: proc, sites: Vec::new() };
+                if let Some(code) = &proc.get().code {
+                    visitor.visit_proc(&proc.get().parameters, code);
+                }
+                visitor.sites
+            })
+    }
+
+    /// Find where a var is read and written in the bodies of procs.
+    ///
+    /// Names are resolved to vars as the compiler would: unscoped names
+    /// through the proc's own type, unless shadowed by a local var, and
+    /// accesses like `a.b.c` through the declared type of each step. Where a
+    /// type isn't declared, as for `list` elements or untyped locals, the
+    /// var being accessed can't be known and isn't reported. Overrides of
+    /// the var's initial value on subtypes are not references.
+    ///
+    /// Only procs whose code was kept when parsing are searched.
+    pub fn var_references(&self, var: VarRef<'_>) -> Vec<(Location, AccessKind)> {
+        let mut references = Vec::new();
+        for proc in self.iter_types().flat_map(|ty| ty.iter_self_procs()) {
+            let Some(code) = &proc.get().code else { continue };
+            let mut visitor = VarReferenceVisitor {
+                var,
+                tree: self,
+                src: proc.ty(),
+                scopes: Vec::new(),
+                references: Vec::new(),
+            };
+            visitor.visit_proc(&proc.get().parameters, code);
+            references.extend(visitor.references);
+        }
+        references.sort_by_key(|&(location, _)| location);
+        references
+    }
+
+    /// Find the types whose paths best match a partially typed path like
+    /// `/obj/it`, best first, for autocompletion.
+    ///
+    /// Paths starting with the prefix come first, then paths containing it,
+    /// then paths containing its characters in order. Matches of equal rank
+    /// are ordered shortest first.
+    pub fn complete_path(&self, prefix: &str, limit: usize) -> Vec<TypeRef<'_>> {
+        let mut candidates: Vec<_> = self.iter_types()
+            .filter(|ty| !ty.is_root())
+            .filter_map(|ty| Some((CompletionRank::of(&ty.path, prefix)?, ty)))
+            .collect();
+        candidates.sort_by(|(rank_a, a), (rank_b, b)| {
+            (rank_a, a.path.len(), &a.path).cmp(&(rank_b, b.path.len(), &b.path))
+        });
+        candidates.into_iter().take(limit).map(|(_, ty)| ty).collect()
+    }
+
+    /// Find the vars and procs of a type which best match a partially typed
+    /// name, as in `type.partial`, ranked as in `complete_path`.
+    ///
+    /// Members inherited from parent types are included once each, but
+    /// global vars and procs are not.
+    pub fn complete_member<'a>(&'a self, ty: TypeRef<'a>, partial: &str, limit: usize) -> Vec<Member<'a>> {
+        let mut seen = std::collections::HashSet::new();
+        let mut candidates = Vec::new();
+        let mut next = Some(ty);
+        while let Some(current) = next {
+            for name in current.get().vars.keys() {
+                if seen.insert(("var", name))
+                    && let Some(rank) = CompletionRank::of(name, partial)
+                {
+                    candidates.push((rank, Member::Var(current, name)));
+                }
+            }
+            for name in current.get().procs.keys() {
+                if seen.insert(("proc", name))
+                    && let Some(rank) = CompletionRank::of(name, partial)
+                    && let Some(proc) = current.get_proc(name)
+                {
+                    candidates.push((rank, Member::Proc(proc)));
+                }
+            }
+            next = current.parent_type_without_root();
+        }
+        candidates.sort_by(|(rank_a, a), (rank_b, b)| {
+            (rank_a, a.name().len(), a.name()).cmp(&(rank_b, b.name().len(), b.name()))
+        });
+        candidates.into_iter().take(limit).map(|(_, member)| member).collect()
+    }
+
+    /// List every type, and every var and proc declaration, in the tree.
+    ///
+    /// Overrides aren't listed separately from the declaration they
+    /// override. The root type is omitted, but global vars and procs are
+    /// listed under it.
+    pub fn symbols(&self) -> Vec<SymbolEntry<'_>> {
+        fn docs(docs: &DocCollection) -> Option<&DocCollection> {
+            (!docs.is_empty()).then_some(docs)
+        }
+        let mut symbols = Vec::new();
+        for ty in self.iter_types() {
+            if !ty.is_root() {
+                symbols.push(SymbolEntry {
+                    path: ty.path.clone(),
+                    kind: SymbolKind::Type,
+                    id: ty.id,
+                    location: ty.location,
+                    docs: docs(&ty.get().docs),
+                });
+            }
+            for (name, var) in ty.get().vars.iter() {
+                if let Some(decl) = var.declaration.as_ref() {
+                    symbols.push(SymbolEntry {
+                        path: format!("{}/var/{}", ty.path, name),
+                        kind: SymbolKind::Var,
+                        id: decl.id,
+                        location: decl.location,
+                        docs: docs(&var.value.docs),
+                    });
+                }
+            }
+            for (name, proc) in ty.get().procs.iter() {
+                if let Some(decl) = proc.declaration.as_ref() {
+                    symbols.push(SymbolEntry {
+                        path: format!("{}/{}/{}", ty.path, decl.kind.name(), name),
+                        kind: SymbolKind::Proc(decl.kind),
+                        id: decl.id,
+                        location: decl.location,
+                        docs: docs(&proc.main_value().docs),
+                    });
+                }
+            }
+        }
+        symbols
+    }
+
+    /// Find the documentation of the type, var, or proc at a path like
+    /// `/mob`, `/mob/var/health`, or `/mob/proc/attack`.
+    ///
+    /// A var or proc which is overridden without docs of its own has the
+    /// docs of the nearest definition it overrides. Returns `None` if
+    /// nothing is at the path or it isn't documented.
+    pub fn docs_for(&self, path: &str) -> Option<&DocCollection> {
+        fn find_type<'a>(tree: &'a ObjectTree, segments: &[&str]) -> Option<TypeRef<'a>> {
+            if segments.is_empty() {
+                return Some(tree.root());
+            }
+            tree.find(&format!("/{}", segments.join("/")))
+        }
+
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        let docs = match segments.as_slice() {
+            [ty @ .., "var", name] => {
+                let mut current = find_type(self, ty);
+                std::iter::from_fn(|| {
+                    let ty = current?;
+                    current = ty.parent_type();
+                    Some(ty)
+                })
+                .filter_map(|ty| ty.get().vars.get(*name))
+                .map(|var| &var.value.docs)
+                .find(|docs| !docs.is_empty())?
+            }
+            [ty @ .., "proc" | "verb", name] => find_type(self, ty)?
+                .proc_chain(name)
+                .map(|proc| &proc.get().docs)
+                .find(|docs| !docs.is_empty())?,
+            ty => &find_type(self, ty)?.get().docs,
+        };
+        (!docs.is_empty()).then_some(docs)
+    }
+
+    /// List the files which contribute declarations to a type: the type
+    /// itself, and the vars and procs it declares or overrides, in file ID
+    /// order. Built-in declarations are not included.
+    pub fn defining_files(&self, ty: TypeRef) -> Vec<FileId> {
+        let ty = ty.get();
+        let mut locations = vec![ty.location];
+        for var in ty.vars.values() {
+            locations.push(var.value.location);
+            locations.extend(var.declaration.as_ref().map(|decl| decl.location));
+        }
+        for proc in ty.procs.values() {
+            locations.extend(proc.value.iter().map(|value| value.location));
+            locations.extend(proc.declaration.as_ref().map(|decl| decl.location));
+        }
+
+        let mut files: Vec<FileId> = locations.into_iter()
+            .filter(|location| !location.is_builtins() && location.file != FileId::default())
+            .map(|location| location.file)
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    fn matching_paths_by(&self, pattern: Vec<String>) -> impl Iterator<Item=TypeRef<'_>> {
+        self.iter_types().filter(move |ty| glob_matches(&pattern, &ty.path.split('/').skip(1).collect::<Vec<_>>()))
+    }
+
     /// Drop all code ASTs to attempt to reduce memory usage.
     pub fn drop_code(&mut self) {
         for node in self.graph.iter_mut() {
@@ -771,6 +1777,302 @@ impl ObjectTree {
             }
         }
     }
+
+    // ------------------------------------------------------------------------
+    // Incremental updates
+
+    /// Replace everything `file` contributed to the tree with the
+    /// declarations of `new_ast`, without reparsing the rest of the
+    /// environment.
+    ///
+    /// The file's vars and procs are removed from the types which hold them,
+    /// and types which nothing else declares are removed entirely, so types
+    /// split across several files keep the other files' contributions. The
+    /// new declarations are then merged in the same way the parser would
+    /// have: proc definitions take their place among the overrides from
+    /// other files by location, and var values replace those which come
+    /// before them. Finally parent types are reassigned and constants
+    /// re-evaluated, registering any errors on `context` as `finish` does.
+    ///
+    /// Where the file assigned a var which other files also assign on the
+    /// same type, the last of their values takes its place. The diagnostics
+    /// from those checks which run over the whole tree replace the ones they
+    /// registered before, rather than being added again. Type docs are not
+    /// tracked per file and are kept.
+    pub fn update_file(&mut self, context: &Context, file: FileId, new_ast: FileAst) {
+        self.forget_finish_errors(context);
+        self.remove_declarations(file);
+
+        let mut symbols = SymbolIdSource(self.next_symbol_id());
+        let mut grafted = Vec::with_capacity(new_ast.tree.graph.len());
+        for (idx, fragment) in new_ast.tree.graph.into_iter().enumerate() {
+            // parents are always added before their children
+            let target = if idx == 0 {
+                NodeIndex::new(0)
+            } else {
+                let parent = grafted[fragment.parent_path.index()];
+                self.graft_type(parent, &fragment, file, &mut symbols)
+            };
+            self.merge_declarations(target, fragment, &mut symbols);
+            grafted.push(target);
+        }
+
+        self.prune_types(file, &grafted);
+        // everything but the constants builtins start with is evaluated
+        // again, as values may depend on what changed
+        for ty in self.graph.iter_mut() {
+            for var in ty.vars.values_mut() {
+                let preset = var.value.location.is_builtins()
+                    && var.value.expression.is_none()
+                    && !matches!(var.value.constant, Some(Constant::Null(_)));
+                if !preset {
+                    var.value.constant = None;
+                }
+            }
+        }
+
+        let builder = ObjectTreeBuilder {
+            inner: std::mem::take(self),
+            symbols,
+        };
+        *self = builder.finish(context, false);
+    }
+
+    /// Remove the diagnostics registered when the tree was last finished
+    /// from `context`, as finishing it again reports them anew.
+    fn forget_finish_errors(&mut self, context: &Context) {
+        let mut stale: BTreeMap<Location, Vec<&str>> = BTreeMap::new();
+        for (location, description) in self.finish_errors.iter() {
+            stale.entry(*location).or_default().push(description);
+        }
+        context.errors_mut().retain(|error| {
+            let Some(descriptions) = stale.get_mut(&error.location()) else { return true };
+            match descriptions.iter().position(|&each| each == error.description()) {
+                Some(i) => {
+                    descriptions.swap_remove(i);
+                    false
+                }
+                None => true,
+            }
+        });
+        self.finish_errors.clear();
+    }
+
+    fn next_symbol_id(&self) -> SymbolId {
+        let mut max = SymbolId(0);
+        for ty in self.graph.iter() {
+            max = max.max(ty.id);
+            for var in ty.vars.values() {
+                if let Some(decl) = &var.declaration {
+                    max = max.max(decl.id);
+                }
+            }
+            for proc in ty.procs.values() {
+                if let Some(decl) = &proc.declaration {
+                    max = max.max(decl.id);
+                }
+            }
+        }
+        SymbolId(max.0 + 1)
+    }
+
+    fn remove_declarations(&mut self, file: FileId) {
+        for ty in self.graph.iter_mut() {
+            ty.vars.retain(|_, var| {
+                if var.declaration.as_ref().is_some_and(|decl| decl.location.file == file) {
+                    var.declaration = None;
+                }
+                var.overridden.retain(|value| value.location.file != file);
+                if var.value.location.file == file {
+                    if let Some(value) = var.overridden.pop() {
+                        var.value = value;
+                        return true;
+                    }
+                    let Some(decl) = &var.declaration else { return false };
+                    var.value = VarValue {
+                        location: decl.location,
+                        expression: None,
+                        constant: None,
+                        being_evaluated: false,
+                        docs: Default::default(),
+                    };
+                }
+                true
+            });
+            ty.procs.retain(|_, proc| {
+                proc.value.retain(|value| value.location.file != file);
+                if proc.declaration.as_ref().is_some_and(|decl| decl.location.file == file) {
+                    proc.declaration = None;
+                }
+                !proc.value.is_empty() || proc.declaration.is_some()
+            });
+        }
+    }
+
+    fn graft_type(&mut self, parent: NodeIndex, fragment: &Type, file: FileId, symbols: &mut SymbolIdSource) -> NodeIndex {
+        let name = &fragment.path[fragment.path_last_slash + 1..];
+        if let Some(&target) = self[parent].children.get(name) {
+            let node = &mut self[target];
+            if node.location_specificity > fragment.location_specificity || node.location.file == file {
+                node.location = fragment.location;
+                node.location_specificity = fragment.location_specificity;
+            }
+            return target;
+        }
+
+        let node = NodeIndex::new(self.graph.len());
+        self.graph.push(Type {
+            path: fragment.path.clone(),
+            path_last_slash: fragment.path_last_slash,
+            vars: Default::default(),
+            procs: Default::default(),
+            location: fragment.location,
+            location_specificity: fragment.location_specificity,
+            parent_type: NodeIndex::end(),
+            docs: Default::default(),
+            id: symbols.allocate(),
+            children: Default::default(),
+            parent_path: parent,
+        });
+        self[parent].children.insert(name.to_owned(), node);
+        self.types.insert(fragment.path.clone(), node);
+        node
+    }
+
+    fn merge_declarations(&mut self, target: NodeIndex, fragment: Type, symbols: &mut SymbolIdSource) {
+        let node = &mut self[target];
+        node.docs.extend(fragment.docs);
+
+        for (name, mut var) in fragment.vars {
+            if let Some(decl) = var.declaration.as_mut() {
+                decl.id = symbols.allocate();
+            }
+            match node.vars.entry(name) {
+                indexmap::map::Entry::Vacant(slot) => {
+                    slot.insert(var);
+                }
+                indexmap::map::Entry::Occupied(slot) => {
+                    let type_var = slot.into_mut();
+                    if let Some(decl) = var.declaration
+                        && type_var.declaration.as_ref().is_none_or(|existing| existing.location < decl.location)
+                    {
+                        type_var.declaration = Some(decl);
+                    }
+                    if type_var.value.location < var.value.location {
+                        type_var.overridden.push(std::mem::replace(&mut type_var.value, var.value));
+                    } else {
+                        let at = type_var.overridden
+                            .iter()
+                            .position(|each| each.location > var.value.location)
+                            .unwrap_or(type_var.overridden.len());
+                        type_var.overridden.insert(at, var.value);
+                    }
+                }
+            }
+        }
+
+        for (name, proc) in fragment.procs {
+            let type_proc = node.procs.entry(name).or_insert_with(|| TypeProc {
+                value: Vec::new(),
+                declaration: None,
+            });
+            let mut declared_at = None;
+            if let Some(mut decl) = proc.declaration
+                && type_proc.declaration.is_none()
+            {
+                decl.id = symbols.allocate();
+                declared_at = Some(decl.location);
+                type_proc.declaration = Some(decl);
+            }
+            for value in proc.value {
+                // as in the parser, the definition comes before every override
+                let at = if declared_at == Some(value.location) {
+                    0
+                } else {
+                    type_proc.value
+                        .iter()
+                        .position(|each| each.location > value.location)
+                        .unwrap_or(type_proc.value.len())
+                };
+                type_proc.value.insert(at, value);
+            }
+        }
+    }
+
+    /// Remove types which were only declared by `file`, and move those which
+    /// are still declared elsewhere to another of their declarations.
+    fn prune_types(&mut self, file: FileId, grafted: &[NodeIndex]) {
+        let mut keep = vec![true; self.graph.len()];
+        let mut touched = vec![false; self.graph.len()];
+        for &idx in grafted {
+            touched[idx.index()] = true;
+        }
+
+        // children always come after their parents
+        for idx in (1..self.graph.len()).rev() {
+            let ty = &self.graph[idx];
+            if touched[idx] || ty.location.file != file || ty.location.is_builtins() {
+                continue;
+            }
+            let remaining = ty.vars.values()
+                .flat_map(|var| std::iter::once(var.value.location).chain(var.declaration.as_ref().map(|decl| decl.location)))
+                .chain(ty.procs.values().flat_map(|proc| {
+                    proc.value.iter().map(|value| value.location).chain(proc.declaration.as_ref().map(|decl| decl.location))
+                }))
+                .chain(ty.children.values().filter(|child| keep[child.index()]).map(|child| self.graph[child.index()].location))
+                .min();
+            match remaining {
+                Some(location) => {
+                    let ty = &mut self.graph[idx];
+                    ty.location = location;
+                    ty.location_specificity = usize::MAX;
+                }
+                None => keep[idx] = false,
+            }
+        }
+        if keep.iter().all(|&kept| kept) {
+            return;
+        }
+
+        let mut remap = vec![NodeIndex::end(); keep.len()];
+        let mut next = 0;
+        for (idx, &kept) in keep.iter().enumerate() {
+            if kept {
+                remap[idx] = NodeIndex::new(next);
+                next += 1;
+            }
+        }
+        let graph = std::mem::take(&mut self.graph);
+        self.graph = graph.into_iter().zip(keep).filter_map(|(ty, kept)| kept.then_some(ty)).collect();
+        for ty in self.graph.iter_mut() {
+            ty.children.retain(|_, child| {
+                *child = remap[child.index()];
+                *child != NodeIndex::end()
+            });
+            if ty.parent_path != NodeIndex::end() {
+                ty.parent_path = remap[ty.parent_path.index()];
+            }
+            // reassigned once the tree is finished again
+            ty.parent_type = NodeIndex::end();
+        }
+        self.types = self.graph.iter()
+            .enumerate()
+            .skip(1)
+            .map(|(idx, ty)| (ty.path.clone(), NodeIndex::new(idx)))
+            .collect();
+    }
+}
+
+/// The declarations parsed from a single file, for `ObjectTree::update_file`.
+#[derive(Debug)]
+pub struct FileAst {
+    tree: ObjectTree,
+}
+
+impl FileAst {
+    pub(crate) fn new(tree: ObjectTree) -> FileAst {
+        FileAst { tree }
+    }
 }
 
 impl std::ops::Index<NodeIndex> for ObjectTree {
@@ -798,6 +2100,7 @@ impl Default for ObjectTreeBuilder {
         let mut tree = ObjectTree {
             graph: Vec::with_capacity(0x4000),
             types: Default::default(),
+            finish_errors: Vec::new(),
         };
         tree.graph.push(Type {
             path: String::new(),
@@ -821,6 +2124,85 @@ impl Default for ObjectTreeBuilder {
 }
 
 impl ObjectTreeBuilder {
+    /// Warn about vars which redeclare a parent's var with a different type.
+    ///
+    /// Redeclaration itself is sometimes legitimate, so this is only a
+    /// warning, and only conflicting type paths are reported.
+    fn check_var_shadowing(&self, context: &Context) {
+        for ty in self.inner.iter_types() {
+            let Some(parent) = ty.parent_type_without_root() else { continue };
+            for (name, var) in ty.get().vars.iter() {
+                let Some(decl) = &var.declaration else { continue };
+                if decl.location.is_builtins() {
+                    continue;
+                }
+                let Some(parent_decl) = parent.get_var_declaration(name) else { continue };
+                if parent_decl.location.is_builtins() || parent_decl.var_type.type_path == decl.var_type.type_path {
+                    continue;
+                }
+                DMError::new(decl.location, format!("{}/var/{} shadows a parent var with a different type", ty.path, name))
+                    .set_severity(Severity::Warning)
+                    .with_errortype("var_shadowing")
+                    .with_note(parent_decl.location, "parent declaration here")
+                    .register(context);
+            }
+        }
+    }
+
+    /// Warn about types, vars, and procs whose names break the configured
+    /// naming conventions.
+    fn check_naming(&self, context: &Context) {
+        let rules = &context.config().code_standards.naming;
+        if rules.types.is_none() && rules.vars.is_none() && rules.procs.is_none() && rules.verbs.is_none() {
+            return;
+        }
+        for symbol in self.inner.symbols() {
+            if symbol.location.is_builtins() {
+                continue;
+            }
+            let (kind, rule) = match symbol.kind {
+                SymbolKind::Type => ("type", rules.types),
+                SymbolKind::Var => ("var", rules.vars),
+                SymbolKind::Proc(ProcDeclKind::Proc) => ("proc", rules.procs),
+                SymbolKind::Proc(ProcDeclKind::Verb) => ("verb", rules.verbs),
+            };
+            let Some(case) = rule else { continue };
+            let name = symbol.path.rsplit('/').next().unwrap_or_default();
+            if !case.matches(name) {
+                DMError::new(symbol.location, format!("{kind} name is not {}: {}", case.name(), symbol.path))
+                    .set_severity(Severity::Warning)
+                    .with_errortype("naming_convention")
+                    .register(context);
+            }
+        }
+    }
+
+    /// Warn about empty proc bodies and control blocks of the configured
+    /// kinds.
+    fn check_empty_blocks(&self, context: &Context) {
+        let rules = &context.config().code_standards.empty_blocks;
+        if !rules.procs && !rules.ifs && !rules.whiles {
+            return;
+        }
+        let mut visitor = EmptyBlockVisitor { rules, found: Vec::new() };
+        for ty in self.inner.iter_types() {
+            for proc in ty.iter_self_procs() {
+                let value = proc.get();
+                let Some(code) = &value.code else { continue };
+                if rules.procs && code.is_empty() && value.body_range.is_some() {
+                    visitor.found.push((value.location, format!("empty proc body: {}/proc/{}", ty.path, proc.name())));
+                }
+                visitor.visit_proc(&value.parameters, code);
+            }
+        }
+        for (location, message) in visitor.found {
+            DMError::new(location, message)
+                .set_severity(Severity::Warning)
+                .with_errortype("empty_block")
+                .register(context);
+        }
+    }
+
     // ------------------------------------------------------------------------
     // Parsing
 
@@ -848,10 +2230,18 @@ impl ObjectTreeBuilder {
     }
 
     pub(crate) fn finish(mut self, context: &Context, parser_fatal_errored: bool) -> ObjectTree {
+        let first_error = context.errors().len();
         self.assign_parent_types(context);
+        self.break_parent_type_cycles(context);
+        self.check_var_shadowing(context);
+        self.check_naming(context);
+        self.check_empty_blocks(context);
         if !parser_fatal_errored {
             super::constants::evaluate_all(context, &mut self.inner);
         }
+        self.inner.finish_errors = context.errors()[first_error..].iter()
+            .map(|error| (error.location(), error.description().to_owned()))
+            .collect();
         self.inner
     }
 
@@ -956,6 +2346,75 @@ impl ObjectTreeBuilder {
         }
     }
 
+    /// Report types whose parent types lead back to themselves, and reparent
+    /// one type in each such cycle to `/datum` so that walking up the parent
+    /// types always terminates.
+    fn break_parent_type_cycles(&mut self, context: &Context) {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Unvisited,
+            OnChain,
+            Done,
+        }
+
+        let datum = self.inner.types.get("/datum").copied().unwrap_or(NodeIndex::new(0));
+        let mut state = vec![State::Unvisited; self.inner.graph.len()];
+        for start in 0..self.inner.graph.len() {
+            let mut chain = Vec::new();
+            let mut next = Some(NodeIndex::new(start));
+            while let Some(idx) = next {
+                match state[idx.index()] {
+                    State::Done => break,
+                    State::OnChain => {
+                        let begin = chain.iter().position(|&each| each == idx).unwrap();
+                        self.break_cycle(context, &chain[begin..], datum);
+                        break;
+                    }
+                    State::Unvisited => {
+                        state[idx.index()] = State::OnChain;
+                        chain.push(idx);
+                        next = self.inner[idx].parent_type_index();
+                    }
+                }
+            }
+            for idx in chain {
+                state[idx.index()] = State::Done;
+            }
+        }
+    }
+
+    fn break_cycle(&mut self, context: &Context, cycle: &[NodeIndex], datum: NodeIndex) {
+        let explicit = |idx: NodeIndex| self.inner[idx].vars.get("parent_type").map(|var| var.value.location);
+        // Only an explicit parent_type can close a cycle, so blame the
+        // earliest-declared type which has one.
+        let culprit = cycle.iter()
+            .enumerate()
+            .filter(|&(_, &idx)| explicit(idx).is_some())
+            .min_by_key(|&(_, &idx)| idx)
+            .map_or(0, |(i, _)| i);
+
+        let mut description = String::from("parent_type cycle: ");
+        for i in (culprit..cycle.len()).chain(0..culprit) {
+            description.push_str(&self.inner[cycle[i]].path);
+            description.push_str(" -> ");
+        }
+        description.push_str(&self.inner[cycle[culprit]].path);
+
+        let culprit_idx = cycle[culprit];
+        let location = explicit(culprit_idx).unwrap_or(self.inner[culprit_idx].location);
+        let mut error = DMError::new(location, description);
+        for &idx in cycle {
+            if idx != culprit_idx
+                && let Some(location) = explicit(idx)
+            {
+                error.add_note(location, format!("{}/parent_type set here", self.inner[idx].path));
+            }
+        }
+        context.register_error(error);
+
+        self.inner.graph[culprit_idx.index()].parent_type = datum;
+    }
+
     // ------------------------------------------------------------------------
     // Parsing
 
@@ -1000,21 +2459,24 @@ impl ObjectTreeBuilder {
         // TODO: warn and merge docs for repeats
         match self.inner[ty].vars.entry(name.to_owned()) {
             indexmap::map::Entry::Vacant(slot) => {
-                slot.insert(TypeVar { value, declaration })
+                slot.insert(TypeVar { value, declaration, overridden: Vec::new() })
             },
             indexmap::map::Entry::Occupied(slot) => {
                 let type_var = slot.into_mut();
                 if let Some(declaration) = declaration {
                     type_var.declaration = Some(declaration);
                 }
-                type_var.value = value;
+                type_var.overridden.push(std::mem::replace(&mut type_var.value, value));
                 type_var
             },
         }
     }
 
+    // It's fine.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn declare_var(
         &mut self,
+        context: &Context,
         ty: NodeIndex,
         name: &str,
         location: Location,
@@ -1022,6 +2484,15 @@ impl ObjectTreeBuilder {
         var_type: VarType,
         expression: Option<Expression>,
     ) -> &mut TypeVar {
+        if let Some(previous) = self.inner[ty].vars.get(name).and_then(|var| var.declaration.as_ref()) {
+            // Builtin declarations are often restated by user code.
+            if !previous.location.is_builtins() {
+                DMError::new(location, format!("duplicate definition of var/{name}"))
+                    .with_note(previous.location, "previous definition")
+                    .register(context);
+            }
+        }
+
         let id = self.symbols.allocate();
         self.insert_var(ty, name, VarValue {
             location,
@@ -1144,6 +2615,7 @@ impl ObjectTreeBuilder {
             } else {
                 None
             },
+            overridden: Vec::new(),
         })))
     }
 
@@ -1186,6 +2658,7 @@ impl ObjectTreeBuilder {
             location,
             parameters: parameters.into(),
             docs: Default::default(),
+            settings: code.as_ref().map(|code| ProcSettings::from_code(context, code)).unwrap_or_default(),
             code,
             body_range
         };
@@ -4,12 +4,13 @@ use std::borrow::Cow;
 use std::collections::{BTreeMap, VecDeque};
 use std::ops::Range;
 use std::str::FromStr;
+use std::time::Instant;
 
 use super::annotation::*;
 use super::ast::*;
 use super::docs::*;
 use super::lexer::{LocatedToken, Punctuation, Token};
-use super::objtree::{NodeIndex, ObjectTree, ObjectTreeBuilder};
+use super::objtree::{FileAst, NodeIndex, ObjectTree, ObjectTreeBuilder};
 use super::{Context, DMError, FileId, HasLocation, Location, Severity};
 
 // ----------------------------------------------------------------------------
@@ -281,7 +282,7 @@ impl Strength {
 // ----------------------------------------------------------------------------
 // Token-tree-based skip and recovery handling
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum TTKind {
     Paren,   // ()
     Brace,   // {}
@@ -329,6 +330,27 @@ enum LoopContext {
     DoWhile,
 }
 
+/// What a [`Declaration`] declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Type,
+    Var,
+    Proc,
+}
+
+/// A top-level object tree entry, as passed to the callback set by
+/// [`Parser::on_declaration`] once the entry has been parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub kind: DeclarationKind,
+    /// The full path, as in `/obj/item`, `/var/count`, or `/mob/proc/attack`.
+    /// Procs and vars include `proc/`, `verb/`, or `var/` only when those
+    /// were written.
+    pub path: String,
+    /// Where the entry starts.
+    pub location: Location,
+}
+
 /// A single-lookahead, recursive-descent DM parser.
 ///
 /// Results are accumulated into an inner `ObjectTree`. To parse an entire
@@ -353,6 +375,16 @@ pub struct Parser<'ctx, 'an, 'inp> {
     procs: bool,
     procs_bad: u64,
     procs_good: u64,
+    lenient: bool,
+
+    depth: usize,
+
+    tokens_read: usize,
+    token_budget: Option<usize>,
+    deadline: Option<Instant>,
+    budget_exceeded: bool,
+
+    on_declaration: Option<Box<dyn FnMut(Declaration) + 'an>>,
 }
 
 impl<'ctx, 'an, 'inp> HasLocation for Parser<'ctx, 'an, 'inp> {
@@ -384,13 +416,71 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             procs: false,
             procs_bad: 0,
             procs_good: 0,
+            lenient: false,
+
+            depth: 0,
+
+            tokens_read: 0,
+            token_budget: None,
+            deadline: None,
+            budget_exceeded: false,
+
+            on_declaration: None,
         }
     }
 
+    /// Parse proc bodies and keep them in the object tree, where they're
+    /// available from `ProcRef::body`. Off by default, to save memory when
+    /// only declarations are needed.
     pub fn enable_procs(&mut self) {
         self.procs = true;
     }
 
+    /// Recover from syntax errors rather than giving up.
+    ///
+    /// Errors are still registered, but a statement, var value, or object
+    /// tree entry which fails to parse is skipped up to the end of its line.
+    /// Skipped statements and var values are replaced by `Statement::Error`
+    /// and `Expression::Error` placeholders so the rest of the AST survives.
+    /// Pair with `IndentProcessor::enable_lenient` to recover from unclosed
+    /// parentheses.
+    pub fn enable_lenient(&mut self) {
+        self.lenient = true;
+    }
+
+    /// Give up after reading this many tokens.
+    ///
+    /// Macro expansion can produce far more tokens than the input contains,
+    /// so this bounds the work done on pathological files. When the budget is
+    /// exceeded, a "parse budget exceeded" error is registered and parsing
+    /// stops as if the input had ended, keeping everything parsed so far.
+    pub fn set_token_budget(&mut self, tokens: usize) {
+        self.token_budget = Some(tokens);
+    }
+
+    /// Give up once the given time has passed, as with `set_token_budget`.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Call `f` with each top-level type, var, or proc as soon as it has
+    /// been parsed, in source order.
+    ///
+    /// A type is reported once its whole block has been parsed, and entries
+    /// nested inside it are not reported separately. Entries inside a
+    /// top-level `proc/` or `var/` block are top-level themselves.
+    ///
+    /// This is only a notification: every entry is still added to the tree
+    /// as usual, so it does not reduce the parser's own memory use.
+    pub fn on_declaration<F: FnMut(Declaration) + 'an>(&mut self, f: F) {
+        self.on_declaration = Some(Box::new(f));
+    }
+
+    /// Check whether parsing was cut short by a budget.
+    pub fn budget_exceeded(&self) -> bool {
+        self.budget_exceeded
+    }
+
     pub fn annotate_to(&mut self, annotations: &'an mut AnnotationTree) {
         self.annotations = Some(annotations);
         self.procs = true;
@@ -414,6 +504,13 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         self.tree.skip_finish()
     }
 
+    /// Parse the declarations of a single file, to be merged into an
+    /// existing tree with `ObjectTree::update_file`.
+    pub fn parse_file_ast(mut self) -> FileAst {
+        self.run();
+        FileAst::new(self.tree.skip_finish())
+    }
+
     pub fn parse_with_module_docs(mut self) -> (ObjectTree, BTreeMap<FileId, Vec<(u32, DocComment)>>) {
         self.tree.register_builtins();
         self.run();
@@ -428,7 +525,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
     fn run(&mut self) {
         let root = self.root();
-        if let Err(mut e) = self.require(root) {
+        if self.budget_exceeded {
+            // whatever broke when the input was cut off isn't worth reporting
+            self.fatal_errored = true;
+        } else if let Err(mut e) = self.require(root) {
             let loc = e.location();
             e = e.set_severity(Severity::Error);
             e.add_note(loc, "fatal error: the parser cannot continue");
@@ -455,6 +555,30 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
     // ------------------------------------------------------------------------
     // Basic setup
 
+    /// Read the next token of input, unless the budget has run out.
+    fn next_input(&mut self) -> Option<LocatedToken> {
+        if self.budget_exceeded {
+            return None;
+        }
+        self.tokens_read += 1;
+        let out_of_tokens = self.token_budget.is_some_and(|budget| self.tokens_read > budget);
+        // checking the time is comparatively slow, so don't do it every token
+        let out_of_time = self.tokens_read.is_multiple_of(1024) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if out_of_tokens || out_of_time {
+            self.budget_exceeded = true;
+            let note = if out_of_tokens {
+                format!("stopped after reading {} tokens", self.tokens_read - 1)
+            } else {
+                "stopped after running out of time".to_owned()
+            };
+            DMError::new(self.location, "parse budget exceeded")
+                .with_note(self.location, note)
+                .register(self.context);
+            return None;
+        }
+        self.input.next()
+    }
+
     // Call this to get a DMError in the event of an entry point returning None
     fn describe_parse_error(&mut self) -> DMError {
         let expected = self.expected.join(", ");
@@ -505,7 +629,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             if let Some(ref next) = self.next {
                 break next;
             }
-            match self.input.next() {
+            match self.next_input() {
                 Some(LocatedToken { location, token: Token::DocComment(comment) }) => {
                     self.doc_comments_pending.push_back((location, comment));
                 }
@@ -549,7 +673,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             if self.next.is_some() {
                 return Ok(None);
             }
-            match self.input.next() {
+            match self.next_input() {
                 Some(LocatedToken { location, token: Token::DocComment(comment) }) => {
                     if comment.target == target {
                         self.location = location;
@@ -643,6 +767,12 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         self.tree_entries(self.tree.root_index(), None, None, Token::Eof)
     }
 
+    fn declared(&mut self, kind: DeclarationKind, path: String, location: Location) {
+        if let Some(f) = self.on_declaration.as_mut() {
+            f(Declaration { kind, path, location });
+        }
+    }
+
     fn tree_entries(&mut self, current: NodeIndex, proc_builder: Option<ProcDeclBuilder>, var_type: Option<VarTypeBuilder>, terminator: Token) -> Status<()> {
         loop {
             self.expected("';'");
@@ -668,7 +798,19 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 tok if tok == &terminator => break,
                 Token::Punct(Punctuation::Semicolon) => continue,
             } else {
-                require!(self.tree_entry(current, proc_builder, var_type.clone()));
+                let entry = self.tree_entry(current, proc_builder, var_type.clone());
+                if self.lenient {
+                    if let Err(err) = self.require(entry) {
+                        self.context.register_error(err);
+                        self.skip_statement();
+                        // a stray `}` would otherwise stop us making progress
+                        if terminator != Token::Punct(Punctuation::RBrace) && self.peek() == &Token::Punct(Punctuation::RBrace) {
+                            self.take();
+                        }
+                    }
+                } else {
+                    self.require(entry)?;
+                }
             });
         }
         SUCCESS
@@ -678,6 +820,11 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
     // Object tree - types
 
     fn tree_path(&mut self, always_absolute: bool) -> Status<(bool, Vec<Ident>)> {
+        Ok(self.tree_path_located(always_absolute)?.map(|(absolute, parts, _)| (absolute, parts)))
+    }
+
+    /// Parse a tree path, also returning the location of its last ident.
+    fn tree_path_located(&mut self, always_absolute: bool) -> Status<(bool, Vec<Ident>, Location)> {
         // path :: '/'? ident ('/' ident?)*
 
         // handle leading slash
@@ -688,6 +835,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         // 2 is ~66.0%, 4 is ~83.4%, 8 is ~99.9%
         let mut parts = Vec::with_capacity(2);
         // expect at least one ident
+        let mut last_loc = self.updated_location();
         match self.ident_in_seq(parts.len())? {
             Some(i) => parts.push(i),
             None if !(absolute || spurious_lead) => return Ok(None),
@@ -697,14 +845,16 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     Annotation::IncompleteTreePath(absolute, parts.clone())
                 });
                 self.context.register_error(self.error("path has no effect"));
-                return success((absolute, Vec::new()));
+                return success((absolute, Vec::new(), last_loc));
             }
         }
         // followed by ('/' ident)*
         while self.slash()?.is_some() {
             let mut slash_loc = self.location;
+            let ident_loc = self.updated_location();
             if let Some(i) = self.ident_in_seq(parts.len())? {
                 parts.push(i);
+                last_loc = ident_loc;
             } else {
                 // .../operator/<non-ident> = ... / "operator/"
                 // but .../operator/ident = ... / "operator" / "ident"
@@ -721,7 +871,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         }
 
         self.annotate(start, || Annotation::TreePath(absolute || always_absolute, parts.clone()));
-        success((absolute, parts))
+        success((absolute, parts, last_loc))
     }
 
     /// Look for nothing, silently accept `/`, and complain but continue if we see a `.` or `:`.
@@ -769,6 +919,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         use super::lexer::Punctuation::*;
 
         let entry_start = self.updated_location();
+        let top_level = current == self.tree.root_index();
 
         let mut docs = DocCollection::default();
         while let Some(doc_comment) = self.following_doc_comment()? {
@@ -891,6 +1042,9 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
                 let node = self.tree.get_path(current).to_owned();
                 self.annotate(start, || Annotation::TreeBlock(reconstruct_path(&node, proc_builder, var_type.as_ref(), "")));
+                if top_level && current != self.tree.root_index() {
+                    self.declared(DeclarationKind::Type, node, entry_start);
+                }
                 SUCCESS
             }
             Punct(Assign) => {
@@ -899,7 +1053,11 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 handle_relative_type_error!();
                 let location = self.location;
 
-                let expression = require!(self.expression());
+                let expression = if self.lenient {
+                    self.lenient_expression()
+                } else {
+                    require!(self.expression())
+                };
                 // TODO: save `in` expression?
                 let (input_type, _) = require!(self.input_specifier());
 
@@ -915,12 +1073,17 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
                 require!(self.statement_terminator());
 
-                if let Some(mut var_type) = var_type {
+                let declared = if let Some(mut var_type) = var_type {
                     var_type.suffix(&var_suffix);
                     var_type.input_type = input_type;
-                    self.tree.declare_var(current, last_part, location, docs, var_type.build(), Some(expression));
+                    self.tree.declare_var(self.context, current, last_part, location, docs, var_type.build(), Some(expression));
+                    format!("{node}/var/{last_part}")
                 } else {
                     self.tree.override_var(current, last_part, location, docs, expression);
+                    format!("{node}/{last_part}")
+                };
+                if top_level {
+                    self.declared(DeclarationKind::Var, declared, entry_start);
                 }
 
                 SUCCESS
@@ -928,6 +1091,14 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             Punct(LParen) => {
                 // `something(` - proc
                 require!(self.proc_params_and_body(current, proc_builder, last_part, entry_start, absolute, docs));
+                if top_level {
+                    let node = self.tree.get_path(current);
+                    let path = match proc_builder {
+                        Some(builder) => format!("{node}/{}/{last_part}", builder.kind),
+                        None => format!("{node}/{last_part}"),
+                    };
+                    self.declared(DeclarationKind::Proc, path, entry_start);
+                }
                 SUCCESS
             }
             _ => {
@@ -959,7 +1130,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                         var_type.input_type = input_type;
                         let node = self.tree.get_path(current).to_owned();
                         self.annotate(entry_start, || Annotation::Variable(reconstruct_path(&node, proc_builder, Some(&var_type), last_part)));
-                        self.tree.declare_var(current, last_part, self.location, docs, var_type.build(), var_suffix.into_initializer());
+                        self.tree.declare_var(self.context, current, last_part, self.location, docs, var_type.build(), var_suffix.into_initializer());
+                        if top_level {
+                            self.declared(DeclarationKind::Var, format!("{node}/var/{last_part}"), entry_start);
+                        }
                     }
                 } else if ProcDeclKind::from_name(last_part).is_some() {
                     self.error("`proc;` item has no effect")
@@ -973,6 +1147,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     let len = self.tree.get_path(current).chars().filter(|&c| c == '/').count() + path_len;
                     current = self.tree.subtype_or_add(self.location, current, last_part, len);
                     self.tree.extend_docs(current, docs);
+                    if top_level {
+                        let node = self.tree.get_path(current).to_owned();
+                        self.declared(DeclarationKind::Type, node, entry_start);
+                    }
                 }
 
                 SUCCESS
@@ -1078,6 +1256,16 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
         let location = self.location;
         let parameters = require!(self.separated(Comma, RParen, None, Parser::proc_parameter));
+        for (i, parameter) in parameters.iter().enumerate() {
+            if parameter.name.is_empty() || parameter.name == "..." {
+                continue;
+            }
+            if let Some(previous) = parameters[..i].iter().find(|each| each.name == parameter.name) {
+                DMError::new(parameter.location, format!("duplicate proc parameter: {}", parameter.name))
+                    .with_note(previous.location, "previous parameter here")
+                    .register(self.context);
+            }
+        }
         let return_type = self.return_type(proc_builder)?.unwrap_or_default();
 
         // split off a subparser so we can keep parsing the objtree
@@ -1090,9 +1278,11 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             docs.push(comment);
         }
 
+        let mut has_body = true;
         if let Some(()) = self.statement_terminator()? {
             // proc has no body and just ends with `;`
             body_tt.push(LocatedToken::new(self.location, Punct(Semicolon)));
+            has_body = false;
         } else if let Some(()) = self.exact(Punct(LBrace))? {
             // proc has a body starting with `{`
             body_tt.push(LocatedToken::new(self.location, Punct(LBrace)));
@@ -1118,6 +1308,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         let code = if self.procs {
             let result = {
                 let mut subparser: Parser<'ctx, '_, '_> = Parser::new(self.context, body_tt);
+                subparser.lenient = self.lenient;
                 if let Some(a) = self.annotations.as_mut() {
                     subparser.annotations = Some(*a);
                 }
@@ -1135,6 +1326,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     None
                 },
                 Ok(code) => {
+                    check_goto_targets(self.context, &code);
                     Some(code)
                 }
             }
@@ -1142,7 +1334,8 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             None
         };
 
-        match self.tree.register_proc(self.context, location, current, name, proc_builder, parameters, return_type, code, Some(Range{start: body_start, end: self.location})) {
+        let body_range = has_body.then_some(body_start..self.location);
+        match self.tree.register_proc(self.context, location, current, name, proc_builder, parameters, return_type, code, body_range) {
             Ok((idx, proc)) => {
                 proc.docs.extend(docs);
                 // manually performed for borrowck reasons
@@ -1177,7 +1370,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
         // `name` or `obj/name` or `var/obj/name` or ...
         let leading_loc = self.updated_location();
-        let (_absolute, mut path) = leading!(self.tree_path(true));
+        let (_absolute, mut path, name_loc) = leading!(self.tree_path_located(true));
         let name = match path.pop() {
             Some(name) => name,
             None => {
@@ -1199,7 +1392,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 .with_errortype("static_in_proc_parameter")
                 .register(self.context);
         }
-        let location = self.location;
+        let location = name_loc;
         // In parameters, the expression within the annotation is ignored.
         var_type.suffix(&require!(self.var_suffix()));
         // = <expr>
@@ -1326,8 +1519,47 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         success(as_what)
     }
 
+    /// In lenient mode, parse an expression or stand in a placeholder for it.
+    fn lenient_expression(&mut self) -> Expression {
+        let location = self.updated_location();
+        let expression = self.expression();
+        match self.require(expression) {
+            Ok(expression) => expression,
+            Err(err) => {
+                let message = err.description().into();
+                self.context.register_error(err);
+                self.skip_statement();
+                Expression::Error { location, message }
+            }
+        }
+    }
+
+    /// After an error in lenient mode, skip to the end of the statement,
+    /// leaving the terminating `;` or the `}` of the enclosing block.
+    fn skip_statement(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Token::Eof => return,
+                Token::Punct(Punctuation::Semicolon) if depth == 0 => return,
+                Token::Punct(Punctuation::RBrace) if depth == 0 => return,
+                Token::Punct(Punctuation::LBrace) => depth += 1,
+                Token::Punct(Punctuation::RBrace) => depth -= 1,
+                _ => {}
+            }
+            self.take();
+        }
+    }
+
     /// Parse a block
     fn block(&mut self, loop_ctx: &LoopContext) -> Status<Block> {
+        self.descend("block")?;
+        let result = self.block_inner(loop_ctx);
+        self.depth -= 1;
+        result
+    }
+
+    fn block_inner(&mut self, loop_ctx: &LoopContext) -> Status<Block> {
         let mut vars = Vec::new();
         let result = if let Some(()) = self.exact(Token::Punct(Punctuation::LBrace))? {
             let mut statements = Vec::new();
@@ -1336,6 +1568,21 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     break;
                 } else if let Some(()) = self.exact(Token::Punct(Punctuation::Semicolon))? {
                     continue;
+                } else if self.lenient {
+                    let start = self.updated_location();
+                    let statement = self.statement(loop_ctx, &mut vars);
+                    match self.require(statement) {
+                        Ok(statement) => statements.push(statement),
+                        Err(err) => {
+                            let message = err.description().into();
+                            self.context.register_error(err);
+                            self.skip_statement();
+                            statements.push(Spanned::new(start, Statement::Error { location: start, message }));
+                            if self.eof {
+                                break;
+                            }
+                        }
+                    }
                 } else {
                     statements.push(require!(self.statement(loop_ctx, &mut vars)));
                 }
@@ -1517,34 +1764,49 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 })
             }
         } else if let Some(()) = self.exact_ident("spawn")? {
-            let expr;
+            let mut expr = None;
+            let mut delay_range = None;
             if let Some(()) = self.exact(Token::Punct(Punctuation::LParen))? {
+                let delay_start = self.updated_location();
                 expr = self.expression()?;
+                if expr.is_some() {
+                    delay_range = Some(delay_start.span_to(self.updated_location()));
+                }
                 require!(self.exact(Token::Punct(Punctuation::RParen)));
-            } else {
-                expr = None;
             }
+            let body_start = self.updated_location();
+            let block = require!(self.block(&LoopContext::None));
+            let body_range = body_start.span_to(self.updated_location());
+            self.annotate(start, || Annotation::SpawnBlock { delay_range, body_range });
             spanned(Statement::Spawn {
                 delay: expr,
-                block: require!(self.block(&LoopContext::None))
+                block,
             })
         } else if let Some(()) = self.exact_ident("switch")? {
+            let switch_start = self.location();
             require!(self.exact(Token::Punct(Punctuation::LParen)));
             let expr = require!(self.expression());
             require!(self.exact(Token::Punct(Punctuation::RParen)));
             require!(self.exact(Token::Punct(Punctuation::LBrace)));
             let mut cases = Vec::new();
             let default = loop {
+                let case_start = self.updated_location();
                 if let Some(()) = self.exact_ident("if")? {
                     require!(self.exact(Token::Punct(Punctuation::LParen)));
+                    let condition_start = self.updated_location();
                     let what = require!(self.separated(Punctuation::Comma, Punctuation::RParen, None, Parser::case));
+                    // The closing paren was just taken.
+                    let condition = condition_start.span_to(self.location());
                     if what.is_empty() {
                         self.context.register_error(self.error("switch case cannot be empty"));
                     }
                     let block = require!(self.block(loop_ctx));
                     cases.push((Spanned::new(self.location(), what), block));
+                    self.annotate(case_start, || Annotation::SwitchCase { range: condition });
                 } else if let Some(()) = self.exact_ident("else")? {
-                    break Some(require!(self.block(loop_ctx)));
+                    let block = require!(self.block(loop_ctx));
+                    self.annotate(case_start, || Annotation::SwitchDefault);
+                    break Some(block);
                 } else if let Some(()) = self.exact(Token::Punct(Punctuation::Semicolon))? {
                     // Tolerate stray semicolons here because inert doc
                     // comments might synthesize them.
@@ -1553,6 +1815,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 }
             };
             require!(self.exact(Token::Punct(Punctuation::RBrace)));
+            self.annotate(switch_start, || Annotation::Switch);
             spanned(Statement::Switch {
                 input: Box::new(expr),
                 cases: cases.into_boxed_slice(),
@@ -1609,6 +1872,7 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 if let Some(Term::Ident(name)) = expr.as_term() {
                     if let Some(()) = self.exact(Token::Punct(Punctuation::Colon))? {
                         // it's a label! check for a block
+                        self.annotate_precise(start..start.add_columns(name.len() as u16), || Annotation::LabelDefinition(name.to_owned()));
                         return spanned(Statement::Label {
                             name: name.to_owned(),
                             block: require!(self.block(loop_ctx)),
@@ -1744,7 +2008,9 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             success(Statement::Throw(expression))
         } else if let Some(()) = self.exact_ident("goto")? {
             // statement :: 'goto' ident ';'
+            let name_start = self.updated_location();
             let label_name = require!(self.ident());
+            self.annotate_precise(name_start..name_start.add_columns(label_name.len() as u16), || Annotation::GotoTarget(label_name.clone()));
             success(Statement::Goto(label_name))
         // EXPRESSION STATEMENTS
         } else {
@@ -1880,6 +2146,24 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
     }
 
     fn expression_ex(&mut self, strength: Option<Strength>, in_ternary: bool) -> Status<Expression> {
+        self.descend("expression")?;
+        let result = self.expression_ex_inner(strength, in_ternary);
+        self.depth -= 1;
+        result
+    }
+
+    /// Enter a nested expression or block, failing if that would nest too
+    /// deeply, so that hostile input can't overflow the stack.
+    fn descend(&mut self, what: &str) -> Result<(), DMError> {
+        if self.depth >= self.context.config().parser.max_nesting_depth {
+            return Err(self.error(format!("{what} nesting too deep")));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn expression_ex_inner(&mut self, strength: Option<Strength>, in_ternary: bool) -> Status<Expression> {
+        let start = self.updated_location();
         let mut expr = leading!(self.group(in_ternary));
         loop {
             // try to read the next operator
@@ -1896,21 +2180,30 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 }
             }
 
-            self.take();
+            let op_range = self.take_operator();
 
             // trampoline high-strength expression parts as the lhs of the newly found op
-            expr = require!(self.expression_part(expr, info, strength,
+            expr = require!(self.expression_part(start, expr, info, op_range, strength,
                 in_ternary || info.strength == Strength::Conditional));
         }
         success(expr)
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn expression_part(&mut self, lhs: Expression, prev_op: OpInfo, strength: Option<Strength>, in_ternary: bool) -> Status<Expression> {
+    #[allow(clippy::only_used_in_recursion, clippy::too_many_arguments)]
+    fn expression_part(
+        &mut self,
+        lhs_start: Location,
+        lhs: Expression,
+        prev_op: OpInfo,
+        prev_op_range: Range<Location>,
+        strength: Option<Strength>,
+        in_ternary: bool,
+    ) -> Status<Expression> {
         use std::cmp::Ordering;
 
-        let mut bits = vec![lhs];
-        let mut ops = vec![prev_op.oper];
+        let mut bits = vec![(lhs_start, lhs)];
+        let mut ops = vec![(prev_op.oper, prev_op_range)];
+        let mut rhs_start = self.updated_location();
         let mut rhs = require!(self.group(in_ternary));
         loop {
             // try to read the next operator...
@@ -1924,8 +2217,8 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             match info.strength.cmp(&prev_op.strength) {
                 Ordering::Less => {
                     // the operator is stronger than us... recurse down
-                    self.take();
-                    rhs = require!(self.expression_part(rhs, info, strength,
+                    let op_range = self.take_operator();
+                    rhs = require!(self.expression_part(rhs_start, rhs, info, op_range, strength,
                         in_ternary || info.strength == Strength::Conditional));
                 }
                 Ordering::Greater => {
@@ -1934,9 +2227,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 }
                 Ordering::Equal => {
                     // the same strength... push it to the list
-                    self.take();
-                    ops.push(info.oper);
-                    bits.push(rhs);
+                    let op_range = self.take_operator();
+                    ops.push((info.oper, op_range));
+                    bits.push((rhs_start, rhs));
+                    rhs_start = self.updated_location();
                     rhs = require!(self.group(in_ternary));
                 }
             }
@@ -1945,25 +2239,31 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         // Handle ternary ops... they should have their own precedence or else.
         if prev_op.strength == Strength::In {
             // "in" is optionally ternary: (x in 1 to 5)
+            let to_start = self.updated_location();
             if let Some(()) = self.exact_ident("to")? {
+                let to_range = to_start.span_to(self.updated_location());
                 rhs = Expression::BinaryOp {
                     op: BinaryOp::To,
                     lhs: Box::new(rhs),
                     rhs: Box::new(require!(self.expression_ex(Some(Strength::In), in_ternary))),
                 };
+                self.annotate(rhs_start, || Annotation::BinaryOperation { op: BinaryOp::To, range: to_range });
                 // "step" could appear here but doesn't actually do anything.
                 // In for statements it is parsed by `for_range`.
             }
         } else if prev_op.strength == Strength::Conditional {
             // This is essentially a special associativity category.
             let mut result = rhs;
-            while let Some(lhs) = bits.pop() {
+            while let Some((lhs_start, lhs)) = bits.pop() {
+                let (_, question) = ops.pop().unwrap();
                 // Ensure that the next thing we see is a ':' by now.
                 self.expected("':'");
+                let colon_start = self.updated_location();
                 take_match!(self {
                     Token::Punct(Punctuation::Colon) |
                     Token::Punct(Punctuation::CloseColon) => {},
                 } else return self.parse_error());
+                let colon = colon_start.span_to(self.updated_location());
                 // Read the else branch.
                 let else_ = match self.expression_ex(Some(Strength::Conditional), true)? {
                     Some(else_) => else_,
@@ -1979,29 +2279,55 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     cond: Box::new(lhs),
                     if_: Box::new(result),
                     else_: Box::new(else_),
-                }
+                };
+                self.annotate(lhs_start, || Annotation::TernaryOperation { question, colon });
             }
             return success(result);
         }
 
         // everything in 'ops' should be the same strength
         success(if prev_op.strength.right_binding() {
+            let end = self.updated_location();
             let mut result = rhs;
-            for (op, bit) in ops.into_iter().zip(bits.into_iter()).rev() {
+            for ((op, op_range), (bit_start, bit)) in ops.into_iter().zip(bits.into_iter()).rev() {
+                self.annotate_operator(op, bit_start..end, op_range);
                 result = op.build(Box::new(bit), Box::new(result));
             }
             result
         } else {
             let mut iter = bits.into_iter();
-            let mut ops_iter = ops.into_iter();
-            let mut result = iter.next().unwrap();
-            for (item, op) in iter.zip(&mut ops_iter) {
+            let mut ops_iter = ops.into_iter().peekable();
+            let (start, mut result) = iter.next().unwrap();
+            for (_, item) in iter {
+                let (op, op_range) = ops_iter.next().unwrap();
+                // the span ends where the next operator at this level begins
+                let end = ops_iter.peek().unwrap().1.start;
+                self.annotate_operator(op, start..end, op_range);
                 result = op.build(Box::new(result), Box::new(item));
             }
-            ops_iter.next().unwrap().build(Box::new(result), Box::new(rhs))
+            let (op, op_range) = ops_iter.next().unwrap();
+            let end = self.updated_location();
+            self.annotate_operator(op, start..end, op_range);
+            op.build(Box::new(result), Box::new(rhs))
         })
     }
 
+    /// Take the operator token which was just peeked, returning its range.
+    fn take_operator(&mut self) -> Range<Location> {
+        let start = self.updated_location();
+        self.take();
+        start.span_to(self.updated_location())
+    }
+
+    fn annotate_operator(&mut self, op: Op, span: Range<Location>, range: Range<Location>) {
+        match op {
+            Op::BinaryOp(BinaryOp::In) => self.annotate_precise(span, || Annotation::InOperation { range }),
+            Op::BinaryOp(op) => self.annotate_precise(span, || Annotation::BinaryOperation { op, range }),
+            Op::AssignOp(op) => self.annotate_precise(span, || Annotation::AssignOperation { op, range }),
+            Op::TernaryOp(_) => {}
+        }
+    }
+
     // parse an Expression::Base (unary ops, term, follows)
     fn group(&mut self, in_ternary: bool) -> Status<Expression> {
         // Read prefix unary ops
@@ -2083,22 +2409,32 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 // The following is what seems a reasonable approximation.
 
                 // Try to read an ident or path, then read the arguments.
+                let type_start = self.updated_location();
                 if let Some(()) = self.exact(Token::Punct(Punctuation::Dot))? {
                     if let Some(ident) = self.ident()? {
                         // prefab
                         // TODO: arrange for this ident to end up in the prefab's annotation
-                        Term::NewPrefab {
-                            prefab: require!(self.prefab_ex(vec![(PathOp::Dot, ident)])),
-                            args: self.arguments(&[], "New")?,
-                        }
+                        let prefab = require!(self.prefab_ex(vec![(PathOp::Dot, ident)]));
+                        let (args, args_range) = self.new_arguments()?.unzip();
+                        self.annotate(start, || Annotation::NewExpr {
+                            type_source: NewTypeSource::Static(prefab.path.clone()),
+                            args_range,
+                        });
+                        Term::NewPrefab { prefab, args }
                     } else {
                         // bare dot
+                        let type_range = type_start.span_to(self.updated_location());
+                        let (args, args_range) = self.new_arguments()?.unzip();
+                        self.annotate(start, || Annotation::NewExpr {
+                            type_source: NewTypeSource::Dynamic(type_range),
+                            args_range,
+                        });
                         Term::NewMiniExpr {
                             expr: Box::new(MiniExpr {
                                 ident: ".".into(),
                                 fields: Default::default(),
                             }),
-                            args: self.arguments(&[], "New")?,
+                            args,
                         }
                     }
                 } else if let Some(ident) = self.ident()? {
@@ -2107,22 +2443,32 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                     while let Some(item) = self.field(&mut belongs_to, false)? {
                         fields.push(item);
                     }
+                    let type_range = type_start.span_to(self.updated_location());
+                    let (args, args_range) = self.new_arguments()?.unzip();
+                    // the type is whatever the fields evaluate to at runtime
+                    self.annotate(start, || Annotation::NewExpr {
+                        type_source: NewTypeSource::Dynamic(type_range),
+                        args_range,
+                    });
                     Term::NewMiniExpr {
                         expr: Box::new(MiniExpr {
                             ident: ident.into(),
                             fields: fields.into_boxed_slice(),
                         }),
-                        args: self.arguments(&[], "New")?,
+                        args,
                     }
                 } else if let Some(prefab) = self.prefab()? {
-                    Term::NewPrefab {
-                        prefab,
-                        args: self.arguments(&[], "New")?,
-                    }
+                    let (args, args_range) = self.new_arguments()?.unzip();
+                    self.annotate(start, || Annotation::NewExpr {
+                        type_source: NewTypeSource::Static(prefab.path.clone()),
+                        args_range,
+                    });
+                    Term::NewPrefab { prefab, args }
                 } else {
-                    Term::NewImplicit {
-                        args: self.arguments(&[], "New")?,
-                    }
+                    let (args, args_range) = self.new_arguments()?.unzip();
+                    // an empty path for the implicit type
+                    self.annotate(start, || Annotation::NewExpr { type_source: NewTypeSource::Static(Vec::new()), args_range });
+                    Term::NewImplicit { args }
                 }
             },
 
@@ -2141,10 +2487,15 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             },
 
             // term :: 'call' arglist arglist
-            Token::Ident(i, _) if i == "call" => Term::DynamicCall(
-                require!(self.arguments(&[], "call")),
-                require!(self.arguments(&[], "call*")),
-            ),
+            Token::Ident(i, _) if i == "call" => {
+                let call_args = require!(self.arguments(&[], "call"));
+                let args_start = self.updated_location();
+                let args = require!(self.arguments(&[], "call*"));
+                let args_range = args_start..self.updated_location();
+                let proc_name = DynamicCallTarget::from_call_args(&call_args).proc_name().map(ToOwned::to_owned);
+                self.annotate(start, || Annotation::DynamicCall { proc_name, args_range });
+                Term::DynamicCall(call_args, args)
+            },
 
             // term :: 'call_ext' ([library,] function) arglist
             Token::Ident(i, _) if i == "call_ext" => {
@@ -2264,8 +2615,11 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
 
             // term :: '..' arglist
             Token::Punct(Punctuation::Super) => {
-                self.annotate(start, || Annotation::ParentCall);
-                Term::ParentCall(require!(self.arguments(&[], "..")))
+                let args_start = self.updated_location();
+                let args = require!(self.arguments(&[], ".."));
+                let args_range = (!args.is_empty()).then(|| args_start.span_to(self.updated_location()));
+                self.annotate_precise(start..args_start, || Annotation::ParentCall { args_range });
+                Term::ParentCall(args)
             },
 
             // term :: '.'
@@ -2289,10 +2643,14 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
                 }
             },
             Token::Punct(Punctuation::Scope) => {
+                let ident_start = self.updated_location();
                 if let Some(ident) = self.ident()? {
+                    let ident_end = self.updated_location();
                     if let Some(args) = self.arguments(&[], "::")? {
+                        self.annotate_precise(ident_start..ident_end, || Annotation::GlobalCall(ident.clone()));
                         Term::GlobalCall(Ident2::from(ident), args)
                     } else {
+                        self.annotate_precise(ident_start..ident_end, || Annotation::GlobalVar(ident.clone()));
                         Term::GlobalIdent(Ident2::from(ident))
                     }
                 } else {
@@ -2327,7 +2685,12 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             Token::InterpStringBegin(begin) => {
                 let mut parts = Vec::new();
                 loop {
+                    let expr_start = self.updated_location();
                     let expr = self.expression()?;
+                    if expr.is_some() {
+                        let expr_range = expr_start..self.updated_location();
+                        self.annotate_precise(expr_range.clone(), || Annotation::StringInterpolation { expr_range });
+                    }
                     self.expected("']'");
                     take_match!(self {
                         Token::InterpStringPart(part) => {
@@ -2406,7 +2769,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             Some(args) => {
                 if !belongs_to.is_empty() {
                     let past = std::mem::take(belongs_to);
-                    self.annotate_precise(start..end, || Annotation::ScopedCall(past, ident.clone()));
+                    if let Some(global) = global_access(kind, true, &past, &ident) {
+                        self.annotate_precise(start..end, || global);
+                    }
+                    self.annotate_precise(start..end, || scoped_access(kind, true, past, ident.clone()));
                 }
                 match kind {
                     PropertyAccessKind::Scope => {
@@ -2419,7 +2785,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             },
             None => {
                 if !belongs_to.is_empty() {
-                    self.annotate_precise(start..end, || Annotation::ScopedVar(belongs_to.clone(), ident.clone()));
+                    if let Some(global) = global_access(kind, false, belongs_to, &ident) {
+                        self.annotate_precise(start..end, || global);
+                    }
+                    self.annotate_precise(start..end, || scoped_access(kind, false, belongs_to.clone(), ident.clone()));
                     belongs_to.push(ident.clone());
                 }
                 match kind {
@@ -2465,7 +2834,10 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         let end = self.updated_location();
 
         if !belongs_to.is_empty() {
-            self.annotate_precise(start..end, || Annotation::ScopedVar(belongs_to.clone(), ident.clone()));
+            if let Some(global) = global_access(kind, false, belongs_to, &ident) {
+                self.annotate_precise(start..end, || global);
+            }
+            self.annotate_precise(start..end, || scoped_access(kind, false, belongs_to.clone(), ident.clone()));
             belongs_to.push(ident.clone());
         }
         success(Field { kind, ident: ident.into() })
@@ -2476,12 +2848,18 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         leading!(self.exact(Token::Punct(Punctuation::LParen)));
         let start = self.location;
 
+        let is_list = parents.is_empty() && (proc == "list" || proc == "alist");
+
         let mut arguments = Vec::new();
         // TODO: account for implicit nulls again
         let result = self.separated(Punctuation::Comma, Punctuation::RParen, Some(()), |this| {
             let arg_start = this.location;
             let result = this.expression();
             this.annotate(arg_start, || Annotation::ProcArgument(arguments.len()));
+            if is_list && let Ok(Some(_)) = result {
+                let range = arg_start..this.updated_location();
+                this.annotate_precise(range.clone(), || Annotation::ListElement { index: arguments.len(), range });
+            }
             match result {
                 Ok(Some(expr)) => {
                     arguments.push(expr);
@@ -2502,6 +2880,13 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
         }
     }
 
+    /// Read the arguments of a `new` call, along with the range they cover.
+    fn new_arguments(&mut self) -> Status<(Box<[Expression]>, Range<Location>)> {
+        let start = self.updated_location();
+        let args = leading!(self.arguments(&[], "New"));
+        success((args, start.span_to(self.updated_location())))
+    }
+
     fn pick_arguments(&mut self) -> Status<Box<PickArgs>> {
         leading!(self.exact(Token::Punct(Punctuation::LParen)));
         success(require!(self.separated(
@@ -2564,21 +2949,61 @@ impl<'ctx, 'an, 'inp> Parser<'ctx, 'an, 'inp> {
             Some(k) => k,
             None => return SUCCESS,
         };
-        let location = self.location;
-        loop {
+        // Groups are tracked on the heap rather than by recursion, so deeply
+        // nested input can't overflow the stack.
+        let mut open = vec![(kind, self.location)];
+        while let Some(&(kind, location)) = open.last() {
             self.expected(kind.end());
             if kind.is_end(self.peek()) {
                 target.push(LocatedToken::new(self.location(), self.take()));
-                return SUCCESS;
+                open.pop();
+                self.skipping_location = None;
+            } else if self.lenient && kind != TTKind::Brace && self.peek() == &Token::Punct(Punctuation::RBrace) {
+                // Leave unclosed parentheses for the subparser to report,
+                // rather than reading the rest of the file into them.
+                open.pop();
+                self.skipping_location = None;
+            } else if self.peek() == &Token::Eof {
+                self.skipping_location = Some(location);
+                return self.parse_error();
             } else {
                 self.skipping_location = Some(location);
-                require!(self.read_any_tt(target));
-                self.skipping_location = None;
+                let token = self.take();
+                let nested = TTKind::from_token(&token);
+                target.push(LocatedToken::new(self.location(), token));
+                match nested {
+                    Some(nested) => open.push((nested, self.location)),
+                    None => self.skipping_location = None,
+                }
             }
         }
+        SUCCESS
+    }
+}
+
+/// Annotate a scoped proc call or var access, loose or otherwise.
+fn scoped_access(kind: PropertyAccessKind, is_call: bool, belongs_to: Vec<Ident>, ident: Ident) -> Annotation {
+    match (kind.is_loose(), is_call) {
+        (false, true) => Annotation::ScopedCall(belongs_to, ident),
+        (false, false) => Annotation::ScopedVar(belongs_to, ident),
+        (true, true) => Annotation::LooseScopedCall(belongs_to, ident),
+        (true, false) => Annotation::LooseScopedVar(belongs_to, ident),
     }
 }
 
+/// Annotate an access through the `global` scope qualifier, like
+/// `global.foo`, in addition to its scoped access annotation.
+fn global_access(kind: PropertyAccessKind, is_call: bool, belongs_to: &[Ident], ident: &Ident) -> Option<Annotation> {
+    if kind == PropertyAccessKind::Scope || belongs_to != ["global"] {
+        return None;
+    }
+    Some(if is_call {
+        Annotation::GlobalCall(ident.clone())
+    } else {
+        Annotation::GlobalVar(ident.clone())
+    })
+}
+
 fn reconstruct_path(node: &str, proc_deets: Option<ProcDeclBuilder>, var_type: Option<&VarTypeBuilder>, last: &str) -> Vec<Ident> {
     let mut result = Vec::new();
     for entry in node.split('/').skip(1) {
@@ -2597,3 +3022,33 @@ fn reconstruct_path(node: &str, proc_deets: Option<ProcDeclBuilder>, var_type: O
     }
     result
 }
+
+/// Report `goto` statements whose label isn't anywhere in the proc body.
+fn check_goto_targets(context: &Context, code: &[Spanned<Statement>]) {
+    #[derive(Default)]
+    struct Labels<'a> {
+        labels: Vec<&'a str>,
+        gotos: Vec<(Location, &'a str)>,
+    }
+
+    impl<'a> Visitor<'a> for Labels<'a> {
+        fn visit_statement(&mut self, location: Location, statement: &'a Statement) {
+            match statement {
+                Statement::Label { name, .. } => self.labels.push(name),
+                Statement::Goto(name) => self.gotos.push((location, name)),
+                _ => {}
+            }
+            walk_statement(self, location, statement);
+        }
+    }
+
+    let mut visitor = Labels::default();
+    visitor.visit_block(code);
+    for (location, name) in visitor.gotos {
+        if !visitor.labels.contains(&name) {
+            DMError::new(location, format!("goto target not found: {name}"))
+                .with_errortype("undefined_label")
+                .register(context);
+        }
+    }
+}
@@ -1,24 +1,23 @@
 //! The preprocessor.
 use std::borrow::Cow;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::{fmt, io};
 
-use foldhash::HashMap;
+use foldhash::{HashMap, HashSet};
 
 use interval_tree::{range, IntervalTree};
+use serde::{Deserialize, Serialize};
 
 use super::annotation::*;
 use super::ast::Ident;
 use super::docs::{CommentKind, DocCollection, DocComment, DocTarget};
 use super::lexer::*;
+use super::objtree::{ObjectTree, TypeRef};
 use super::{Context, DMError, FileId, HasLocation, Location, Severity};
 
-/// The maximum recursion depth of macro expansion.
-const MAX_RECURSION_DEPTH: usize = 32;
-
 // ----------------------------------------------------------------------------
 // Macro representation and predefined macros
 
@@ -53,6 +52,31 @@ impl Define {
         }
     }
 
+    /// Check whether this is a function-like define.
+    pub fn is_function(&self) -> bool {
+        matches!(self, Define::Function { .. })
+    }
+
+    /// Check whether two defines expand identically, ignoring their docs.
+    pub fn same_expansion(&self, other: &Define) -> bool {
+        match (self, other) {
+            (Define::Constant { subst: a, .. }, Define::Constant { subst: b, .. }) => a == b,
+            (
+                Define::Function { params: params_a, subst: a, variadic: variadic_a, .. },
+                Define::Function { params: params_b, subst: b, variadic: variadic_b, .. },
+            ) => params_a == params_b && a == b && variadic_a == variadic_b,
+            _ => false,
+        }
+    }
+
+    /// Get this define's parameter names, or `None` if it is object-like.
+    pub fn params(&self) -> Option<&[Ident]> {
+        match self {
+            Define::Constant { .. } => None,
+            Define::Function { params, .. } => Some(params),
+        }
+    }
+
     pub fn display_with_name<'a>(&'a self, name: &'a str) -> impl fmt::Display + 'a {
         NameAndDefine(name, self)
     }
@@ -104,6 +128,7 @@ impl DefineHistory {
             env_file: self.env_file.clone(),
             include_stack: Default::default(),
             include_locations: Default::default(),
+            include_graph: Default::default(),
             multiple_locations: Default::default(),
             history: Default::default(),  // TODO: support branching a second time
             defines,
@@ -118,7 +143,14 @@ impl DefineHistory {
             danger_idents: Default::default(),
             docs_in: Default::default(),
             in_interp_string: 0,
+            undefined_uses: Default::default(),
+            undefined_names: Default::default(),
+            possible_macro_call: None,
             annotations: None,
+            expansion_steps: Default::default(),
+            lint_controls_seen: 0,
+            keep_inactive: false,
+            inactive: None,
         }
     }
 
@@ -129,6 +161,7 @@ impl DefineHistory {
             env_file: self.env_file.clone(),
             include_stack: Default::default(),
             include_locations: Default::default(),
+            include_graph: Default::default(),
             multiple_locations: Default::default(),
             history: Default::default(),  // TODO: support branching a second time
             defines: DefineMap::from_history(self, self.last_input_loc),
@@ -143,7 +176,14 @@ impl DefineHistory {
             danger_idents: Default::default(),
             docs_in: Default::default(),
             in_interp_string: 0,
+            undefined_uses: Default::default(),
+            undefined_names: Default::default(),
+            possible_macro_call: None,
             annotations: None,
+            expansion_steps: Default::default(),
+            lint_controls_seen: 0,
+            keep_inactive: false,
+            inactive: None,
         }
     }
 }
@@ -201,6 +241,11 @@ impl DefineMap {
         self.inner.get(key).and_then(|v| v.last())
     }
 
+    /// Iterate over the current definition of each macro.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &(Location, Define))> {
+        self.inner.iter().filter_map(|(name, stack)| Some((name.as_str(), stack.last()?)))
+    }
+
     /// Inserts a key-value pair into the map.
     ///
     /// Returns `None` if the key was not present, or its most recent location
@@ -257,6 +302,384 @@ impl DefineMap {
     */
 }
 
+/// A token output by the preprocessor, along with the macro it came from.
+#[derive(Debug, Clone)]
+pub struct ExpandedToken {
+    pub token: LocatedToken,
+    /// The outermost macro whose expansion produced this token, or `None` if
+    /// the token was read directly from the source.
+    ///
+    /// Expanded tokens are located at the use of that macro, so nested
+    /// expansions are attributed to the macro the source actually named.
+    pub macro_name: Option<Rc<str>>,
+}
+
+/// One macro substitution made while expanding a use site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpansionStep {
+    /// The name of the macro which was substituted.
+    pub name: String,
+    /// Where that macro was defined.
+    pub definition_location: Location,
+    /// The invocation which was replaced, including any arguments.
+    pub before: String,
+    /// The tokens it was replaced with.
+    pub after: String,
+}
+
+/// Whether a name is written in capitals, as macros conventionally are.
+fn is_macro_like(name: &str) -> bool {
+    name.bytes().any(|b| b.is_ascii_uppercase())
+        && name.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+}
+
+fn tokens_to_string<'a, I: IntoIterator<Item = &'a Token>>(tokens: I) -> String {
+    use std::fmt::Write;
+
+    let mut string = String::new();
+    let mut prev: Option<&Token> = None;
+    for token in tokens {
+        if token.is_whitespace() {
+            continue;
+        }
+        if let Some(prev) = prev && token.separate_from(prev) {
+            string.push(' ');
+        }
+        let _ = write!(string, "{token}");
+        prev = Some(token);
+    }
+    string
+}
+
+/// Paste two tokens together for `##`, or `None` if the text of the two
+/// together isn't a single token.
+fn paste_tokens(file: FileId, left: &Token, right: &Token) -> Option<Token> {
+    let text = format!("{left}{right}");
+    // Whatever fails to lex is left unpasted, so use a scratch context.
+    let scratch = Context::default();
+    let mut tokens = Lexer::new(&scratch, file, text.as_bytes())
+        .map(|token| token.token)
+        .filter(|token| !token.is_whitespace());
+    let pasted = match (tokens.next(), tokens.next()) {
+        (Some(Token::Ident(ident, _)), None) => Token::Ident(ident, matches!(right, Token::Ident(_, true))),
+        (Some(token), None) => token,
+        _ => return None,
+    };
+    scratch.errors().is_empty().then_some(pasted)
+}
+
+/// Whether a failure to paste this token is worth reporting. Pastes with
+/// punctuation, like the `.proc/##X` in `PROC_REF`, are left alone.
+fn is_word_token(token: &Token) -> bool {
+    matches!(token, Token::Ident(..) | Token::Int(_) | Token::Float(_))
+}
+
+// ----------------------------------------------------------------------------
+// The graph of #include relationships
+
+/// How an `#include` names its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IncludeKind {
+    /// `#include "file.dm"`, found relative to the including file or the
+    /// environment.
+    Local,
+    /// `#include <library.dme>`, found in the configured library directories.
+    System,
+}
+
+/// A single `#include` directive: the file it appears in, the file it names,
+/// and where the directive was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncludeEdge {
+    pub from: FileId,
+    pub to: FileId,
+    pub location: Location,
+}
+
+/// The directed graph of `#include` relationships between files.
+///
+/// Every directive which names a DM file is an edge, including those which
+/// were skipped because the file had already been included.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeGraph {
+    edges: Vec<IncludeEdge>,
+}
+
+impl IncludeGraph {
+    /// All edges, in the order their directives were processed.
+    pub fn edges(&self) -> &[IncludeEdge] {
+        &self.edges
+    }
+
+    /// All files which include or are included by another, in the order they
+    /// were first seen.
+    pub fn files(&self) -> Vec<FileId> {
+        let mut files = Vec::new();
+        for edge in self.edges.iter() {
+            for file in [edge.from, edge.to] {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    /// The directives which appear in the given file.
+    pub fn includes(&self, file: FileId) -> impl Iterator<Item = &IncludeEdge> {
+        self.edges.iter().filter(move |edge| edge.from == file)
+    }
+
+    /// The directives which name the given file.
+    pub fn included_by(&self, file: FileId) -> impl Iterator<Item = &IncludeEdge> {
+        self.edges.iter().filter(move |edge| edge.to == file)
+    }
+
+    /// Find include cycles, each as the chain of edges leading back to its
+    /// first file.
+    ///
+    /// A cycle is reported for each directive that closes one during a
+    /// depth-first walk, rather than every elementary cycle in the graph.
+    pub fn cycles(&self) -> Vec<Vec<IncludeEdge>> {
+        let mut cycles = Vec::new();
+        let mut finished = Vec::new();
+        for file in self.files() {
+            if !finished.contains(&file) {
+                self.walk_cycles(file, &mut Vec::new(), &mut finished, &mut cycles);
+            }
+        }
+        cycles
+    }
+
+    fn walk_cycles(
+        &self,
+        file: FileId,
+        path: &mut Vec<IncludeEdge>,
+        finished: &mut Vec<FileId>,
+        cycles: &mut Vec<Vec<IncludeEdge>>,
+    ) {
+        for &edge in self.includes(file) {
+            if finished.contains(&edge.to) {
+                continue;
+            }
+            if let Some(start) = path.iter().position(|each| each.from == edge.to) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(edge);
+                cycles.push(cycle);
+            } else if edge.to == edge.from {
+                cycles.push(vec![edge]);
+            } else {
+                path.push(edge);
+                self.walk_cycles(edge.to, path, finished, cycles);
+                path.pop();
+            }
+        }
+        finished.push(file);
+    }
+
+    /// Register a warning for each include cycle.
+    pub fn report_cycles(&self, context: &Context) {
+        for cycle in self.cycles() {
+            let mut chain = context.file_path(cycle[0].from).display().to_string();
+            for edge in cycle.iter() {
+                chain.push_str(" -> ");
+                chain.push_str(&context.file_path(edge.to).display().to_string());
+            }
+            let (last, rest) = cycle.split_last().unwrap();
+            let mut error = DMError::new(last.location, format!("include cycle: {chain}"))
+                .set_severity(Severity::Warning)
+                .with_errortype("include_cycle");
+            for edge in rest {
+                error = error.with_note(edge.location, "included here");
+            }
+            context.register_error(error);
+        }
+    }
+
+    /// Find directives which contribute no used symbols to the file they
+    /// appear in.
+    ///
+    /// A directive counts as used if the including file refers to a macro,
+    /// type, proc, or var declared in the included file or in any file it
+    /// includes in turn, according to the references recorded in
+    /// `annotations` and resolved against `tree`. This is a heuristic:
+    ///
+    /// * Types, procs, and vars are attributed to the file of their first
+    ///   declaration, so an include which only adds overrides to things
+    ///   declared elsewhere is reported.
+    /// * References which the annotations don't resolve, such as calls on
+    ///   locals of unknown type, are not seen.
+    /// * DM symbols are global, so an include whose symbols are used only by
+    ///   later files is reported even though removing it would break them.
+    ///   For this reason directives in files which aren't themselves
+    ///   included, like the `.dme`, are never reported, but those in a file
+    ///   which only includes others are.
+    pub fn unused_includes(&self, annotations: &AnnotationTree, tree: &ObjectTree) -> Vec<IncludeEdge> {
+        let mut used: HashMap<FileId, Vec<FileId>> = HashMap::default();
+        for (place, annotation) in annotations.iter() {
+            if let Some(definition) = referenced_location(annotations, place.start, annotation, tree) {
+                let files = used.entry(place.start.file).or_default();
+                if definition.file != place.start.file && !files.contains(&definition.file) {
+                    files.push(definition.file);
+                }
+            }
+        }
+
+        self.edges
+            .iter()
+            .filter(|edge| self.included_by(edge.from).next().is_some())
+            .filter(|edge| {
+                let Some(used) = used.get(&edge.from) else {
+                    return true;
+                };
+                let mut provided = vec![edge.to];
+                let mut i = 0;
+                while let Some(&file) = provided.get(i) {
+                    if used.contains(&file) {
+                        return false;
+                    }
+                    for next in self.includes(file) {
+                        if !provided.contains(&next.to) {
+                            provided.push(next.to);
+                        }
+                    }
+                    i += 1;
+                }
+                true
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Register a warning for each included file which lacks an include
+    /// guard, as found by `include_guard`.
+    ///
+    /// Files are read from disk relative to `root`, the environment's
+    /// directory. Those which can't be read, and the files only including
+    /// others, like the `.dme`, are skipped.
+    pub fn report_missing_include_guards(&self, context: &Context, root: &Path) {
+        for file in self.files() {
+            if self.included_by(file).next().is_none() {
+                continue;
+            }
+            let path = context.file_path(file).to_owned();
+            let Ok(source) = std::fs::read_to_string(root.join(&path)) else {
+                continue;
+            };
+            if include_guard(file, &source).is_none() {
+                DMError::new(Location { file, line: 1, column: 1 }, format!("missing include guard: {}", path.display()))
+                    .set_severity(Severity::Warning)
+                    .with_errortype("missing_include_guard")
+                    .register(context);
+            }
+        }
+    }
+
+    /// Register a warning for each include found by `unused_includes`.
+    pub fn report_unused_includes(&self, context: &Context, annotations: &AnnotationTree, tree: &ObjectTree) {
+        for edge in self.unused_includes(annotations, tree) {
+            DMError::new(edge.location, format!("unused include: {}", context.file_path(edge.to).display()))
+                .set_severity(Severity::Hint)
+                .with_errortype("unused_include")
+                .register(context);
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Include guards
+
+/// An include guard wrapping the whole of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeGuard {
+    /// The name of the macro the guard defines.
+    pub name: String,
+    /// Where the guard's `#ifndef` is.
+    pub location: Location,
+}
+
+/// Find the include guard of a file's source, if it has one.
+///
+/// A guard is an `#ifndef NAME` followed by `#define NAME` before any other
+/// code, with the `#endif` which closes the `#ifndef` ending the file. Blank
+/// lines and comments may appear anywhere around them.
+pub fn include_guard(file: FileId, source: &str) -> Option<IncludeGuard> {
+    let lexed = lex_file(file, source);
+    let mut lines = lexed.tokens
+        .split(|lexed| lexed.token == Token::Punct(Punctuation::Newline))
+        .filter(|line| !line.iter().all(|lexed| matches!(lexed.token, Token::DocComment(_))));
+
+    // the name of a directive line, and the identifier following it
+    fn directive(line: &[LexedToken]) -> Option<(&str, Option<&str>)> {
+        let [hash, LexedToken { token: Token::Ident(directive, _), .. }, rest @ ..] = line else {
+            return None;
+        };
+        if hash.token != Token::Punct(Punctuation::Hash) {
+            return None;
+        }
+        let name = match rest.first() {
+            Some(LexedToken { token: Token::Ident(name, _), .. }) => Some(name.as_str()),
+            _ => None,
+        };
+        Some((directive.as_str(), name))
+    }
+
+    let first = lines.next()?;
+    let Some(("ifndef", Some(name))) = directive(first) else {
+        return None;
+    };
+    if directive(lines.next()?) != Some(("define", Some(name))) {
+        return None;
+    }
+    let mut depth = 1usize;
+    for line in lines.by_ref() {
+        match directive(line) {
+            Some(("if" | "ifdef" | "ifndef", _)) => depth += 1,
+            Some(("endif", _)) => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    (depth == 0 && lines.next().is_none()).then(|| IncludeGuard {
+        name: name.to_owned(),
+        location: first[0].location,
+    })
+}
+
+/// Find where the symbol referred to by an annotation is declared.
+fn referenced_location(annotations: &AnnotationTree, loc: Location, annotation: &Annotation, tree: &ObjectTree) -> Option<Location> {
+    // the type whose code contains the reference
+    let scope = || -> TypeRef<'_> {
+        let path = match annotations.enclosing_proc(loc) {
+            Some((path, _)) => path,
+            None => annotations.enclosing_type(loc).unwrap_or_default(),
+        };
+        tree.type_by_path_approx(path).1
+    };
+    let declared_type = |ty: TypeRef| if ty.is_root() { None } else { Some(ty.get().location) };
+    match annotation {
+        Annotation::MacroUse { definition_location, .. } => Some(*definition_location),
+        Annotation::TypePath(path) => declared_type(tree.type_by_path(path.iter().map(|(_, name)| name))?),
+        Annotation::TreeBlock(path) | Annotation::TreePath(true, path) => declared_type(tree.type_by_path_approx(path).1),
+        Annotation::LocalVarScope(var_type, _) => declared_type(tree.type_by_path(var_type.type_path.iter())?),
+        Annotation::UnscopedCall(name) => Some(scope().get_proc(name)?.get().location),
+        Annotation::UnscopedVar(name) => Some(scope().get_var_declaration(name)?.location),
+        Annotation::ScopedCall(base, name) | Annotation::LooseScopedCall(base, name) => {
+            Some(tree.resolve_scope(scope(), base)?.get_proc(name)?.get().location)
+        }
+        Annotation::ScopedVar(base, name) | Annotation::LooseScopedVar(base, name) => {
+            Some(tree.resolve_scope(scope(), base)?.get_var_declaration(name)?.location)
+        }
+        Annotation::GlobalVar(name) => Some(tree.resolve_global_var(name)?.declaration.as_ref()?.location),
+        Annotation::GlobalCall(name) => Some(tree.resolve_global_proc(name)?.get().location),
+        _ => None,
+    }
+}
+
 // ----------------------------------------------------------------------------
 // The stack of currently #included files
 
@@ -268,7 +691,7 @@ enum Include<'ctx> {
         lexer: Lexer<'ctx>,
     },
     Expansion {
-        //name: Ident,
+        name: Rc<str>,
         location: Location,
         tokens: VecDeque<Token>,
     },
@@ -312,6 +735,25 @@ impl<'ctx> IncludeStack<'ctx> {
     fn in_expansion(&self) -> bool {
         matches!(self.stack.last(), Some(Include::Expansion { .. }))
     }
+
+    /// How many macro expansions are nested inside the current file.
+    fn expansion_depth(&self) -> usize {
+        self.stack.iter()
+            .rev()
+            .take_while(|include| matches!(include, Include::Expansion { .. }))
+            .count()
+    }
+
+    /// The name of the outermost macro currently being expanded.
+    fn outermost_expansion(&self) -> Option<&Rc<str>> {
+        self.stack.iter()
+            .rev()
+            .map_while(|include| match include {
+                Include::Expansion { name, .. } => Some(name),
+                Include::File { .. } => None,
+            })
+            .last()
+    }
 }
 
 impl<'ctx> Iterator for IncludeStack<'ctx> {
@@ -385,14 +827,21 @@ pub struct Preprocessor<'ctx> {
 
     include_stack: IncludeStack<'ctx>,
     include_locations: HashMap<FileId, Location>,
+    include_graph: IncludeGraph,
     // list of files with #pragma multiple to allow for more then one include
     // should this be done as an enum in include_locations instead?
     multiple_locations: HashMap<FileId, Location>,
     last_input_loc: Location,
-    output: VecDeque<LocatedToken>,
+    output: VecDeque<ExpandedToken>,
     ifdef_stack: Vec<Ifdef>,
     ifdef_history: IntervalTree<Location, bool>,
     annotations: Option<AnnotationTree>,
+    // macro substitutions made at each use site, outermost first
+    expansion_steps: BTreeMap<Location, Vec<ExpansionStep>>,
+    lint_controls_seen: usize,
+    keep_inactive: bool,
+    // where the current inactive region began, and its tokens so far
+    inactive: Option<(Location, Vec<LocatedToken>)>,
 
     history: InnerDefineHistory,
     defines: DefineMap,
@@ -403,6 +852,12 @@ pub struct Preprocessor<'ctx> {
     last_printable_input_loc: Location,
     danger_idents: HashMap<Ident, Location>,
     in_interp_string: u32,
+    // names used where a macro would be expanded which were never defined
+    undefined_uses: Vec<(String, Location)>,
+    // names which have been `#undef`'d
+    undefined_names: HashSet<String>,
+    // an undefined name which is a use if a `(` follows it
+    possible_macro_call: Option<(String, Location)>,
 
     docs_in: VecDeque<(Location, DocComment)>,
 }
@@ -429,6 +884,7 @@ impl<'ctx> Preprocessor<'ctx> {
             env_file,
             include_stack: IncludeStack { stack: vec![include] },
             include_locations: Default::default(),
+            include_graph: Default::default(),
             multiple_locations: Default::default(),
             history: Default::default(),
             defines: DefineMap::with_builtins(),
@@ -443,7 +899,14 @@ impl<'ctx> Preprocessor<'ctx> {
             danger_idents: Default::default(),
             docs_in: Default::default(),
             in_interp_string: 0,
+            undefined_uses: Default::default(),
+            undefined_names: Default::default(),
+            possible_macro_call: None,
             annotations: None,
+            expansion_steps: Default::default(),
+            lint_controls_seen: 0,
+            keep_inactive: false,
+            inactive: None,
         })
     }
 
@@ -458,6 +921,7 @@ impl<'ctx> Preprocessor<'ctx> {
             env_file,
             include_stack: IncludeStack { stack: vec![include] },
             include_locations: Default::default(),
+            include_graph: Default::default(),
             multiple_locations: Default::default(),
             history: Default::default(),
             defines: DefineMap::with_builtins(),
@@ -472,7 +936,14 @@ impl<'ctx> Preprocessor<'ctx> {
             danger_idents: Default::default(),
             docs_in: Default::default(),
             in_interp_string: 0,
+            undefined_uses: Default::default(),
+            undefined_names: Default::default(),
+            possible_macro_call: None,
             annotations: None,
+            expansion_steps: Default::default(),
+            lint_controls_seen: 0,
+            keep_inactive: false,
+            inactive: None,
         }
     }
 
@@ -499,6 +970,105 @@ impl<'ctx> Preprocessor<'ctx> {
         }
     }
 
+    /// Define a macro as though it were provided from outside the sources,
+    /// like a `-D` flag to a C compiler.
+    ///
+    /// The value is lexed as the macro's substitution, and `None` defines it
+    /// as empty. The macro is located among the builtins, so a source file
+    /// defining it again is reported as a redefinition.
+    pub fn define_external(&mut self, name: &str, value: Option<&str>) {
+        let location = Location::builtins();
+        let subst = match value {
+            Some(value) => Lexer::new(self.context, location.file, value.as_bytes())
+                .map(|token| token.token)
+                .filter(|token| !token.is_whitespace())
+                .collect(),
+            None => Vec::new(),
+        };
+        self.defines.insert(name.to_owned(), (location, Define::Constant { subst, docs: Default::default() }));
+    }
+
+    /// Iterate over the macros which are currently defined, along with where
+    /// each was defined.
+    pub fn defines(&self) -> impl Iterator<Item = (&str, &(Location, Define))> {
+        self.defines.iter()
+    }
+
+    /// Fold the constant macros whose names start with `prefix` to integers,
+    /// such as a family of `#define FLAG_X (1<<N)` bit flags.
+    ///
+    /// Other constant macros used by a value are expanded first. Macros which
+    /// don't fold to a whole number are skipped.
+    pub fn integer_defines(&self, prefix: &str) -> HashMap<String, i64> {
+        // Skipped macros may well not parse, so use a scratch context to keep
+        // their errors from being reported.
+        let scratch = Context::default();
+        let mut result = HashMap::default();
+        for (name, (location, define)) in self.defines.iter() {
+            let Define::Constant { subst, .. } = define else { continue };
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            let mut tokens = Vec::new();
+            if !self.expand_constants(subst, *location, &mut vec![name], &mut tokens) {
+                continue;
+            }
+            let Ok(expr) = crate::parser::parse_expression(&scratch, *location, tokens) else { continue };
+            if let Ok(crate::constants::Constant::Float(value)) = crate::constants::preprocessor_evaluate(*location, expr, &self.defines, None)
+                && value.fract() == 0.0
+            {
+                result.insert(name.to_owned(), value as i64);
+            }
+        }
+        result
+    }
+
+    /// Expand the constant macros in a substitution, returning false if it
+    /// uses a function macro or nests too deeply.
+    fn expand_constants<'a>(&'a self, subst: &'a [Token], location: Location, expanding: &mut Vec<&'a str>, out: &mut Vec<LocatedToken>) -> bool {
+        for token in subst {
+            if let Token::Ident(ident, _) = token
+                && !expanding.contains(&ident.as_str())
+                && let Some((_, define)) = self.defines.get(ident)
+            {
+                match define {
+                    Define::Constant { subst, .. } if expanding.len() < self.context.config().parser.max_macro_expansion_depth => {
+                        expanding.push(ident);
+                        let expanded = self.expand_constants(subst, location, expanding, out);
+                        expanding.pop();
+                        if !expanded {
+                            return false;
+                        }
+                    }
+                    _ => return false,
+                }
+                continue;
+            }
+            out.push(LocatedToken::new(location, token.clone()));
+        }
+        true
+    }
+
+    /// List the names used where a macro would be expanded which were never
+    /// defined there, in the order they were read, to catch misspelled
+    /// macros.
+    ///
+    /// `#ifdef`, `#ifndef`, and `defined()` are ignored, as testing for an
+    /// undefined name is legitimate. Any other name in an `#if` which isn't
+    /// a macro is listed, as is any use of a name after it was `#undef`'d.
+    /// In code, where a name may as well be a var or proc, only names
+    /// written in capitals and called like function-like macros, as in
+    /// `SEND_SIGNAL(...)`, are listed.
+    pub fn undefined_macro_uses(&self) -> Vec<(String, Location)> {
+        self.undefined_uses.clone()
+    }
+
+    /// Access the history of macros which have been `#undef`'d, spanning
+    /// from their definition to the `#undef`.
+    pub fn define_history(&self) -> &IntervalTree<Location, (String, Define)> {
+        &self.history
+    }
+
     /// Access the ifdef history.
     pub fn ifdef_history(&self) -> &IntervalTree<Location, bool> {
         &self.ifdef_history
@@ -514,6 +1084,11 @@ impl<'ctx> Preprocessor<'ctx> {
     }
     */
 
+    /// Get the graph of `#include` relationships seen so far.
+    pub fn include_graph(&self) -> IncludeGraph {
+        self.include_graph.clone()
+    }
+
     /// Push a DM file to the top of this preprocessor's stack.
     pub fn push_file<R: io::Read + 'static>(&mut self, path: PathBuf, read: R) -> Result<FileId, DMError> {
         let idx = self.context.register_file(&path);
@@ -528,11 +1103,33 @@ impl<'ctx> Preprocessor<'ctx> {
     /// Enable source file annotations.
     pub fn enable_annotations(&mut self) {
         self.annotations = Some(AnnotationTree::default());
+        self.lint_controls_seen = self.context.lint_controls().len();
     }
 
     /// Retrieve computer annotations.
+    ///
+    /// Lint control directives lexed since annotations were enabled are
+    /// included as `LintControl` annotations.
     pub fn take_annotations(&mut self) -> Option<AnnotationTree> {
-        self.annotations.take()
+        let mut annotations = self.annotations.take()?;
+        for control in self.context.lint_controls().get(self.lint_controls_seen..).unwrap_or_default() {
+            annotations.insert(control.directive.clone(), Annotation::LintControl {
+                rule: control.rule.clone(),
+                enable: control.enable,
+                range: control.range.clone(),
+            });
+        }
+        Some(annotations)
+    }
+
+    /// Set whether code in inactive conditional branches is annotated.
+    ///
+    /// When enabled along with annotations, each region skipped by `#if` and
+    /// friends is marked with `Annotation::InactiveBranch`, and its tokens
+    /// are parsed for annotations as best they can be. That code is not
+    /// macro-expanded, and errors in it are discarded rather than reported.
+    pub fn keep_inactive_branches(&mut self, keep: bool) {
+        self.keep_inactive = keep;
     }
 
     // ------------------------------------------------------------------------
@@ -544,6 +1141,7 @@ impl<'ctx> Preprocessor<'ctx> {
         }
 
         if let Some(annotations) = self.annotations.as_mut() {
+            let docs = docs.filter(|docs| !docs.is_empty()).map(|docs| annotations.intern_macro_docs(docs));
             annotations.insert(
                 self.last_input_loc .. self.last_input_loc.add_columns(ident.len() as u16),
                 Annotation::MacroUse {
@@ -555,6 +1153,32 @@ impl<'ctx> Preprocessor<'ctx> {
         }
     }
 
+    fn record_expansion(&mut self, step: ExpansionStep) {
+        if self.annotations.is_none() {
+            return;
+        }
+        // Expansions nested inside another are located at the outer use site,
+        // so they extend its trace rather than starting their own.
+        let steps = self.expansion_steps.entry(self.last_input_loc).or_default();
+        if !self.include_stack.in_expansion() {
+            steps.clear();
+        }
+        steps.push(step);
+    }
+
+    /// Get the sequence of macro substitutions which produced the tokens at
+    /// the given location, outermost first.
+    ///
+    /// Only available when annotations are enabled.
+    pub fn expansion_trace(&self, at: Location) -> Option<Vec<ExpansionStep>> {
+        let (start, steps) = self.expansion_steps.range(..=at).next_back()?;
+        let first = steps.first()?;
+        if start.file != at.file || start.line != at.line || at.column >= start.column + first.name.len() as u16 {
+            return None;
+        }
+        Some(steps.clone())
+    }
+
     fn in_environment(&self) -> bool {
         for include in self.include_stack.stack.iter().rev() {
             if let Include::File { ref path, .. } = *include {
@@ -564,6 +1188,25 @@ impl<'ctx> Preprocessor<'ctx> {
         false
     }
 
+    /// The paths an `#include` of `path` may refer to, in the order they are
+    /// tried.
+    fn include_candidates(&self, path: &Path, kind: IncludeKind) -> Vec<PathBuf> {
+        let env_dir = self.env_file.parent().unwrap();
+        match kind {
+            IncludeKind::Local => vec![
+                // 1. relative to file in which `#include` appears.
+                self.include_stack.top_file_path().parent().unwrap().join(path),
+                // 2. relative to root `.dme` file.
+                env_dir.join(path),
+            ],
+            // in each library directory, in the order configured, with
+            // relative directories taken relative to the root `.dme` file.
+            IncludeKind::System => self.context.config().parser.library_dirs.iter()
+                .map(|dir| env_dir.join(dir).join(path))
+                .collect(),
+        }
+    }
+
     fn is_defined(&self, name: &str) -> bool {
         match name {
             "__MAIN__" => self.in_environment(),
@@ -571,10 +1214,72 @@ impl<'ctx> Preprocessor<'ctx> {
         }
     }
 
+    /// Substitute object-like macros into the message of a `#warn` or
+    /// `#error` directive.
+    fn expand_message(&self, text: &str) -> String {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(is_word) {
+            result.push_str(&rest[..start]);
+            let len = rest[start..].find(|c: char| !is_word(c)).unwrap_or(rest.len() - start);
+            let word = &rest[start..start + len];
+            rest = &rest[start + len..];
+            self.expand_message_word(word, &mut result, &mut Vec::new());
+        }
+        result.push_str(rest);
+        result
+    }
+
+    fn expand_message_word<'a>(&'a self, word: &str, result: &mut String, expanding: &mut Vec<&'a str>) {
+        use std::fmt::Write;
+
+        // like stringification, tokens of the expansion are space-separated
+        if let Some((name, stack)) = self.defines.inner.get_key_value(word)
+            && !expanding.contains(&name.as_str())
+            && let Some((_, Define::Constant { subst, .. })) = stack.last()
+        {
+            expanding.push(name);
+            for (i, token) in subst.iter().enumerate() {
+                if i > 0 {
+                    result.push(' ');
+                }
+                match token {
+                    Token::Ident(ident, _) => self.expand_message_word(ident, result, expanding),
+                    other => {
+                        let _ = write!(result, "{other}");
+                    }
+                }
+            }
+            expanding.pop();
+            return;
+        }
+        result.push_str(word);
+    }
+
     fn move_to_history(&mut self, name: String, previous: (Location, Define)) {
         self.history.insert(range(previous.0, self.last_input_loc), (name, previous.1));
     }
 
+    /// Note a name which isn't a macro, read where one would be expanded.
+    ///
+    /// Every such name in an `#if` is a use, as is any name which has been
+    /// `#undef`'d. Elsewhere, names written in capitals are uses if called
+    /// like function-like macros, unless they're builtin procs.
+    fn note_undefined_use(&mut self, name: &str, inside_condition: bool) {
+        const BUILTIN_PROCS: &[&str] = &["CRASH", "REGEX_QUOTE", "REGEX_QUOTE_REPLACEMENT"];
+        if self.is_disabled() {
+            // conditions nested in skipped code aren't evaluated either
+            return;
+        }
+        let location = self.last_input_loc;
+        if inside_condition || self.undefined_names.contains(name) {
+            self.undefined_uses.push((name.to_owned(), location));
+        } else if is_macro_like(name) && !BUILTIN_PROCS.contains(&name) {
+            self.possible_macro_call = Some((name.to_owned(), location));
+        }
+    }
+
     // ------------------------------------------------------------------------
     // Conditional compilation handling
 
@@ -582,21 +1287,57 @@ impl<'ctx> Preprocessor<'ctx> {
         self.ifdef_stack.iter().any(|x| !x.active)
     }
 
+    /// Start or finish recording an inactive region after an `#if`-family
+    /// directive, depending on whether it changed the disabled state.
+    fn update_inactive(&mut self, was_disabled: bool) {
+        if !self.keep_inactive || self.annotations.is_none() {
+            return;
+        }
+        match (was_disabled, self.is_disabled()) {
+            (false, true) => self.inactive = Some((self.last_input_loc, Vec::new())),
+            (true, false) => {
+                if let Some((start, tokens)) = self.inactive.take() {
+                    self.annotate_inactive(start..self.last_input_loc, tokens);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn annotate_inactive(&mut self, range: std::ops::Range<Location>, tokens: Vec<LocatedToken>) {
+        // Inactive code may well not parse, so use a scratch context to
+        // keep its errors from being reported.
+        let scratch = Context::default();
+        let mut inactive = AnnotationTree::default();
+        crate::parser::Parser::new(&scratch, crate::indents::IndentProcessor::new(&scratch, tokens))
+            .parse_annotations_only(&mut inactive);
+
+        if let Some(annotations) = self.annotations.as_mut() {
+            annotations.merge(inactive);
+            annotations.insert(range.clone(), Annotation::InactiveBranch { range });
+        }
+    }
+
     fn pop_ifdef(&mut self) -> Option<Ifdef> {
         self.ifdef_stack.pop().inspect(|ifdef| {
             self.ifdef_history.insert(range(ifdef.location, self.last_input_loc), ifdef.active);
         })
     }
 
-    fn evaluate_inner(&mut self) -> Result<bool, DMError> {
+    fn evaluate_inner(&mut self, condition: &mut Option<std::ops::Range<Location>>) -> Result<bool, DMError> {
         // pump real_next to fill output until we get a real newline on input
         let start = self.last_input_loc;
+        let mut condition_start = None;
         while let Some(tok) = self.inner_next() {
             self.last_input_loc = tok.location;
 
             if let Token::Punct(Punctuation::Newline) = tok.token {
+                *condition = condition_start.map(|start| start..tok.location);
                 break;
             }
+            if !tok.token.is_whitespace() {
+                condition_start.get_or_insert(tok.location);
+            }
 
             if let Err(e) = self.real_next(tok.token, true) {
                 self.context.register_error(e);
@@ -613,20 +1354,75 @@ impl<'ctx> Preprocessor<'ctx> {
         let expr = crate::parser::parse_expression(
             self.context,
             start,
-            self.output.drain(..),
+            self.output.drain(..).map(|expanded| expanded.token),
         )?;
         Ok(crate::constants::preprocessor_evaluate(start, expr, &self.defines, Some(self.context))?.to_bool())
     }
 
-    fn evaluate(&mut self) -> bool {
+    /// Evaluate `defined X`, `defined(X)`, or `__has_include("path")` in a
+    /// condition, the name of the function having just been read.
+    fn condition_function(&mut self, function: &str) -> Result<bool, DMError> {
+        let mut token = self.next_nonspace();
+        let parenthesized = token == Token::Punct(Punctuation::LParen);
+        if parenthesized {
+            token = self.next_nonspace();
+        }
+        let value = match token {
+            Token::Ident(name, _) if function == "defined" => self.is_defined(&name),
+            Token::String(path) if function == "__has_include" && parenthesized => {
+                let path = PathBuf::from(path.replace('\\', "/"));
+                self.include_candidates(&path, IncludeKind::Local).iter().any(|candidate| candidate.exists())
+            }
+            other => {
+                let expected = if function == "defined" { "a macro name" } else { "a string" };
+                return Err(self.malformed_condition_function(function, other, expected));
+            }
+        };
+        if parenthesized {
+            let token = self.next_nonspace();
+            if token != Token::Punct(Punctuation::RParen) {
+                return Err(self.malformed_condition_function(function, token, "')'"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn malformed_condition_function(&mut self, function: &str, token: Token, expected: &str) -> DMError {
+        let error = self.error(format!("malformed {function}, expected {expected} but got {token:?}"));
+        if token == Token::Punct(Punctuation::Newline) {
+            // leave the end of the condition to be read
+            self.include_stack.stack.push(Include::Expansion {
+                name: function.into(),
+                location: self.last_input_loc,
+                tokens: [token].into(),
+            });
+        }
+        error
+    }
+
+    fn next_nonspace(&mut self) -> Token {
+        loop {
+            match self.inner_next() {
+                Some(LocatedToken { token: Token::Punct(Punctuation::Space | Punctuation::Tab), .. }) => {}
+                Some(located) => return located.token,
+                None => return Token::Eof,
+            }
+        }
+    }
+
+    /// Evaluate the condition of an `#if` or `#elif`, returning whether it
+    /// holds and where it was written, up to the end of the line.
+    fn evaluate(&mut self) -> (bool, Option<std::ops::Range<Location>>) {
+        let mut condition = None;
         // always succeed in order to avoid phantom "unmatched #endif" messages
-        match self.evaluate_inner() {
+        let value = match self.evaluate_inner(&mut condition) {
             Ok(value) => value,
             Err(err) => {
                 self.context.register_error(err);
                 false
             }
-        }
+        };
+        (value, condition)
     }
 
     // ------------------------------------------------------------------------
@@ -638,7 +1434,10 @@ impl<'ctx> Preprocessor<'ctx> {
             self.output.extend(
                 self.docs_in
                     .drain(..)
-                    .map(|(l, d)| LocatedToken::new(l, Token::DocComment(d))),
+                    .map(|(l, d)| ExpandedToken {
+                        token: LocatedToken::new(l, Token::DocComment(d)),
+                        macro_name: None,
+                    }),
             );
         }
     }
@@ -658,6 +1457,11 @@ impl<'ctx> Preprocessor<'ctx> {
         // Make sure the file hasn't already been included.
         // All DM source is effectively `#pragma once`.
         let file_id = self.context.register_file(register);
+        self.include_graph.edges.push(IncludeEdge {
+            from: self.last_input_loc.file,
+            to: file_id,
+            location: self.last_input_loc,
+        });
         if let Some(&loc) = self.include_locations.get(&file_id) {
             if !self.multiple_locations.contains_key(&file_id) {
                 Err(DMError::new(self.last_input_loc, format!("duplicate #include {path:?}"))
@@ -691,7 +1495,10 @@ impl<'ctx> Preprocessor<'ctx> {
     }
 
     fn push_output(&mut self, token: Token) {
-        self.output.push_back(LocatedToken::new(self.last_input_loc, token));
+        self.output.push_back(ExpandedToken {
+            token: LocatedToken::new(self.last_input_loc, token),
+            macro_name: self.include_stack.outermost_expansion().cloned(),
+        });
     }
 
     fn inner_next(&mut self) -> Option<LocatedToken> {
@@ -700,12 +1507,16 @@ impl<'ctx> Preprocessor<'ctx> {
 
     #[allow(unreachable_code)]
     fn real_next(&mut self, read: Token, inside_condition: bool) -> Result<(), DMError> {
-        let mut _last_expected_loc = self.last_input_loc;
+        // the location of the token last read by `next!`, which only some
+        // directives go on to use
+        #[allow(unused_assignments)]
+        let mut last_expected_loc = self.last_input_loc;
         macro_rules! next {
             () => {
                 match self.inner_next() {
                     Some(x) => {
-                        _last_expected_loc = x.location;
+                        #[allow(unused_assignments)]
+                        { last_expected_loc = x.location; }
                         x.token
                     }
                     None => return Err(self.error("unexpected EOF")),
@@ -724,11 +1535,19 @@ impl<'ctx> Preprocessor<'ctx> {
         #[rustfmt::skip]
         const ALL_DIRECTIVES: &[&str] = &[
             "if", "ifdef", "ifndef", "elif", "else", "endif",
-            "include", "define", "undef", "warn", "error", "pragma",
+            "include", "define", "undef", "warn", "warning", "error", "pragma",
         ];
         let disabled = !inside_condition && self.is_disabled();
+        if let Some(call) = self.possible_macro_call.take()
+            && read == Token::Punct(Punctuation::LParen)
+        {
+            self.undefined_uses.push(call);
+        }
         match read {
             Token::Punct(Punctuation::Hash) => {
+                let was_disabled = self.is_disabled();
+                let directive_start = self.last_input_loc;
+                let mut conditional = None;
                 // preprocessor directive, next thing ought to be an ident
                 expect_token!((ident) = Token::Ident(ident, _));
                 match &ident[..] {
@@ -736,33 +1555,43 @@ impl<'ctx> Preprocessor<'ctx> {
                     "endif" => {
                         self.pop_ifdef().ok_or_else(||
                             DMError::new(self.last_input_loc, "unmatched #endif"))?;
+                        conditional = Some((ConditionalKind::Endif, None));
                     }
                     "else" => {
                         let last = self.pop_ifdef().ok_or_else(||
                             DMError::new(self.last_input_loc, "unmatched #else"))?;
                         self.ifdef_stack.push(last.else_(self.last_input_loc));
+                        conditional = Some((ConditionalKind::Else, None));
                     }
                     "ifdef" => {
                         expect_token!((define_name) = Token::Ident(define_name, _));
+                        let name_loc = last_expected_loc;
                         expect_token!(() = Token::Punct(Punctuation::Newline));
                         let enabled = self.is_defined(&define_name);
                         self.ifdef_stack.push(Ifdef::new(self.last_input_loc, enabled));
+                        let name_range = name_loc..name_loc.add_columns(define_name.len() as u16);
+                        conditional = Some((ConditionalKind::Ifdef, Some(name_range)));
                     }
                     "ifndef" => {
                         expect_token!((define_name) = Token::Ident(define_name, _));
+                        let name_loc = last_expected_loc;
                         expect_token!(() = Token::Punct(Punctuation::Newline));
                         let enabled = !self.is_defined(&define_name);
                         self.ifdef_stack.push(Ifdef::new(self.last_input_loc, enabled));
+                        let name_range = name_loc..name_loc.add_columns(define_name.len() as u16);
+                        conditional = Some((ConditionalKind::Ifndef, Some(name_range)));
                     }
                     "if" => {
-                        let enabled = self.evaluate();
+                        let (enabled, condition) = self.evaluate();
                         self.ifdef_stack.push(Ifdef::new(self.last_input_loc, enabled));
+                        conditional = Some((ConditionalKind::If, condition));
                     }
                     "elif" => {
                         let last = self.pop_ifdef().ok_or_else(||
                             DMError::new(self.last_input_loc, "unmatched #elif"))?;
-                        let enabled = self.evaluate();
+                        let (enabled, condition) = self.evaluate();
                         self.ifdef_stack.push(last.else_if(self.last_input_loc, enabled));
+                        conditional = Some((ConditionalKind::Elif, condition));
                     }
                     // --------------------------------------------------------
                     // anything other than ifdefs may be ifdef'd out
@@ -770,17 +1599,19 @@ impl<'ctx> Preprocessor<'ctx> {
                     // include searches relevant paths for files
                     "include" if disabled => {}
                     "include" => {
-                        expect_token!((path_str) = Token::String(path_str));
-                        let include_loc = _last_expected_loc;
+                        let (kind, path_str, include_loc) = match next!() {
+                            Token::Punct(Punctuation::Less) => {
+                                let include_loc = last_expected_loc;
+                                expect_token!((path_str) = Token::String(path_str));
+                                (IncludeKind::System, path_str, include_loc)
+                            }
+                            Token::String(path_str) => (IncludeKind::Local, path_str, last_expected_loc),
+                            other => return Err(self.error(format!("unexpected token {other:?}, expecting a file to include"))),
+                        };
                         expect_token!(() = Token::Punct(Punctuation::Newline));
                         let path = PathBuf::from(path_str.replace('\\', "/"));
 
-                        for candidate in [
-                            // 1. relative to file in which `#include` appears.
-                            self.include_stack.top_file_path().parent().unwrap().join(&path),
-                            // 2. relative to root `.dme` file.
-                            self.env_file.parent().unwrap().join(&path)
-                        ] {
+                        for candidate in self.include_candidates(&path, kind) {
                             if !candidate.exists() {
                                 continue;
                             }
@@ -816,7 +1647,7 @@ impl<'ctx> Preprocessor<'ctx> {
                             if let Some(annotations) = self.annotations.as_mut() {
                                 annotations.insert(
                                     include_loc .. include_loc.add_columns(2 + path_str.len() as u16),
-                                    Annotation::Include(candidate.clone()));
+                                    Annotation::Include { path: candidate.clone(), kind });
                             }
 
                             match file_type {
@@ -838,7 +1669,11 @@ impl<'ctx> Preprocessor<'ctx> {
                             return Ok(());
                         }
 
-                        self.context.register_error(DMError::new(self.last_input_loc, format!("failed to find #include {path:?}")));
+                        let message = match kind {
+                            IncludeKind::Local => format!("failed to find #include {path:?}"),
+                            IncludeKind::System => format!("failed to find #include <{}> in library directories", path.display()),
+                        };
+                        self.context.register_error(DMError::new(self.last_input_loc, message));
                         return Ok(());
                     }
                     // both constant and function defines
@@ -857,13 +1692,21 @@ impl<'ctx> Preprocessor<'ctx> {
                         docs.extend(self.docs_in.drain(..).map(|x| x.1));
 
                         expect_token!((define_name, ws) = Token::Ident(define_name, ws));
-                        let define_name_loc = _last_expected_loc;
+                        let define_name_loc = last_expected_loc;
                         if let Some(annotations) = self.annotations.as_mut() {
                             annotations.insert(
                                 define_name_loc .. define_name_loc.add_columns(define_name.len() as u16),
                                 Annotation::MacroDefinition(define_name.to_owned()));
                         }
                         self.check_danger_ident(&define_name, "defined");
+                        if let Some(case) = self.context.config().code_standards.naming.macros
+                            && !case.matches(&define_name)
+                        {
+                            DMError::new(define_name_loc, format!("macro name is not {}: {define_name}", case.name()))
+                                .set_severity(Severity::Warning)
+                                .with_errortype("naming_convention")
+                                .register(self.context);
+                        }
                         let mut params = Vec::new();
                         let mut subst = Vec::new();
                         let mut variadic = false;
@@ -937,10 +1780,13 @@ impl<'ctx> Preprocessor<'ctx> {
                         } else {
                             Define::Function { params, subst, variadic, docs: Rc::new(docs) }
                         };
+                        // Identical redefinitions are harmless and common in
+                        // headers, so they keep the first definition.
+                        let identical = self.defines.get(&define_name)
+                            .is_some_and(|(_, previous)| previous.same_expansion(&define));
                         // DEBUG can only be defined in the root .dme file
-                        if define_name != "DEBUG" || self.in_environment() {
+                        if !identical && (define_name != "DEBUG" || self.in_environment()) {
                             if let Some(previous_loc) = self.defines.insert(define_name.clone(), (define_name_loc, define)) {
-                                // DM doesn't issue a warning for this, but it's usually a mistake, so let's.
                                 // FILE_DIR is handled specially and sometimes makes sense to define multiple times.
                                 if define_name != "FILE_DIR" {
                                     DMError::new(define_name_loc, format!("macro redefined: {define_name}"))
@@ -955,10 +1801,11 @@ impl<'ctx> Preprocessor<'ctx> {
                     "undef" if disabled => {}
                     "undef" => {
                         expect_token!((define_name) = Token::Ident(define_name, _));
-                        let define_name_loc = _last_expected_loc;
+                        let define_name_loc = last_expected_loc;
                         self.check_danger_ident(&define_name, "undefined");
                         expect_token!(() = Token::Punct(Punctuation::Newline));
                         if let Some(previous) = self.defines.remove(&define_name) {
+                            self.undefined_names.insert(define_name.clone());
                             self.move_to_history(define_name, previous);
                         } else {
                             DMError::new(define_name_loc, format!("macro undefined while not defined: {define_name}"))
@@ -967,22 +1814,26 @@ impl<'ctx> Preprocessor<'ctx> {
                                 .register(self.context);
                         }
                     }
-                    "warn" if disabled => {}
-                    "warn" => {
+                    "warn" | "warning" if disabled => {}
+                    "warn" | "warning" => {
                         expect_token!((text) = Token::String(text));
-                        DMError::new(self.last_input_loc, format!("#{} {}", ident, text.trim_end_matches(['\r', '\n'])))
+                        let message = self.expand_message(text.trim_end_matches(['\r', '\n']));
+                        DMError::new(self.last_input_loc, format!("#{ident} {message}"))
                             .set_severity(Severity::Warning)
                             .register(self.context);
                     }
                     "error" if disabled => {}
                     "error" => {
+                        // Registered rather than returned, so the rest of the
+                        // file is still preprocessed.
                         expect_token!((text) = Token::String(text));
-                        self.context.register_error(DMError::new(self.last_input_loc, format!("#{} {}", ident, text.trim_end_matches(['\r', '\n']))));
+                        let message = self.expand_message(text.trim_end_matches(['\r', '\n']));
+                        self.context.register_error(DMError::new(self.last_input_loc, format!("#{ident} {message}")));
                     }
                     "pragma" if disabled => {}
                     "pragma" => {
                         expect_token!((text) = Token::Ident(text, _));
-                        let pragma_use_loc = _last_expected_loc;
+                        let pragma_use_loc = last_expected_loc;
                         if text.as_str() == "multiple" {
                             self.multiple_locations.insert(pragma_use_loc.file, pragma_use_loc);
                         }
@@ -999,14 +1850,32 @@ impl<'ctx> Preprocessor<'ctx> {
                             if !meant.is_empty() { ", did you mean #" } else { "" }, meant)));
                     }
                 }
-                // yield a newline
+                self.update_inactive(was_disabled);
+                // yield a newline where the directive actually ended, which
+                // may be several physical lines down if it was continued
+                self.last_input_loc = self.last_input_loc.max(last_expected_loc);
+                if let Some((kind, condition_range)) = conditional {
+                    let active = !self.is_disabled();
+                    if let Some(annotations) = self.annotations.as_mut() {
+                        annotations.insert(directive_start..self.last_input_loc, Annotation::PreprocessorConditional {
+                            kind,
+                            condition_range,
+                            active,
+                        });
+                    }
+                }
                 self.push_output(Token::Punct(Punctuation::Newline));
                 return Ok(());
             }
             // anything other than directives may be ifdef'd out
-            _ if disabled => return Ok(()),
+            _ if disabled => {
+                if let Some((_, tokens)) = self.inactive.as_mut() {
+                    tokens.push(LocatedToken::new(self.last_input_loc, read));
+                }
+                return Ok(());
+            }
             // identifiers may be macros
-            Token::Ident(ref ident, whitespace) => {
+            Token::Ident(ref ident, _) => {
                 self.flush_docs();
 
                 // lint for BYOND bug
@@ -1031,30 +1900,39 @@ impl<'ctx> Preprocessor<'ctx> {
                     return Ok(());
                 }
 
-                // special case for inside a defined() call
-                if let Some(LocatedToken { token: Token::Punct(Punctuation::LParen), .. }) = self.output.back() {
-                    if let Some(idx) = self.output.len().checked_sub(2) {
-                        if let Some(LocatedToken { token: Token::Ident(identname, _), .. }) = self.output.get(idx) {
-                            if identname.as_str() == "defined" {
-                                self.push_output(Token::Ident(ident.to_owned(), whitespace));
-                                return Ok(());
-                            }
-                        }
-                    }
+                // `defined X`, `defined(X)`, and `__has_include("path")` in
+                // conditions are replaced by 0 or 1
+                if inside_condition && (ident == "defined" || ident == "__has_include") {
+                    let value = self.condition_function(ident).unwrap_or_else(|err| {
+                        // still give the condition something to evaluate
+                        self.context.register_error(err);
+                        false
+                    });
+                    self.push_output(Token::Int(value.into()));
+                    return Ok(());
                 }
 
                 // if it's a define, perform the substitution
                 let mut expansion = self.defines.get(ident).cloned();  // TODO: don't clone?
-                if expansion.is_some() && self.include_stack.stack.len() > MAX_RECURSION_DEPTH {
-                    self.error(format!("expanding {ident:?} would exceed max recursion depth of {MAX_RECURSION_DEPTH} levels")).register(self.context);
+                let max_depth = self.context.config().parser.max_macro_expansion_depth;
+                if expansion.is_some() && self.include_stack.expansion_depth() >= max_depth {
+                    self.error(format!("expanding {ident:?} would exceed max macro expansion depth of {max_depth} levels"))
+                        .with_errortype("macro_expansion_depth")
+                        .register(self.context);
                     expansion = None;
                 }
 
                 match expansion {
                     Some((location, Define::Constant { subst, docs })) => {
                         self.annotate_macro(ident, location, Some(docs));
+                        self.record_expansion(ExpansionStep {
+                            name: ident.clone(),
+                            definition_location: location,
+                            before: ident.clone(),
+                            after: tokens_to_string(&subst),
+                        });
                         self.include_stack.stack.push(Include::Expansion {
-                            //name: ident.to_owned(),
+                            name: ident.as_str().into(),
                             tokens: subst.into_iter().collect(),
                             location: self.last_input_loc,
                         });
@@ -1106,6 +1984,7 @@ impl<'ctx> Preprocessor<'ctx> {
                         }
 
                         // check for correct number of arguments
+                        let given = if args.len() == 1 && args[0].is_empty() { 0 } else { args.len() };
                         if variadic {
                             if args.len() > params.len() {
                                 let new_arg = args.split_off(params.len() - 1).join(&Token::Punct(Punctuation::Comma));
@@ -1115,63 +1994,68 @@ impl<'ctx> Preprocessor<'ctx> {
                             }
                         }
                         if args.len() != params.len() {
-                            return Err(self.error("wrong number of arguments to macro call"));
+                            let expected = if variadic {
+                                format!("at least {}", params.len() - 1)
+                            } else {
+                                params.len().to_string()
+                            };
+                            return Err(self.error(format!("wrong number of arguments to macro {ident}: expected {expected}, got {given}"))
+                                .with_note(location, format!("{ident} defined here")));
                         }
 
                         // paste them into the expansion
                         let mut expansion = VecDeque::new();
                         let mut input = subst.iter().cloned();
+                        // the length of the expansion just after an empty argument
+                        let mut empty_arg = None;
                         while let Some(token) = input.next() {
                             match token {
                                 // just an ident = expand it
                                 Token::Ident(ident, ws) => match params.iter().position(|x| *x == ident) {
-                                    Some(i) => expansion.extend(args[i].iter().cloned()),
+                                    Some(i) => {
+                                        expansion.extend(args[i].iter().cloned());
+                                        if args[i].is_empty() {
+                                            empty_arg = Some(expansion.len());
+                                        }
+                                    }
                                     None => expansion.push_back(Token::Ident(ident, ws)),
                                 },
-                                // token paste = concat two idents together, if at all possible
+                                // token paste = join the tokens either side into one
                                 Token::Punct(Punctuation::TokenPaste) => {
-                                    match (expansion.pop_back(), input.next()) {
-                                        (Some(Token::Ident(first, ws1)), Some(Token::Ident(param_name, ws))) => {
-                                            match params.iter().position(|x| *x == param_name) {
-                                                Some(i) => {
-                                                    let mut arg = args[i].iter().cloned();
-                                                    match arg.next() {
-                                                        Some(Token::Ident(param_ident, ws)) => {
-                                                            expansion.push_back(Token::Ident(
-                                                                format!("{first}{param_ident}"),
-                                                                ws,
-                                                            ));
-                                                        }
-                                                        Some(Token::Int(param_int)) => {
-                                                            expansion.push_back(Token::Ident(
-                                                                format!("{first}{param_int}"),
-                                                                ws,
-                                                            ))
-                                                        }
-                                                        Some(other) => {
-                                                            expansion.push_back(Token::Ident(first, ws1));
-                                                            expansion.push_back(other);
-                                                        }
-                                                        None => {}
-                                                    }
-                                                    expansion.extend(arg);
+                                    // an argument on the right is pasted by its first token
+                                    let mut right: VecDeque<Token> = match input.next() {
+                                        Some(Token::Ident(name, ws)) => match params.iter().position(|x| *x == name) {
+                                            Some(i) => args[i].iter().cloned().collect(),
+                                            None => VecDeque::from([Token::Ident(name, ws)]),
+                                        },
+                                        Some(other) => VecDeque::from([other]),
+                                        None => VecDeque::new(),
+                                    };
+                                    // an empty argument on the left leaves nothing to paste to
+                                    let left = if empty_arg.take().is_some_and(|len| len == expansion.len()) {
+                                        None
+                                    } else {
+                                        expansion.pop_back()
+                                    };
+                                    match (left, right.pop_front()) {
+                                        (Some(left), Some(first)) => match paste_tokens(self.last_input_loc.file, &left, &first) {
+                                            Some(pasted) => expansion.push_back(pasted),
+                                            None => {
+                                                if is_word_token(&left) && is_word_token(&first) {
+                                                    DMError::new(self.last_input_loc, format!("pasting '{left}' and '{first}' does not give a valid token"))
+                                                        .set_severity(Severity::Warning)
+                                                        .register(self.context);
                                                 }
-                                                None => expansion.push_back(Token::Ident(format!("{first}{param_name}"), ws)),
-                                            }
-                                        }
-                                        (non_ident_first, Some(Token::Ident(second, ws))) => {
-                                            expansion.extend(non_ident_first);
-                                            match params.iter().position(|x| *x == second) {
-                                                Some(i) => expansion.extend(args[i].iter().cloned()),
-                                                None => expansion.push_back(Token::Ident(second, ws)),
+                                                expansion.push_back(left);
+                                                expansion.push_back(first);
                                             }
-                                        }
-                                        (non_ident_first, non_ident_second) => {
-                                            expansion.extend(non_ident_first);
-                                            expansion.extend(non_ident_second);
+                                        },
+                                        (left, first) => {
+                                            expansion.extend(left);
+                                            expansion.extend(first);
                                         }
                                     }
-                                    // read the next ident and concat it into the previous ident
+                                    expansion.extend(right);
                                 },
                                 // hash = must be followed by a param name, stringify the whole argument
                                 Token::Punct(Punctuation::Hash) => {
@@ -1200,14 +2084,23 @@ impl<'ctx> Preprocessor<'ctx> {
                                 _ => expansion.push_back(token),
                             }
                         }
+                        if self.annotations.is_some() {
+                            let args: Vec<_> = args.iter().map(tokens_to_string).collect();
+                            self.record_expansion(ExpansionStep {
+                                name: ident.clone(),
+                                definition_location: location,
+                                before: format!("{ident}({})", args.join(", ")),
+                                after: tokens_to_string(&expansion),
+                            });
+                        }
                         self.include_stack.stack.push(Include::Expansion {
-                            //name: ident.to_owned(),
+                            name: ident.as_str().into(),
                             tokens: expansion,
                             location: self.last_input_loc,
                         });
                         return Ok(());
                     }
-                    None => {}
+                    None => self.note_undefined_use(ident, inside_condition),
                 }
             }
             Token::InterpStringBegin(_) => self.in_interp_string += 1,
@@ -1228,10 +2121,9 @@ impl<'ctx> Preprocessor<'ctx> {
     }
 }
 
-impl<'ctx> Iterator for Preprocessor<'ctx> {
-    type Item = LocatedToken;
-
-    fn next(&mut self) -> Option<LocatedToken> {
+impl<'ctx> Preprocessor<'ctx> {
+    /// Read the next output token, noting which macro expansion produced it.
+    pub fn next_expanded(&mut self) -> Option<ExpandedToken> {
         loop {
             if let Some(token) = self.output.pop_front() {
                 return Some(token);
@@ -1261,4 +2153,18 @@ impl<'ctx> Iterator for Preprocessor<'ctx> {
             }
         }
     }
+
+    /// Iterate over the output tokens, noting which macro expansion produced
+    /// each.
+    pub fn expanded_tokens(&mut self) -> impl Iterator<Item = ExpandedToken> + '_ {
+        std::iter::from_fn(move || self.next_expanded())
+    }
+}
+
+impl<'ctx> Iterator for Preprocessor<'ctx> {
+    type Item = LocatedToken;
+
+    fn next(&mut self) -> Option<LocatedToken> {
+        self.next_expanded().map(|expanded| expanded.token)
+    }
 }
@@ -1,9 +1,11 @@
 extern crate dreammaker as dm;
 
 use dm::annotation::*;
+use dm::ast::{AssignOp, BinaryOp, Expression, InOperand, Statement, Term};
 use dm::indents::IndentProcessor;
 use dm::lexer::*;
 use dm::parser::Parser;
+use dm::preprocessor::Preprocessor;
 use dm::Location;
 
 #[test]
@@ -39,3 +41,1049 @@ fn annotation_basic() {
         }
     }
 }
+
+#[test]
+fn annotation_round_trip() {
+    let code = r#"
+/// The answer.
+#define ANSWER 42
+
+/datum/globals
+    var/number = ANSWER + 5
+
+    proc/Init(a, b = 2)
+        var/obj/item/thing = new /obj/item()
+        var/obj/item/copy = new thing.type(a)
+        world.log << thing.name << copy
+        return ..()
+"#.trim();
+
+    let context = dm::Context::default();
+    let mut preprocessor = Preprocessor::from_buffer(&context, "round_trip.dm".into(), code);
+    preprocessor.enable_annotations();
+    let mut annotations = AnnotationTree::default();
+    Parser::new(&context, IndentProcessor::new(&context, &mut preprocessor))
+        .parse_annotations_only(&mut annotations);
+    annotations.merge(preprocessor.take_annotations().unwrap());
+    context.assert_success();
+
+    let serialized = serde_json::to_string(&annotations).unwrap();
+    let restored = AnnotationTree::from_serialized(&mut serde_json::Deserializer::from_str(&serialized)).unwrap();
+
+    assert_eq!(restored.len(), annotations.len());
+    let before: Vec<_> = annotations.iter().map(|pair| format!("{pair:?}")).collect();
+    let after: Vec<_> = restored.iter().map(|pair| format!("{pair:?}")).collect();
+    assert_eq!(before, after);
+    assert!(before.iter().any(|each| each.contains("MacroUse")));
+}
+
+fn annotate(code: &str) -> AnnotationTree {
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.trim().as_bytes());
+    let indent = IndentProcessor::new(&context, lexer);
+    let mut annotations = AnnotationTree::default();
+    Parser::new(&context, indent).parse_annotations_only(&mut annotations);
+    context.assert_success();
+    annotations
+}
+
+fn at(line: u32, column: u16) -> Location {
+    Location { file: Default::default(), line, column }
+}
+
+#[test]
+fn enclosing_proc_and_type() {
+    let annotations = annotate(r#"
+/datum/outer
+    var/x = 1
+    inner
+        proc/first()
+            return 1
+        proc/second()
+            spawn(1)
+                x = 2
+            return 2
+/proc/global_proc()
+    return
+"#);
+
+    let inner: &[_] = &["datum".to_owned(), "outer".to_owned(), "inner".to_owned()];
+    let second = annotations.enclosing_proc(at(8, 17)).unwrap();
+    assert_eq!(second.0.last().unwrap(), "second");
+    assert_eq!(annotations.enclosing_type(at(8, 17)), Some(inner));
+
+    let first = annotations.enclosing_proc(at(5, 13)).unwrap();
+    assert_eq!(first.0.last().unwrap(), "first");
+    assert_eq!(annotations.enclosing_type(at(5, 13)), Some(inner));
+
+    let outer: &[_] = &["datum".to_owned(), "outer".to_owned()];
+    assert_eq!(annotations.enclosing_proc(at(2, 9)), None);
+    assert_eq!(annotations.enclosing_type(at(2, 9)), Some(outer));
+
+    let global = annotations.enclosing_proc(at(11, 5)).unwrap();
+    assert_eq!(global.0, &["proc".to_owned(), "global_proc".to_owned()][..]);
+    assert_eq!(annotations.enclosing_type(at(11, 5)), None);
+}
+
+#[test]
+fn operator_annotations() {
+    let annotations = annotate("/var/x = a + b * c ? d : e");
+
+    let mut binary = Vec::new();
+    let mut ternary = Vec::new();
+    for (place, annotation) in annotations.iter() {
+        let span = (place.start.column, place.end.column);
+        match annotation {
+            Annotation::BinaryOperation { op, range } => {
+                binary.push((*op, span, (range.start.column, range.end.column)));
+            }
+            Annotation::TernaryOperation { question, colon } => {
+                ternary.push((span, question.start.column, colon.start.column));
+            }
+            _ => {}
+        }
+    }
+    binary.sort_by_key(|&(_, span, _)| span);
+
+    assert_eq!(binary, vec![
+        (BinaryOp::Add, (10, 19), (12, 14)),
+        (BinaryOp::Mul, (14, 19), (16, 18)),
+    ]);
+    assert_eq!(ternary, vec![((10, 26), 20, 24)]);
+}
+
+#[test]
+fn splice_region() {
+    let mut annotations = annotate(r#"
+/datum
+    proc/first()
+        return 1
+    proc/second()
+        return x
+    proc/third()
+        return 3
+"#);
+    let before = annotations.len();
+    let region = at(4, 1)..at(6, 1);
+    let removed = annotations.get_range(region.clone())
+        .filter(|(place, _)| place.start >= region.start && place.end < region.end)
+        .count();
+    assert!(removed > 0);
+
+    let mut replacement = AnnotationTree::default();
+    replacement.insert(at(5, 16)..at(5, 17), Annotation::UnscopedVar("y".to_owned()));
+    annotations.splice(region, replacement);
+    assert_eq!(annotations.len(), before - removed + 1);
+    assert_eq!(annotations.iter().count(), annotations.len());
+
+    // the edited proc is gone, its neighbours and the enclosing type survive
+    assert_eq!(annotations.enclosing_proc(at(5, 16)), None);
+    assert_eq!(annotations.enclosing_proc(at(3, 9)).unwrap().0.last().unwrap(), "first");
+    assert_eq!(annotations.enclosing_proc(at(7, 9)).unwrap().0.last().unwrap(), "third");
+    assert_eq!(annotations.enclosing_type(at(5, 16)), Some(&["datum".to_owned()][..]));
+
+    let vars: Vec<_> = annotations.get_location(at(5, 16))
+        .filter_map(|(_, annotation)| match annotation {
+            Annotation::UnscopedVar(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vars, ["y"]);
+}
+
+#[test]
+fn new_expr_annotations() {
+    let annotations = annotate(r#"
+/proc/f()
+    var/a = new()
+    var/b = new /foo
+    var/c = new .foo/bar(1, 2)
+"#);
+
+    let mut found = Vec::new();
+    for (place, annotation) in annotations.iter() {
+        if let Annotation::NewExpr { type_source: NewTypeSource::Static(type_path), args_range } = annotation {
+            found.push((
+                place.start.line,
+                place.start.column,
+                dm::ast::FormatTypePath(type_path).to_string(),
+                args_range.as_ref().map(|range| (range.start.column, range.end.column)),
+            ));
+        }
+    }
+    found.sort();
+
+    assert_eq!(found, vec![
+        (2, 13, String::new(), Some((16, 18))),
+        (3, 13, "/foo".to_owned(), None),
+        (4, 13, ".foo/bar".to_owned(), Some((25, 31))),
+    ]);
+}
+
+#[test]
+fn new_expr_type_sources() {
+    let annotations = annotate(r#"
+/proc/f(some_type_var)
+    var/a = new /obj/foo()
+    var/b = new some_type_var()
+    var/c = new src.type_var
+"#);
+
+    let mut found = Vec::new();
+    for (place, annotation) in annotations.iter() {
+        if let Annotation::NewExpr { type_source, .. } = annotation {
+            let source = match type_source {
+                NewTypeSource::Static(type_path) => format!("static {}", dm::ast::FormatTypePath(type_path)),
+                NewTypeSource::Dynamic(range) => format!("dynamic {}..{}", range.start.column, range.end.column),
+            };
+            found.push((place.start.line, source));
+        }
+    }
+    found.sort();
+
+    assert_eq!(found, vec![
+        (2, "static /obj/foo".to_owned()),
+        (3, "dynamic 17..30".to_owned()),
+        (4, "dynamic 17..29".to_owned()),
+    ]);
+}
+
+#[test]
+fn remove_file() {
+    let context = dm::Context::default();
+    let mut by_file = Vec::new();
+    for (name, code) in [("a.dm", "/datum/a\n    var/x = 1\n"), ("b.dm", "/datum/b\n    var/y = 2\n")] {
+        let file = context.register_file(name.as_ref());
+        let lexer = Lexer::new(&context, file, code.as_bytes());
+        let indent = IndentProcessor::new(&context, lexer);
+        let mut annotations = AnnotationTree::default();
+        Parser::new(&context, indent).parse_annotations_only(&mut annotations);
+        by_file.push((file, annotations));
+    }
+    context.assert_success();
+
+    let (_, b_only) = &by_file[1];
+    let b_len = b_only.len();
+    let b_pairs: Vec<_> = b_only.iter().map(|pair| format!("{pair:?}")).collect();
+
+    let mut merged = AnnotationTree::default();
+    let file_a = by_file[0].0;
+    for (_, annotations) in by_file {
+        merged.merge(annotations);
+    }
+
+    merged.remove_file(file_a);
+    assert_eq!(merged.len(), b_len);
+    let remaining: Vec<_> = merged.iter().map(|pair| format!("{pair:?}")).collect();
+    assert_eq!(remaining, b_pairs);
+    assert!(merged.iter().all(|(place, _)| place.start.file != file_a));
+}
+
+#[test]
+fn resolve_resources() {
+    let root = std::env::temp_dir().join(format!("dm-resolve-resources-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("icons")).unwrap();
+    std::fs::write(root.join("icons/mob.dmi"), b"").unwrap();
+    std::fs::write(root.join("icons/Obj.dmi"), b"").unwrap();
+
+    let annotations = annotate(r#"
+/var/a = 'icons/mob.dmi'
+/var/b = 'icons/missing.dmi'
+/var/c = 'icons/obj.dmi'
+/var/d = 'icons\\mob.dmi'
+/var/e = 'icons'
+"#);
+    let results = annotations.resolve_resources(&root);
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(results, [
+        (at(1, 10)..at(1, 25), ResourceStatus::Found),
+        (at(2, 10)..at(2, 29), ResourceStatus::Missing),
+        (at(3, 10)..at(3, 25), ResourceStatus::CaseMismatch(root.join("icons").join("Obj.dmi"))),
+        (at(4, 10)..at(4, 25), ResourceStatus::Found),
+        // directories are not resources
+        (at(5, 10)..at(5, 17), ResourceStatus::Missing),
+    ]);
+}
+
+#[test]
+fn switch_outline() {
+    let annotations = annotate(r#"
+/proc/test(x)
+    switch(x)
+        if(1 to 5)
+            switch(x)
+                if(2)
+                    return
+        if(6, 7)
+            return
+        else
+            return
+    switch(x) {}
+    switch(x)
+        if(8)
+            return
+"#);
+
+    let outer = annotations.enclosing_switch(at(8, 13)).unwrap();
+    assert_eq!(outer.cases, [at(3, 12)..at(3, 18), at(7, 12)..at(7, 16)]);
+    assert_eq!(outer.default, Some(at(9, 9)..at(10, 19)));
+
+    // nested switches are outlined separately
+    let inner = annotations.enclosing_switch(at(6, 21)).unwrap();
+    assert_eq!(inner.cases, [at(5, 20)..at(5, 21)]);
+    assert_eq!(inner.default, None);
+
+    let empty = annotations.enclosing_switch(at(11, 7)).unwrap();
+    assert!(empty.cases.is_empty());
+    assert_eq!(empty.default, None);
+
+    let last = annotations.enclosing_switch(at(14, 13)).unwrap();
+    assert_eq!(last.cases, [at(13, 12)..at(13, 13)]);
+
+    assert_eq!(annotations.enclosing_switch(at(1, 3)), None);
+}
+
+#[test]
+fn inactive_branches() {
+    let code = r#"
+#define DEBUG
+/datum/thing
+    var/active = 1
+#ifndef DEBUG
+/datum/thing/var/inactive = 2
+/datum/thing/proc/debug_only()
+    return
+#endif
+#if 0
+/datum/broken(
+    var/x = )
+#else
+/proc/after()
+    return
+#endif
+"#.trim();
+
+    let context = dm::Context::default();
+    let mut preprocessor = Preprocessor::from_buffer(&context, "inactive.dm".into(), code);
+    preprocessor.enable_annotations();
+    preprocessor.keep_inactive_branches(true);
+    let mut annotations = AnnotationTree::default();
+    let tree = Parser::new(&context, IndentProcessor::new(&context, &mut preprocessor)).parse_object_tree();
+    annotations.merge(preprocessor.take_annotations().unwrap());
+    // the broken branch doesn't disturb the active code
+    context.assert_success();
+    assert!(tree.root().get_proc("after").is_some());
+    assert!(tree.expect("/datum/thing").get().vars.get("inactive").is_none());
+
+    let file = context.get_file("inactive.dm".as_ref()).unwrap();
+    let at = |line, column| Location { file, line, column };
+    let branches: Vec<_> = annotations.iter()
+        .filter_map(|(_, annotation)| match annotation {
+            Annotation::InactiveBranch { range } => Some((range.start.line, range.end.line)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(branches, [(4, 8), (9, 12)]);
+
+    // the inactive code is still navigable
+    let in_inactive_proc = annotations.get_location(at(6, 22)).any(|(_, annotation)|
+        matches!(annotation, Annotation::TreePath(_, path) if path.last().is_some_and(|last| last == "debug_only")));
+    assert!(in_inactive_proc);
+}
+
+#[test]
+fn diff_annotations() {
+    let before = annotate(r#"
+/datum
+    proc/first()
+        return x
+    proc/second()
+        return y
+"#);
+    let after = annotate(r#"
+/datum
+    proc/first()
+        return foo(2)
+    proc/second()
+        return y
+"#);
+    assert!(before.diff(&before).is_empty());
+
+    let diff = before.diff(&after);
+    assert!(!diff.added.is_empty());
+    assert!(!diff.removed.is_empty());
+    for (place, annotation) in diff.added.iter().chain(diff.removed.iter()) {
+        assert!(place.start >= at(2, 1) && place.end <= at(3, !0), "{annotation:?} at {place:?}");
+    }
+    assert!(diff.added.iter().any(|(_, annotation)| *annotation == Annotation::UnscopedCall("foo".to_owned())));
+    assert!(diff.removed.iter().any(|(_, annotation)| *annotation == Annotation::UnscopedVar("x".to_owned())));
+}
+
+#[test]
+fn json_export() {
+    let annotations = annotate("/var/x = y");
+    let mut json = Vec::new();
+    annotations.to_json(&mut json).unwrap();
+
+    let expected = r#"[
+        {"start": {"file": 65535, "line": 1, "column": 1}, "end": {"file": 65535, "line": 1, "column": 8}, "annotation": {"TreePath": [true, ["var", "x"]]}},
+        {"start": {"file": 65535, "line": 1, "column": 1}, "end": {"file": 65535, "line": 1, "column": 11}, "annotation": {"Variable": ["x"]}},
+        {"start": {"file": 65535, "line": 1, "column": 2}, "end": {"file": 65535, "line": 1, "column": 5}, "annotation": {"InSequence": 0}},
+        {"start": {"file": 65535, "line": 1, "column": 6}, "end": {"file": 65535, "line": 1, "column": 8}, "annotation": {"InSequence": 1}},
+        {"start": {"file": 65535, "line": 1, "column": 10}, "end": {"file": 65535, "line": 1, "column": 11}, "annotation": {"UnscopedVar": "y"}}
+    ]"#;
+    let actual: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(expected).unwrap();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn macro_docs_side_table() {
+    let mut code = String::new();
+    for i in 0..200 {
+        code.push_str(&format!("/// Line {i} of a very long explanation of this macro.\n"));
+    }
+    code.push_str("#define BIG 1\n/var/a = BIG\n/var/b = BIG\n");
+
+    let context = dm::Context::default();
+    let mut preprocessor = Preprocessor::from_buffer(&context, "macro_docs.dm".into(), code);
+    preprocessor.enable_annotations();
+    let mut annotations = AnnotationTree::default();
+    Parser::new(&context, IndentProcessor::new(&context, &mut preprocessor))
+        .parse_annotations_only(&mut annotations);
+    annotations.merge(preprocessor.take_annotations().unwrap());
+    context.assert_success();
+
+    let uses: Vec<_> = annotations.iter()
+        .filter_map(|(_, annotation)| match annotation {
+            Annotation::MacroUse { docs, .. } => Some((annotation, *docs)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(uses.len(), 2);
+    let id = uses[0].1.unwrap();
+    assert_eq!(uses[1].1, Some(id));
+
+    let docs = annotations.macro_docs(id).unwrap();
+    assert!(docs.text().contains("Line 199"));
+    let annotation_json = serde_json::to_string(uses[0].0).unwrap();
+    assert!(annotation_json.len() < 200, "{annotation_json}");
+}
+
+#[test]
+fn parent_call_targets() {
+    let code = r#"
+/datum/proc/act()
+    return 1
+/datum/middle/act()
+    . = ..() + 1
+/datum/middle/act()
+    return ..() + 2
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.enable_procs();
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let targets: Vec<_> = annotations.iter()
+        .filter(|(_, annotation)| matches!(annotation, Annotation::ParentCall { .. } | Annotation::ReturnVal))
+        .map(|(place, annotation)| {
+            let target = annotations.proc_target(place.start, annotation, &tree).unwrap();
+            (place.start.line, target.ty().path.clone(), target.get().location.line)
+        })
+        .collect();
+    assert_eq!(targets, [
+        // `.` is the enclosing proc, and `..` the definition it overrides
+        (4, "/datum/middle".to_owned(), 3),
+        (4, "/datum".to_owned(), 1),
+        // the second override's `..` calls the first
+        (6, "/datum/middle".to_owned(), 3),
+    ]);
+
+    assert!(annotations.proc_target(at(4, 5), &Annotation::UnscopedVar("x".into()), &tree).is_none());
+    assert!(annotations.proc_target(at(1, 1), &Annotation::ParentCall { args_range: None }, &tree).is_none());
+}
+
+#[test]
+fn parent_call_arguments() {
+    let code = r#"
+/proc/root()
+    ..()
+/datum/proc/act(a, b)
+/datum/act(a, b)
+    ..()
+    ..(a, b)
+    ..(a, b, 3)
+/datum/proc/spread(...)
+/datum/spread()
+    ..(1, 2, 3)
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.enable_procs();
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let calls: Vec<_> = annotations.iter()
+        .filter(|(_, annotation)| matches!(annotation, Annotation::ParentCall { .. }))
+        .map(|(place, annotation)| {
+            let Annotation::ParentCall { args_range } = annotation else { unreachable!() };
+            let args = args_range.as_ref().map(|range| (range.start.column, range.end.column));
+            let target = annotations.parent_call(place.start, annotation, &tree)
+                .map(|(proc, fits)| (proc.get().location.line, fits));
+            (place.start.line, args, target)
+        })
+        .collect();
+    assert_eq!(calls, [
+        // a global proc has nothing to call
+        (2, None, None),
+        // `..()` forwards the caller's arguments
+        (5, None, Some((3, true))),
+        (6, Some((7, 13)), Some((3, true))),
+        (7, Some((7, 16)), Some((3, false))),
+        (10, Some((7, 16)), Some((8, true))),
+    ]);
+}
+
+#[test]
+fn overlapping_selection() {
+    let annotations = annotate(r#"
+/proc/first()
+    foo()
+    bar(foo())
+/proc/second()
+    baz()
+/proc/third()
+    qux()
+"#);
+
+    let calls: Vec<_> = annotations
+        .overlapping(at(1, 1)..at(5, 10), |annotation| matches!(annotation, Annotation::UnscopedCall(_)))
+        .into_iter()
+        .map(|(place, annotation)| match annotation {
+            Annotation::UnscopedCall(name) => (place.start.line, place.start.column, name.as_str()),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(calls, [
+        (2, 5, "foo"),
+        (3, 5, "bar"),
+        (3, 9, "foo"),
+        (5, 5, "baz"),
+    ]);
+}
+
+#[test]
+fn list_elements() {
+    let code = r#"
+/var/list/pairs = list("a" = 1, "b" = 2)
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let elements: Vec<_> = annotations.iter()
+        .filter_map(|(_, annotation)| match annotation {
+            Annotation::ListElement { index, range } => Some((*index, range.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(elements, [
+        (0, at(1, 24)..at(1, 31)),
+        (1, at(1, 33)..at(1, 40)),
+    ]);
+
+    let pairs = tree.root().get().vars["pairs"].value.expression.as_ref().unwrap();
+    let Some(Term::List(args)) = pairs.as_term() else {
+        panic!("not a list literal: {pairs:?}");
+    };
+    assert_eq!(args.len(), 2);
+    assert!(args.iter().all(|arg| matches!(arg, Expression::AssignOp { op: AssignOp::Assign, .. })));
+}
+
+#[test]
+fn tab_indented_columns() {
+    assert_eq!(COLUMN_UNIT, ColumnUnit::Bytes);
+    let annotations = annotate("/proc/test()\n\tif (1)\n\t\tvar/sum = a + b\n\t\treturn sum\n");
+
+    let ops: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::BinaryOperation { range, .. } => Some((place.start, range.start)),
+            _ => None,
+        })
+        .collect();
+    // each tab is one column, as BYOND reports it
+    assert_eq!(ops, [(at(3, 13), at(3, 15))]);
+}
+
+#[test]
+fn goto_labels() {
+    let annotations = annotate(r#"
+/proc/retry()
+    start:
+        world.log << "trying"
+    goto start
+"#);
+
+    let label = annotations.iter()
+        .find_map(|(place, annotation)| match annotation {
+            Annotation::LabelDefinition(name) => Some((place.start, name.as_str())),
+            _ => None,
+        });
+    assert_eq!(label, Some((at(2, 5), "start")));
+    assert!(annotations.get_location(at(4, 11)).any(|(_, annotation)| *annotation == Annotation::GotoTarget("start".to_owned())));
+    assert_eq!(annotations.goto_label(at(4, 11)), Some(at(2, 5)..at(2, 10)));
+    assert_eq!(annotations.goto_label(at(3, 9)), None);
+}
+
+#[test]
+fn dangling_goto() {
+    let code = r#"
+/proc/first()
+    start:
+        return
+/proc/second()
+    goto start
+"#.trim();
+
+    let context = dm::Context::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.enable_procs();
+    parser.parse_object_tree();
+
+    let errors = context.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "goto target not found: start");
+    assert_eq!(errors[0].errortype(), Some("undefined_label"));
+    assert_eq!(errors[0].location().line, 5);
+}
+
+#[test]
+fn annotation_source_text() {
+    let code = r#"
+/proc/greet(who)
+    var/greeting = "hello"
+    world.log << greeting
+    return who
+"#.trim();
+    let annotations = annotate(code);
+    let mut sources = std::collections::HashMap::new();
+    sources.insert(dm::FileId::default(), code);
+
+    let text_of = |wanted: fn(&Annotation) -> bool| annotations.iter()
+        .find(|(_, annotation)| wanted(annotation))
+        .and_then(|(place, _)| source_text(&sources, place.start..place.end.succ()));
+
+    assert_eq!(text_of(|a| matches!(a, Annotation::UnscopedVar(name) if name == "greeting")), Some("greeting"));
+    assert_eq!(
+        text_of(|a| matches!(a, Annotation::ProcBody(..))),
+        Some("\n    var/greeting = \"hello\"\n    world.log << greeting\n    return who"),
+    );
+
+    // degenerate ranges
+    assert_eq!(source_text(&sources, at(2, 5)..at(2, 5)), Some(""));
+    assert_eq!(source_text(&sources, at(3, 1)..at(2, 1)), None);
+    assert_eq!(source_text(&sources, at(2, 90)..at(99, 1)), Some("\n    world.log << greeting\n    return who"));
+    assert_eq!(source_text(&sources, at(4, 5)..at(4, 999)), Some("return who"));
+}
+
+#[test]
+fn in_operations() {
+    let code = r#"
+/var/member = item in contents
+/var/ranged = n in 1 to 10
+/var/both = ready && item in contents
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let mut ins: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::InOperation { range } => Some((place.start, range.clone())),
+            Annotation::BinaryOperation { op: BinaryOp::In, .. } => panic!("`in` annotated as a binary operation"),
+            _ => None,
+        })
+        .collect();
+    ins.sort_by_key(|(start, _)| *start);
+    assert_eq!(ins, [
+        (at(1, 15), at(1, 20)..at(1, 23)),
+        (at(2, 15), at(2, 17)..at(2, 20)),
+        (at(3, 13), at(3, 27)..at(3, 30)),
+    ]);
+
+    let value = |name: &str| tree.root().get().vars[name].value.expression.clone().unwrap();
+
+    let member = value("member");
+    let (lhs, InOperand::List(list)) = member.as_in().unwrap() else {
+        panic!("not a membership test: {member:?}");
+    };
+    assert_eq!(lhs.to_string(), "item");
+    assert_eq!(list.to_string(), "contents");
+
+    let ranged = value("ranged");
+    let (_, InOperand::Range { low, high }) = ranged.as_in().unwrap() else {
+        panic!("not a range check: {ranged:?}");
+    };
+    assert_eq!((low.to_string(), high.to_string()), ("1".to_owned(), "10".to_owned()));
+
+    // `in` binds more loosely than `&&`
+    let both = value("both");
+    let (lhs, _) = both.as_in().unwrap();
+    assert!(matches!(lhs, Expression::BinaryOp { op: BinaryOp::And, .. }));
+    assert_eq!(Expression::from(Term::Null).as_in(), None);
+}
+
+#[test]
+fn iter_from_location() {
+    let annotations = annotate(r#"
+/var/first = 1
+/var/second = a + b
+/var/third = c
+"#);
+
+    let all: Vec<_> = annotations.iter().collect();
+    let from: Vec<_> = annotations.iter_from(at(2, 15)).collect();
+    let expected: Vec<_> = all.iter().filter(|(place, _)| place.start >= at(2, 15)).cloned().collect();
+    assert_eq!(from, expected);
+    // the shorter range sharing a start comes first
+    assert_eq!(from[0].0.start, at(2, 15));
+    assert_eq!(from[0].1, &Annotation::UnscopedVar("a".to_owned()));
+    assert!(matches!(from[1].1, Annotation::BinaryOperation { op: BinaryOp::Add, .. }));
+
+    assert_eq!(annotations.iter_from(at(1, 1)).count(), all.len());
+    assert_eq!(annotations.iter_from(at(99, 1)).count(), 0);
+}
+
+#[test]
+fn spawn_blocks() {
+    let code = r#"
+/proc/test()
+    spawn(5) { sleep(1) }
+    spawn()
+        world.log << "later"
+    spawn(-1)
+        sleep_offline(2)
+    spawn
+        return
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let mut spawns: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::SpawnBlock { delay_range, body_range } => Some((place.start, delay_range.clone(), body_range.start)),
+            _ => None,
+        })
+        .collect();
+    spawns.sort_by_key(|(start, ..)| *start);
+    assert_eq!(spawns, [
+        (at(2, 5), Some(at(2, 11)..at(2, 12)), at(2, 14)),
+        (at(3, 5), None, at(3, 12)),
+        (at(5, 5), Some(at(5, 11)..at(5, 13)), at(5, 14)),
+        (at(7, 5), None, at(7, 10)),
+    ]);
+
+    let code = tree.root().get_proc("test").unwrap().get().code.as_ref().unwrap();
+    let sleeps: Vec<_> = code.iter()
+        .filter_map(|statement| match &statement.elem {
+            Statement::Spawn { block, .. } => block.first(),
+            _ => None,
+        })
+        .filter_map(|statement| match &statement.elem {
+            Statement::Expr(expr) => expr.as_term()?.as_sleep_call(),
+            _ => None,
+        })
+        .map(|args| args.len())
+        .collect();
+    assert_eq!(sleeps, [1, 1]);
+}
+
+#[test]
+fn loose_scoped_access() {
+    let annotations = annotate(r#"
+/proc/test(datum/D)
+    D:foo()
+    D.checked()
+    return D:bar + D?:baz + D.field
+"#);
+
+    let mut accesses: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::ScopedCall(priors, name) => Some((place.start, "call", false, priors.clone(), name.clone())),
+            Annotation::ScopedVar(priors, name) => Some((place.start, "var", false, priors.clone(), name.clone())),
+            Annotation::LooseScopedCall(priors, name) => Some((place.start, "call", true, priors.clone(), name.clone())),
+            Annotation::LooseScopedVar(priors, name) => Some((place.start, "var", true, priors.clone(), name.clone())),
+            _ => None,
+        })
+        .collect();
+    accesses.sort_by_key(|access| access.0);
+    let d = vec!["D".to_owned()];
+    assert_eq!(accesses, [
+        (at(2, 7), "call", true, d.clone(), "foo".to_owned()),
+        (at(3, 7), "call", false, d.clone(), "checked".to_owned()),
+        (at(4, 14), "var", true, d.clone(), "bar".to_owned()),
+        (at(4, 23), "var", true, d.clone(), "baz".to_owned()),
+        (at(4, 31), "var", false, d, "field".to_owned()),
+    ]);
+}
+
+#[test]
+fn string_interpolations() {
+    let annotations = annotate(r#"
+/proc/test(obj/obj)
+    var/a = 1
+    world << "[a]"
+    world << "[obj.name]"
+    world << "[foo("[bar]")]"
+"#);
+
+    let mut interpolations: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::StringInterpolation { expr_range } => {
+                assert_eq!(place.start, expr_range.start);
+                Some(expr_range.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    interpolations.sort_by_key(|range| range.start);
+    assert_eq!(interpolations, [
+        at(3, 16)..at(3, 17),
+        at(4, 16)..at(4, 24),
+        at(5, 16)..at(5, 28),
+        at(5, 22)..at(5, 25),
+    ]);
+
+    // the embedded expressions are annotated as usual
+    let mut names: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::UnscopedVar(name) | Annotation::UnscopedCall(name) => Some((place.start, name.clone())),
+            Annotation::ScopedVar(_, name) => Some((place.start, name.clone())),
+            _ => None,
+        })
+        .filter(|(start, _)| interpolations.iter().any(|range| range.contains(start)))
+        .collect();
+    names.sort();
+    assert_eq!(names, [
+        (at(3, 16), "a".to_owned()),
+        (at(4, 16), "obj".to_owned()),
+        (at(4, 20), "name".to_owned()),
+        (at(5, 16), "foo".to_owned()),
+        (at(5, 22), "bar".to_owned()),
+    ]);
+}
+
+#[test]
+fn tree_stats() {
+    const N: usize = 1000;
+
+    let mut tree = AnnotationTree::default();
+    assert_eq!(tree.stats(), TreeStats { len: 0, node_count: 0, max_depth: 0 });
+    for line in 1..=N as u32 {
+        tree.insert(at(line, 1)..at(line, 5), Annotation::ReturnVal);
+    }
+    // a second annotation on the same range shares its node
+    tree.insert(at(1, 1)..at(1, 5), Annotation::InSequence(0));
+
+    let stats = tree.stats();
+    assert_eq!(stats.len, N + 1);
+    assert_eq!(stats.len, tree.len());
+    assert_eq!(stats.node_count, N);
+    // the tree is kept balanced, so its depth is logarithmic
+    let log2 = N.ilog2() as usize;
+    assert!(stats.max_depth > log2, "depth {} too shallow", stats.max_depth);
+    assert!(stats.max_depth <= 2 * log2, "depth {} too deep", stats.max_depth);
+}
+
+#[test]
+fn assign_operations() {
+    let code = r#"
+/proc/test(x, flags)
+    x += 1
+    flags |= 2
+    x >>= flags
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.enable_procs();
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let mut assigns: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::AssignOperation { op, range } => Some((place.start, *op, range.clone())),
+            _ => None,
+        })
+        .collect();
+    assigns.sort_by_key(|(start, ..)| *start);
+    assert_eq!(assigns, [
+        (at(2, 5), AssignOp::AddAssign, at(2, 7)..at(2, 10)),
+        (at(3, 5), AssignOp::BitOrAssign, at(3, 11)..at(3, 14)),
+        (at(4, 5), AssignOp::RShiftAssign, at(4, 7)..at(4, 11)),
+    ]);
+
+    // each is kept as its own operator rather than desugared to `x = x + 1`
+    let code = tree.root().get_proc("test").unwrap().get().code.as_ref().unwrap();
+    let ops: Vec<_> = code.iter()
+        .map(|statement| match &statement.elem {
+            Statement::Expr(Expression::AssignOp { op, lhs, .. }) => (*op, lhs.to_string()),
+            other => panic!("not an assignment: {other:?}"),
+        })
+        .collect();
+    assert_eq!(ops, [
+        (AssignOp::AddAssign, "x".to_owned()),
+        (AssignOp::BitOrAssign, "flags".to_owned()),
+        (AssignOp::RShiftAssign, "x".to_owned()),
+    ]);
+}
+
+#[test]
+fn dynamic_calls() {
+    let annotations = annotate(r#"
+/proc/test(obj, varname)
+    call(src, "foo")(1)
+    call(obj, varname)()
+    call(/proc/test)(1, 2)
+"#);
+
+    let mut calls: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::DynamicCall { proc_name, args_range } => Some((place.start, proc_name.clone(), args_range.clone())),
+            _ => None,
+        })
+        .collect();
+    calls.sort_by_key(|call| call.0);
+    assert_eq!(calls, [
+        (at(2, 5), Some("foo".to_owned()), at(2, 21)..at(2, 24)),
+        (at(3, 5), None, at(3, 23)..at(3, 25)),
+        (at(4, 5), Some("test".to_owned()), at(4, 21)..at(4, 27)),
+    ]);
+}
+
+#[test]
+fn shift_after_inserted_lines() {
+    let before = r#"
+/proc/first()
+    return 1
+
+/proc/second(a)
+    return a + first()
+    var/copy = new a.type(a)
+"#;
+    // the same code with two blank lines inserted above `second`
+    let after = r#"
+/proc/first()
+    return 1
+
+
+
+/proc/second(a)
+    return a + first()
+    var/copy = new a.type(a)
+"#;
+
+    let mut shifted = annotate(before);
+    let pivot = at(4, 1);
+    let upstream: Vec<_> = shifted.iter()
+        .filter(|(place, _)| place.end < pivot)
+        .map(|(place, annotation)| (place, annotation.clone()))
+        .collect();
+    assert!(!upstream.is_empty());
+    shifted.shift_after(pivot, 2, 0);
+
+    let sorted = |tree: &AnnotationTree| {
+        let mut entries: Vec<_> = tree.iter()
+            .map(|(place, annotation)| (place.start, place.end, format!("{annotation:?}")))
+            .collect();
+        entries.sort();
+        entries
+    };
+    assert_eq!(sorted(&shifted), sorted(&annotate(after)));
+    assert_eq!(shifted.len(), annotate(after).len());
+
+    // annotations above the pivot stay where they were
+    for (place, annotation) in upstream {
+        assert!(shifted.get_range_raw(place).any(|(found, value)| found == place && *value == annotation));
+    }
+    // and those below move, along with the locations they hold
+    let operation = shifted.iter().find_map(|(place, annotation)| match annotation {
+        Annotation::BinaryOperation { op: BinaryOp::Add, range } => Some((place.start, range.clone())),
+        _ => None,
+    });
+    assert_eq!(operation, Some((at(7, 12), at(7, 14)..at(7, 16))));
+}
+
+#[test]
+fn global_scope_qualifier() {
+    let code = r#"
+/var/ticker = 5
+/proc/do_thing()
+    return 1
+/mob/proc/test()
+    global.ticker = global.do_thing()
+    return ::ticker + ::do_thing()
+"#.trim();
+
+    let context = Default::default();
+    let lexer = Lexer::new(&context, Default::default(), code.as_bytes());
+    let mut annotations = AnnotationTree::default();
+    let mut parser = Parser::new(&context, IndentProcessor::new(&context, lexer));
+    parser.enable_procs();
+    parser.annotate_to(&mut annotations);
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+
+    let mut globals: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::GlobalVar(name) => Some((place.start, "var", name.clone())),
+            Annotation::GlobalCall(name) => Some((place.start, "call", name.clone())),
+            _ => None,
+        })
+        .collect();
+    globals.sort_by_key(|global| global.0);
+    assert_eq!(globals, [
+        (at(5, 12), "var", "ticker".to_owned()),
+        (at(5, 28), "call", "do_thing".to_owned()),
+        (at(6, 14), "var", "ticker".to_owned()),
+        (at(6, 25), "call", "do_thing".to_owned()),
+    ]);
+
+    // both resolve to their declarations at the root
+    let ticker = tree.resolve_global_var("ticker").unwrap();
+    assert_eq!(ticker.declaration.as_ref().unwrap().location.line, 1);
+    assert_eq!(tree.resolve_global_proc("do_thing").unwrap().get().location.line, 2);
+    assert!(tree.resolve_global_var("do_thing").is_none());
+    assert!(tree.resolve_global_proc("test").is_none());
+}
@@ -4,7 +4,7 @@ use core::panic;
 
 use dm::ast::*;
 use dm::constants::*;
-use dm::objtree::ObjectTree;
+use dm::objtree::{Callee, ObjectTree};
 use dm::preprocessor::Preprocessor;
 use dm::*;
 
@@ -288,3 +288,1197 @@ fn proc_operator_slash() {
         tree.expect("/datum/operator").get_proc("bar").unwrap();
     });
 }
+
+#[test]
+fn visitor_reaches_nested_calls() {
+    #[derive(Default)]
+    struct Calls(Vec<String>);
+
+    impl<'ast> Visitor<'ast> for Calls {
+        fn visit_term(&mut self, term: &'ast Spanned<Term>) {
+            if let Term::Call(name, _) = &term.elem {
+                self.0.push(name.to_string());
+            }
+            walk_term(self, term);
+        }
+
+        fn visit_follow(&mut self, follow: &'ast Spanned<Follow>) {
+            if let Follow::Call(_, name, _) = &follow.elem {
+                self.0.push(name.to_string());
+            }
+            walk_follow(self, follow);
+        }
+    }
+
+    with_code(r#"
+/proc/test(x = a())
+    if (b())
+        for (var/i = c(); i < 10; i++)
+            spawn(d())
+                while (e())
+                    switch (f())
+                        if (1 to g())
+                            x.h(list(i()))
+                        else
+                            return new /obj{name = j()}(k("[l()]"))
+"#, |context, tree| {
+        context.assert_success();
+        let proc = tree.root().get_proc("test").unwrap().get();
+        let mut calls = Calls::default();
+        calls.visit_proc(&proc.parameters, proc.code.as_ref().unwrap());
+        assert_eq!(calls.0, ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l"]);
+    });
+}
+
+#[test]
+fn proc_override_chain() {
+    with_code(r#"
+/datum/proc/act()
+    return 1
+/datum/middle/act()
+    return ..() + 1
+/datum/middle/act()
+    return ..() + 2
+/datum/middle/leaf
+/datum/adopted
+    parent_type = /datum/middle
+/datum/adopted/act()
+    return ..()
+"#, |context, tree| {
+        context.assert_success();
+        let describe = |proc: dm::objtree::ProcRef| (proc.ty().path.clone(), proc.get().location.line);
+
+        // the leaf inherits the middle's overrides, which lead back to the root
+        let leaf: Vec<_> = tree.expect("/datum/middle/leaf").proc_chain("act").map(describe).collect();
+        assert_eq!(leaf, [
+            ("/datum/middle".to_owned(), 5),
+            ("/datum/middle".to_owned(), 3),
+            ("/datum".to_owned(), 1),
+        ]);
+
+        // parent_type is respected over the path
+        let adopted = tree.expect("/datum/adopted").get_proc("act").unwrap();
+        assert_eq!(describe(adopted.parent_proc().unwrap()), ("/datum/middle".to_owned(), 5));
+        assert_eq!(adopted.proc_chain().count(), 4);
+
+        assert_eq!(tree.expect("/datum").proc_chain("missing").count(), 0);
+    });
+}
+
+#[test]
+fn parent_type_cycles() {
+    with_code(r#"
+/datum/ouroboros
+    parent_type = /datum/ouroboros
+/datum/ping
+    parent_type = /datum/pong
+/datum/pong
+    parent_type = /datum/ping
+/datum/pong/var/x = 1
+/datum/pong/proc/serve()
+"#, |context, tree| {
+        let errors = context.errors();
+        let cycles: Vec<_> = errors.iter().map(|error| (error.description(), error.location().line)).collect();
+        assert_eq!(cycles, [
+            ("parent_type cycle: /datum/ouroboros -> /datum/ouroboros", 2),
+            ("parent_type cycle: /datum/ping -> /datum/pong -> /datum/ping", 4),
+        ]);
+        assert_eq!(errors[1].notes().len(), 1);
+
+        // each cycle is broken by reparenting one of its types to /datum
+        let parent = |path: &str| tree.expect(path).parent_type().unwrap().path.clone();
+        assert_eq!(parent("/datum/ouroboros"), "/datum");
+        assert_eq!(parent("/datum/ping"), "/datum");
+        assert_eq!(parent("/datum/pong"), "/datum/ping");
+        assert!(tree.expect("/datum/ping").get_proc("serve").is_none());
+        assert!(tree.expect("/datum/pong").get_value("x").is_some());
+    });
+}
+
+#[test]
+fn duplicate_var_definition() {
+    with_code(r#"
+/datum/thing
+    var/foo = 1
+    var/bar
+/datum/thing/var/foo = 2
+"#, |context, tree| {
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].description(), "duplicate definition of var/foo");
+        assert_eq!(errors[0].severity(), Severity::Error);
+        assert_eq!(errors[0].location().line, 4);
+        assert_eq!(errors[0].notes()[0].location().line, 2);
+
+        // the later definition still wins
+        let foo = tree.expect("/datum/thing").get().vars.get("foo").unwrap();
+        assert_eq!(foo.value.location.line, 4);
+    });
+}
+
+#[test]
+fn shadowed_parent_var() {
+    with_code(r#"
+/datum/parent
+    var/same
+    var/obj/changed
+/datum/parent/child
+    var/same
+    var/mob/changed
+"#, |context, _| {
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].description(), "/datum/parent/child/var/changed shadows a parent var with a different type");
+        assert_eq!(errors[0].severity(), Severity::Warning);
+        assert_eq!(errors[0].errortype(), Some("var_shadowing"));
+        assert_eq!(errors[0].location().line, 6);
+        assert_eq!(errors[0].notes()[0].location().line, 3);
+    });
+}
+
+#[test]
+fn proc_settings() {
+    with_code(r#"
+/mob/verb/panic()
+    set waitfor = FALSE
+    set category = "X"
+    set name = "Panic Button"
+    set src in view(1)
+    set SpacemanDMM_should_not_sleep = TRUE
+/mob/proc/broken()
+    set background = 2
+    set invisibility = 101
+"#, |context, tree| {
+        let mob = tree.expect("/mob");
+        let settings = &mob.get_proc("panic").unwrap().get().settings;
+        assert_eq!(settings.waitfor, Some(false));
+        assert_eq!(settings.category, Some(Some("X".to_owned())));
+        assert_eq!(settings.name.as_deref(), Some("Panic Button"));
+        assert_eq!(settings.background, None);
+        assert!(matches!(settings.src, Some((SettingMode::In, _))));
+        assert!(settings.other.contains_key("SpacemanDMM_should_not_sleep"));
+
+        let broken = &mob.get_proc("broken").unwrap().get().settings;
+        assert_eq!(broken.background, None);
+        assert_eq!(broken.invisibility, None);
+
+        let errors = context.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].description(), "set background must be 0/1/TRUE/FALSE");
+        assert_eq!(errors[0].errortype(), Some("invalid_set_value"));
+        assert_eq!(errors[1].description(), "set invisibility must be 0-100");
+    });
+}
+
+#[test]
+fn lint_control_comments() {
+    with_code(r#"
+/mob/proc/a()
+    set background = 2 // SDMM: disable invalid-set-value
+    set waitfor = 2
+/mob/proc/b()
+    set background = 2 // SDMM: disable other_rule
+"#, |context, _| {
+        let errors = context.errors();
+        let lines: Vec<_> = errors.iter().map(|error| error.location().line).collect();
+        assert_eq!(lines, [3, 5]);
+        assert_eq!(context.lint_controls().len(), 2);
+    });
+
+    with_code(r#"
+/mob/proc/a()
+    set background = 2
+    set waitfor = 2 // SDMM: enable invalid_set_value
+/mob/proc/b()
+    set background = 2
+// SDMM: disable-file invalid_set_value
+"#, |context, _| {
+        let errors = context.errors();
+        let lines: Vec<_> = errors.iter().map(|error| error.location().line).collect();
+        assert_eq!(lines, [3]);
+    });
+
+    with_code("/var/x = 1 // SDMM: frobnicate unused_var", |context, _| {
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].description(), "unknown SDMM directive");
+    });
+
+    let context = Context::default();
+    let mut pp = Preprocessor::from_buffer(&context, "test.dm".into(), "/var/x = 1 // SDMM: disable a, b\n");
+    pp.enable_annotations();
+    pp.by_ref().count();
+    let annotations = pp.take_annotations().unwrap();
+    let rules: Vec<_> = annotations.iter()
+        .filter_map(|(_, annotation)| match annotation {
+            annotation::Annotation::LintControl { rule, enable: false, range } if range.start.line == 1 => Some(rule.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rules, ["a", "b"]);
+}
+
+#[test]
+fn parameter_input_types() {
+    with_code(r#"
+/mob/verb/give(amount as num, mob/target as mob|null in usr)
+/mob/verb/bad(x as banana)
+"#, |context, tree| {
+        let mob = tree.expect("/mob");
+        let give = mob.get_proc("give").unwrap();
+        assert_eq!(give.parameters[0].input_type, Some(InputType::NUM));
+        assert!(give.parameters[0].in_list.is_none());
+        assert_eq!(give.parameters[1].input_type, Some(InputType::MOB | InputType::NULL));
+        assert_eq!(give.parameters[1].in_list.as_ref().map(ToString::to_string).as_deref(), Some("usr"));
+
+        let bad = mob.get_proc("bad").unwrap();
+        assert_eq!(bad.parameters[0].input_type, Some(InputType::empty()));
+
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].description(), "bad input type: 'banana'");
+        assert_eq!(errors[0].location().line, 2);
+    });
+}
+
+#[test]
+fn call_graph() {
+    with_code(r#"
+/datum/proc/helper()
+    return 1
+/datum/proc/caller(datum/other)
+    helper()
+    other.helper()
+    call(other, "helper")()
+    return ..()
+"#, |context, tree| {
+        context.assert_success();
+        let sites: Vec<_> = tree.call_sites()
+            .map(|site| (site.caller.name().to_owned(), site.callee, site.location.line, site.is_scoped))
+            .collect();
+        assert_eq!(sites, [
+            ("caller".to_owned(), Callee::Named("helper".to_owned()), 4, false),
+            ("caller".to_owned(), Callee::Named("helper".to_owned()), 5, true),
+            ("caller".to_owned(), Callee::Unresolved, 6, false),
+            ("caller".to_owned(), Callee::Named("caller".to_owned()), 7, false),
+        ]);
+    });
+}
+
+#[test]
+fn proc_parameter_at() {
+    with_code(r#"
+/proc/normal(mob/target, amount = 1)
+/proc/spread(obj/first, ...)
+"#, |context, tree| {
+        context.assert_success();
+        let root = tree.root();
+
+        let normal = root.get_proc("normal").unwrap();
+        let target = normal.parameter_at(0).unwrap();
+        assert_eq!(target.name, "target");
+        assert_eq!(&target.var_type.type_path[..], ["mob".to_owned()]);
+        assert_eq!(normal.parameter_at(1).unwrap().name, "amount");
+        assert!(normal.parameter_at(2).is_none());
+
+        let spread = root.get_proc("spread").unwrap();
+        assert_eq!(spread.parameter_at(0).unwrap().name, "first");
+        assert_eq!(spread.parameter_at(1).unwrap().name, "...");
+        assert_eq!(spread.parameter_at(7).unwrap().name, "...");
+    });
+}
+
+#[test]
+fn deeply_nested_expression() {
+    let depth = 5000;
+    let code = format!("/proc/deep()\n\treturn {}1{}\n", "(".repeat(depth), ")".repeat(depth));
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), code);
+    let indents = indents::IndentProcessor::new(&context, pp);
+    let mut parser = parser::Parser::new(&context, indents);
+    parser.enable_procs();
+    parser.parse_object_tree();
+
+    let errors = context.errors();
+    assert!(errors.iter().any(|error| error.description() == "expression nesting too deep"));
+}
+
+#[test]
+fn resolve_scoped_access() {
+    with_code("
+/proc/helper()
+/datum/proc/bar()
+/datum/thing/var/datum/thing/partner
+/datum/thing/var/x = 1
+/datum/thing/bar()
+/datum/thing/proc/foo()
+/datum/thing/sub/x = 2
+", |context, tree| {
+        context.assert_success();
+        let thing = tree.expect("/datum/thing");
+        let sub = tree.expect("/datum/thing/sub");
+        let call = |scope, base: &[&str], name: &str| {
+            let base: Vec<Ident> = base.iter().map(|&s| s.to_owned()).collect();
+            tree.resolve_scoped_call(scope, &base, &name.to_owned()).map(|proc| proc.ty().path.clone())
+        };
+
+        assert_eq!(call(thing, &["src"], "foo").as_deref(), Some("/datum/thing"));
+        assert_eq!(call(sub, &["src"], "bar").as_deref(), Some("/datum/thing"));
+        assert_eq!(call(thing, &["/datum"], "bar").as_deref(), Some("/datum"));
+        assert_eq!(call(sub, &["partner"], "foo").as_deref(), Some("/datum/thing"));
+        assert_eq!(call(thing, &["usr"], "Login").as_deref(), Some("/mob"));
+        assert_eq!(call(thing, &["global"], "helper").as_deref(), Some(""));
+
+        assert_eq!(call(thing, &["src"], "missing"), None);
+        assert_eq!(call(thing, &["src"], "helper"), None);
+        assert_eq!(call(thing, &["nonexistent"], "foo"), None);
+        assert_eq!(call(thing, &["partner", "nonexistent"], "foo"), None);
+
+        let var = |scope, base: &[&str], name: &str| {
+            let base: Vec<Ident> = base.iter().map(|&s| s.to_owned()).collect();
+            tree.resolve_scoped_var(scope, &base, &name.to_owned()).map(|(ty, _)| ty.path.clone())
+        };
+        assert_eq!(var(sub, &["src"], "x").as_deref(), Some("/datum/thing/sub"));
+        assert_eq!(var(sub, &["partner"], "x").as_deref(), Some("/datum/thing"));
+        assert_eq!(var(thing, &["src"], "missing"), None);
+    });
+}
+
+#[test]
+fn path_glob_matching() {
+    with_code("
+/proc/attack()
+/obj/item/weapon
+/obj/item/weapon/proc/attack()
+/obj/item/weapon/sword/attack()
+/obj/item/weapon/sword/rusty
+/obj/item/tool/proc/attack()
+/obj/item/tool/wrench
+", |context, tree| {
+        context.assert_success();
+        let paths = |pattern| tree.matching_paths(pattern).map(|ty| ty.path.clone()).collect::<Vec<_>>();
+        let procs = |pattern| tree.matching_procs(pattern).map(|proc| proc.ty().path.clone()).collect::<Vec<_>>();
+
+        assert_eq!(paths("/obj/item/*"), ["/obj/item/weapon", "/obj/item/tool"]);
+        assert_eq!(paths("/obj/item/*/wrench"), ["/obj/item/tool/wrench"]);
+        assert_eq!(paths("/obj/item/weapon/**"), ["/obj/item/weapon", "/obj/item/weapon/sword", "/obj/item/weapon/sword/rusty"]);
+        assert_eq!(paths("/**/rusty"), ["/obj/item/weapon/sword/rusty"]);
+        assert_eq!(paths("/obj/item/weapon/sword"), ["/obj/item/weapon/sword"]);
+        assert_eq!(paths("/"), [""]);
+        assert!(paths("/obj/item/*/missing").is_empty());
+
+        assert_eq!(procs("/**/proc/attack"), ["", "/obj/item/weapon", "/obj/item/weapon/sword", "/obj/item/tool"]);
+        assert_eq!(procs("/obj/item/weapon/**/attack"), ["/obj/item/weapon", "/obj/item/weapon/sword"]);
+        assert!(procs("/obj/item/tool/wrench/attack").is_empty());
+    });
+}
+
+#[test]
+fn path_completion() {
+    with_code("
+/obj/item/kit
+/obj/machinery/toilet
+/obj/machinery/obj_item_dispenser
+/obj/item
+    var/weight_class
+    var/overweight
+/obj/item/proc/weigh()
+/obj/item/kit/weight_class = 2
+", |context, tree| {
+        context.assert_success();
+        let paths = |prefix, limit| tree.complete_path(prefix, limit).into_iter().map(|ty| ty.path.clone()).collect::<Vec<_>>();
+
+        // prefix matches come before substring matches, then subsequences
+        assert_eq!(paths("/obj/it", 10), [
+            "/obj/item",
+            "/obj/item/kit",
+            "/obj/machinery/obj_item_dispenser",
+            "/obj/machinery/toilet",
+        ]);
+        assert_eq!(paths("/OBJ/IT", 1), ["/obj/item"]);
+        assert!(paths("/obj/zz", 10).is_empty());
+
+        let kit = tree.expect("/obj/item/kit");
+        let members: Vec<_> = tree.complete_member(kit, "weigh", 3).into_iter()
+            .map(|member| match member {
+                dm::objtree::Member::Var(ty, name) => format!("{}/var/{}", ty.path, name),
+                dm::objtree::Member::Proc(proc) => format!("{}/proc/{}", proc.ty().path, proc.name()),
+            })
+            .collect();
+        assert_eq!(members, [
+            "/obj/item/proc/weigh",
+            "/obj/item/kit/var/weight_class",
+            "/obj/item/var/overweight",
+        ]);
+    });
+}
+
+#[test]
+fn parse_budget() {
+    // `A0` expands to 2^30 copies of `1`, within the macro depth limit
+    let mut code = String::new();
+    for i in 0..30 {
+        code.push_str(&format!("#define A{i} (A{}+A{})\n", i + 1, i + 1));
+    }
+    code.push_str("#define A30 1\n/var/before = 1\n/var/bomb = A0\n");
+
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), code.as_str());
+    let indents = indents::IndentProcessor::new(&context, pp);
+    let mut parser = parser::Parser::new(&context, indents);
+    parser.set_token_budget(10_000);
+    parser.set_deadline(std::time::Instant::now() + std::time::Duration::from_secs(60));
+    let tree = parser.parse_object_tree();
+
+    let errors = context.errors();
+    let descriptions: Vec<_> = errors.iter().map(|error| error.description()).collect();
+    assert_eq!(descriptions, ["parse budget exceeded"]);
+    assert_eq!(errors[0].notes()[0].description(), "stopped after reading 10000 tokens");
+    // what was parsed before the budget ran out is kept
+    assert!(tree.root().get_value("before").is_some());
+}
+
+fn with_lenient_code<F: FnOnce(Context, ObjectTree)>(code: &'static str, f: F) {
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), code.trim());
+    let mut indents = indents::IndentProcessor::new(&context, pp);
+    indents.enable_lenient();
+    let mut parser = parser::Parser::new(&context, indents);
+    parser.enable_procs();
+    parser.enable_lenient();
+    let tree = parser.parse_object_tree();
+
+    f(context, tree)
+}
+
+#[test]
+fn lenient_parse() {
+    with_lenient_code("
+/proc/missing_semicolon()
+    var/a = 1
+    a = 2 a = 3
+    return a
+
+/proc/unclosed_paren()
+    world.log << (1 + 2
+    return 4
+
+/proc/stray_token()
+    var/b = 1 )
+    return b
+
+/var/bad = 1 +
+/var/good = 2
+", |context, tree| {
+        let root = tree.root();
+        let body = |name: &str| -> Vec<String> {
+            let code = root.get_proc(name).unwrap().get().code.as_ref().unwrap();
+            code.iter().map(|statement| match &statement.elem {
+                Statement::Error { .. } => "error".to_owned(),
+                Statement::Return(Some(expr)) => format!("return {expr}"),
+                Statement::Var(_) => "var".to_owned(),
+                Statement::Expr(expr) => expr.to_string(),
+                other => panic!("unexpected statement {other:?}"),
+            }).collect()
+        };
+
+        assert_eq!(body("missing_semicolon"), ["var", "error", "return a"]);
+        assert_eq!(body("unclosed_paren"), ["error", "return 4"]);
+        assert_eq!(body("stray_token"), ["error", "return b"]);
+
+        assert!(matches!(root.get_value("bad").unwrap().expression, Some(Expression::Error { .. })));
+        assert_eq!(root.get_value("good").unwrap().expression.as_ref().unwrap().to_string(), "2");
+        assert_eq!(context.errors().len(), 4);
+    });
+}
+
+#[test]
+fn proc_return_types() {
+    with_code(r#"
+#define RETURN_TYPE(X) set SpacemanDMM_return_type = X
+/datum/proc/hinted()
+    RETURN_TYPE(/obj/item)
+    return new /obj/item
+/datum/child/hinted()
+    return ..()
+/datum/proc/declared() as /mob/living
+/datum/proc/input_typed() as turf
+/datum/proc/unhinted()
+    return 1
+/datum/proc/late_hint()
+    world.log << "first"
+    RETURN_TYPE(/obj/item)
+/obj/item
+/mob/living
+"#, |context, tree| {
+        context.assert_success();
+        let datum = tree.find("/datum").unwrap();
+        let return_type = |ty: dm::objtree::TypeRef, name| {
+            ty.get_proc(name).unwrap().return_type().map(|path| FormatTypePath(&path).to_string())
+        };
+        assert_eq!(return_type(datum, "hinted").as_deref(), Some("/obj/item"));
+        assert_eq!(return_type(tree.find("/datum/child").unwrap(), "hinted").as_deref(), Some("/obj/item"));
+        assert_eq!(return_type(datum, "declared").as_deref(), Some("/mob/living"));
+        assert_eq!(return_type(datum, "input_typed").as_deref(), Some("/turf"));
+        assert_eq!(return_type(datum, "unhinted"), None);
+        // settings only count before the first other statement
+        assert_eq!(return_type(datum, "late_hint"), None);
+    });
+}
+
+#[test]
+fn symbol_index() {
+    with_code(r#"
+/// A widget.
+/obj/widget
+    var/size = 2
+    size = 3
+/obj/widget/proc/spin()
+/obj/widget/spin()
+/mob/verb/wave()
+"#, |context, tree| {
+        context.assert_success();
+        let symbols = tree.symbols();
+        let find = |path: &str| {
+            let matching: Vec<_> = symbols.iter().filter(|symbol| symbol.path == path).collect();
+            assert_eq!(matching.len(), 1, "{path}: {matching:?}");
+            matching[0].clone()
+        };
+
+        let widget = find("/obj/widget");
+        assert_eq!(widget.kind, dm::objtree::SymbolKind::Type);
+        assert_eq!(widget.location.line, 2);
+        assert_eq!(widget.docs.map(|docs| docs.text()).as_deref(), Some("A widget."));
+
+        let size = find("/obj/widget/var/size");
+        assert_eq!(size.kind, dm::objtree::SymbolKind::Var);
+        assert_eq!(size.location.line, 3);
+        assert!(size.docs.is_none());
+
+        let spin = find("/obj/widget/proc/spin");
+        assert_eq!(spin.kind, dm::objtree::SymbolKind::Proc(ProcDeclKind::Proc));
+        assert_eq!(spin.location.line, 5);
+
+        assert_eq!(find("/mob/verb/wave").kind, dm::objtree::SymbolKind::Proc(ProcDeclKind::Verb));
+        // builtins are listed too
+        find("/datum/var/type");
+        assert!(symbols.iter().all(|symbol| !symbol.path.is_empty()));
+    });
+}
+
+#[test]
+fn effective_var_values() {
+    with_code(r#"
+/obj/base
+    var/size = 1
+    var/color
+    var/weight = 5
+/obj/base/child
+    size = 2
+    var/weight
+/obj/base/child/grandchild
+"#, |context, tree| {
+        context.assert_success();
+        let grandchild = tree.find("/obj/base/child/grandchild").unwrap();
+        let effective = |name: &str| {
+            let (ty, decl) = grandchild.effective_var(name).unwrap();
+            (ty.get().path.as_str(), decl.location.line)
+        };
+
+        // overridden on the child
+        assert_eq!(effective("size"), ("/obj/base/child", 2));
+        // declared without a value
+        assert_eq!(effective("color"), ("/obj/base", 3));
+        // redeclared without a value on the child, valued on the parent
+        assert_eq!(effective("weight"), ("/obj/base", 7));
+        assert!(grandchild.effective_var("missing").is_none());
+    });
+}
+
+#[test]
+fn sorted_diagnostics() {
+    with_code(r#"
+/datum/parent
+    var/obj/changed
+/datum/parent/child
+    var/mob/changed
+    var/other
+#warn after the shadowing
+/datum/other
+"#, |context, _| {
+        // the shadowing check runs after parsing finishes
+        let registered: Vec<_> = context.errors().iter().map(|error| error.location().line).collect();
+        assert_eq!(registered, [6, 4]);
+
+        let held = context.errors();
+        let sorted: Vec<_> = context.sorted_errors().iter().map(|error| error.location().line).collect();
+        assert_eq!(sorted, [4, 6]);
+        // the registered order is untouched
+        assert_eq!(held[0].location().line, 6);
+    });
+}
+
+#[test]
+fn verb_and_proc_kinds() {
+    with_code(r#"
+/mob/verb/poke(mob/target as mob in view(1))
+/mob/proc/helper(count = 1)
+/mob/poke(mob/target)
+"#, |context, tree| {
+        context.assert_success();
+        let mob = tree.find("/mob").unwrap();
+
+        let poke = mob.get_proc("poke").unwrap();
+        assert_eq!(poke.kind(), Some(ProcDeclKind::Verb));
+        assert_eq!(mob.get_proc("helper").unwrap().kind(), Some(ProcDeclKind::Proc));
+
+        let verbs: Vec<_> = mob.iter_self_procs()
+            .filter(|proc| proc.kind().is_some_and(ProcDeclKind::is_verb))
+            .map(|proc| proc.index())
+            .collect();
+        assert_eq!(verbs, [0, 1]);
+
+        let declared = mob.get_self_proc("poke", 0).unwrap();
+        let target = &declared.get().parameters[0];
+        assert_eq!(target.input_type, Some(InputType::MOB));
+        let Some(Expression::Base { term, .. }) = &target.in_list else {
+            panic!("{:?}", target.in_list);
+        };
+        assert!(matches!(&term.elem, Term::Call(name, args) if name == "view" && args.len() == 1));
+    });
+}
+
+#[test]
+fn type_keywords() {
+    use dm::objtree::TypeKeyword;
+
+    with_code(r#"
+/datum/outer
+    inner
+        proc/describe()
+            return "[type] from [parent_type]"
+"#, |context, tree| {
+        context.assert_success();
+        let enclosing = ["datum".to_owned(), "outer".to_owned(), "inner".to_owned()];
+        let resolve = |path: &[String], kw| tree.resolve_type_keyword(path, kw).map(|ty| ty.path.clone());
+
+        assert_eq!(TypeKeyword::from_name("type"), Some(TypeKeyword::Type));
+        assert_eq!(TypeKeyword::from_name("parent_type"), Some(TypeKeyword::ParentType));
+        assert_eq!(TypeKeyword::from_name("src"), None);
+
+        assert_eq!(resolve(&enclosing, TypeKeyword::Type).as_deref(), Some("/datum/outer/inner"));
+        assert_eq!(resolve(&enclosing, TypeKeyword::ParentType).as_deref(), Some("/datum/outer"));
+        assert_eq!(resolve(&enclosing[..1], TypeKeyword::ParentType), None);
+        assert_eq!(resolve(&[], TypeKeyword::Type), None);
+        assert_eq!(resolve(&["datum".to_owned(), "missing".to_owned()], TypeKeyword::Type), None);
+    });
+}
+
+#[test]
+fn naming_conventions() {
+    use dm::config::{Config, NamingCase};
+
+    let mut config = Config::default();
+    config.code_standards.naming.procs = Some(NamingCase::SnakeCase);
+    config.code_standards.naming.vars = Some(NamingCase::SnakeCase);
+    let mut context = Context::default();
+    context.set_config(config);
+
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), r#"
+/mob/var/max_health = 100
+/mob/var/MoveSpeed = 2
+/mob/proc/take_damage()
+/mob/proc/TakeDamage()
+/mob/verb/Say()
+/mob/Move()
+"#.trim());
+    let indents = indents::IndentProcessor::new(&context, pp);
+    parser::Parser::new(&context, indents).parse_object_tree();
+
+    let mut errors: Vec<_> = context.errors().iter().map(|error| (error.location().line, error.description().to_owned())).collect();
+    errors.sort();
+    assert_eq!(errors, [
+        (2, "var name is not snake_case: /mob/var/MoveSpeed".to_owned()),
+        (4, "proc name is not snake_case: /mob/proc/TakeDamage".to_owned()),
+    ]);
+}
+
+#[test]
+fn streamed_declarations() {
+    use dm::parser::DeclarationKind::*;
+
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), r#"
+/obj/item
+    var/size = 1
+    proc/use()
+/proc/global_helper()
+/var/global_count = 0
+/mob/Login()
+/proc
+    grouped()
+/turf/floor
+"#.trim());
+    let indents = indents::IndentProcessor::new(&context, pp);
+    let mut declarations = Vec::new();
+    let mut parser = parser::Parser::new(&context, indents);
+    parser.on_declaration(|declaration| declarations.push((declaration.kind, declaration.path, declaration.location.line)));
+    parser.parse_object_tree();
+    context.assert_success();
+
+    assert_eq!(declarations, [
+        (Type, "/obj/item".to_owned(), 1),
+        (Proc, "/proc/global_helper".to_owned(), 4),
+        (Var, "/var/global_count".to_owned(), 5),
+        (Proc, "/mob/Login".to_owned(), 6),
+        (Proc, "/proc/grouped".to_owned(), 8),
+        (Type, "/turf/floor".to_owned(), 9),
+    ]);
+}
+
+#[test]
+fn duplicate_proc_parameters() {
+    with_code(r#"
+/proc/foo(a, b, a)
+/proc/bar(a, ...)
+"#, |context, _| {
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].description(), "duplicate proc parameter: a");
+        assert_eq!(errors[0].location().column, 17);
+        assert_eq!(errors[0].notes()[0].location().column, 11);
+    });
+}
+
+#[test]
+fn proc_overrides() {
+    with_code(r#"
+/mob/proc/Life()
+/mob/living/Life()
+/mob/living/carbon
+/mob/living/carbon/human/Life()
+/obj/Life()
+/obj/proc/Life()
+"#, |_, tree| {
+        let mob = tree.expect("/mob");
+        let overrides: Vec<_> = tree.overrides_of(mob, &"Life".to_owned())
+            .into_iter()
+            .map(|proc| (proc.ty().path.clone(), proc.get().location.line))
+            .collect();
+        assert_eq!(overrides, [
+            ("/mob/living".to_owned(), 2),
+            ("/mob/living/carbon/human".to_owned(), 4),
+        ]);
+
+        // inherited, so the override below still counts
+        let living = tree.expect("/mob/living");
+        assert_eq!(tree.overrides_of(living, &"Life".to_owned()).len(), 1);
+        assert!(tree.overrides_of(mob, &"Die".to_owned()).is_empty());
+        assert!(tree.overrides_of(tree.expect("/turf"), &"Life".to_owned()).is_empty());
+    });
+}
+
+#[test]
+fn type_hierarchy() {
+    with_code(r#"
+/datum/shape
+/datum/shape/circle
+/datum/square
+    parent_type = /datum/shape
+/datum/shape/circle/big
+"#, |_, tree| {
+        let hierarchy = tree.hierarchy();
+        assert_eq!(hierarchy.len(), tree.iter_types().count());
+        assert!(hierarchy[0].0.is_root());
+
+        let children_of = |path: &str| -> Vec<&str> {
+            let (_, children) = hierarchy.iter().find(|(ty, _)| ty.get().path == path).unwrap();
+            children.iter().map(|child| child.get().path.as_str()).collect()
+        };
+        // square inherits from shape, not from the path it's declared under
+        assert_eq!(children_of("/datum/shape"), ["/datum/shape/circle", "/datum/square"]);
+        assert_eq!(children_of("/datum/shape/circle"), ["/datum/shape/circle/big"]);
+        assert!(children_of("/datum/shape/circle/big").is_empty());
+        assert!(!children_of("/datum").contains(&"/datum/square"));
+        assert!(children_of("").contains(&"/datum"));
+
+        // every type but the root has exactly one parent
+        let listed: usize = hierarchy.iter().map(|(_, children)| children.len()).sum();
+        assert_eq!(listed, hierarchy.len() - 1);
+    });
+}
+
+fn parse_files(context: &Context, files: &[(FileId, &'static str)]) -> ObjectTree {
+    let tokens = files.iter().flat_map(|&(file, code)| lexer::Lexer::new(context, file, code.trim_start().as_bytes()));
+    let mut parser = parser::Parser::new(context, indents::IndentProcessor::new(context, tokens));
+    parser.enable_procs();
+    parser.parse_object_tree()
+}
+
+fn parse_file_ast(context: &Context, file: FileId, code: &'static str) -> objtree::FileAst {
+    let lexer = lexer::Lexer::new(context, file, code.trim_start().as_bytes());
+    let mut parser = parser::Parser::new(context, indents::IndentProcessor::new(context, lexer));
+    parser.enable_procs();
+    parser.parse_file_ast()
+}
+
+#[test]
+fn update_file_adds_proc() {
+    let context = Context::default();
+    let first = context.register_file(std::path::Path::new("first.dm"));
+    let second = context.register_file(std::path::Path::new("second.dm"));
+    let mut tree = parse_files(&context, &[
+        (first, r#"
+/datum/thing
+    var/power = 1
+/datum/thing/proc/act()
+    return power
+"#),
+        (second, r#"
+/datum/thing/act()
+    return 2
+"#),
+    ]);
+    assert!(context.errors().is_empty());
+    assert!(tree.find("/datum/thing").unwrap().get_proc("extra").is_none());
+
+    tree.update_file(&context, second, parse_file_ast(&context, second, r#"
+/datum/thing/act()
+    return 3
+/datum/thing/proc/extra()
+/datum/thing/child
+    power = 5
+"#));
+    assert!(context.errors().is_empty());
+
+    let thing = tree.find("/datum/thing").unwrap();
+    let act = &thing.get().procs["act"];
+    assert_eq!(act.value.len(), 2);
+    assert_eq!(act.value[0].location.file, first);
+    assert_eq!(act.value[1].location.file, second);
+    let extra = thing.get_proc("extra").unwrap();
+    assert_eq!(extra.location.file, second);
+    assert!(extra.get_declaration().is_some());
+
+    let child = tree.find("/datum/thing/child").unwrap();
+    assert!(child.is_subtype_of(&thing));
+    assert_eq!(child.get_value("power").unwrap().constant, Some(Constant::Float(5.)));
+    assert_eq!(thing.get_value("power").unwrap().constant, Some(Constant::Float(1.)));
+}
+
+#[test]
+fn update_file_removes_type() {
+    let context = Context::default();
+    let first = context.register_file(std::path::Path::new("first.dm"));
+    let second = context.register_file(std::path::Path::new("second.dm"));
+    let mut tree = parse_files(&context, &[
+        (first, r#"
+/datum/thing
+    var/power = 1
+"#),
+        (second, r#"
+/datum/thing
+    proc/act()
+/datum/gadget
+    var/charge = 3
+/datum/gadget/small
+"#),
+    ]);
+    assert!(context.errors().is_empty());
+    assert!(tree.find("/datum/gadget/small").is_some());
+
+    tree.update_file(&context, second, parse_file_ast(&context, second, r#"
+/datum/widget
+"#));
+    assert!(context.errors().is_empty());
+
+    assert!(tree.find("/datum/gadget").is_none());
+    assert!(tree.find("/datum/gadget/small").is_none());
+    assert!(tree.find("/datum").unwrap().children().all(|child| child.get().path != "/datum/gadget"));
+    assert!(tree.find("/datum/widget").unwrap().is_subtype_of(&tree.find("/datum").unwrap()));
+
+    // the half of the split type declared in the other file remains
+    let thing = tree.find("/datum/thing").unwrap();
+    assert_eq!(thing.location.file, first);
+    assert!(thing.get_proc("act").is_none());
+    assert_eq!(thing.get_value("power").unwrap().constant, Some(Constant::Float(1.)));
+}
+
+#[test]
+fn update_file_keeps_other_values() {
+    let context = Context::default();
+    let first = context.register_file(std::path::Path::new("first.dm"));
+    let second = context.register_file(std::path::Path::new("second.dm"));
+    let mut tree = parse_files(&context, &[
+        (first, r#"
+/datum/gadget
+    var/x = 1
+"#),
+        (second, r#"
+/datum/gadget
+    x = 2
+"#),
+    ]);
+    assert!(context.errors().is_empty());
+    assert_eq!(tree.find("/datum/gadget").unwrap().get_value("x").unwrap().constant, Some(Constant::Float(2.)));
+
+    tree.update_file(&context, second, parse_file_ast(&context, second, r#"
+/datum/other
+"#));
+    assert!(context.errors().is_empty());
+
+    // the first file's value takes the place of the removed one
+    let x = tree.find("/datum/gadget").unwrap().get_value("x").unwrap();
+    assert_eq!(x.location.file, first);
+    assert!(x.expression.is_some());
+    assert_eq!(x.constant, Some(Constant::Float(1.)));
+
+    // and a value added back comes after it again
+    tree.update_file(&context, second, parse_file_ast(&context, second, r#"
+/datum/gadget
+    x = 3
+"#));
+    assert_eq!(tree.find("/datum/gadget").unwrap().get_value("x").unwrap().constant, Some(Constant::Float(3.)));
+}
+
+#[test]
+fn update_file_replaces_diagnostics() {
+    use dm::config::{Config, NamingCase};
+
+    let mut config = Config::default();
+    config.code_standards.naming.procs = Some(NamingCase::SnakeCase);
+    let mut context = Context::default();
+    context.set_config(config);
+    let first = context.register_file(std::path::Path::new("first.dm"));
+    let second = context.register_file(std::path::Path::new("second.dm"));
+    let mut tree = parse_files(&context, &[
+        (first, r#"
+/datum/thing/proc/BadName()
+"#),
+        (second, r#"
+/datum/thing/proc/good_name()
+"#),
+    ]);
+    assert_eq!(context.errors().len(), 1);
+
+    for _ in 0..2 {
+        tree.update_file(&context, second, parse_file_ast(&context, second, r#"
+/datum/thing/proc/other_name()
+"#));
+        let errors = context.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location().file, first);
+    }
+}
+
+#[test]
+fn var_references() {
+    with_code(r#"
+/datum/thing
+    var/power = 1
+/datum/thing/proc/read()
+    return power
+/datum/thing/proc/write()
+    power = 2
+    src.power += 1
+/datum/thing/proc/shadowed()
+    var/power = 3
+    if (power)
+        return power
+/datum/thing/proc/parameter(power)
+    return power
+/datum/thing/sub/proc/inherited()
+    for (power in list(1, 2))
+        world.log << power
+/datum/other
+    var/power
+/proc/outside(datum/thing/T)
+    T.power = 4
+    var/datum/other/O = new
+    return O.power
+"#, |_, tree| {
+        let var = tree.expect("/datum/thing/sub").var_ref("power").unwrap();
+        assert_eq!(var.ty().path, "/datum/thing");
+        assert!(tree.expect("/datum").var_ref("power").is_none());
+
+        let references: Vec<_> = tree.var_references(var)
+            .into_iter()
+            .map(|(location, kind)| (location.line, kind))
+            .collect();
+        assert_eq!(references, [
+            (4, objtree::AccessKind::Read),
+            (6, objtree::AccessKind::Write),
+            (7, objtree::AccessKind::Write),
+            (15, objtree::AccessKind::Write),
+            (16, objtree::AccessKind::Read),
+            (20, objtree::AccessKind::Write),
+        ]);
+    });
+}
+
+#[test]
+fn type_depth_and_order() {
+    with_code(r#"
+/obj/item
+/obj/item_holder
+/obj/item/tool
+/area
+/obj/armor
+"#, |_, tree| {
+        assert_eq!(tree.root().depth(), 0);
+        assert_eq!(tree.expect("/obj").depth(), 1);
+        assert_eq!(tree.expect("/obj/item").depth(), 2);
+        assert_eq!(tree.expect("/obj/item/tool").depth(), 3);
+
+        let sorted = tree.types_sorted();
+        assert_eq!(sorted.len(), tree.iter_types().count());
+        assert!(sorted[0].is_root());
+        let paths: Vec<&str> = sorted.iter()
+            .map(|ty| ty.path.as_str())
+            .filter(|path| path.starts_with("/obj"))
+            .collect();
+        // subpaths come before siblings whose names merely extend the parent's
+        assert_eq!(paths, ["/obj", "/obj/armor", "/obj/item", "/obj/item/tool", "/obj/item_holder"]);
+    });
+}
+
+#[test]
+fn empty_blocks() {
+    use dm::config::Config;
+
+    let mut config = Config::default();
+    config.code_standards.empty_blocks.procs = true;
+    config.code_standards.empty_blocks.ifs = true;
+    config.code_standards.empty_blocks.whiles = true;
+    let mut context = Context::default();
+    context.set_config(config);
+
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), r#"
+/mob/proc/attack()
+/mob/proc/nothing() {}
+/mob/proc/check(x)
+    if(x)
+    else if(!x)
+        return 1
+    while(x)
+    return 0
+"#.trim());
+    let indents = indents::IndentProcessor::new(&context, pp);
+    let mut parser = parser::Parser::new(&context, indents);
+    parser.enable_procs();
+    parser.parse_object_tree();
+
+    // `attack` has no body at all, so it's abstract rather than empty
+    let mut errors: Vec<_> = context.errors().iter().map(|error| {
+        assert_eq!(error.errortype(), Some("empty_block"));
+        (error.location().line, error.description().to_owned())
+    }).collect();
+    errors.sort();
+    assert_eq!(errors, [
+        (2, "empty proc body: /mob/proc/nothing".to_owned()),
+        (4, "empty if block".to_owned()),
+        (7, "empty while loop".to_owned()),
+    ]);
+}
+
+#[test]
+fn empty_blocks_off_by_default() {
+    with_code("/proc/nothing() {}\n/proc/check(x)\n    if(x)\n", |context, _| {
+        context.assert_success();
+    });
+}
+
+#[test]
+fn docs_for_paths() {
+    with_code(r#"
+/// A widget.
+/obj/widget
+    /// How big it is.
+    var/size = 2
+    var/color
+/// Spin it around.
+/obj/widget/proc/spin()
+/obj/widget/proc/stop()
+/obj/widget/big
+    size = 3
+/obj/widget/big/spin()
+/// Greet everyone.
+/proc/hello()
+"#, |context, tree| {
+        context.assert_success();
+        let docs = |path: &str| tree.docs_for(path).map(|docs| docs.text());
+
+        assert_eq!(docs("/obj/widget").as_deref(), Some("A widget."));
+        assert_eq!(docs("/obj/widget/var/size").as_deref(), Some("How big it is."));
+        assert_eq!(docs("/obj/widget/proc/spin").as_deref(), Some("Spin it around."));
+        assert_eq!(docs("/proc/hello").as_deref(), Some("Greet everyone."));
+        // undocumented overrides and subtypes fall back to what they inherit
+        assert_eq!(docs("/obj/widget/big/var/size").as_deref(), Some("How big it is."));
+        assert_eq!(docs("/obj/widget/big/proc/spin").as_deref(), Some("Spin it around."));
+
+        assert_eq!(docs("/obj/widget/big"), None);
+        assert_eq!(docs("/obj/widget/var/color"), None);
+        assert_eq!(docs("/obj/widget/proc/stop"), None);
+        assert_eq!(docs("/obj/widget/var/missing"), None);
+        assert_eq!(docs("/obj/missing"), None);
+    });
+}
+
+#[test]
+fn defining_files() {
+    let root = std::env::temp_dir().join(format!("dm-defining-files-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.dm"), "/obj/widget\n    var/size = 2\n").unwrap();
+    std::fs::write(root.join("b.dm"), "/obj/widget/proc/spin()\n    return\n").unwrap();
+    std::fs::write(root.join("c.dm"), "/obj/other\n    name = \"other\"\n").unwrap();
+
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, root.join("test.dme"), "#include \"a.dm\"\n#include \"b.dm\"\n#include \"c.dm\"\n");
+    let mut parser = parser::Parser::new(&context, indents::IndentProcessor::new(&context, pp));
+    parser.enable_procs();
+    let tree = parser.parse_object_tree();
+    std::fs::remove_dir_all(&root).unwrap();
+    context.assert_success();
+
+    let names = |path: &str| -> Vec<String> {
+        tree.defining_files(tree.find(path).unwrap()).into_iter()
+            .map(|file| context.file_path(file).display().to_string())
+            .collect()
+    };
+    assert_eq!(names("/obj/widget"), ["a.dm", "b.dm"]);
+    assert_eq!(names("/obj/other"), ["c.dm"]);
+    // built-in types declare nothing in any file
+    assert!(names("/datum").is_empty());
+}
+
+#[test]
+fn proc_bodies() {
+    let code = r#"
+/mob/proc/attack()
+/mob/proc/heal(amount)
+    var/total = amount * 2
+    return total
+"#.trim();
+
+    with_code(code, |context, tree| {
+        context.assert_success();
+        let mob = tree.find("/mob").unwrap();
+        let body = mob.get_proc("heal").unwrap().body().unwrap();
+        assert_eq!(body.len(), 2);
+        assert!(matches!(body[0].elem, Statement::Var(ref var) if var.name == "total"));
+        assert!(matches!(body[1].elem, Statement::Return(Some(_))));
+        assert_eq!(body[1].location.line, 4);
+        // declared without a body, meant to be overridden
+        assert!(mob.get_proc("attack").unwrap().body().is_none());
+        // builtins have no body
+        assert!(mob.get_proc("Login").unwrap().body().is_none());
+    });
+
+    // without proc parsing enabled, bodies aren't kept
+    let context = Context::default();
+    let pp = Preprocessor::from_buffer(&context, "test.dm".into(), code);
+    let parser = parser::Parser::new(&context, indents::IndentProcessor::new(&context, pp));
+    let tree = parser.parse_object_tree();
+    context.assert_success();
+    assert!(tree.find("/mob").unwrap().get_proc("heal").unwrap().body().is_none());
+}
@@ -111,6 +111,26 @@ fn rgb_hcy() {
     );
 }
 
+#[test]
+fn rgb_folds_to_color() {
+    let black = eval("rgb(0,0,0)").unwrap();
+    assert_eq!(black, Constant::string("#000000"));
+    assert_eq!(black.as_color(), Some([0, 0, 0, 255]));
+    assert_eq!(eval("rgb(255, 0, 0, 128)").unwrap().as_color(), Some([255, 0, 0, 128]));
+
+    // Unresolved arguments leave the call unevaluated.
+    assert!(eval("rgb(r,g,b)").is_err());
+}
+
+#[test]
+fn matrix_folds_to_components() {
+    let identity = eval("matrix()").unwrap();
+    assert_eq!(identity.as_matrix(), Some([1., 0., 0., 0., 1., 0.]));
+    assert_eq!(eval("matrix(2, 0, 4, 0, 2 * 3, -1)").unwrap().as_matrix(), Some([2., 0., 4., 0., 6., -1.]));
+    assert_eq!(eval("matrix(2, 0)").unwrap().as_matrix(), None);
+    assert!(eval("matrix(a, 0, 0, 0, 1, 0)").is_err());
+}
+
 #[test]
 fn no_fexists_outside_preproc() {
     assert_eq!(
@@ -118,3 +138,248 @@ fn no_fexists_outside_preproc() {
         "non-constant function call: fexists",
     );
 }
+
+#[test]
+fn bitwise_ops() {
+    assert_eq!(eval("6 & 3").unwrap(), Constant::Float(2.));
+    assert_eq!(eval("6 | 3").unwrap(), Constant::Float(7.));
+    assert_eq!(eval("6 ^ 3").unwrap(), Constant::Float(5.));
+    assert_eq!(eval("1 << 3").unwrap(), Constant::Float(8.));
+    assert_eq!(eval("64 >> 2").unwrap(), Constant::Float(16.));
+    assert_eq!(eval("~0").unwrap(), Constant::Float(16777215.));
+}
+
+#[test]
+fn bitwise_truncation() {
+    // BYOND keeps only the low 24 bits
+    assert_eq!(eval("1 << 24").unwrap(), Constant::Float(0.));
+    assert_eq!(eval("1 << 40").unwrap(), Constant::Float(0.));
+    assert_eq!(eval("16777220 & 7").unwrap(), Constant::Float(4.));
+    assert_eq!(eval("-1 & 255").unwrap(), Constant::Float(255.));
+}
+
+#[test]
+fn bitwise_precedence() {
+    assert_eq!(eval("1<<3 | 1<<5").unwrap(), Constant::Float(40.));
+    assert_eq!(eval("1 + 2 << 1").unwrap(), Constant::Float(6.));
+    assert_eq!(eval("2 * 3 & 4").unwrap(), Constant::Float(4.));
+    assert_eq!(eval("1 | 2 * 4").unwrap(), Constant::Float(9.));
+}
+
+#[test]
+fn bitwise_non_integer() {
+    assert_eq!(
+        eval("1.5 | 2").unwrap_err().description(),
+        "non-integer operand to bitwise operation: 1.5 | 2",
+    );
+    assert_eq!(
+        eval("~0.5").unwrap_err().description(),
+        "non-integer operand to bitwise operation: ~0.5",
+    );
+}
+
+#[test]
+fn string_concatenation() {
+    assert_eq!(eval(r#""a" + "b""#).unwrap(), Constant::string("ab"));
+    assert_eq!(eval(r#""icons/" + "mob" + ".dmi""#).unwrap(), Constant::string("icons/mob.dmi"));
+}
+
+#[test]
+fn text_block() {
+    assert_eq!(
+        eval("{\"first\nsecond\"}").unwrap(),
+        Constant::string("first\nsecond"),
+    );
+    assert_eq!(eval("{\"a\"} + \"b\"").unwrap(), Constant::string("ab"));
+}
+
+#[test]
+fn literal_interpolation() {
+    assert_eq!(eval(r#""[1]""#).unwrap(), Constant::string("1"));
+    assert_eq!(eval(r#""x=[1 + 2], y=[0.5][null]""#).unwrap(), Constant::string("x=3, y=0.5"));
+    assert_eq!(eval(r#""[ "nested [4]" ]!""#).unwrap(), Constant::string("nested 4!"));
+}
+
+#[test]
+fn unresolved_interpolation() {
+    assert!(eval(r#""hello [world]""#).is_err());
+    assert_eq!(
+        eval(r#""\the [1]""#).unwrap_err().description(),
+        "non-constant string interpolation: text macros are not supported",
+    );
+    assert_eq!(
+        eval(r#""[list(1)]""#).unwrap_err().description(),
+        "non-constant string interpolation: [list(1)]",
+    );
+}
+
+#[test]
+fn cached_evaluation() {
+    let context = dm::Context::default();
+    let lexer = dm::lexer::Lexer::new(&context, Default::default(), "1<<3".as_bytes());
+    let expr = dm::parser::parse_expression(&context, Default::default(), lexer).unwrap();
+
+    let mut cache = ConstantCache::default();
+    for _ in 0..10_000 {
+        let value = expr.clone().cached_evaluate(Default::default(), &mut cache).unwrap();
+        assert_eq!(value, Constant::Float(8.0));
+    }
+    assert_eq!(cache.misses(), 1);
+    assert_eq!(cache.hits(), 9_999);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn cached_evaluation_ignores_locations() {
+    let context = dm::Context::default();
+    let parse = |code: &str| {
+        let lexer = dm::lexer::Lexer::new(&context, Default::default(), code.as_bytes());
+        dm::parser::parse_expression(&context, Default::default(), lexer).unwrap()
+    };
+
+    let mut cache = ConstantCache::default();
+    let first = parse("list(\"a\" = 1 << 3)").cached_evaluate(Default::default(), &mut cache).unwrap();
+    let second = parse("list( \"a\"=1<<3 )").cached_evaluate(Default::default(), &mut cache).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(cache.misses(), 1);
+    assert_eq!(cache.hits(), 1);
+
+    parse("list(\"a\" = 1 << 4)").cached_evaluate(Default::default(), &mut cache).unwrap();
+    assert_eq!(cache.misses(), 2);
+}
+
+#[test]
+fn cached_evaluation_respects_vars() {
+    let context = dm::Context::default();
+    let code = r#"
+/datum/var/x = 1
+/datum/var/y = x << 3
+/datum/var/z = 1 << 3
+/datum/child/x = 2
+/datum/child/y = x << 3
+/datum/child/z = 1 << 3
+"#;
+    let pp = dm::preprocessor::Preprocessor::from_buffer(&context, "test.dm".into(), code.trim());
+    let indents = dm::indents::IndentProcessor::new(&context, pp);
+    let tree = dm::parser::Parser::new(&context, indents).parse_object_tree();
+    context.assert_success();
+
+    let value = |path: &str, var: &str| tree.find(path).unwrap().get_value(var).unwrap().constant.clone();
+    assert_eq!(value("/datum", "y"), Some(Constant::Float(8.0)));
+    assert_eq!(value("/datum/child", "y"), Some(Constant::Float(16.0)));
+    assert_eq!(value("/datum", "z"), Some(Constant::Float(8.0)));
+    assert_eq!(value("/datum/child", "z"), Some(Constant::Float(8.0)));
+}
+
+#[test]
+fn constant_classification() {
+    let context = dm::Context::default();
+    let code = r#"
+#define WIDTH 32
+#define VALUE_OF(x) initial(x)
+var/const/BASE = 4
+var/const/DERIVED = BASE * 2
+var/mutable = 1
+/proc/helper()
+/datum/var/const/LIMIT = 10
+/datum/var/plain = 5
+/expr/var/literal = "text"
+/expr/var/path = /datum
+/expr/var/macro = WIDTH * 2
+/expr/var/const_ref = DERIVED + 1
+/expr/var/static_field = /datum::LIMIT
+/expr/var/ternary = BASE ? -BASE : ~BASE
+/expr/var/builtin = rgb(255, WIDTH, 0)
+/expr/var/interp = "[BASE] items"
+/expr/var/mutable_ref = mutable
+/expr/var/mutable_operand = 1 + mutable
+/expr/var/proc_call = helper()
+/expr/var/macro_call = VALUE_OF(BASE)
+/expr/var/static_plain = /datum::plain
+/expr/var/field = BASE.foo
+"#;
+    let pp = dm::preprocessor::Preprocessor::from_buffer(&context, "test.dm".into(), code.trim());
+    let indents = dm::indents::IndentProcessor::new(&context, pp);
+    let tree = dm::parser::Parser::new(&context, indents).parse_object_tree();
+
+    let expr = tree.find("/expr").unwrap();
+    let is_const = |var: &str| expr.get_value(var).unwrap().expression.as_ref().unwrap().is_const(&tree);
+    for var in ["literal", "path", "macro", "const_ref", "static_field", "ternary", "builtin", "interp"] {
+        assert!(is_const(var), "{var} should be constant");
+    }
+    for var in ["mutable_ref", "mutable_operand", "proc_call", "macro_call", "static_plain", "field"] {
+        assert!(!is_const(var), "{var} should not be constant");
+    }
+
+    // a const var initialized from another const var is itself constant
+    let root = tree.root();
+    assert!(root.get_value("DERIVED").unwrap().expression.as_ref().unwrap().is_const(&tree));
+
+    // var references resolve against the given type
+    let datum = tree.find("/datum").unwrap();
+    let lexer = dm::lexer::Lexer::new(&context, Default::default(), "LIMIT - 1".as_bytes());
+    let limit = dm::parser::parse_expression(&context, Default::default(), lexer).unwrap();
+    assert!(limit.is_const_in(datum));
+    assert!(!limit.is_const(&tree));
+    let lexer = dm::lexer::Lexer::new(&context, Default::default(), "plain".as_bytes());
+    let plain = dm::parser::parse_expression(&context, Default::default(), lexer).unwrap();
+    assert!(!plain.is_const_in(datum));
+}
+
+#[test]
+fn vars_with_values() {
+    let context = dm::Context::default();
+    let code = r#"
+/datum/config
+    var/const/FOO = 1<<2
+    var/x = rand(1, 5)
+    var/list/unset
+/datum/config/child
+    x = 3
+"#;
+    let pp = dm::preprocessor::Preprocessor::from_buffer(&context, "test.dm".into(), code.trim());
+    let indents = dm::indents::IndentProcessor::new(&context, pp);
+    let tree = dm::parser::Parser::new(&context, indents).parse_object_tree();
+
+    let summarize = |path: &str| tree.find(path).unwrap()
+        .vars_with_values()
+        .map(|(name, decl, value)| (name.clone(), decl.var_type.to_string(), value))
+        .collect::<Vec<_>>();
+    assert_eq!(summarize("/datum/config"), [
+        ("FOO".to_owned(), "const/".to_owned(), Some(Constant::Float(4.0))),
+        ("x".to_owned(), "".to_owned(), None),
+        ("unset".to_owned(), "list/".to_owned(), Some(Constant::Null(Some(vec!["list".to_owned()].into())))),
+    ]);
+    // overrides report the parent's declaration
+    assert_eq!(summarize("/datum/config/child"), [
+        ("x".to_owned(), "".to_owned(), Some(Constant::Float(3.0))),
+    ]);
+}
+
+#[test]
+fn math_functions() {
+    assert_eq!(eval("max(1, 2, 3)").unwrap(), Constant::Float(3.));
+    assert_eq!(eval("min(4, -2, 3)").unwrap(), Constant::Float(-2.));
+    assert_eq!(eval("max(list(5, 7, 6))").unwrap(), Constant::Float(7.));
+    assert_eq!(eval("max(0, 10 - 15)").unwrap(), Constant::Float(0.));
+    assert_eq!(eval("abs(-4)").unwrap(), Constant::Float(4.));
+    assert_eq!(eval("sqrt(16)").unwrap(), Constant::Float(4.));
+    // like floor() when no multiple to round to is given
+    assert_eq!(eval("round(3.7)").unwrap(), Constant::Float(3.));
+    assert_eq!(eval("round(-3.2)").unwrap(), Constant::Float(-4.));
+    assert_eq!(eval("round(3.7, 1)").unwrap(), Constant::Float(4.));
+    assert_eq!(eval("round(17, 5)").unwrap(), Constant::Float(15.));
+}
+
+#[test]
+fn math_functions_unresolved() {
+    assert!(eval("max(x, 1)").is_err());
+    assert_eq!(
+        eval("max(\"a\", 1)").unwrap_err().description(),
+        "non-numeric argument to max(): \"a\"",
+    );
+    assert_eq!(
+        eval("abs(1, 2)").unwrap_err().description(),
+        "abs() requires exactly 1 argument, instead found 2",
+    );
+}
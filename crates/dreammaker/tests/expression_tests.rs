@@ -194,3 +194,56 @@ fn loaded_call_ext() {
         }
     )
 }
+
+#[test]
+fn display_round_trip() {
+    for source in [
+        "a + b * c - d",
+        "(a + b) * c",
+        "a - (b - c)",
+        "a ** b ** c",
+        "x = y += 3",
+        "foo = 1 ? 2 : 3 in 4",
+        "1 ? 2 ? 3 : 4 : 5",
+        "(1 ? 2 : 3) ? 4 : 5",
+        "x in 1 to 5",
+        "!a.b[c].d(e) && -f++ + ~g--",
+        "(-a).b",
+        "a?.b?[1]:c",
+        "..() + .(1) + ::glob + ::glob_proc(2)",
+        "new /obj {name = \"thing\"; x = 1.5}(src)",
+        "new .subtype(1)",
+        "new foo.bar()",
+        "new",
+        "list(1, \"a\" = 2, /datum)",
+        "input(usr, \"pick\") as mob|text in world",
+        "locate(/turf) in view(7)",
+        "pick(10; \"a\", \"b\")",
+        "call(src, \"proc\")(1) + call_ext(\"lib\", \"func\")(2)",
+        "\"a\\\"b [x + 1] c[]\" + 'icon.dmi'",
+        "as(num) || as() || __PROC__ || __TYPE__",
+        "/datum::var_name + type::proc_name()",
+        "1.0 + 2.5e+20 + 1.#INF",
+    ] {
+        let parsed = parse_expr(source);
+        let printed = parsed.to_string();
+        assert_eq!(parse_expr(&printed), parsed, "{source:?} printed as {printed:?}");
+    }
+
+    // minimal parentheses are added to trees built by hand
+    let sum = Expression::BinaryOp {
+        op: BinaryOp::Add,
+        lhs: Box::new(Expression::from(Term::Int(1))),
+        rhs: Box::new(Expression::from(Term::Int(2))),
+    };
+    let product = Expression::BinaryOp {
+        op: BinaryOp::Mul,
+        lhs: Box::new(sum.clone()),
+        rhs: Box::new(sum),
+    };
+    assert_eq!(product.to_string(), "(1 + 2) * (1 + 2)");
+
+    let path = vec![(PathOp::Slash, "obj".to_owned()), (PathOp::Slash, "item".to_owned())];
+    assert_eq!(FormatTypePath(&path).to_string(), "/obj/item");
+    assert_eq!(Prefab::from(path).to_string(), "/obj/item");
+}
@@ -0,0 +1,143 @@
+extern crate dreammaker as dm;
+
+use dm::format::*;
+use dm::indents::IndentProcessor;
+use dm::lexer::Token;
+use dm::preprocessor::Preprocessor;
+
+fn format(source: &str) -> String {
+    format_source(source, FormatOptions::default())
+}
+
+/// The tokens the parser would see, ignoring where whitespace was.
+fn parsed_tokens(source: &str) -> Vec<Token> {
+    let context = dm::Context::default();
+    let preprocessor = Preprocessor::from_buffer(&context, "test.dm".into(), source.to_owned());
+    IndentProcessor::new(&context, preprocessor)
+        .map(|token| match token.token {
+            Token::Ident(ident, _) => Token::Ident(ident, false),
+            other => other,
+        })
+        .collect()
+}
+
+fn assert_formats(source: &str, expected: &str) {
+    let formatted = format(source);
+    assert_eq!(formatted, expected, "\n--- formatted ---\n{formatted}");
+    assert_eq!(format(&formatted), formatted, "formatting is not idempotent");
+    assert_eq!(parsed_tokens(&formatted), parsed_tokens(source), "formatting changed the code");
+}
+
+#[test]
+fn indentation() {
+    assert_formats(
+        "/datum/thing\n    var/x = 1\n    proc/go()\n        return x\n",
+        "/datum/thing\n\tvar/x = 1\n\tproc/go()\n\t\treturn x\n",
+    );
+}
+
+#[test]
+fn mixed_tabs_and_spaces() {
+    // the indentation processor counts each tab or space as one
+    assert_formats(
+        "/proc/go()\n  if(x)\n \t\t return 1\n\t return 0\n",
+        "/proc/go()\n\tif(x)\n\t\treturn 1\n\treturn 0\n",
+    );
+}
+
+#[test]
+fn operator_spacing() {
+    assert_formats(
+        "/proc/go(a=1,b)\n\tvar/list/L=list( a ,b )\n\tif(a==b&&a!=-1)\n\t\tL[ 1 ]+=2\n\tfor(var/i=0;i<3;i++)\n\t\tx = - i\n",
+        "/proc/go(a = 1, b)\n\tvar/list/L = list(a, b)\n\tif(a == b && a != -1)\n\t\tL[1] += 2\n\tfor(var/i = 0; i < 3; i++)\n\t\tx = - i\n",
+    );
+}
+
+#[test]
+fn comments_are_kept() {
+    assert_formats(
+        "// header\n/proc/go()   \n    // explain\n    x = 1 // trailing   \n    /* block\n       comment */\n    return x\n",
+        "// header\n/proc/go()\n\t// explain\n\tx = 1 // trailing\n\t/* block\n       comment */\n\treturn x\n",
+    );
+}
+
+#[test]
+fn braces() {
+    assert_formats(
+        "/proc/go(){\n\tx = 1\n\tif(x){\n\t\ty = 2 ;  z = 3\n\t}\n}\n",
+        "/proc/go() {\n\tx = 1\n\tif(x) {\n\t\ty = 2; z = 3\n\t}\n}\n",
+    );
+}
+
+#[test]
+fn continued_parentheses() {
+    assert_formats(
+        "/proc/go()\n    call(a,\n            b,\n    c\n      )\n",
+        "/proc/go()\n\tcall(a,\n\t\tb,\n\t\tc\n\t)\n",
+    );
+}
+
+#[test]
+fn verbatim_regions() {
+    let source = concat!(
+        "#define ADD(a,b) \\\n",
+        "    ((a)+(b))\n",
+        "  #ifndef DEBUG\n",
+        "/proc/go()\n",
+        "    var/s = {\"line one  [ x+1 ]\n",
+        "   line two\"}\n",
+        "    world << \"[ a ] , [b]\"\n",
+        "  #endif\n",
+    );
+    assert_formats(source, concat!(
+        "#define ADD(a,b) \\\n",
+        "    ((a)+(b))\n",
+        "  #ifndef DEBUG\n",
+        "/proc/go()\n",
+        "\tvar/s = {\"line one  [ x+1 ]\n",
+        "   line two\"}\n",
+        "\tworld << \"[ a ] , [b]\"\n",
+        "  #endif\n",
+    ));
+}
+
+#[test]
+fn blank_lines() {
+    assert_formats(
+        "\n/proc/a()\n\n\n\n\n/proc/b()\n\n\n",
+        "\n/proc/a()\n\n\n/proc/b()\n",
+    );
+}
+
+#[test]
+fn unlexable_source_is_unchanged() {
+    let source = "/proc/go()\n    x = \"unterminated\n";
+    assert_eq!(format(source), source);
+}
+
+#[test]
+fn idempotent() {
+    let source = r#"
+/datum/thing
+    var/name="thing" // the name
+    var/list/parts=list("a"=1,
+        "b" = 2)
+
+    proc/describe(mob/user,verbose=FALSE)
+      if(!user||!verbose)
+          return
+      for(var/part in parts) {
+        user << "[part]: [parts[part]]"
+        continue
+      }
+      switch(parts.len)
+          if(1 to 2)
+              . = {"few
+parts"}
+          else
+             . = ..()
+"#;
+    let once = format(source);
+    assert_eq!(format(&once), once);
+    assert_eq!(parsed_tokens(&once), parsed_tokens(source));
+}
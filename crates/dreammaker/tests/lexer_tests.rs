@@ -57,7 +57,7 @@ fn nested_interpolation() {
 #[test]
 fn empty_block_comment() {
     // This is legal. It should not do either of the following:
-    // - Error with "still skipping comments at end of file"
+    // - Error with "unterminated block comment"
     // - Yield a DocComment { text: "", .. }
     assert_eq!(
         lex(r#"/**/"#),
@@ -84,6 +84,26 @@ fn raw_strings() {
     }
 }
 
+#[test]
+fn library_includes() {
+    assert_eq!(lex("#include <lib/my-lib 1.0.dme>"), vec![
+        Punct(Hash),
+        Ident("include".into(), true),
+        Punct(Less),
+        String("lib/my-lib 1.0.dme".into()),
+        Punct(Newline),
+    ]);
+    // `<` is only special right after `#include`
+    assert_eq!(lex("#if A < B"), vec![
+        Punct(Hash),
+        Ident("if".into(), true),
+        Ident("A".into(), true),
+        Punct(Less),
+        Ident("B".into(), false),
+        Punct(Newline),
+    ]);
+}
+
 #[test]
 fn heredoc_with_quotes() {
     // 1-3 quotes in the middle of ordinary characters
@@ -139,3 +159,129 @@ fn heredoc_with_quotes() {
         ]
     );
 }
+
+fn loc(line: u32, column: u16) -> dm::Location {
+    dm::Location { file: Default::default(), line, column }
+}
+
+fn check_relex(old: &str, edit: TextEdit) {
+    let start = old.lines().take(edit.range.start.line as usize - 1).map(|l| l.len() + 1).sum::<usize>()
+        + edit.range.start.column as usize - 1;
+    let end = old.lines().take(edit.range.end.line as usize - 1).map(|l| l.len() + 1).sum::<usize>()
+        + edit.range.end.column as usize - 1;
+    let new = format!("{}{}{}", &old[..start], edit.text, &old[end..]);
+
+    let context = Default::default();
+    let old_tokens: Vec<_> = Lexer::new(&context, Default::default(), old.as_bytes()).collect();
+    let expected: Vec<_> = Lexer::new(&context, Default::default(), new.as_bytes()).collect();
+    let relexed = Lexer::new(&context, Default::default(), new.as_bytes()).relex(&old_tokens, edit);
+    assert_eq!(relexed, expected, "relexing {new:?}");
+}
+
+const RELEX_SOURCE: &str = r#"/proc/first()
+    var/value = 1
+    return value
+
+var/text = {"line one
+line [value] two
+"}
+
+/proc/second()
+    return text
+"#;
+
+#[test]
+fn relex_identifier_edit() {
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(2, 14)..loc(2, 14), text: "x".to_owned() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(3, 12)..loc(3, 15), text: "b\n    return a".to_owned() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(1, 7)..loc(2, 1), text: "f()\n".to_owned() });
+}
+
+#[test]
+fn relex_opening_comment() {
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(2, 5)..loc(2, 5), text: "/*".to_owned() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(3, 5)..loc(3, 5), text: "/* a */ ".to_owned() });
+}
+
+#[test]
+fn relex_text_block_edit() {
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(6, 14)..loc(6, 17), text: "2\n".to_owned() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(6, 7)..loc(6, 14), text: "[text]\n".to_owned() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(7, 1)..loc(7, 3), text: std::string::String::new() });
+    check_relex(RELEX_SOURCE, TextEdit { range: loc(5, 12)..loc(5, 12), text: "\"}\n".to_owned() });
+}
+
+#[test]
+fn lexed_file_round_trip() {
+    let code = "\u{feff}/obj  // the base\r\n\tvar/x = \"a[b ]c\" /* inline */ + 'icon.dmi'\n\n#define X \\\n  1\n/// docs\nproc/f() // trailing";
+    let lexed = lex_file(Default::default(), code);
+    assert!(lexed.errors.is_empty());
+
+    let mut rebuilt = std::string::String::new();
+    for token in lexed.tokens.iter() {
+        rebuilt.push_str(lexed.leading_trivia(token));
+        rebuilt.push_str(lexed.text(token));
+        rebuilt.push_str(lexed.trailing_trivia(token));
+    }
+    assert_eq!(rebuilt, code);
+
+    let find = |text: &str| lexed.tokens.iter().find(|token| lexed.text(token) == text).unwrap();
+    let obj = find("obj");
+    assert_eq!((obj.location.line, obj.location.column), (1, 2));
+    assert_eq!(lexed.trailing_trivia(obj), "  // the base\r");
+    let var = find("var");
+    assert_eq!((var.location.line, var.location.column), (2, 2));
+    assert_eq!(lexed.leading_trivia(var), "\t");
+    assert_eq!(lexed.leading_trivia(find("+")), " /* inline */ ");
+    assert_eq!(find("'icon.dmi'").token, Resource("icon.dmi".into()));
+    assert_eq!(lexed.leading_trivia(find("1")), " \\\n  ");
+    assert!(matches!(find("/// docs").token, DocComment(_)));
+    assert_eq!(lexed.trailing_trivia(find(")")), " // trailing");
+}
+
+fn lex_errors(code: &str) -> Vec<(u32, u16, std::string::String)> {
+    let context = dm::Context::default();
+    Lexer::new(&context, Default::default(), code.as_bytes()).for_each(drop);
+    context.errors().iter()
+        .map(|error| (error.location().line, error.location().column, error.description().to_owned()))
+        .collect()
+}
+
+#[test]
+fn unterminated_at_opener() {
+    assert_eq!(
+        lex_errors("var/x = 1\n  /* comment /* nested */\nmore"),
+        [(2, 3, "unterminated block comment".to_owned())],
+    );
+    assert_eq!(
+        lex_errors("var/s = {\"abc\n[x] def"),
+        [(1, 9, "unterminated text block".to_owned())],
+    );
+    assert_eq!(
+        lex_errors("var/s = \"a[b"),
+        [(1, 9, "unterminated string literal".to_owned())],
+    );
+
+    // the rest of the file becomes the string's contents
+    let context = dm::Context::default();
+    let tokens: Vec<_> = Lexer::new(&context, Default::default(), "x = {\"abc\ndef".as_bytes()).map(|t| t.token).collect();
+    assert_eq!(tokens.last(), Some(&Punct(Newline)));
+    assert_eq!(tokens[tokens.len() - 2], String("abc\ndef".into()));
+}
+
+#[test]
+fn comment_tags() {
+    let mut config = dm::config::Config::default();
+    config.code_standards.comment_tags = vec!["TODO".to_owned(), "FIXME".to_owned()];
+    let mut context = dm::Context::default();
+    context.set_config(config);
+    let code = "var/x = 1 // TODO: x\n// just a comment\n// TODOS are not tags\n/// FIXME later\n";
+    Lexer::new(&context, Default::default(), code.as_bytes()).for_each(drop);
+    let errors: Vec<_> = context.errors().iter()
+        .map(|error| (error.location().line, error.location().column, error.severity(), error.description().to_owned()))
+        .collect();
+    assert_eq!(errors, [
+        (1, 11, dm::Severity::Info, "TODO: x".to_owned()),
+        (4, 1, dm::Severity::Info, "FIXME later".to_owned()),
+    ]);
+}
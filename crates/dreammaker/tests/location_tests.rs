@@ -80,3 +80,140 @@ fn reconstruct(tokens: &[LocatedToken], iffy: bool) -> String {
     }
     reconstructed.join("")
 }
+
+#[test]
+fn utf16_columns() {
+    let code = "var/a = \"héllo\" + b\nvar/c = \"🎉\"; d\n";
+
+    let context = Default::default();
+    let tokens: Vec<_> = Lexer::new(&context, Default::default(), code.as_bytes()).collect();
+    context.assert_success();
+    let find = |name: &str| tokens.iter()
+        .find(|token| matches!(&token.token, Token::Ident(ident, _) if ident == name))
+        .unwrap()
+        .location;
+
+    let index = dm::LineIndex::new(Default::default(), code);
+
+    // "é" is two bytes but one UTF-16 unit, "🎉" is four bytes but two units
+    let b = find("b");
+    assert_eq!(b.column, 20);
+    assert_eq!(index.to_utf16(b), Some((0, 18)));
+    let d = find("d");
+    assert_eq!(d.column, 17);
+    assert_eq!(index.to_utf16(d), Some((1, 14)));
+
+    assert_eq!(index.from_utf16(0, 18), Some(b));
+    assert_eq!(index.from_utf16(1, 14), Some(d));
+
+    // the middle of a surrogate pair rounds down to the start of the emoji
+    assert_eq!(index.from_utf16(1, 10), index.from_utf16(1, 9));
+    // past the end of a line clamps to its end, past the last line fails
+    assert_eq!(index.from_utf16(1, 100).unwrap().column, 18);
+    assert_eq!(index.from_utf16(5, 0), None);
+}
+
+#[test]
+fn location_arithmetic() {
+    use dm::Location;
+
+    let context = dm::Context::default();
+    let file = context.register_file(std::path::Path::new("a.dm"));
+    let other = context.register_file(std::path::Path::new("b.dm"));
+    let at = |line, column| Location { file, line, column };
+
+    // pred and succ are inverses, stepping across line boundaries
+    assert_eq!(at(3, 5).pred(), at(3, 4));
+    assert_eq!(at(3, 0).pred(), at(2, !0));
+    assert_eq!(at(2, !0).succ(), at(3, 0));
+    assert_eq!(at(3, 0).pred().succ(), at(3, 0));
+    assert_eq!(at(3, 5).succ().pred(), at(3, 5));
+
+    // the extremes saturate rather than wrapping or panicking
+    let lowest = Location { line: 0, column: 0, ..Location::builtins() };
+    assert_eq!(lowest.pred(), lowest);
+    let bad = Location::default();
+    assert_eq!(bad.pred(), bad);
+    let highest = Location { line: !0, column: !0, ..bad };
+    assert_eq!(highest.succ(), highest);
+
+    let span = at(1, 4).span_to(at(1, 9));
+    assert!(span.contains(&at(1, 4)));
+    assert!(!span.contains(&at(1, 9)));
+
+    assert!(at(2, 1).contains(at(2, 1)));
+    assert!(at(2, 1).contains(at(7, 1)));
+    assert!(!at(2, 1).contains(at(1, 30)));
+    assert!(!at(2, 1).contains(Location { file: other, line: 9, column: 1 }));
+}
+
+#[test]
+fn continued_macro_locations() {
+    let code = "#define FOO a + \\\n    b + \\\n    c\n/var/x = FOO\n";
+
+    let context = Default::default();
+    let located_tokens: Vec<_> = Lexer::new(&context, Default::default(), code.as_bytes()).collect();
+    context.assert_success();
+    let position = |name: &str| located_tokens.iter()
+        .find(|t| matches!(&t.token, Token::Ident(ident, _) if ident == name))
+        .map(|t| (t.location.line, t.location.column))
+        .unwrap();
+    assert_eq!(position("a"), (1, 13));
+    assert_eq!(position("b"), (2, 5));
+    assert_eq!(position("c"), (3, 5));
+
+    let mut preprocessor = dm::preprocessor::Preprocessor::from_buffer(&context, "test.dm".into(), code);
+    preprocessor.enable_annotations();
+    let tokens: Vec<_> = preprocessor.by_ref().collect();
+    context.assert_success();
+
+    // the directive's newline belongs to the last physical line it covers
+    let newline = tokens.iter().find(|t| t.token == Token::Punct(Punctuation::Newline)).unwrap();
+    assert_eq!((newline.location.line, newline.location.column), (3, 6));
+
+    let annotations = preprocessor.take_annotations().unwrap();
+    let (range, definition) = annotations.iter()
+        .find_map(|(range, annotation)| match annotation {
+            dm::annotation::Annotation::MacroUse { definition_location, .. } => Some((range, *definition_location)),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!((range.start.line, range.start.column), (4, 10));
+    assert_eq!((definition.line, definition.column), (1, 9));
+}
+
+#[test]
+fn byte_offsets() {
+    let code = "var/a = 1\n\nvar/b = \"é\"\n";
+    let index = dm::LineIndex::new(Default::default(), code);
+    let at = |line, column| dm::Location { file: Default::default(), line, column };
+
+    let cases = [
+        (0, at(1, 1)),
+        (4, at(1, 5)),
+        // the newline ending a line is its last column
+        (9, at(1, 10)),
+        (10, at(2, 1)),
+        (11, at(3, 1)),
+        // "é" is two bytes, so columns count past it twice
+        (21, at(3, 11)),
+        (22, at(3, 12)),
+        // the final offset starts the empty line after the last newline
+        (code.len(), at(4, 1)),
+    ];
+    for (offset, location) in cases {
+        assert_eq!(index.offset_to_location(offset), location);
+        assert_eq!(index.location_to_offset(location), offset);
+    }
+
+    // out of range values clamp
+    assert_eq!(index.offset_to_location(100), at(4, 1));
+    assert_eq!(index.location_to_offset(at(1, 50)), 9);
+    assert_eq!(index.location_to_offset(at(9, 1)), code.len());
+
+    // offsets count a BOM which columns don't
+    let index = dm::LineIndex::new(Default::default(), "\u{feff}var/a\n");
+    assert_eq!(index.offset_to_location(0), at(1, 1));
+    assert_eq!(index.offset_to_location(7), at(1, 5));
+    assert_eq!(index.location_to_offset(at(1, 5)), 7);
+}
@@ -2,6 +2,9 @@ extern crate dreammaker as dm;
 
 use dm::lexer::Punctuation::*;
 use dm::lexer::Token::*;
+use dm::annotation::{Annotation, AnnotationTree, ConditionalKind};
+use dm::indents::IndentProcessor;
+use dm::parser::Parser;
 use dm::preprocessor::*;
 
 fn process(source: &'static str) -> Vec<dm::lexer::Token> {
@@ -68,6 +71,77 @@ ok2
 "#), &[
         Ident("ok2".into(), false),
     ]);
+
+    assert_eq!(process(r#"
+#define FOO
+#if defined FOO && !defined BAR
+ok3
+#endif
+"#), &[
+        Ident("ok3".into(), false),
+    ]);
+
+    assert_eq!(process(r#"
+#if defined(BAR) || defined BAR
+bad
+#endif
+"#), &[]);
+
+    assert_eq!(process(r#"
+#if !defined( BAR )
+ok4
+#endif
+"#), &[
+        Ident("ok4".into(), false),
+    ]);
+}
+
+#[test]
+fn defined_is_not_expanded() {
+    // the name given to defined() is not itself expanded
+    assert_eq!(process(r#"
+#define FOO BAR
+#if defined FOO && !defined(BAR)
+ok
+#endif
+"#), &[
+        Ident("ok".into(), false),
+    ]);
+}
+
+#[test]
+fn malformed_defined() {
+    let ctx = dm::Context::default();
+    Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), "#if defined\n#endif\n").for_each(drop);
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "malformed defined, expected a macro name but got Punct(Newline)");
+}
+
+#[test]
+fn has_include_function() {
+    let root = std::env::temp_dir().join(format!("dm-has-include-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("present.dm"), "").unwrap();
+
+    let ctx = dm::Context::default();
+    let source = r#"
+#if __has_include("present.dm")
+exists
+#endif
+#if __has_include("this file does not exist") || !__has_include( "present.dm" )
+missing
+#endif
+"#;
+    let tokens: Vec<_> = Preprocessor::from_buffer(&ctx, root.join("test.dme"), source)
+        .map(|loctok| loctok.token)
+        .filter(|tok| *tok != Punct(Newline))
+        .collect();
+    std::fs::remove_dir_all(&root).unwrap();
+    ctx.assert_success();
+    assert_eq!(tokens, &[
+        Ident("exists".into(), false),
+    ]);
 }
 
 #[test]
@@ -86,3 +160,617 @@ exists
 #endif
 "#), &[]);
 }
+
+#[test]
+fn expanded_token_origins() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define INNER 2
+#define OUTER(x) (x + INNER)
+a = OUTER(1)
+"#);
+
+    let tokens: Vec<_> = pp.expanded_tokens()
+        .filter(|expanded| !expanded.token.token.is_whitespace())
+        .map(|expanded| (expanded.token.token, expanded.token.location.column, expanded.macro_name))
+        .collect();
+    ctx.assert_success();
+
+    let outer = Some("OUTER".into());
+    assert_eq!(tokens, &[
+        (Ident("a".into(), true), 1, None),
+        (Punct(Assign), 3, None),
+        (Punct(LParen), 5, outer.clone()),
+        (Int(1), 5, outer.clone()),
+        (Punct(Add), 5, outer.clone()),
+        (Int(2), 5, outer.clone()),
+        (Punct(RParen), 5, outer.clone()),
+    ]);
+}
+
+#[test]
+fn nested_expansion_trace() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define INNER(x) (x + 1)
+#define OUTER(y) INNER(y) * 2
+a = OUTER(3)
+"#);
+    pp.enable_annotations();
+    pp.by_ref().for_each(drop);
+    ctx.assert_success();
+
+    let file = ctx.get_file("macro_tests.rs".as_ref()).unwrap();
+    let at = |column| dm::Location { file, line: 4, column };
+    let trace = pp.expansion_trace(at(7)).expect("no trace at OUTER");
+    let steps: Vec<_> = trace.iter()
+        .map(|step| (step.name.as_str(), step.before.as_str(), step.after.as_str()))
+        .collect();
+    assert_eq!(steps, &[
+        ("OUTER", "OUTER(3)", "INNER(3) * 2"),
+        ("INNER", "INNER(3)", "(3 + 1)"),
+    ]);
+    assert_eq!(trace[0].definition_location.line, 3);
+    assert_eq!(trace[1].definition_location.line, 2);
+
+    assert!(pp.expansion_trace(at(1)).is_none());
+    assert!(pp.expansion_trace(at(12)).is_none());
+}
+
+#[test]
+fn external_defines() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#ifdef DEBUG
+debug_on = DEBUG
+#endif
+#ifndef RELEASE
+release_off
+#endif
+flag = RELEASE
+"#);
+    pp.define_external("DEBUG", Some("1"));
+    pp.define_external("RELEASE", None);
+
+    let tokens: Vec<_> = pp.map(|loctok| loctok.token)
+        .filter(|tok| !tok.is_whitespace())
+        .collect();
+    ctx.assert_success();
+    assert_eq!(tokens, &[
+        Ident("debug_on".into(), true),
+        Punct(Assign),
+        Int(1),
+        Ident("flag".into(), true),
+        Punct(Assign),
+    ]);
+}
+
+#[test]
+fn warning_and_error_directives() {
+    let ctx = dm::Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define VERSION 515
+#define NAME "tg"
+#define MINIMUM VERSION - 1
+#warning compiling NAME for VERSION
+#error MINIMUM is unsupported
+/var/after = 1
+"#);
+    let indents = dm::indents::IndentProcessor::new(&ctx, pp);
+    let tree = dm::parser::Parser::new(&ctx, indents).parse_object_tree();
+
+    let errors: Vec<_> = ctx.errors().iter()
+        .map(|error| (error.location().line, error.severity(), error.description().to_owned()))
+        .collect();
+    assert_eq!(errors, vec![
+        (5, dm::Severity::Warning, "#warning compiling \"tg\" for 515".to_owned()),
+        (6, dm::Severity::Error, "#error 515 - 1 is unsupported".to_owned()),
+    ]);
+
+    // parsing continues past both directives
+    assert!(tree.root().get_value("after").is_some());
+}
+
+#[test]
+fn define_table() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define LIMIT 10
+#define CLAMP_TO(x, args...) min(x, args)
+#define TEMPORARY 1
+#undef TEMPORARY
+"#);
+    pp.by_ref().for_each(drop);
+    ctx.assert_success();
+
+    let (location, limit) = pp.defines().find(|&(name, _)| name == "LIMIT").map(|(_, d)| d).unwrap();
+    assert_eq!(location.line, 2);
+    assert!(!limit.is_function());
+    assert_eq!(limit.params(), None);
+    assert_eq!(limit.substitution(), &[Int(10)]);
+
+    let (_, clamp) = pp.defines().find(|&(name, _)| name == "CLAMP_TO").map(|(_, d)| d).unwrap();
+    assert!(clamp.is_function());
+    assert_eq!(clamp.params(), Some(&["x".to_owned(), "args".to_owned()][..]));
+    assert!(matches!(clamp, Define::Function { variadic: true, .. }));
+    assert_eq!(clamp.substitution()[0], Ident("min".into(), false));
+
+    // undefined macros only appear in the history
+    assert!(pp.defines().all(|(name, _)| name != "TEMPORARY"));
+    let undefined: Vec<_> = pp.define_history().iter()
+        .map(|(range, (name, _))| (name.as_str(), range.start.line, range.end.line))
+        .collect();
+    assert_eq!(undefined, [("TEMPORARY", 4, 5)]);
+}
+
+#[test]
+fn include_graph() {
+    let root = std::env::temp_dir().join(format!("dm-include-graph-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    // a diamond: a includes b and c, which both include d...
+    std::fs::write(root.join("a.dm"), "#include \"b.dm\"\n#include \"c.dm\"\n").unwrap();
+    std::fs::write(root.join("b.dm"), "#include \"d.dm\"\n").unwrap();
+    std::fs::write(root.join("c.dm"), "#include \"d.dm\"\n").unwrap();
+    // ...and d closes a cycle back to a
+    std::fs::write(root.join("d.dm"), "#include \"a.dm\"\n").unwrap();
+
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, root.join("test.dme"), "#include \"a.dm\"\n");
+    pp.by_ref().for_each(drop);
+    let graph = pp.include_graph();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let name = |file| ctx.file_path(file).display().to_string();
+    let edges: Vec<_> = graph.edges().iter()
+        .map(|edge| (name(edge.from), name(edge.to), edge.location.line))
+        .collect();
+    let dme = root.join("test.dme").display().to_string();
+    assert_eq!(edges, [
+        (dme, "a.dm".to_owned(), 1),
+        ("a.dm".to_owned(), "b.dm".to_owned(), 1),
+        ("b.dm".to_owned(), "d.dm".to_owned(), 1),
+        ("d.dm".to_owned(), "a.dm".to_owned(), 1),
+        ("a.dm".to_owned(), "c.dm".to_owned(), 2),
+        ("c.dm".to_owned(), "d.dm".to_owned(), 1),
+    ]);
+
+    let d = graph.edges()[2].to;
+    assert_eq!(graph.included_by(d).count(), 2);
+    assert_eq!(graph.includes(d).count(), 1);
+
+    let cycles: Vec<Vec<_>> = graph.cycles().iter()
+        .map(|cycle| cycle.iter().map(|edge| name(edge.to)).collect())
+        .collect();
+    assert_eq!(cycles, [["b.dm", "d.dm", "a.dm"]]);
+
+    let before = ctx.errors().len();
+    graph.report_cycles(&ctx);
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), before + 1);
+    let cycle = errors.last().unwrap();
+    assert_eq!(cycle.description(), "include cycle: a.dm -> b.dm -> d.dm -> a.dm");
+    assert_eq!(cycle.errortype(), Some("include_cycle"));
+    assert_eq!(cycle.notes().len(), 2);
+}
+
+#[test]
+fn macro_redefinition() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define SAME(x) (x + 1)
+#define SAME(x) (x + 1)
+#define DIFFERENT 1
+#define DIFFERENT 2
+"#);
+    pp.by_ref().for_each(drop);
+
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    let error = &errors[0];
+    assert_eq!(error.description(), "macro redefined: DIFFERENT");
+    assert_eq!(error.location().line, 5);
+    assert_eq!(error.notes().len(), 1);
+    assert_eq!(error.notes()[0].location().line, 4);
+
+    // the identical redefinition keeps the first definition, and the
+    // conflicting one wins
+    let defines: Vec<_> = pp.defines()
+        .filter(|(name, _)| matches!(*name, "SAME" | "DIFFERENT"))
+        .map(|(name, (location, define))| (name, location.line, define.substitution().to_vec()))
+        .collect();
+    assert_eq!(defines.len(), 2);
+    assert!(defines.contains(&("SAME", 2, vec![
+        Punct(LParen), Ident("x".into(), true), Punct(Add), Int(1), Punct(RParen),
+    ])));
+    assert!(defines.contains(&("DIFFERENT", 5, vec![Int(2)])));
+}
+
+#[test]
+fn integer_defines() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define FLAG_A (1<<0)
+#define FLAG_B (1<<1)
+#define FLAG_BOTH (FLAG_A|FLAG_B)
+#define FLAG_NAME "flag"
+#define FLAG_CALL(x) (1<<x)
+#define FLAG_USES_CALL FLAG_CALL(2)
+#define OTHER_C (1<<2)
+"#);
+    pp.by_ref().for_each(drop);
+    ctx.assert_success();
+
+    let mut flags: Vec<_> = pp.integer_defines("FLAG_").into_iter().collect();
+    flags.sort();
+    assert_eq!(flags, [
+        ("FLAG_A".to_owned(), 1),
+        ("FLAG_B".to_owned(), 2),
+        ("FLAG_BOTH".to_owned(), 3),
+    ]);
+}
+
+#[test]
+fn macro_naming_convention() {
+    use dm::config::{Config, NamingCase};
+
+    let mut config = Config::default();
+    config.code_standards.naming.macros = Some(NamingCase::UpperCase);
+    let mut ctx = dm::Context::default();
+    ctx.set_config(config);
+
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define MAX_HEALTH 100
+#define max_speed 5
+#define IS_MOB(x) ismob(x)
+"#);
+    pp.for_each(drop);
+
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "macro name is not UPPER_CASE: max_speed");
+    assert_eq!(errors[0].errortype(), Some("naming_convention"));
+    assert_eq!(errors[0].location().line, 3);
+}
+
+#[test]
+fn macro_naming_off_by_default() {
+    process("#define max_speed 5\n");
+}
+
+#[test]
+fn stringify_and_paste() {
+    assert_eq!(process(r#"
+#define STR(x) #x
+#define CAT(a, b) a##b
+#define OP(a) a##=
+STR(x) STR(a + "b"  1)
+CAT(foo, bar) CAT(var, 2) CAT(foo, ) CAT(, bar) CAT(a b, c d)
+x OP(+) 1
+"#), &[
+        String("x".to_owned()),
+        String(r#"a + "b" 1"#.to_owned()),
+        Punct(Newline),
+        Ident("foobar".to_owned(), false),
+        Ident("var2".to_owned(), false),
+        Ident("foo".to_owned(), false),
+        Ident("bar".to_owned(), false),
+        Ident("a".to_owned(), true),
+        Ident("bc".to_owned(), true),
+        Ident("d".to_owned(), false),
+        Punct(Newline),
+        Ident("x".to_owned(), true),
+        Punct(AddAssign),
+        Int(1),
+    ][..]);
+}
+
+#[test]
+fn invalid_paste() {
+    let ctx = dm::Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), "#define BAD(a) a##1.5\nx = BAD(q)\n");
+    let tokens: Vec<_> = pp.map(|loctok| loctok.token).collect();
+
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "pasting 'q' and '1.5' does not give a valid token");
+    assert_eq!(errors[0].severity(), dm::Severity::Warning);
+    assert_eq!(errors[0].location().line, 2);
+    // the tokens are kept unpasted
+    assert_eq!(tokens[2..5], [Punct(Assign), Ident("q".to_owned(), false), Float(1.5)]);
+}
+
+#[test]
+fn paste_with_punctuation() {
+    // as defined by tgstation
+    assert_eq!(process(r#"
+#define PROC_REF(X) (nameof(.proc/##X))
+#define TYPE_PROC_REF(TYPE, X) (nameof(##TYPE.proc/##X))
+#define GLOBAL_PROC_REF(X) (/proc/##X)
+#define CAT(a, b) a##b
+x = PROC_REF(foo)
+x = TYPE_PROC_REF(/datum, foo)
+x = GLOBAL_PROC_REF(foo)
+x = CAT(q, +)
+"#), process(r#"
+x = (nameof(.proc/foo))
+x = (nameof(/datum.proc/foo))
+x = (/proc/foo)
+x = q+
+"#));
+}
+
+#[test]
+fn unused_includes() {
+    let root = std::env::temp_dir().join(format!("dm-unused-includes-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("main.dm"), "#include \"used.dm\"\n#include \"unused.dm\"\n/proc/main()\n    return helper() + LIMIT\n").unwrap();
+    std::fs::write(root.join("used.dm"), "#define LIMIT 10\n/proc/helper()\n    return 1\n").unwrap();
+    std::fs::write(root.join("unused.dm"), "/obj/decoration\n").unwrap();
+
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, root.join("test.dme"), "#include \"main.dm\"\n");
+    pp.enable_annotations();
+    let mut annotations = AnnotationTree::default();
+    let tree = {
+        let mut parser = Parser::new(&ctx, IndentProcessor::new(&ctx, &mut pp));
+        parser.enable_procs();
+        parser.annotate_to(&mut annotations);
+        parser.parse_object_tree()
+    };
+    annotations.merge(pp.take_annotations().unwrap());
+    let graph = pp.include_graph();
+    std::fs::remove_dir_all(&root).unwrap();
+    ctx.assert_success();
+
+    let name = |file| ctx.file_path(file).display().to_string();
+    let unused: Vec<_> = graph.unused_includes(&annotations, &tree).iter()
+        .map(|edge| (name(edge.from), name(edge.to), edge.location.line))
+        .collect();
+    assert_eq!(unused, [("main.dm".to_owned(), "unused.dm".to_owned(), 2)]);
+
+    graph.report_unused_includes(&ctx, &annotations, &tree);
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "unused include: unused.dm");
+    assert_eq!(errors[0].errortype(), Some("unused_include"));
+}
+
+#[test]
+fn conditional_directive_annotations() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define FOO
+#ifdef BAR
+/var/a = 1
+#else
+/var/b = 2
+#endif
+#if defined(FOO) && 1
+#endif
+"#.trim());
+    pp.enable_annotations();
+    pp.by_ref().for_each(drop);
+    ctx.assert_success();
+
+    let annotations = pp.take_annotations().unwrap();
+    let mut conditionals: Vec<_> = annotations.iter()
+        .filter_map(|(place, annotation)| match annotation {
+            Annotation::PreprocessorConditional { kind, condition_range, active } => Some((
+                place.start.line,
+                *kind,
+                condition_range.as_ref().map(|range| (range.start.column, range.end.column)),
+                *active,
+            )),
+            _ => None,
+        })
+        .collect();
+    conditionals.sort_by_key(|&(line, ..)| line);
+    assert_eq!(conditionals, [
+        (2, ConditionalKind::Ifdef, Some((8, 11)), false),
+        (4, ConditionalKind::Else, None, true),
+        (6, ConditionalKind::Endif, None, true),
+        (7, ConditionalKind::If, Some((5, 22)), true),
+        (8, ConditionalKind::Endif, None, true),
+    ]);
+}
+
+#[test]
+fn undefined_macro_uses() {
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), r#"
+#define SEND_SIGNAL(target, signal) target.signal()
+#define TEMPORARY 1
+#ifdef OPTIONAL_FEATURE
+#endif
+#if !defined(OTHER_FEATURE) && !MISSPELLED_FEATURE
+#endif
+#ifdef DISABLED
+#if NESTED_BUT_SKIPPED
+#endif
+#endif
+#undef TEMPORARY
+/proc/test(atom/A)
+    SEND_SIGNAL(A, Moved)
+    SEND_SIGANL(A, Moved)
+    CRASH("[TEMPORARY]")
+    return FORMAT_NAME(A) + NOT_CALLED + Mixed_Case(A) + lower_call(A)
+"#.trim());
+    pp.by_ref().for_each(drop);
+
+    let uses: Vec<_> = pp.undefined_macro_uses()
+        .into_iter()
+        .map(|(name, location)| (name, location.line, location.column))
+        .collect();
+    assert_eq!(uses, [
+        ("MISSPELLED_FEATURE".to_owned(), 5, 33),
+        ("SEND_SIGANL".to_owned(), 14, 5),
+        ("TEMPORARY".to_owned(), 15, 13),
+        ("FORMAT_NAME".to_owned(), 16, 12),
+    ]);
+}
+
+/// Define a chain of `depth` macros, each expanding to the next, and use the
+/// first of them.
+fn macro_chain(depth: usize) -> std::string::String {
+    let mut source: Vec<_> = (1..depth)
+        .map(|i| format!("#define CHAIN_{i} CHAIN_{}\n", i + 1))
+        .collect();
+    source.push(format!("#define CHAIN_{depth} 1\nCHAIN_1\n"));
+    source.concat()
+}
+
+#[test]
+fn deep_macro_chain() {
+    let ctx = dm::Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), macro_chain(100));
+    let tokens: Vec<_> = pp.map(|loctok| loctok.token)
+        .filter(|tok| *tok != Punct(Newline))
+        .collect();
+    ctx.assert_success();
+    assert_eq!(tokens, [Int(1)]);
+}
+
+#[test]
+fn max_macro_expansion_depth() {
+    let ctx = dm::Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), macro_chain(300));
+    let tokens: Vec<_> = pp.map(|loctok| loctok.token)
+        .filter(|tok| *tok != Punct(Newline))
+        .collect();
+    // expansion stops at the use which would go too deep
+    assert_eq!(tokens, [Ident("CHAIN_257".to_owned(), false)]);
+
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "expanding \"CHAIN_257\" would exceed max macro expansion depth of 256 levels");
+    assert_eq!(errors[0].errortype(), Some("macro_expansion_depth"));
+}
+
+#[test]
+fn macro_call_arity() {
+    for (call, given) in [("MAX(1)", 1), ("MAX(1, 2, 3)", 3)] {
+        let ctx = dm::Context::default();
+        let source = format!("#define MAX(a, b) max(a, b)\nvar/x = {call}\n");
+        let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), source);
+        pp.for_each(drop);
+
+        let errors = ctx.errors();
+        assert_eq!(errors.len(), 1, "{call}");
+        assert_eq!(errors[0].description(), format!("wrong number of arguments to macro MAX: expected 2, got {given}"));
+        assert_eq!(errors[0].location().line, 2);
+        let notes = errors[0].notes();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].location().line, 1);
+        assert_eq!(notes[0].description(), "MAX defined here");
+    }
+
+    // variadic macros take any number of extra arguments
+    assert_eq!(process(r#"
+#define LOG(fmt, args...) log(fmt, args)
+LOG("a", 1, 2, 3, 4)
+"#), process(r#"log("a", 1, 2, 3, 4)"#));
+    assert_eq!(process(r#"
+#define LOG(fmt, args...) log(fmt, args)
+LOG("a")
+"#), process(r#"log("a", )"#));
+
+    // but still need their named arguments
+    let ctx = dm::Context::default();
+    let source = "#define PAIR(a, b, rest...) list(a, b, rest)\nvar/x = PAIR(1)\n";
+    Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), source).for_each(drop);
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "wrong number of arguments to macro PAIR: expected at least 2, got 1");
+}
+
+#[test]
+fn include_kinds() {
+    use dm::config::Config;
+
+    let root = std::env::temp_dir().join(format!("dm-include-kinds-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("game")).unwrap();
+    std::fs::create_dir_all(root.join("libs")).unwrap();
+    // a local file with the same name as the library, which `<...>` must skip
+    std::fs::write(root.join("game/helpers.dme"), "#define LOCAL_HELPERS\n").unwrap();
+    std::fs::write(root.join("game/code.dm"), "#define LOCAL_CODE\n").unwrap();
+    std::fs::write(root.join("libs/helpers.dme"), "#define LIBRARY_HELPERS\n").unwrap();
+
+    let mut config = Config::default();
+    config.parser.library_dirs = vec!["../libs".into()];
+    let mut ctx = dm::Context::default();
+    ctx.set_config(config);
+    let mut pp = Preprocessor::from_buffer(&ctx, root.join("game/test.dme"), "#include \"code.dm\"\n#include <helpers.dme>\n");
+    pp.enable_annotations();
+    pp.by_ref().for_each(drop);
+    let annotations = pp.take_annotations().unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    ctx.assert_success();
+
+    let mut includes: Vec<_> = annotations.iter()
+        .filter_map(|(range, annotation)| match annotation {
+            Annotation::Include { path, kind } => Some((range.start.line, range.start.column, range.end.column, path.clone(), *kind)),
+            _ => None,
+        })
+        .collect();
+    includes.sort_by_key(|include| include.0);
+    assert_eq!(includes, [
+        (1, 10, 18, root.join("game/code.dm"), IncludeKind::Local),
+        (2, 10, 22, root.join("game/../libs/helpers.dme"), IncludeKind::System),
+    ]);
+    assert!(pp.defines().any(|(name, _)| name == "LIBRARY_HELPERS"));
+    assert!(pp.defines().all(|(name, _)| name != "LOCAL_HELPERS"));
+}
+
+#[test]
+fn include_library_without_library_dirs() {
+    let ctx = dm::Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "macro_tests.rs".into(), "#include <helpers.dme>\n");
+    pp.for_each(drop);
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "failed to find #include <helpers.dme> in library directories");
+}
+
+#[test]
+fn include_guards() {
+    let root = std::env::temp_dir().join(format!("dm-include-guards-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("guarded.dm"), "\
+// Helpers for everything.
+#ifndef GUARDED_DM
+#define GUARDED_DM
+#ifdef DEBUG
+#define LOG(x) world.log << x
+#endif
+/proc/helper()
+    return 1
+#endif
+").unwrap();
+    std::fs::write(root.join("unguarded.dm"), "/proc/other()\n    return 2\n").unwrap();
+
+    let ctx = dm::Context::default();
+    let mut pp = Preprocessor::from_buffer(&ctx, root.join("test.dme"), "#include \"guarded.dm\"\n#include \"unguarded.dm\"\n");
+    pp.by_ref().for_each(drop);
+    ctx.assert_success();
+    pp.include_graph().report_missing_include_guards(&ctx, &root);
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let errors = ctx.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].description(), "missing include guard: unguarded.dm");
+    assert_eq!(errors[0].errortype(), Some("missing_include_guard"));
+    assert_eq!(ctx.file_path(errors[0].location().file).display().to_string(), "unguarded.dm");
+}
+
+#[test]
+fn include_guard_shapes() {
+    let guard = |source: &str| include_guard(Default::default(), source).map(|guard| (guard.name, guard.location.line));
+
+    assert_eq!(guard("\n#ifndef FOO\n#define FOO\n/obj\n#endif\n\n"), Some(("FOO".to_owned(), 2)));
+    // the guard must define the macro it checks
+    assert_eq!(guard("#ifndef FOO\n#define BAR\n#endif\n"), None);
+    // and must wrap the whole file
+    assert_eq!(guard("/obj\n#ifndef FOO\n#define FOO\n#endif\n"), None);
+    assert_eq!(guard("#ifndef FOO\n#define FOO\n#endif\n/obj\n"), None);
+    assert_eq!(guard("#ifndef FOO\n#define FOO\n/obj\n"), None);
+}
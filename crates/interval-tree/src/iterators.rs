@@ -28,6 +28,23 @@ impl<'a, K: Ord, V> RangePairIter<'a, K, V> {
         RangePairIter { start, end, stack }
     }
 
+    /// Iterate over every member whose range starts at or after `start`,
+    /// skipping the rest of the tree without visiting it.
+    pub(crate) fn starting_at(tree: &'a IntervalTree<K, V>, start: &K) -> Self {
+        let mut stack = Vec::with_capacity(tree.height());
+        let mut next = tree.root.as_deref();
+        while let Some(node) = next {
+            if node.key.start >= *start {
+                // this node and its right subtree come after its left subtree
+                stack.push((node, Visiting::Center));
+                next = node.left.as_deref();
+            } else {
+                next = node.right.as_deref();
+            }
+        }
+        RangePairIter { start: Bound::Unbounded, end: Bound::Unbounded, stack }
+    }
+
     fn visit_left(&mut self, node: &'a Node<K, V>) {
         match node.left {
             Some(ref lsucc) => {
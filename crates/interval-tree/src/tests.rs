@@ -172,3 +172,24 @@ fn test_range_iter_nontrivial() {
         assert_eq!(should, is);
     }
 }
+
+#[test]
+fn test_iter_from() {
+    let mut t = IntervalTree::<i32>::new();
+    for _ in 1..500 {
+        t.insert(random_range(), 1337);
+    }
+    let starts = t.iter().map(|(r, _)| r.start).step_by(10).collect::<Vec<u64>>();
+    for start in starts.into_iter().chain((1..50).map(|_| rand::random::<u64>())) {
+        let should = t
+            .iter()
+            .map(|(r, _)| r)
+            .filter(|r| r.start >= start)
+            .collect::<Vec<RangeInclusive<u64>>>();
+        let is = t
+            .iter_from(start)
+            .map(|(r, _)| r)
+            .collect::<Vec<RangeInclusive<u64>>>();
+        assert_eq!(should, is);
+    }
+}
@@ -56,6 +56,30 @@ impl<K, V> IntervalTree<K, V> {
     pub fn height(&self) -> usize {
         height(&self.root) as usize
     }
+
+    /// This function will return the number of nodes in the tree. Values inserted with the same
+    /// key share a node.
+    /// # Examples
+    /// ```
+    /// extern crate interval_tree;
+    ///
+    /// let mut t=interval_tree::IntervalTree::<u64, i32>::new();
+    /// t.insert(interval_tree::range(2,2),3);
+    /// t.insert(interval_tree::range(2,2),4);
+    /// t.insert(interval_tree::range(3,5),5);
+    /// assert_eq!(t.node_count(), 2);
+    ///
+    /// ```
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&Node<K, V>> = self.root.as_deref().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.left.as_deref());
+            stack.extend(node.right.as_deref());
+        }
+        count
+    }
 }
 
 impl<K: Ord + Clone, V> IntervalTree<K, V> {
@@ -234,6 +258,21 @@ impl<K: Ord, V> IntervalTree<K, V> {
     pub fn iter(&self) -> RangePairIter<K, V> {
         RangePairIter::new(self, Bound::Unbounded, Bound::Unbounded)
     }
+
+    /// This function will return a read only iterator for all (key,value) pairs whose key starts
+    /// at or after `start`, in order. Finding the first pair takes time proportional to the
+    /// height of the tree.
+    /// # Examples
+    /// ```
+    /// # let mut t=interval_tree::IntervalTree::<u64, i32>::new();
+    /// for (key,val) in t.iter_from(9) {
+    ///     println!("{:?} -> {}",key,val)
+    /// }
+    ///
+    /// ```
+    pub fn iter_from(&self, start: K) -> RangePairIter<'_, K, V> {
+        RangePairIter::starting_at(self, &start)
+    }
 }
 
 #[cfg(test)]